@@ -0,0 +1,64 @@
+// Times each backend on a few programs. Run with `cargo bench`, adding a
+// filter (such as `cargo bench -- fib`) to run only the matching programs.
+
+use monkey::engine::{Backend, Engine};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "fib",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(22)",
+    ),
+    (
+        "loop",
+        "let sum = fn(i, total) { if (i == 0) { total } else { sum(i - 1, total + i * 2) } };
+         sum(200000, 0)",
+    ),
+    (
+        "strings",
+        r#"let build = fn(i, s) { if (i == 0) { s } else { build(i - 1, s + "ab") } };
+           len(build(20000, ""))"#,
+    ),
+];
+
+const BACKENDS: [(&str, Backend); 3] = [
+    ("interpreter", Backend::Interpreter),
+    ("vm", Backend::Vm),
+    ("register", Backend::Register),
+];
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    println!(
+        "{:<10} {:<12} {:>10} {:>10}",
+        "program", "backend", "best", "mean"
+    );
+    for (name, source) in PROGRAMS.iter() {
+        if filter.as_ref().is_some_and(|f| !name.contains(f.as_str())) {
+            continue;
+        }
+        let mut expected = None;
+        for (backend_name, backend) in BACKENDS.iter() {
+            let mut times = vec![];
+            for _ in 0..RUNS {
+                let mut engine = Engine::new(*backend);
+                let start = Instant::now();
+                let result = engine.eval_str(source).unwrap();
+                times.push(start.elapsed());
+                // Every backend has to get the same answer.
+                assert_eq!(*expected.get_or_insert_with(|| result.clone()), result);
+            }
+            let best = times.iter().min().unwrap();
+            let mean = times.iter().sum::<Duration>() / RUNS as u32;
+            println!(
+                "{:<10} {:<12} {:>8.1}ms {:>8.1}ms",
+                name,
+                backend_name,
+                best.as_secs_f64() * 1000.0,
+                mean.as_secs_f64() * 1000.0
+            );
+        }
+    }
+}
@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use monkey::{compiler, lexer, parser};
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    let mut lexer = lexer::new(input);
+    let program = match parser::Parser::new(&mut lexer).parse_program() {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+    if let Ok(bytecode) = compiler::compile_program(&program) {
+        // Whatever the compiler makes, the VM must be able to trust.
+        if let Err(error) = bytecode.verify() {
+            panic!("The compiler made bytecode that fails to verify: {}", error);
+        }
+    }
+});
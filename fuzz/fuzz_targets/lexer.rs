@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use monkey::{lexer, token::Token};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let mut lexer = lexer::new(input);
+        // Every token but the last consumes at least one character, so a
+        // lexer that stops making progress is caught here.
+        for _ in 0..=input.chars().count() {
+            if lexer.next_token() == Token::Eof {
+                return;
+            }
+        }
+        panic!("The lexer never reached the end of the input");
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use monkey::{lexer, parser};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let mut lexer = lexer::new(input);
+        let _ = parser::Parser::new(&mut lexer).parse_program();
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use monkey::{compiler::Bytecode, limits::Limits, vm};
+
+// Enough to run any of the seed programs, but small enough that a run of
+// looping bytecode stays quick.
+const LIMITS: Limits = Limits {
+    max_depth: Some(100),
+    fuel: Some(100_000),
+    max_allocations: Some(10_000),
+    max_string_length: Some(10_000),
+    timeout: None,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // Loading verifies the bytecode, which is meant to rule out anything
+    // that would make the VM panic.
+    if let Ok(bytecode) = Bytecode::read_from(&mut &data[..]) {
+        let mut vm = vm::Vm::new(&bytecode);
+        vm.set_limits(LIMITS);
+        let _ = vm.run();
+    }
+});
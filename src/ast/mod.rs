@@ -1,20 +1,42 @@
 use crate::logic;
 use core::fmt::Display;
 use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+pub mod visit;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    Let { name: String, right: Expression },
-    Return { value: Expression },
-    Expression { expression: Expression },
+    Let {
+        name: String,
+        right: Expression,
+        /// Where the name is.
+        position: Position,
+        /// Which of its scope's slots it binds, once resolved. Names bound
+        /// at the top level have none, and are kept by name.
+        slot: Option<usize>,
+        annotation: Option<Type>,
+    },
+    Return {
+        value: Expression,
+    },
+    Expression {
+        expression: Expression,
+    },
 }
 
 impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         match self {
-            Statement::Let { name, right } => {
-                write!(f, "let {} = {};", name, right)?;
-            }
+            Statement::Let {
+                name,
+                right,
+                annotation,
+                ..
+            } => match annotation {
+                Some(annotation) => write!(f, "let {}: {} = {};", name, annotation, right)?,
+                None => write!(f, "let {} = {};", name, right)?,
+            },
             Statement::Return { value } => {
                 write!(f, "return {};", value)?;
             }
@@ -26,10 +48,24 @@ impl Display for Statement {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/**
+ * A resolved local: the binding at `index` in the scope `depth` scopes out
+ * from where it's used.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Identifier {
         value: String,
+        position: Position,
+        /// Where its binding is kept, once resolved. Globals and builtins
+        /// have none, and are looked up by name.
+        slot: Option<Slot>,
     },
     IntegerLiteral {
         value: i64,
@@ -40,11 +76,14 @@ pub enum Expression {
     Prefix {
         operator: PrefixOperator,
         right: Box<Expression>,
+        position: Position,
     },
     Infix {
         left: Box<Expression>,
         operator: InfixOperator,
         right: Box<Expression>,
+        /// Where the operator is.
+        position: Position,
     },
     Boolean {
         value: bool,
@@ -53,36 +92,58 @@ pub enum Expression {
         condition: Box<Expression>,
         consequence: BlockStatement,
         alternative: Option<BlockStatement>,
+        position: Position,
     },
     FnLiteral {
         param_names: Vec<String>,
-        body: BlockStatement,
+        /// Where each parameter's name is.
+        param_positions: Vec<Position>,
+        param_types: Vec<Option<Type>>,
+        return_type: Option<Type>,
+        body: Rc<BlockStatement>,
+        /// Where the `fn` keyword is.
+        position: Position,
     },
     CallExpression {
         left: Box<Expression>,
         arguments: Vec<Expression>,
+        /// Where the opening parenthesis is.
+        position: Position,
     },
     Block {
         statements: Vec<Statement>,
     },
+    ArrayLiteral {
+        elements: Vec<Expression>,
+    },
+    Index {
+        left: Box<Expression>,
+        index: Box<Expression>,
+        /// Where the opening bracket is.
+        position: Position,
+    },
 }
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let string_repr: String = match &self {
-            Expression::Identifier { value } => value.clone(),
+            Expression::Identifier { value, .. } => value.clone(),
             Expression::IntegerLiteral { value } => value.to_string(),
             Expression::StringLiteral { value } => value.clone(),
-            Expression::Prefix { operator, right } => format!("({}{})", operator, right),
+            Expression::Prefix {
+                operator, right, ..
+            } => format!("({}{})", operator, right),
             Expression::Infix {
                 left,
                 operator,
                 right,
+                ..
             } => format!("({} {} {})", left, operator, right),
             Expression::Boolean { value } => value.to_string(),
             &Expression::If {
                 condition,
                 consequence,
                 alternative,
+                ..
             } => format!(
                 "if ({}) {} {}",
                 condition,
@@ -92,10 +153,24 @@ impl fmt::Display for Expression {
                     .map(|a| format!("else {}", a))
                     .unwrap_or_else(|| String::from(""))
             ),
-            Expression::FnLiteral { param_names, body } => {
-                format!("fn({}) {}", param_names.join(", "), body)
-            }
-            &Expression::CallExpression { left, arguments } => format!(
+            Expression::FnLiteral {
+                param_names,
+                param_types,
+                return_type,
+                body,
+                ..
+            } => match return_type {
+                Some(return_type) => format!(
+                    "fn({}) -> {} {}",
+                    format_params(param_names, param_types),
+                    return_type,
+                    body
+                ),
+                None => format!("fn({}) {}", format_params(param_names, param_types), body),
+            },
+            &Expression::CallExpression {
+                left, arguments, ..
+            } => format!(
                 "{}({})",
                 left,
                 arguments
@@ -104,13 +179,154 @@ impl fmt::Display for Expression {
                     .collect::<Vec<String>>()
                     .join(", ")
             ),
-            Expression::Block { .. } => String::from("Block statement"),
+            Expression::Block { statements } => format!(
+                "{{{}}}",
+                statements
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>()
+                    .join("")
+            ),
+            Expression::ArrayLiteral { elements } => format!(
+                "[{}]",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Expression::Index { left, index, .. } => format!("({}[{}])", left, index),
         };
         write!(f, "{}", string_repr)
     }
 }
 
-#[derive(Debug, PartialEq)]
+/**
+ * A type written in an annotation. Code without annotations isn't checked,
+ * so `any` is only needed to mark part of a type as unchecked.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    String,
+    Null,
+    Any,
+    Array(Box<Type>),
+    Function {
+        params: Vec<Type>,
+        result: Box<Type>,
+    },
+    /// A single capital letter, standing for whatever type fits. Each use
+    /// of the same letter in one annotation is the same type.
+    Variable(String),
+}
+
+impl Type {
+    /**
+     * The type a name stands for, if it is a type name or a type variable.
+     */
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "int" => Some(Type::Int),
+            "bool" => Some(Type::Bool),
+            "string" => Some(Type::String),
+            "null" => Some(Type::Null),
+            "any" => Some(Type::Any),
+            _ if name.len() == 1 && name.chars().all(|c| c.is_ascii_uppercase()) => {
+                Some(Type::Variable(String::from(name)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Null => write!(f, "null"),
+            Type::Any => write!(f, "any"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Function { params, result } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), result)
+            }
+            Type::Variable(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/**
+ * A function's parameter list as written, without the brackets.
+ */
+pub fn format_params(names: &[String], types: &[Option<Type>]) -> String {
+    let params: Vec<String> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match types.get(i) {
+            Some(Some(ty)) => format!("{}: {}", name, ty),
+            _ => name.clone(),
+        })
+        .collect();
+    params.join(", ")
+}
+
+impl Expression {
+    /**
+     * Whether two expressions are the same but for where they are in the
+     * source.
+     */
+    pub fn same_shape(&self, other: &Expression) -> bool {
+        let mut left = self.clone();
+        let mut right = other.clone();
+        ClearPositions.visit_expression_mut(&mut left);
+        ClearPositions.visit_expression_mut(&mut right);
+        left == right
+    }
+}
+
+/**
+ * Sets every position in a tree to the default, so that trees can be
+ * compared by shape alone, however the source was laid out.
+ */
+pub struct ClearPositions;
+
+impl VisitorMut for ClearPositions {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        if let Statement::Let { position, .. } = statement {
+            *position = Position::default();
+        }
+        visit::walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Identifier { position, .. }
+            | Expression::Prefix { position, .. }
+            | Expression::Infix { position, .. }
+            | Expression::If { position, .. }
+            | Expression::CallExpression { position, .. }
+            | Expression::Index { position, .. } => *position = Position::default(),
+            Expression::FnLiteral {
+                param_positions,
+                position,
+                ..
+            } => {
+                *position = Position::default();
+                for param in param_positions {
+                    *param = Position::default();
+                }
+            }
+            _ => {}
+        }
+        visit::walk_expression_mut(self, expression);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
@@ -124,7 +340,7 @@ impl Display for Program {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockStatement {
     pub statements: Vec<Statement>,
 }
@@ -140,4 +356,6 @@ impl Display for BlockStatement {
     }
 }
 
+pub use crate::token::{Position, Span};
 pub use logic::{InfixOperator, PrefixOperator};
+pub use visit::{Visitor, VisitorMut};
@@ -0,0 +1,269 @@
+use super::{BlockStatement, Expression, Program, Statement};
+use std::rc::Rc;
+
+/**
+ * Walks a syntax tree. Each method walks into its node's children by
+ * default, so a pass overrides only the nodes it cares about, calling the
+ * matching `walk_` function to carry on into their children.
+ */
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    /**
+     * Visits the body of an `if` branch or of a function. A block
+     * expression's statements are visited one by one instead.
+     */
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        walk_block_statement(self, block);
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let { right, .. } => visitor.visit_expression(right),
+        Statement::Return { value } => visitor.visit_expression(value),
+        Statement::Expression { expression } => visitor.visit_expression(expression),
+    }
+}
+
+pub fn walk_block_statement<V: Visitor + ?Sized>(visitor: &mut V, block: &BlockStatement) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+/**
+ * Visits an expression's children in the order they appear in the source.
+ */
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Identifier { .. }
+        | Expression::IntegerLiteral { .. }
+        | Expression::StringLiteral { .. }
+        | Expression::Boolean { .. } => {}
+        Expression::Prefix { right, .. } => visitor.visit_expression(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block_statement(consequence);
+            if let Some(alternative) = alternative {
+                visitor.visit_block_statement(alternative);
+            }
+        }
+        Expression::FnLiteral { body, .. } => visitor.visit_block_statement(body),
+        Expression::CallExpression {
+            left, arguments, ..
+        } => {
+            visitor.visit_expression(left);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Block { statements } => {
+            for statement in statements {
+                visitor.visit_statement(statement);
+            }
+        }
+        Expression::ArrayLiteral { elements } => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        }
+        Expression::Index { left, index, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(index);
+        }
+    }
+}
+
+/**
+ * Walks a syntax tree, able to change it in place. Otherwise just like
+ * `Visitor`.
+ */
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_block_statement_mut(&mut self, block: &mut BlockStatement) {
+        walk_block_statement_mut(self, block);
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let { right, .. } => visitor.visit_expression_mut(right),
+        Statement::Return { value } => visitor.visit_expression_mut(value),
+        Statement::Expression { expression } => visitor.visit_expression_mut(expression),
+    }
+}
+
+pub fn walk_block_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    block: &mut BlockStatement,
+) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+/**
+ * Visits an expression's children in the order they appear in the source.
+ * A function body shared with other trees is copied before it is visited.
+ */
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Identifier { .. }
+        | Expression::IntegerLiteral { .. }
+        | Expression::StringLiteral { .. }
+        | Expression::Boolean { .. } => {}
+        Expression::Prefix { right, .. } => visitor.visit_expression_mut(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_statement_mut(consequence);
+            if let Some(alternative) = alternative {
+                visitor.visit_block_statement_mut(alternative);
+            }
+        }
+        Expression::FnLiteral { body, .. } => visitor.visit_block_statement_mut(Rc::make_mut(body)),
+        Expression::CallExpression {
+            left, arguments, ..
+        } => {
+            visitor.visit_expression_mut(left);
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        Expression::Block { statements } => {
+            for statement in statements {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        Expression::ArrayLiteral { elements } => {
+            for element in elements {
+                visitor.visit_expression_mut(element);
+            }
+        }
+        Expression::Index { left, index, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{walk_expression, walk_expression_mut, Visitor, VisitorMut};
+    use crate::ast::{Expression, Program};
+    use crate::{lexer, parser};
+
+    fn parse(input: &str) -> Program {
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        parser.parse_program().unwrap()
+    }
+
+    #[derive(Default)]
+    struct Names {
+        names: Vec<String>,
+    }
+
+    impl Visitor for Names {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Identifier { value, .. } = expression {
+                self.names.push(value.clone());
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::Identifier { value, .. } = expression {
+                value.make_ascii_uppercase();
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    const PROGRAM: &str = "let f = fn(x) { if (x) { a } else { { b } } };
+        return f(c)[d] + -e;
+        [g, h];";
+
+    #[test]
+    fn test_visits_in_source_order() {
+        let mut names = Names::default();
+        names.visit_program(&parse(PROGRAM));
+        assert_eq!(
+            names.names,
+            vec!["x", "a", "b", "f", "c", "d", "e", "g", "h"]
+        );
+    }
+
+    #[test]
+    fn test_changes_in_place() {
+        let original = parse(PROGRAM);
+        let mut program = original.clone();
+        Rename.visit_program_mut(&mut program);
+        let mut names = Names::default();
+        names.visit_program(&program);
+        assert_eq!(
+            names.names,
+            vec!["X", "A", "B", "F", "C", "D", "E", "G", "H"]
+        );
+        // The function body was shared with the original, which is left as
+        // it was.
+        let mut names = Names::default();
+        names.visit_program(&original);
+        assert_eq!(names.names[1], "a");
+    }
+}
@@ -0,0 +1,85 @@
+use super::Instruction;
+use crate::object::Object;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/**
+ * Renders instructions as a listing with one instruction per line, after
+ * its offset. Constants are shown next to the instructions that load them,
+ * and jumps refer to labels placed before their targets. Anything that
+ * can't be decoded ends the listing.
+ */
+pub fn disassemble(instructions: &[u8], constants: &[Rc<Object>]) -> String {
+    let mut decoded = vec![];
+    let mut position = 0;
+    let mut error = None;
+    loop {
+        match Instruction::read_at(instructions, position) {
+            Ok(Some((instruction, next))) => {
+                decoded.push((position, instruction));
+                position = next;
+            }
+            Ok(None) => break,
+            Err(decode_error) => {
+                error = Some((position, decode_error));
+                break;
+            }
+        }
+    }
+
+    // Labels are numbered in the order they appear.
+    let mut labels: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, instruction) in &decoded {
+        if let Some(target) = instruction.jump_target() {
+            labels.insert(target, 0);
+        }
+    }
+    for (number, label) in labels.values_mut().enumerate() {
+        *label = number;
+    }
+
+    let mut listing = String::new();
+    for (position, instruction) in &decoded {
+        if let Some(label) = labels.get(position) {
+            writeln!(listing, "L{}:", label).unwrap();
+        }
+        let label = |target: &u16| labels[&(*target as usize)];
+        let wide_label = |target: &u32| labels[&(*target as usize)];
+        let text = match instruction {
+            Instruction::Jump(target) => format!("Jump L{}", label(target)),
+            Instruction::JumpFalse(target) => format!("JumpFalse L{}", label(target)),
+            Instruction::JumpWide(target) => format!("JumpWide L{}", wide_label(target)),
+            Instruction::JumpFalseWide(target) => {
+                format!("JumpFalseWide L{}", wide_label(target))
+            }
+            _ => match instruction.constant_index() {
+                Some(index) => format!(
+                    "{:<16} ; {}",
+                    instruction.to_string(),
+                    describe_constant(constants, index)
+                ),
+                None => instruction.to_string(),
+            },
+        };
+        writeln!(listing, "{:04} {}", position, text).unwrap();
+    }
+    if let Some((position, error)) = error {
+        writeln!(listing, "{:04} <{}>", position, error).unwrap();
+    } else if let Some(label) = labels.get(&instructions.len()) {
+        // A jump past the last instruction, out of a trailing if.
+        writeln!(listing, "L{}:", label).unwrap();
+    }
+    listing
+}
+
+pub(crate) fn describe_constant(constants: &[Rc<Object>], index: usize) -> String {
+    match constants.get(index).map(|constant| &**constant) {
+        Some(Object::String(value)) => format!("{:?}", value),
+        Some(Object::CompiledFunction(function)) => {
+            format!("fn {}", function.name.as_deref().unwrap_or("<anonymous>"))
+        }
+        Some(constant) => constant.to_string(),
+        None => String::from("<missing constant>"),
+    }
+}
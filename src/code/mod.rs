@@ -1,5 +1,30 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+mod disassembler;
 #[cfg(test)]
 mod test;
+
+pub(crate) use disassembler::describe_constant;
+pub use disassembler::disassemble;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    UnknownOpcode(u8),
+    /// The instructions end part way through an instruction's operands.
+    Truncated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {}", opcode),
+            DecodeError::Truncated => write!(f, "The instruction is cut short"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Constant(u16),
     Add,
@@ -12,10 +37,44 @@ pub enum Instruction {
     Equal,
     NotEqual,
     GreaterThan,
+    LessThan,
     Minus,
     Bang,
     JumpFalse(u16),
     Jump(u16),
+    Null,
+    GetGlobal(u16),
+    SetGlobal(u16),
+    Array(u16),
+    Index,
+    Call(u8),
+    ReturnValue,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetBuiltin(u8),
+    /// Index of the compiled function in the constant pool, then the number
+    /// of free variables to take off the stack.
+    Closure(u16, u8),
+    GetFree(u8),
+    CurrentClosure,
+    /// A call whose result the calling function returns, which can reuse
+    /// the caller's frame. A return always follows, for calls to builtins.
+    TailCall(u8),
+    /// Forms of the instructions above with 32-bit operands, for constants
+    /// and jump targets that don't fit in 16 bits. The compiler only uses
+    /// them where it has to.
+    ConstantWide(u32),
+    JumpFalseWide(u32),
+    JumpWide(u32),
+    ClosureWide(u32, u8),
+    /// Puts an empty cell in the local, for a closure to capture before the
+    /// local is bound. `GetLocal` then reads the cell itself, for capturing.
+    NewCell(u8),
+    /// Reads and writes the value in the cell kept in a local.
+    GetCell(u8),
+    SetCell(u8),
+    /// Reads the value in the cell that is a free variable.
+    GetFreeCell(u8),
 }
 impl Instruction {
     fn opcode_byte(&self) -> u8 {
@@ -35,6 +94,93 @@ impl Instruction {
             Self::Bang => 12,
             Self::JumpFalse(_) => 13,
             Self::Jump(_) => 14,
+            Self::Null => 15,
+            Self::GetGlobal(_) => 16,
+            Self::SetGlobal(_) => 17,
+            Self::Array(_) => 18,
+            Self::Index => 19,
+            Self::Call(_) => 20,
+            Self::ReturnValue => 21,
+            Self::GetLocal(_) => 22,
+            Self::SetLocal(_) => 23,
+            Self::GetBuiltin(_) => 24,
+            Self::Closure(..) => 25,
+            Self::GetFree(_) => 26,
+            Self::CurrentClosure => 27,
+            Self::TailCall(_) => 28,
+            Self::ConstantWide(_) => 29,
+            Self::JumpFalseWide(_) => 30,
+            Self::JumpWide(_) => 31,
+            Self::ClosureWide(..) => 32,
+            Self::LessThan => 33,
+            Self::NewCell(_) => 34,
+            Self::GetCell(_) => 35,
+            Self::SetCell(_) => 36,
+            Self::GetFreeCell(_) => 37,
+        }
+    }
+
+    /**
+     * Loads the constant at `index`, using the wide form only if it has to.
+     */
+    pub fn constant(index: u32) -> Self {
+        match u16::try_from(index) {
+            Ok(index) => Self::Constant(index),
+            Err(_) => Self::ConstantWide(index),
+        }
+    }
+
+    pub fn closure(index: u32, num_free: u8) -> Self {
+        match u16::try_from(index) {
+            Ok(index) => Self::Closure(index, num_free),
+            Err(_) => Self::ClosureWide(index, num_free),
+        }
+    }
+
+    /**
+     * The index of the constant the instruction loads or makes a closure
+     * of, in either width.
+     */
+    pub fn constant_index(&self) -> Option<usize> {
+        match self {
+            Self::Constant(index) | Self::Closure(index, _) => Some(*index as usize),
+            Self::ConstantWide(index) | Self::ClosureWide(index, _) => Some(*index as usize),
+            _ => None,
+        }
+    }
+
+    /**
+     * Where the instruction jumps to, if it is a jump of either width.
+     */
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Self::Jump(target) | Self::JumpFalse(target) => Some(*target as usize),
+            Self::JumpWide(target) | Self::JumpFalseWide(target) => Some(*target as usize),
+            _ => None,
+        }
+    }
+
+    pub fn is_unconditional_jump(&self) -> bool {
+        matches!(self, Self::Jump(_) | Self::JumpWide(_))
+    }
+
+    /**
+     * The same jump sent to `target` instead, in the narrow form if the
+     * target fits. Anything other than a jump is returned unchanged.
+     */
+    pub fn retarget(&self, target: usize) -> Self {
+        let target = u32::try_from(target).expect("Jump target beyond 32 bits");
+        let narrow = u16::try_from(target).ok();
+        match (self, narrow) {
+            (Self::Jump(_), Some(target)) | (Self::JumpWide(_), Some(target)) => Self::Jump(target),
+            (Self::Jump(_), None) | (Self::JumpWide(_), None) => Self::JumpWide(target),
+            (Self::JumpFalse(_), Some(target)) | (Self::JumpFalseWide(_), Some(target)) => {
+                Self::JumpFalse(target)
+            }
+            (Self::JumpFalse(_), None) | (Self::JumpFalseWide(_), None) => {
+                Self::JumpFalseWide(target)
+            }
+            _ => self.clone(),
         }
     }
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -50,10 +196,41 @@ impl Instruction {
             Self::Equal => vec![],
             Self::NotEqual => vec![],
             Self::GreaterThan => vec![],
+            Self::LessThan => vec![],
             Self::Minus => vec![],
             Self::Bang => vec![],
             Self::JumpFalse(position) => position.to_be_bytes().to_vec(),
             Self::Jump(position) => position.to_be_bytes().to_vec(),
+            Self::Null => vec![],
+            Self::GetGlobal(index) => index.to_be_bytes().to_vec(),
+            Self::SetGlobal(index) => index.to_be_bytes().to_vec(),
+            Self::Array(length) => length.to_be_bytes().to_vec(),
+            Self::Index => vec![],
+            Self::Call(num_args) => vec![*num_args],
+            Self::ReturnValue => vec![],
+            Self::GetLocal(index) => vec![*index],
+            Self::SetLocal(index) => vec![*index],
+            Self::GetBuiltin(index) => vec![*index],
+            Self::Closure(constant, num_free) => {
+                let mut bytes = constant.to_be_bytes().to_vec();
+                bytes.push(*num_free);
+                bytes
+            }
+            Self::GetFree(index) => vec![*index],
+            Self::CurrentClosure => vec![],
+            Self::TailCall(num_args) => vec![*num_args],
+            Self::ConstantWide(constant) => constant.to_be_bytes().to_vec(),
+            Self::JumpFalseWide(position) => position.to_be_bytes().to_vec(),
+            Self::JumpWide(position) => position.to_be_bytes().to_vec(),
+            Self::ClosureWide(constant, num_free) => {
+                let mut bytes = constant.to_be_bytes().to_vec();
+                bytes.push(*num_free);
+                bytes
+            }
+            Self::NewCell(index) => vec![*index],
+            Self::GetCell(index) => vec![*index],
+            Self::SetCell(index) => vec![*index],
+            Self::GetFreeCell(index) => vec![*index],
         };
         let mut result = vec![self.opcode_byte()];
         Vec::append(&mut result, &mut operand_bytes);
@@ -61,34 +238,230 @@ impl Instruction {
     }
 
     /**
-     * Takes an iterator, reads some bytes and returns the corresponding instruction.
+     * Reads one instruction from the iterator, which must not be empty.
+     */
+    pub fn from_bytes(iter: &mut std::slice::Iter<u8>) -> Result<Self, DecodeError> {
+        let op_byte = *iter.next().ok_or(DecodeError::Truncated)?;
+        let instruction = match op_byte {
+            0 => Self::Constant(read_2_bytes(iter)?),
+            1 => Self::Add,
+            2 => Self::Sub,
+            3 => Self::Pop,
+            4 => Self::Mul,
+            5 => Self::Div,
+            6 => Self::True,
+            7 => Self::False,
+            8 => Self::Equal,
+            9 => Self::NotEqual,
+            10 => Self::GreaterThan,
+            11 => Self::Minus,
+            12 => Self::Bang,
+            13 => Self::JumpFalse(read_2_bytes(iter)?),
+            14 => Self::Jump(read_2_bytes(iter)?),
+            15 => Self::Null,
+            16 => Self::GetGlobal(read_2_bytes(iter)?),
+            17 => Self::SetGlobal(read_2_bytes(iter)?),
+            18 => Self::Array(read_2_bytes(iter)?),
+            19 => Self::Index,
+            20 => Self::Call(read_byte(iter)?),
+            21 => Self::ReturnValue,
+            22 => Self::GetLocal(read_byte(iter)?),
+            23 => Self::SetLocal(read_byte(iter)?),
+            24 => Self::GetBuiltin(read_byte(iter)?),
+            25 => Self::Closure(read_2_bytes(iter)?, read_byte(iter)?),
+            26 => Self::GetFree(read_byte(iter)?),
+            27 => Self::CurrentClosure,
+            28 => Self::TailCall(read_byte(iter)?),
+            29 => Self::ConstantWide(read_4_bytes(iter)?),
+            30 => Self::JumpFalseWide(read_4_bytes(iter)?),
+            31 => Self::JumpWide(read_4_bytes(iter)?),
+            32 => Self::ClosureWide(read_4_bytes(iter)?, read_byte(iter)?),
+            33 => Self::LessThan,
+            34 => Self::NewCell(read_byte(iter)?),
+            35 => Self::GetCell(read_byte(iter)?),
+            36 => Self::SetCell(read_byte(iter)?),
+            37 => Self::GetFreeCell(read_byte(iter)?),
+            _ => return Err(DecodeError::UnknownOpcode(op_byte)),
+        };
+        Ok(instruction)
+    }
+
+    /**
+     * Reads the instruction starting at `position`, returning it along with
+     * the position of the instruction after it, or `None` at the end.
      */
-    pub fn from_bytes(iter: &mut std::slice::Iter<u8>) -> Option<Self> {
-        // If there is no next, return none to signify we've reached the end.
-        let op_byte = iter.next()?;
-        match op_byte {
-            0 => Some(Self::Constant(read_2_bytes(iter))),
-            1 => Some(Self::Add),
-            2 => Some(Self::Sub),
-            3 => Some(Self::Pop),
-            4 => Some(Self::Mul),
-            5 => Some(Self::Div),
-            6 => Some(Self::True),
-            7 => Some(Self::False),
-            8 => Some(Self::Equal),
-            9 => Some(Self::NotEqual),
-            10 => Some(Self::GreaterThan),
-            11 => Some(Self::Minus),
-            12 => Some(Self::Bang),
-            13 => Some(Self::JumpFalse(read_2_bytes(iter))),
-            14 => Some(Self::Jump(read_2_bytes(iter))),
-            _ => panic!("Unknown op byte"),
+    pub fn read_at(
+        instructions: &[u8],
+        position: usize,
+    ) -> Result<Option<(Self, usize)>, DecodeError> {
+        let mut iter = match instructions.get(position..) {
+            Some(rest) if !rest.is_empty() => rest.iter(),
+            _ => return Ok(None),
+        };
+        let instruction = Self::from_bytes(&mut iter)?;
+        Ok(Some((
+            instruction,
+            instructions.len() - iter.as_slice().len(),
+        )))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant(constant) => write!(f, "Constant {}", constant),
+            Self::Add => write!(f, "Add"),
+            Self::Sub => write!(f, "Sub"),
+            Self::Pop => write!(f, "Pop"),
+            Self::Mul => write!(f, "Mul"),
+            Self::Div => write!(f, "Div"),
+            Self::True => write!(f, "True"),
+            Self::False => write!(f, "False"),
+            Self::Equal => write!(f, "Equal"),
+            Self::NotEqual => write!(f, "NotEqual"),
+            Self::GreaterThan => write!(f, "GreaterThan"),
+            Self::LessThan => write!(f, "LessThan"),
+            Self::Minus => write!(f, "Minus"),
+            Self::Bang => write!(f, "Bang"),
+            Self::JumpFalse(position) => write!(f, "JumpFalse {}", position),
+            Self::Jump(position) => write!(f, "Jump {}", position),
+            Self::Null => write!(f, "Null"),
+            Self::GetGlobal(index) => write!(f, "GetGlobal {}", index),
+            Self::SetGlobal(index) => write!(f, "SetGlobal {}", index),
+            Self::Array(length) => write!(f, "Array {}", length),
+            Self::Index => write!(f, "Index"),
+            Self::Call(num_args) => write!(f, "Call {}", num_args),
+            Self::ReturnValue => write!(f, "ReturnValue"),
+            Self::GetLocal(index) => write!(f, "GetLocal {}", index),
+            Self::SetLocal(index) => write!(f, "SetLocal {}", index),
+            Self::GetBuiltin(index) => write!(f, "GetBuiltin {}", index),
+            Self::Closure(constant, num_free) => write!(f, "Closure {} {}", constant, num_free),
+            Self::GetFree(index) => write!(f, "GetFree {}", index),
+            Self::CurrentClosure => write!(f, "CurrentClosure"),
+            Self::TailCall(num_args) => write!(f, "TailCall {}", num_args),
+            Self::ConstantWide(constant) => write!(f, "ConstantWide {}", constant),
+            Self::JumpFalseWide(position) => write!(f, "JumpFalseWide {}", position),
+            Self::JumpWide(position) => write!(f, "JumpWide {}", position),
+            Self::ClosureWide(constant, num_free) => {
+                write!(f, "ClosureWide {} {}", constant, num_free)
+            }
+            Self::NewCell(index) => write!(f, "NewCell {}", index),
+            Self::GetCell(index) => write!(f, "GetCell {}", index),
+            Self::SetCell(index) => write!(f, "SetCell {}", index),
+            Self::GetFreeCell(index) => write!(f, "GetFreeCell {}", index),
+        }
+    }
+}
+
+fn read_byte(iter: &mut std::slice::Iter<u8>) -> Result<u8, DecodeError> {
+    iter.next().copied().ok_or(DecodeError::Truncated)
+}
+
+fn read_2_bytes(iter: &mut std::slice::Iter<u8>) -> Result<u16, DecodeError> {
+    let first = read_byte(iter)?;
+    let second = read_byte(iter)?;
+    Ok(u16::from_be_bytes([first, second]))
+}
+
+fn read_4_bytes(iter: &mut std::slice::Iter<u8>) -> Result<u32, DecodeError> {
+    let bytes = [
+        read_byte(iter)?,
+        read_byte(iter)?,
+        read_byte(iter)?,
+        read_byte(iter)?,
+    ];
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/**
+ * Decodes a whole function's instructions, giving each jump's target as an
+ * index into them rather than a byte position. The end of the instructions
+ * is index `instructions.len()`.
+ */
+pub(crate) fn decode_with_targets(
+    bytes: &[u8],
+) -> Result<(Vec<Instruction>, Vec<Option<usize>>), DecodeError> {
+    let mut positions = vec![];
+    let mut instructions = vec![];
+    let mut position = 0;
+    while let Some((instruction, next)) = Instruction::read_at(bytes, position)? {
+        positions.push(position);
+        instructions.push(instruction);
+        position = next;
+    }
+    let end = instructions.len();
+    let targets = instructions
+        .iter()
+        .map(|instruction| {
+            let target = instruction.jump_target()?;
+            Some(positions.binary_search(&target).unwrap_or(end))
+        })
+        .collect();
+    Ok((instructions, targets))
+}
+
+/**
+ * The offset each instruction has when they are encoded one after another,
+ * followed by the offset of the end.
+ */
+pub(crate) fn offsets(instructions: &[Instruction]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(instructions.len() + 1);
+    let mut offset = 0;
+    for instruction in instructions {
+        offsets.push(offset);
+        offset += instruction.to_bytes().len();
+    }
+    offsets.push(offset);
+    offsets
+}
+
+/**
+ * The inverse of `decode_with_targets`, also returning the offset of each
+ * instruction as `offsets` does. Each jump gets the narrowest form that
+ * reaches its target, which takes a few passes, as widening one jump can
+ * push another's target out of reach.
+ */
+pub(crate) fn assemble(
+    instructions: &[Instruction],
+    targets: &[Option<usize>],
+) -> (Vec<u8>, Vec<usize>) {
+    let mut encoded: Vec<Instruction> = instructions
+        .iter()
+        .map(|instruction| instruction.retarget(0))
+        .collect();
+    loop {
+        let positions = offsets(&encoded);
+        let mut changed = false;
+        for (index, target) in targets.iter().enumerate() {
+            if let Some(target) = target {
+                let jump = instructions[index].retarget(positions[*target]);
+                changed |= jump.to_bytes().len() != encoded[index].to_bytes().len();
+                encoded[index] = jump;
+            }
+        }
+        if !changed {
+            let bytes = encoded.iter().flat_map(Instruction::to_bytes).collect();
+            return (bytes, positions);
         }
     }
 }
 
-fn read_2_bytes(iter: &mut std::slice::Iter<u8>) -> u16 {
-    let first = *iter.next().unwrap();
-    let second = *iter.next().unwrap();
-    u16::from_be_bytes([first, second])
+/**
+ * Where instructions moved to when code was laid out again, as pairs of
+ * their old and new offsets in order.
+ */
+pub(crate) type Moves = Vec<(usize, usize)>;
+
+/**
+ * Re-encodes instructions so that each jump is as narrow as it can be,
+ * also returning where each instruction moved to.
+ */
+pub(crate) fn relayout(bytes: &[u8]) -> Result<(Vec<u8>, Moves), DecodeError> {
+    let (instructions, targets) = decode_with_targets(bytes)?;
+    let (relaid, new_offsets) = assemble(&instructions, &targets);
+    let moves = offsets(&instructions)
+        .into_iter()
+        .zip(new_offsets)
+        .collect();
+    Ok((relaid, moves))
 }
@@ -5,3 +5,128 @@ fn test_to_bytes() {
     let bytes = code::Instruction::Constant(65534).to_bytes();
     assert_eq!(bytes, vec![0, 0xFF, 0xFE]);
 }
+
+#[test]
+fn test_operand_round_trip() {
+    let instructions = vec![
+        code::Instruction::Constant(65534),
+        code::Instruction::GetLocal(255),
+        code::Instruction::Closure(65535, 3),
+        code::Instruction::Call(2),
+        code::Instruction::ReturnValue,
+        code::Instruction::TailCall(1),
+    ];
+    let bytes: Vec<u8> = instructions.iter().flat_map(|i| i.to_bytes()).collect();
+    assert_eq!(
+        bytes,
+        vec![0, 0xFF, 0xFE, 22, 0xFF, 25, 0xFF, 0xFF, 3, 20, 2, 21, 28, 1]
+    );
+    let mut position = 0;
+    for expected in instructions {
+        let (instruction, next) = code::Instruction::read_at(&bytes, position)
+            .unwrap()
+            .unwrap();
+        assert_eq!(instruction, expected);
+        position = next;
+    }
+    assert_eq!(code::Instruction::read_at(&bytes, position), Ok(None));
+}
+
+#[test]
+fn test_wide_operands() {
+    assert_eq!(
+        code::Instruction::constant(65535),
+        code::Instruction::Constant(65535)
+    );
+    assert_eq!(
+        code::Instruction::constant(65536),
+        code::Instruction::ConstantWide(65536)
+    );
+    let instruction = code::Instruction::ClosureWide(0x0102_0304, 5);
+    let bytes = instruction.to_bytes();
+    assert_eq!(bytes, vec![32, 1, 2, 3, 4, 5]);
+    assert_eq!(
+        code::Instruction::read_at(&bytes, 0),
+        Ok(Some((instruction, 6)))
+    );
+    assert_eq!(
+        code::Instruction::JumpWide(3).retarget(70000),
+        code::Instruction::JumpWide(70000)
+    );
+    assert_eq!(
+        code::Instruction::JumpFalseWide(70000).retarget(3),
+        code::Instruction::JumpFalse(3)
+    );
+}
+
+#[test]
+fn test_assemble_widens_far_jumps() {
+    let mut instructions = vec![
+        code::Instruction::True,
+        code::Instruction::JumpFalse(0),
+        code::Instruction::Jump(0),
+    ];
+    instructions.resize(70003, code::Instruction::Null);
+    let mut targets = vec![None; instructions.len()];
+    targets[1] = Some(3);
+    targets[2] = Some(instructions.len());
+
+    let (bytes, offsets) = code::assemble(&instructions, &targets);
+    assert_eq!(offsets[..4], [0, 1, 4, 9]);
+    // The far jump is wide, which moves the near one's target along.
+    assert_eq!(
+        code::Instruction::read_at(&bytes, 1).unwrap(),
+        Some((code::Instruction::JumpFalse(9), 4))
+    );
+    assert_eq!(
+        code::Instruction::read_at(&bytes, 4).unwrap(),
+        Some((code::Instruction::JumpWide(70009), 9))
+    );
+    instructions[1] = code::Instruction::JumpFalse(9);
+    instructions[2] = code::Instruction::JumpWide(70009);
+    assert_eq!(
+        code::decode_with_targets(&bytes),
+        Ok((instructions, targets))
+    );
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(code::Instruction::Constant(3).to_string(), "Constant 3");
+    assert_eq!(code::Instruction::Closure(2, 1).to_string(), "Closure 2 1");
+    assert_eq!(code::Instruction::ReturnValue.to_string(), "ReturnValue");
+}
+
+#[test]
+fn test_disassemble_labels() {
+    let instructions: Vec<u8> = [
+        code::Instruction::True,
+        code::Instruction::JumpFalse(8),
+        code::Instruction::Null,
+        code::Instruction::Jump(9),
+        code::Instruction::Null,
+    ]
+    .iter()
+    .flat_map(|i| i.to_bytes())
+    .collect();
+    assert_eq!(
+        code::disassemble(&instructions, &[]),
+        "0000 True\n0001 JumpFalse L0\n0004 Null\n0005 Jump L1\nL0:\n0008 Null\nL1:\n"
+    );
+}
+
+#[test]
+fn test_decode_errors() {
+    assert_eq!(
+        code::Instruction::read_at(&[200], 0),
+        Err(code::DecodeError::UnknownOpcode(200))
+    );
+    assert_eq!(
+        code::Instruction::read_at(&[25, 0, 1], 0),
+        Err(code::DecodeError::Truncated)
+    );
+    assert_eq!(
+        code::disassemble(&[6, 0, 0], &[]),
+        "0000 True\n0001 <The instruction is cut short>\n"
+    );
+}
@@ -0,0 +1,445 @@
+use super::{Bytecode, LineTable, SourceMap, VerifyError};
+use crate::ast::Position;
+use crate::object::{CompiledFunction, Object};
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/*
+ * A `.mkc` file holds one `Bytecode`. All numbers are big endian.
+ *
+ *   magic          b"MKC\0"
+ *   version        u16
+ *   flags          u8, DEBUG_INFO if there is a debug section
+ *   constants      u32 count, then each a tag byte followed by
+ *                    TAG_INTEGER   i64
+ *                    TAG_STRING    string
+ *                    TAG_FUNCTION  u32 locals, u32 parameters, bytes
+ *   instructions   bytes
+ *   debug section  u32 count, then each a u32 constant index and the
+ *                  string name of the function there, then the line
+ *                  table of the main program, then a u32 count of
+ *                  function line tables, each after its u32 constant index
+ *
+ * where bytes and strings are a u32 length followed by the data, and a line
+ * table is a u32 count of entries, each a u32 offset, line and column.
+ */
+
+const MAGIC: &[u8; 4] = b"MKC\0";
+/// Bumped whenever the format, the instruction set or the order of the
+/// default builtins changes, as any of them can change what a file means.
+pub const FORMAT_VERSION: u16 = 5;
+
+const DEBUG_INFO: u8 = 1;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file doesn't start with the magic number.
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Corrupt(String),
+    /// The file is well formed, but its bytecode isn't safe to run.
+    Invalid(VerifyError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "Couldn't read bytecode: {}", error),
+            LoadError::NotBytecode => write!(f, "Not a Monkey bytecode file"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode format version {} isn't supported (expected {})",
+                version, FORMAT_VERSION
+            ),
+            LoadError::Corrupt(message) => write!(f, "Corrupt bytecode file: {}", message),
+            LoadError::Invalid(error) => write!(f, "Invalid bytecode: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl Bytecode {
+    /**
+     * Writes the bytecode in the `.mkc` format, including the names of its
+     * functions and its source map.
+     */
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode(true))
+    }
+
+    /**
+     * Like `write_to`, but leaves out debug info.
+     */
+    pub fn write_stripped_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode(false))
+    }
+
+    /**
+     * Reads bytecode written by `write_to`, and verifies it so that the VM
+     * can run it without panicking.
+     */
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Bytecode, LoadError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+
+    fn encode(&self, debug_info: bool) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.push(if debug_info { DEBUG_INFO } else { 0 });
+
+        let mut names = vec![];
+        write_length(&mut bytes, self.constants.len());
+        for (index, constant) in self.constants.iter().enumerate() {
+            match &**constant {
+                Object::Integer(value) => {
+                    bytes.push(TAG_INTEGER);
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                }
+                Object::String(value) => {
+                    bytes.push(TAG_STRING);
+                    write_bytes(&mut bytes, value.as_bytes());
+                }
+                Object::CompiledFunction(function) => {
+                    bytes.push(TAG_FUNCTION);
+                    write_length(&mut bytes, function.num_locals);
+                    write_length(&mut bytes, function.num_parameters);
+                    write_bytes(&mut bytes, &function.instructions);
+                    if let Some(name) = &function.name {
+                        names.push((index, name));
+                    }
+                }
+                // The compiler only ever puts the above in the pool.
+                other => panic!("Can't write a {} constant", other.type_name()),
+            }
+        }
+        write_bytes(&mut bytes, &self.instructions);
+
+        if debug_info {
+            write_length(&mut bytes, names.len());
+            for (index, name) in names {
+                write_length(&mut bytes, index);
+                write_bytes(&mut bytes, name.as_bytes());
+            }
+            write_line_table(&mut bytes, &self.source_map.main);
+            write_length(&mut bytes, self.source_map.functions.len());
+            for (index, line_table) in &self.source_map.functions {
+                write_length(&mut bytes, *index);
+                write_line_table(&mut bytes, line_table);
+            }
+        }
+        bytes
+    }
+}
+
+fn write_line_table(bytes: &mut Vec<u8>, line_table: &LineTable) {
+    write_length(bytes, line_table.entries.len());
+    for (offset, position) in &line_table.entries {
+        write_length(bytes, *offset);
+        write_length(bytes, position.line);
+        write_length(bytes, position.column);
+    }
+}
+
+fn write_length(bytes: &mut Vec<u8>, length: usize) {
+    let length: u32 = length.try_into().expect("Bytecode too large to write");
+    bytes.extend_from_slice(&length.to_be_bytes());
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_length(bytes, data.len());
+    bytes.extend_from_slice(data);
+}
+
+fn decode(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len(), "the magic number").ok() != Some(&MAGIC[..]) {
+        return Err(LoadError::NotBytecode);
+    }
+    let version = reader.u16("the version")?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let flags = reader.u8("the flags")?;
+    if flags & !DEBUG_INFO != 0 {
+        return Err(corrupt(format!("Unknown flags {:#04x}", flags)));
+    }
+
+    let num_constants = reader.length("the constant count")?;
+    let mut constants = vec![];
+    for _ in 0..num_constants {
+        let constant = match reader.u8("a constant")? {
+            TAG_INTEGER => Object::Integer(i64::from_be_bytes(
+                reader.take(8, "an integer")?.try_into().unwrap(),
+            )),
+            TAG_STRING => Object::String(reader.string("a string")?),
+            TAG_FUNCTION => {
+                let num_locals = reader.length("a function")?;
+                let num_parameters = reader.length("a function")?;
+                let instructions = reader.bytes("a function")?.to_vec();
+                Object::CompiledFunction(Rc::new(CompiledFunction {
+                    instructions,
+                    num_locals,
+                    num_parameters,
+                    name: None,
+                }))
+            }
+            tag => return Err(corrupt(format!("Unknown constant tag {}", tag))),
+        };
+        constants.push(constant);
+    }
+    let instructions = reader.bytes("the instructions")?.to_vec();
+
+    let mut source_map = SourceMap::default();
+    if flags & DEBUG_INFO != 0 {
+        let num_names = reader.length("the debug info")?;
+        for _ in 0..num_names {
+            let index = reader.length("the debug info")?;
+            let name = reader.string("the debug info")?;
+            match constants.get_mut(index) {
+                Some(Object::CompiledFunction(function)) => {
+                    Rc::get_mut(function).unwrap().name = Some(name);
+                }
+                _ => {
+                    return Err(corrupt(format!(
+                        "Constant {} is named but isn't a function",
+                        index
+                    )))
+                }
+            }
+        }
+        source_map.main = reader.line_table()?;
+        let num_tables = reader.length("the source map")?;
+        for _ in 0..num_tables {
+            let index = reader.length("the source map")?;
+            if !matches!(constants.get(index), Some(Object::CompiledFunction(_))) {
+                return Err(corrupt(format!(
+                    "Constant {} has a line table but isn't a function",
+                    index
+                )));
+            }
+            source_map.functions.insert(index, reader.line_table()?);
+        }
+    }
+    if reader.position != bytes.len() {
+        return Err(corrupt(String::from("There is data after the end")));
+    }
+
+    let bytecode = Bytecode {
+        instructions,
+        constants: constants.into_iter().map(Rc::new).collect(),
+        source_map,
+    };
+    bytecode.verify().map_err(LoadError::Invalid)?;
+    Ok(bytecode)
+}
+
+fn corrupt(message: String) -> LoadError {
+    LoadError::Corrupt(message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /**
+     * Takes the next `count` bytes, which are part of `what`.
+     */
+    fn take(&mut self, count: usize, what: &str) -> Result<&'a [u8], LoadError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| corrupt(format!("The file ends in {}", what)))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, LoadError> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, LoadError> {
+        Ok(u16::from_be_bytes(self.take(2, what)?.try_into().unwrap()))
+    }
+
+    fn length(&mut self, what: &str) -> Result<usize, LoadError> {
+        let length = u32::from_be_bytes(self.take(4, what)?.try_into().unwrap());
+        Ok(length as usize)
+    }
+
+    fn bytes(&mut self, what: &str) -> Result<&'a [u8], LoadError> {
+        let length = self.length(what)?;
+        self.take(length, what)
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, LoadError> {
+        let bytes = self.bytes(what)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| corrupt(format!("{} isn't valid UTF-8", what)))
+    }
+
+    fn line_table(&mut self) -> Result<LineTable, LoadError> {
+        let num_entries = self.length("the source map")?;
+        let mut line_table = LineTable::default();
+        for _ in 0..num_entries {
+            let offset = self.length("the source map")?;
+            let line = self.length("the source map")?;
+            let column = self.length("the source map")?;
+            line_table.entries.push((offset, Position { line, column }));
+        }
+        Ok(line_table)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoadError, FORMAT_VERSION};
+    use crate::compiler::{self, Bytecode};
+    use crate::object::Object;
+    use crate::{lexer, parser, vm};
+    use std::rc::Rc;
+
+    fn compile(input: &str) -> Bytecode {
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        compiler::compile_program(&program).unwrap()
+    }
+
+    fn run(bytecode: &Bytecode) -> Rc<Object> {
+        vm::Vm::new(bytecode).run().unwrap().unwrap()
+    }
+
+    fn line_tables(bytecode: &Bytecode) -> Vec<Vec<(usize, usize, usize)>> {
+        let source_map = &bytecode.source_map;
+        std::iter::once(&source_map.main)
+            .chain(source_map.functions.values())
+            .map(|line_table| {
+                line_table
+                    .entries
+                    .iter()
+                    .map(|(offset, position)| (*offset, position.line, position.column))
+                    .collect()
+            })
+            .collect()
+    }
+
+    const PROGRAM: &str = r#"
+        let greet = fn(name) { "hello " + name };
+        let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } };
+        let big = 9000000000;
+        len(greet("world")) + count(-1 + 11)
+    "#;
+
+    #[test]
+    fn test_round_trip() {
+        let bytecode = compile(PROGRAM);
+        let mut bytes = vec![];
+        bytecode.write_to(&mut bytes).unwrap();
+        let loaded = Bytecode::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(loaded.instructions, bytecode.instructions);
+        assert_eq!(loaded.constants, bytecode.constants);
+        assert_eq!(loaded.disassemble(), bytecode.disassemble());
+        assert_eq!(line_tables(&loaded), line_tables(&bytecode));
+        assert_eq!(
+            loaded.source_map.functions.keys().collect::<Vec<_>>(),
+            bytecode.source_map.functions.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(*run(&loaded), Object::Integer(11));
+    }
+
+    #[test]
+    fn test_stripped() {
+        let bytecode = compile(PROGRAM);
+        let mut bytes = vec![];
+        bytecode.write_stripped_to(&mut bytes).unwrap();
+        let loaded = Bytecode::read_from(&mut &bytes[..]).unwrap();
+        assert!(loaded.constants.iter().all(|constant| match &**constant {
+            Object::CompiledFunction(function) => function.name.is_none(),
+            _ => true,
+        }));
+        assert!(line_tables(&loaded).iter().all(Vec::is_empty));
+        assert_eq!(*run(&loaded), Object::Integer(11));
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let mut bytes = vec![];
+        compile(PROGRAM).write_to(&mut bytes).unwrap();
+
+        for length in 0..bytes.len() {
+            assert!(Bytecode::read_from(&mut &bytes[..length]).is_err());
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(matches!(
+            Bytecode::read_from(&mut &extended[..]),
+            Err(LoadError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Bytecode::read_from(&mut &b"#!/usr/bin/env monkey"[..]),
+            Err(LoadError::NotBytecode)
+        ));
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            Bytecode::read_from(&mut &newer[..]),
+            Err(LoadError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+
+        // Flipping any single byte either still loads or is reported, but
+        // never panics.
+        for position in 0..bytes.len() {
+            for mask in [0x01, 0xff].iter() {
+                let mut flipped = bytes.clone();
+                flipped[position] ^= mask;
+                let _ = Bytecode::read_from(&mut &flipped[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_instructions() {
+        let bytecode = |instructions: Vec<u8>| {
+            let mut bytes = vec![];
+            Bytecode {
+                instructions,
+                constants: vec![],
+                source_map: Default::default(),
+            }
+            .write_to(&mut bytes)
+            .unwrap();
+            Bytecode::read_from(&mut &bytes[..])
+        };
+        let message = |result: Result<Bytecode, LoadError>| match result {
+            Err(error) => error.to_string(),
+            Ok(_) => panic!("Expected an error"),
+        };
+        assert_eq!(
+            message(bytecode(vec![200])),
+            "Invalid bytecode: In the main program at 0: Unknown opcode 200"
+        );
+        assert_eq!(
+            message(bytecode(vec![6, 3, 0, 0, 0])),
+            "Invalid bytecode: In the main program at 2: There is no constant 0"
+        );
+        assert!(bytecode(vec![6, 14, 0, 4, 3]).is_ok());
+    }
+}
@@ -1,8 +1,20 @@
-use ast::InfixOperator;
-
+use crate::eval::builtins::BuiltinRegistry;
 use crate::{ast, code, object};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
 use std::rc::Rc;
+use symbol_table::{Symbol, SymbolScope, SymbolTable};
+
+mod file;
+mod optimize;
+mod source_map;
+pub(crate) mod symbol_table;
+mod verify;
+
+pub use file::{LoadError, FORMAT_VERSION};
+pub use optimize::OptimizationLevel;
+pub use source_map::{LineTable, SourceMap};
+pub use verify::VerifyError;
 
 #[derive(Debug)]
 pub enum AstNode<'a> {
@@ -11,70 +23,210 @@ pub enum AstNode<'a> {
     Expression(&'a ast::Expression),
 }
 
-pub struct Compiler<'ast> {
+#[derive(Debug)]
+pub enum CompilerError {
+    UnboundIdentifier(String),
+    TooManyOperands(String),
+}
+
+impl CompilerError {
+    /**
+     * What's wrong, without saying that compiling failed.
+     */
+    pub(crate) fn message(&self) -> String {
+        match self {
+            CompilerError::UnboundIdentifier(name) => {
+                format!("The identifier '{}' has not been bound", name)
+            }
+            CompilerError::TooManyOperands(what) => {
+                format!("Too many {} for the bytecode to encode", what)
+            }
+        }
+    }
+}
+
+type CompilerResult = Result<(), CompilerError>;
+
+/**
+ * A compiler can be fed several programs in turn (as the REPL and
+ * `engine::Engine` do), with later programs able to see the globals and
+ * constants defined by earlier ones.
+ */
+pub struct Compiler {
+    constants: Vec<Rc<object::Object>>,
+    symbol_table: SymbolTable,
+    // One per function being compiled, innermost last.
+    scopes: Vec<Scope>,
+    optimization: OptimizationLevel,
+    // Where each integer and string is in the pool, when sharing them.
+    constant_indexes: HashMap<ConstantKey, u32>,
+    // The line table of each compiled function in the pool.
+    function_tables: BTreeMap<usize, LineTable>,
+}
+
+#[derive(Default)]
+struct Scope {
     instructions: Vec<u8>,
-    constants: Vec<object::Object<'ast>>,
+    line_table: LineTable,
 }
 
-impl<'a> Compiler<'a> {
-    fn new() -> Self {
-        Compiler {
-            instructions: vec![],
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    String(String),
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler::with_builtins(&BuiltinRegistry::with_defaults())
+    }
+
+    /**
+     * Creates a compiler whose programs can call the builtins in `builtins`.
+     * The VM running them has to be given the same registry.
+     */
+    pub fn with_builtins(builtins: &BuiltinRegistry) -> Self {
+        let mut compiler = Compiler {
             constants: vec![],
+            symbol_table: SymbolTable::new(),
+            scopes: vec![Scope::default()],
+            optimization: OptimizationLevel::default(),
+            constant_indexes: HashMap::new(),
+            function_tables: BTreeMap::new(),
+        };
+        compiler.set_builtins(builtins);
+        compiler
+    }
+
+    /**
+     * Replaces the builtins that later programs can refer to.
+     */
+    pub fn set_builtins(&mut self, builtins: &BuiltinRegistry) {
+        self.symbol_table.clear_builtins();
+        for (index, builtin) in builtins.iter() {
+            self.symbol_table.define_builtin(index, &builtin.name);
+        }
+    }
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) {
+        self.optimization = level;
+    }
+    fn add_constant(&mut self, obj: object::Object) -> Result<u32, CompilerError> {
+        let key = match (&obj, self.optimization) {
+            (_, OptimizationLevel::None) => None,
+            (object::Object::Integer(value), _) => Some(ConstantKey::Integer(*value)),
+            (object::Object::String(value), _) => Some(ConstantKey::String(value.clone())),
+            _ => None,
+        };
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indexes.get(key)) {
+            return Ok(*index);
+        }
+        let next_const_index: u32 = self
+            .constants
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("constants")))?;
+        self.constants.push(Rc::new(obj));
+        if let Some(key) = key {
+            self.constant_indexes.insert(key, next_const_index);
         }
+        Ok(next_const_index)
+    }
+    fn push_constant(&mut self, obj: object::Object) -> CompilerResult {
+        let index = self.add_constant(obj)?;
+        self.push_instruction(code::Instruction::constant(index));
+        Ok(())
+    }
+    fn instructions(&mut self) -> &mut Vec<u8> {
+        &mut self.scopes.last_mut().unwrap().instructions
+    }
+    /**
+     * Pushes the instruction and returns its position.
+     */
+    fn push_instruction(&mut self, instruction: code::Instruction) -> usize {
+        let position = self.instructions().len();
+        Vec::append(self.instructions(), &mut instruction.to_bytes());
+        position
+    }
+    /**
+     * Pushes an instruction that can fail, recording where in the source
+     * it came from.
+     */
+    fn push_located(&mut self, instruction: code::Instruction, position: ast::Position) -> usize {
+        let offset = self.push_instruction(instruction);
+        let scope = self.scopes.last_mut().unwrap();
+        scope.line_table.entries.push((offset, position));
+        offset
+    }
+    fn replace_instruction(&mut self, position: usize, instruction: code::Instruction) {
+        let bytes = instruction.to_bytes();
+        self.instructions()[position..position + bytes.len()].copy_from_slice(&bytes);
+    }
+    fn current_position(&mut self) -> Result<u32, CompilerError> {
+        let position = self.instructions().len();
+        position
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("instructions to jump over")))
     }
-    fn add_constant(&mut self, obj: object::Object<'a>) {
-        let next_const_index = self.constants.len();
-        let next_const_index: u16 = next_const_index.try_into().unwrap();
-        self.constants.push(obj);
-        self.push_instruction(code::Instruction::Constant(next_const_index))
+    fn enter_scope(&mut self) {
+        self.scopes.push(Scope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
     }
-    fn push_instruction(&mut self, instruction: code::Instruction) {
-        Vec::append(&mut self.instructions, &mut instruction.to_bytes());
+    fn leave_scope(&mut self) -> (Scope, SymbolTable) {
+        let scope = self.scopes.pop().unwrap();
+        let outer = self.symbol_table.take_outer().unwrap();
+        let inner = std::mem::replace(&mut self.symbol_table, outer);
+        (scope, inner)
     }
-    fn compile(&mut self, node: AstNode) {
+    fn compile(&mut self, node: AstNode) -> CompilerResult {
         match node {
             AstNode::Expression(expression) => match expression {
                 ast::Expression::IntegerLiteral { value } => {
-                    self.add_constant(object::Object::Integer(*value));
+                    self.push_constant(object::Object::Integer(*value))?;
                 }
-                ast::Expression::Prefix { right, operator } => {
-                    self.compile(AstNode::Expression(&right));
+                ast::Expression::StringLiteral { value } => {
+                    self.push_constant(object::Object::String(value.clone()))?;
+                }
+                ast::Expression::Prefix {
+                    right,
+                    operator,
+                    position,
+                } => {
+                    self.compile(AstNode::Expression(right))?;
                     let instruction = match operator {
                         ast::PrefixOperator::Bang => code::Instruction::Bang,
                         ast::PrefixOperator::Minus => code::Instruction::Minus,
                     };
-                    self.push_instruction(instruction);
+                    self.push_located(instruction, *position);
                 }
                 ast::Expression::Infix {
                     left,
                     right,
                     operator,
+                    position,
                 } => {
-                    if let InfixOperator::Lt = operator {
-                        self.compile(AstNode::Expression(&right));
-                        self.compile(AstNode::Expression(&left));
-                        self.push_instruction(code::Instruction::GreaterThan);
-                    } else {
-                        self.compile(AstNode::Expression(&left));
-                        self.compile(AstNode::Expression(&right));
-                        let instruction: code::Instruction = match operator {
-                            ast::InfixOperator::Plus => code::Instruction::Add,
-                            ast::InfixOperator::Minus => code::Instruction::Sub,
-                            ast::InfixOperator::Multiply => code::Instruction::Mul,
-                            ast::InfixOperator::Divide => code::Instruction::Div,
-                            ast::InfixOperator::Eq => code::Instruction::Equal,
-                            ast::InfixOperator::NotEq => code::Instruction::NotEqual,
-                            ast::InfixOperator::Gt => code::Instruction::GreaterThan,
-                            _ => unimplemented!(),
-                        };
-                        self.push_instruction(instruction);
-                    }
+                    self.compile(AstNode::Expression(left))?;
+                    self.compile(AstNode::Expression(right))?;
+                    let instruction: code::Instruction = match operator {
+                        ast::InfixOperator::Plus => code::Instruction::Add,
+                        ast::InfixOperator::Minus => code::Instruction::Sub,
+                        ast::InfixOperator::Multiply => code::Instruction::Mul,
+                        ast::InfixOperator::Divide => code::Instruction::Div,
+                        ast::InfixOperator::Eq => code::Instruction::Equal,
+                        ast::InfixOperator::NotEq => code::Instruction::NotEqual,
+                        ast::InfixOperator::Gt => code::Instruction::GreaterThan,
+                        ast::InfixOperator::Lt => code::Instruction::LessThan,
+                    };
+                    self.push_located(instruction, *position);
                 }
                 ast::Expression::Block { statements } => {
-                    for statement in statements {
-                        self.compile(AstNode::Statement(&statement));
-                    }
+                    self.compile_block_value(statements, false)?;
                 }
                 ast::Expression::Boolean { value } => {
                     let instruction = if *value {
@@ -82,57 +234,424 @@ impl<'a> Compiler<'a> {
                     } else {
                         code::Instruction::False
                     };
-                    self.push_instruction(instruction)
+                    self.push_instruction(instruction);
+                }
+                ast::Expression::If {
+                    condition,
+                    consequence,
+                    alternative,
+                    position,
+                } => {
+                    self.compile_if(condition, consequence, alternative, *position, false)?;
+                }
+                ast::Expression::Identifier { value, .. } => {
+                    let symbol = self
+                        .symbol_table
+                        .resolve(value)
+                        .ok_or_else(|| CompilerError::UnboundIdentifier(value.clone()))?;
+                    self.load_symbol(&symbol)?;
+                }
+                ast::Expression::ArrayLiteral { elements } => {
+                    for element in elements {
+                        self.compile(AstNode::Expression(element))?;
+                    }
+                    let length = elements.len().try_into().map_err(|_| {
+                        CompilerError::TooManyOperands(String::from("array elements"))
+                    })?;
+                    self.push_instruction(code::Instruction::Array(length));
+                }
+                ast::Expression::Index {
+                    left,
+                    index,
+                    position,
+                } => {
+                    self.compile(AstNode::Expression(left))?;
+                    self.compile(AstNode::Expression(index))?;
+                    self.push_located(code::Instruction::Index, *position);
+                }
+                ast::Expression::FnLiteral {
+                    param_names, body, ..
+                } => {
+                    self.compile_function(None, param_names, body)?;
+                }
+                ast::Expression::CallExpression {
+                    left,
+                    arguments,
+                    position,
+                } => {
+                    self.compile_call(left, arguments, *position, false)?;
                 }
-                _ => {}
             },
             AstNode::Program(program) => {
+                self.symbol_table.declare(&program.statements);
                 for statement in &program.statements {
-                    self.compile(AstNode::Statement(&statement));
+                    self.compile(AstNode::Statement(statement))?;
                 }
             }
-            AstNode::Statement(statement) => {
-                match statement {
-                    ast::Statement::Let { .. } => {
-                        //
+            AstNode::Statement(statement) => match statement {
+                ast::Statement::Let { name, right, .. } => {
+                    if let ast::Expression::FnLiteral {
+                        param_names, body, ..
+                    } = right
+                    {
+                        self.compile_function(Some(name), param_names, body)?;
+                    } else {
+                        self.compile(AstNode::Expression(right))?;
                     }
-                    ast::Statement::Return { .. } => {
-                        //
+                    // Defined after compiling the right hand side, so that
+                    // `let a = a + 1;` in a block refers to the outer `a`.
+                    let symbol = self.symbol_table.define(name);
+                    let globals_error = |_| CompilerError::TooManyOperands(String::from("globals"));
+                    let locals_error = |_| CompilerError::TooManyOperands(String::from("locals"));
+                    let instruction = match symbol.scope {
+                        SymbolScope::Global => code::Instruction::SetGlobal(
+                            symbol.index.try_into().map_err(globals_error)?,
+                        ),
+                        SymbolScope::Cell => code::Instruction::SetCell(
+                            symbol.index.try_into().map_err(locals_error)?,
+                        ),
+                        _ => code::Instruction::SetLocal(
+                            symbol.index.try_into().map_err(locals_error)?,
+                        ),
+                    };
+                    self.push_instruction(instruction);
+                }
+                ast::Statement::Return { value } => {
+                    // The main program has no frame to reuse.
+                    if self.scopes.len() > 1 {
+                        self.compile_tail_expression(value)?;
+                    } else {
+                        self.compile(AstNode::Expression(value))?;
+                    }
+                    self.push_instruction(code::Instruction::ReturnValue);
+                }
+                ast::Statement::Expression { expression } => {
+                    self.compile(AstNode::Expression(expression))?;
+                    self.push_instruction(code::Instruction::Pop);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /**
+     * Compiles the statements so that they leave the value of the block on
+     * the stack: the value of a trailing expression statement, or null.
+     * `tail` is set for blocks whose value the function returns.
+     */
+    fn compile_block_value(&mut self, statements: &[ast::Statement], tail: bool) -> CompilerResult {
+        self.symbol_table.push_block();
+        for symbol in self.symbol_table.declare(statements) {
+            if symbol.scope == SymbolScope::Cell {
+                let index = symbol
+                    .index
+                    .try_into()
+                    .map_err(|_| CompilerError::TooManyOperands(String::from("locals")))?;
+                self.push_instruction(code::Instruction::NewCell(index));
+            }
+        }
+        match statements.split_last() {
+            Some((last, rest)) => {
+                for statement in rest {
+                    self.compile(AstNode::Statement(statement))?;
+                }
+                match last {
+                    ast::Statement::Expression { expression } if tail => {
+                        self.compile_tail_expression(expression)?;
                     }
                     ast::Statement::Expression { expression } => {
-                        self.compile(AstNode::Expression(expression));
-                        self.push_instruction(code::Instruction::Pop);
+                        self.compile(AstNode::Expression(expression))?;
+                    }
+                    _ => {
+                        self.compile(AstNode::Statement(last))?;
+                        self.push_instruction(code::Instruction::Null);
                     }
                 }
             }
+            None => {
+                self.push_instruction(code::Instruction::Null);
+            }
+        }
+        self.symbol_table.pop_block();
+        Ok(())
+    }
+
+    /**
+     * Compiles an expression whose value the function returns, so that a
+     * call it ends in can reuse the function's frame.
+     */
+    fn compile_tail_expression(&mut self, expression: &ast::Expression) -> CompilerResult {
+        match expression {
+            ast::Expression::CallExpression {
+                left,
+                arguments,
+                position,
+            } => self.compile_call(left, arguments, *position, true),
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                position,
+            } => self.compile_if(condition, consequence, alternative, *position, true),
+            ast::Expression::Block { statements } => self.compile_block_value(statements, true),
+            _ => self.compile(AstNode::Expression(expression)),
+        }
+    }
+
+    fn compile_if(
+        &mut self,
+        condition: &ast::Expression,
+        consequence: &ast::BlockStatement,
+        alternative: &Option<ast::BlockStatement>,
+        position: ast::Position,
+        tail: bool,
+    ) -> CompilerResult {
+        self.compile(AstNode::Expression(condition))?;
+        // The jump targets aren't known yet so are patched in once the
+        // blocks have been compiled. Until then every jump is wide, and
+        // `finish` narrows those that can be once the layout is known.
+        let jump_false_position = self.push_located(code::Instruction::JumpFalseWide(0), position);
+        self.compile_block_value(&consequence.statements, tail)?;
+        let jump_position = self.push_instruction(code::Instruction::JumpWide(0));
+        let alternative_start = self.current_position()?;
+        self.replace_instruction(
+            jump_false_position,
+            code::Instruction::JumpFalseWide(alternative_start),
+        );
+        match alternative {
+            Some(alternative) => self.compile_block_value(&alternative.statements, tail)?,
+            None => {
+                self.push_instruction(code::Instruction::Null);
+            }
         }
+        let end = self.current_position()?;
+        self.replace_instruction(jump_position, code::Instruction::JumpWide(end));
+        Ok(())
     }
 
-    fn bytecode(self) -> Bytecode<'a> {
-        Bytecode {
-            instructions: self.instructions,
-            constants: self.constants.into_iter().map(Rc::new).collect(),
+    fn compile_call(
+        &mut self,
+        left: &ast::Expression,
+        arguments: &[ast::Expression],
+        position: ast::Position,
+        tail: bool,
+    ) -> CompilerResult {
+        self.compile(AstNode::Expression(left))?;
+        for argument in arguments {
+            self.compile(AstNode::Expression(argument))?;
+        }
+        let num_args = arguments
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("call arguments")))?;
+        let instruction = if tail {
+            code::Instruction::TailCall(num_args)
+        } else {
+            code::Instruction::Call(num_args)
+        };
+        self.push_located(instruction, position);
+        Ok(())
+    }
+
+    fn compile_function(
+        &mut self,
+        name: Option<&str>,
+        param_names: &[String],
+        body: &ast::BlockStatement,
+    ) -> CompilerResult {
+        self.enter_scope();
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(name);
+        }
+        for param_name in param_names {
+            self.symbol_table.define(param_name);
+        }
+        self.compile_block_value(&body.statements, true)?;
+        self.push_instruction(code::Instruction::ReturnValue);
+        let (scope, symbol_table) = self.leave_scope();
+        let (instructions, line_table) = self.finish(scope, false);
+
+        if symbol_table.num_definitions > u8::MAX as usize + 1 {
+            return Err(CompilerError::TooManyOperands(String::from("locals")));
+        }
+        for free_symbol in &symbol_table.free_symbols {
+            self.load_captured(free_symbol)?;
+        }
+        let num_free = symbol_table
+            .free_symbols
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("free variables")))?;
+        let function = object::CompiledFunction {
+            instructions,
+            num_locals: symbol_table.num_definitions,
+            num_parameters: param_names.len(),
+            name: name.map(String::from),
+        };
+        let index = self.add_constant(object::Object::CompiledFunction(Rc::new(function)))?;
+        self.function_tables.insert(index as usize, line_table);
+        self.push_instruction(code::Instruction::closure(index, num_free));
+        Ok(())
+    }
+
+    /**
+     * Lays out a finished function or main program, running the peephole
+     * optimiser over it if asked to.
+     */
+    fn finish(&self, scope: Scope, keep_result: bool) -> (Vec<u8>, LineTable) {
+        let (instructions, moves) = match self.optimization {
+            OptimizationLevel::None => {
+                code::relayout(&scope.instructions).expect("The compiler made bad instructions")
+            }
+            OptimizationLevel::Full => optimize::peephole(&scope.instructions, keep_result),
+        };
+        let mut line_table = scope.line_table;
+        line_table.relocate(&moves);
+        (instructions, line_table)
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) -> CompilerResult {
+        let operand_error = |_| CompilerError::TooManyOperands(String::from("symbols"));
+        let instruction = match symbol.scope {
+            SymbolScope::Global => {
+                code::Instruction::GetGlobal(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Local => {
+                code::Instruction::GetLocal(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Builtin => {
+                code::Instruction::GetBuiltin(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Free => {
+                code::Instruction::GetFree(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Function => code::Instruction::CurrentClosure,
+            SymbolScope::Cell => {
+                code::Instruction::GetCell(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::FreeCell => {
+                code::Instruction::GetFreeCell(symbol.index.try_into().map_err(operand_error)?)
+            }
+        };
+        self.push_instruction(instruction);
+        Ok(())
+    }
+
+    /**
+     * Loads a variable for a closure to capture: a cell itself rather than
+     * its value, so that the closure sees the value once it is set.
+     */
+    fn load_captured(&mut self, symbol: &Symbol) -> CompilerResult {
+        let operand_error = |_| CompilerError::TooManyOperands(String::from("symbols"));
+        let instruction = match symbol.scope {
+            SymbolScope::Cell => {
+                code::Instruction::GetLocal(symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::FreeCell => {
+                code::Instruction::GetFree(symbol.index.try_into().map_err(operand_error)?)
+            }
+            _ => return self.load_symbol(symbol),
+        };
+        self.push_instruction(instruction);
+        Ok(())
+    }
+
+    /**
+     * Compiles a program on top of whatever has been compiled already. If
+     * it fails, the compiler is left as it was before the call.
+     */
+    pub fn compile_program(&mut self, program: &ast::Program) -> Result<Bytecode, CompilerError> {
+        let symbol_table = self.symbol_table.clone();
+        let num_constants = self.constants.len();
+        self.scopes = vec![Scope::default()];
+        let folded;
+        let program = match self.optimization {
+            OptimizationLevel::None => program,
+            OptimizationLevel::Full => {
+                folded = optimize::fold_program(program);
+                &folded
+            }
+        };
+        if let Err(err) = self.compile(AstNode::Program(program)) {
+            self.symbol_table = symbol_table;
+            self.constants.truncate(num_constants);
+            self.constant_indexes
+                .retain(|_, index| (*index as usize) < num_constants);
+            self.function_tables
+                .retain(|index, _| *index < num_constants);
+            return Err(err);
+        }
+        let scope = self.scopes.pop().unwrap();
+        let (instructions, line_table) = self.finish(scope, true);
+        Ok(Bytecode {
+            instructions,
+            constants: self.constants.clone(),
+            source_map: SourceMap {
+                main: line_table,
+                functions: self.function_tables.clone(),
+            },
+        })
+    }
+
+    /**
+     * Reserves a global slot for `name`, returning its index into the VM's
+     * globals. Used by hosts to give scripts values of their own.
+     */
+    pub fn define_global(&mut self, name: &str) -> usize {
+        self.symbol_table.define(name).index
+    }
+
+    pub fn resolve_global(&mut self, name: &str) -> Option<usize> {
+        match self.symbol_table.resolve(name) {
+            Some(Symbol {
+                scope: SymbolScope::Global,
+                index,
+            }) => Some(index),
+            _ => None,
         }
     }
 }
 
-pub fn compile_program<'ast, 'bytecode>(program: &'ast ast::Program) -> Bytecode<'bytecode> {
-    let mut compiler = Compiler::new();
-    compiler.compile(AstNode::Program(program));
-    compiler.bytecode()
+pub fn compile_program(program: &ast::Program) -> Result<Bytecode, CompilerError> {
+    Compiler::new().compile_program(program)
 }
 
-pub struct Bytecode<'ast> {
+pub struct Bytecode {
     pub instructions: Vec<u8>,
-    pub constants: Vec<Rc<object::Object<'ast>>>,
+    pub constants: Vec<Rc<object::Object>>,
+    /// Where the instructions came from, for reporting runtime errors.
+    pub source_map: SourceMap,
+}
+
+impl Bytecode {
+    /**
+     * A listing of the main program followed by each function in the
+     * constant pool.
+     */
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::from("main:\n");
+        listing.push_str(&code::disassemble(&self.instructions, &self.constants));
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let object::Object::CompiledFunction(function) = &**constant {
+                listing.push_str(&format!(
+                    "\nconstant {}, fn {}:\n",
+                    index,
+                    function.name.as_deref().unwrap_or("<anonymous>")
+                ));
+                listing.push_str(&code::disassemble(&function.instructions, &self.constants));
+            }
+        }
+        listing
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{ast, code, compiler, lexer, object, parser};
-    struct CompilerTestCase<'a> {
+    use super::OptimizationLevel;
+    use crate::{ast, code, compiler, lexer, object, parser, vm};
+    struct CompilerTestCase {
         input: &'static str,
-        expected_constants: Vec<object::Object<'a>>,
+        expected_constants: Vec<object::Object>,
         expected_instructions: Vec<Vec<u8>>,
     }
 
@@ -153,6 +672,223 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_conditionals() {
+        let tests: Vec<CompilerTestCase> = vec![
+            CompilerTestCase {
+                input: "if (true) { 10 }; 3333;",
+                expected_instructions: vec![
+                    // 0000
+                    code::Instruction::True.to_bytes(),
+                    // 0001
+                    code::Instruction::JumpFalse(10).to_bytes(),
+                    // 0004
+                    code::Instruction::Constant(0).to_bytes(),
+                    // 0007
+                    code::Instruction::Jump(11).to_bytes(),
+                    // 0010
+                    code::Instruction::Null.to_bytes(),
+                    // 0011
+                    code::Instruction::Pop.to_bytes(),
+                    // 0012
+                    code::Instruction::Constant(1).to_bytes(),
+                    code::Instruction::Pop.to_bytes(),
+                ],
+                expected_constants: vec![
+                    object::Object::Integer(10),
+                    object::Object::Integer(3333),
+                ],
+            },
+            CompilerTestCase {
+                input: "if (true) { 10 } else { 20 };",
+                expected_instructions: vec![
+                    // 0000
+                    code::Instruction::True.to_bytes(),
+                    // 0001
+                    code::Instruction::JumpFalse(10).to_bytes(),
+                    // 0004
+                    code::Instruction::Constant(0).to_bytes(),
+                    // 0007
+                    code::Instruction::Jump(13).to_bytes(),
+                    // 0010
+                    code::Instruction::Constant(1).to_bytes(),
+                    // 0013
+                    code::Instruction::Pop.to_bytes(),
+                ],
+                expected_constants: vec![object::Object::Integer(10), object::Object::Integer(20)],
+            },
+        ];
+        for test in tests {
+            run_compiler_test(test);
+        }
+    }
+
+    #[test]
+    fn test_global_let_statements() {
+        let tests: Vec<CompilerTestCase> = vec![CompilerTestCase {
+            input: "let one = 1; let two = one; two;",
+            expected_instructions: vec![
+                code::Instruction::Constant(0).to_bytes(),
+                code::Instruction::SetGlobal(0).to_bytes(),
+                code::Instruction::GetGlobal(0).to_bytes(),
+                code::Instruction::SetGlobal(1).to_bytes(),
+                code::Instruction::GetGlobal(1).to_bytes(),
+                code::Instruction::Pop.to_bytes(),
+            ],
+            expected_constants: vec![object::Object::Integer(1)],
+        }];
+        for test in tests {
+            run_compiler_test(test);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let inner = vec![
+            code::Instruction::GetFree(0).to_bytes(),
+            code::Instruction::GetLocal(0).to_bytes(),
+            code::Instruction::Add.to_bytes(),
+            code::Instruction::ReturnValue.to_bytes(),
+        ];
+        let outer = vec![
+            code::Instruction::GetLocal(0).to_bytes(),
+            code::Instruction::Closure(0, 1).to_bytes(),
+            code::Instruction::ReturnValue.to_bytes(),
+        ];
+        let tests: Vec<CompilerTestCase> = vec![CompilerTestCase {
+            input: "fn(a) { fn(b) { a + b } }",
+            expected_instructions: vec![
+                code::Instruction::Closure(1, 0).to_bytes(),
+                code::Instruction::Pop.to_bytes(),
+            ],
+            expected_constants: vec![
+                object::Object::CompiledFunction(std::rc::Rc::new(object::CompiledFunction {
+                    instructions: inner.into_iter().flatten().collect(),
+                    num_locals: 1,
+                    num_parameters: 1,
+                    name: None,
+                })),
+                object::Object::CompiledFunction(std::rc::Rc::new(object::CompiledFunction {
+                    instructions: outer.into_iter().flatten().collect(),
+                    num_locals: 1,
+                    num_parameters: 1,
+                    name: None,
+                })),
+            ],
+        }];
+        for test in tests {
+            run_compiler_test(test);
+        }
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let function = |body: Vec<code::Instruction>| {
+            object::Object::CompiledFunction(std::rc::Rc::new(object::CompiledFunction {
+                instructions: body.iter().flat_map(|i| i.to_bytes()).collect(),
+                num_locals: 1,
+                num_parameters: 1,
+                name: None,
+            }))
+        };
+        let tests: Vec<CompilerTestCase> = vec![
+            CompilerTestCase {
+                input: "fn(f) { f(1) }",
+                expected_instructions: vec![
+                    code::Instruction::Closure(1, 0).to_bytes(),
+                    code::Instruction::Pop.to_bytes(),
+                ],
+                expected_constants: vec![
+                    object::Object::Integer(1),
+                    function(vec![
+                        code::Instruction::GetLocal(0),
+                        code::Instruction::Constant(0),
+                        code::Instruction::TailCall(1),
+                        code::Instruction::ReturnValue,
+                    ]),
+                ],
+            },
+            CompilerTestCase {
+                input: "fn(f) { return f(1); }",
+                expected_instructions: vec![
+                    code::Instruction::Closure(1, 0).to_bytes(),
+                    code::Instruction::Pop.to_bytes(),
+                ],
+                expected_constants: vec![
+                    object::Object::Integer(1),
+                    function(vec![
+                        code::Instruction::GetLocal(0),
+                        code::Instruction::Constant(0),
+                        code::Instruction::TailCall(1),
+                        code::Instruction::ReturnValue,
+                        code::Instruction::Null,
+                        code::Instruction::ReturnValue,
+                    ]),
+                ],
+            },
+            CompilerTestCase {
+                input: "fn(f) { f(1) + 1 }",
+                expected_instructions: vec![
+                    code::Instruction::Closure(2, 0).to_bytes(),
+                    code::Instruction::Pop.to_bytes(),
+                ],
+                expected_constants: vec![
+                    object::Object::Integer(1),
+                    object::Object::Integer(1),
+                    function(vec![
+                        code::Instruction::GetLocal(0),
+                        code::Instruction::Constant(0),
+                        code::Instruction::Call(1),
+                        code::Instruction::Constant(1),
+                        code::Instruction::Add,
+                        code::Instruction::ReturnValue,
+                    ]),
+                ],
+            },
+        ];
+        for test in tests {
+            run_compiler_test(test);
+        }
+    }
+
+    #[test]
+    fn test_unbound_identifier() {
+        let program = parse("let a = fn() { b };");
+        assert!(matches!(
+            compiler::compile_program(&program),
+            Err(compiler::CompilerError::UnboundIdentifier(name)) if name == "b"
+        ));
+    }
+
+    #[test]
+    fn test_wide_operands() {
+        // More constants than fit in 16 bits.
+        let statements: Vec<String> = (0..70000).map(|i| i.to_string()).collect();
+        let many = statements.join("; ");
+        // A jump over more than 64KB of instructions.
+        let far = format!("if (false) {{ {} }} else {{ \"far\" }}", many);
+        let tests = [
+            (many, "ConstantWide 69999", "Integer(69999)"),
+            (far, "JumpFalseWide", "String(\"far\")"),
+        ];
+        for (input, wide, expected) in tests.iter() {
+            for level in [OptimizationLevel::None, OptimizationLevel::Full].iter() {
+                let mut lexer = lexer::new(input);
+                let mut parser = parser::Parser::new(&mut lexer);
+                let program = parser.parse_program().unwrap();
+                let mut compiler = compiler::Compiler::new();
+                compiler.set_optimization_level(*level);
+                let bytecode = compiler.compile_program(&program).unwrap();
+                if *level == OptimizationLevel::None {
+                    assert!(bytecode.disassemble().contains(wide));
+                }
+                bytecode.verify().unwrap();
+                let result = vm::Vm::new(&bytecode).run().unwrap().unwrap();
+                assert_eq!(format!("{:?}", result), *expected);
+            }
+        }
+    }
+
     fn parse(input: &'static str) -> ast::Program {
         let mut lexer = lexer::new(input);
         let mut parser = parser::Parser::new(&mut lexer);
@@ -161,16 +897,79 @@ mod test {
 
     fn run_compiler_test(test: CompilerTestCase) {
         let program = parse(test.input);
-        let bytecode = compiler::compile_program(&program);
+        let bytecode = compiler::compile_program(&program).unwrap();
         let expected_instructions_bytecode = test
             .expected_instructions
             .into_iter()
             .flatten()
             .collect::<Vec<u8>>();
+        // Compared as listings first, which are far easier to read.
+        assert_eq!(
+            code::disassemble(&expected_instructions_bytecode, &bytecode.constants),
+            code::disassemble(&bytecode.instructions, &bytecode.constants)
+        );
         assert_eq!(expected_instructions_bytecode, bytecode.instructions);
+        for (expected, actual) in test.expected_constants.iter().zip(&bytecode.constants) {
+            if let (
+                object::Object::CompiledFunction(expected),
+                object::Object::CompiledFunction(actual),
+            ) = (expected, &**actual)
+            {
+                assert_eq!(
+                    code::disassemble(&expected.instructions, &bytecode.constants),
+                    code::disassemble(&actual.instructions, &bytecode.constants)
+                );
+            }
+        }
         assert_eq!(
             test.expected_constants,
-            vec![object::Object::Integer(1), object::Object::Integer(2)]
+            bytecode
+                .constants
+                .iter()
+                .map(|c| match &**c {
+                    object::Object::Integer(i) => object::Object::Integer(*i),
+                    object::Object::String(s) => object::Object::String(s.clone()),
+                    object::Object::CompiledFunction(f) => {
+                        object::Object::CompiledFunction(std::rc::Rc::clone(f))
+                    }
+                    other => panic!("Unexpected constant {}", other),
+                })
+                .collect::<Vec<object::Object>>()
         )
     }
+
+    #[test]
+    fn test_disassemble() {
+        let program =
+            parse("let countdown = fn(x) { if (x > 0) { countdown(x - 1) } }; countdown(\"ten\");");
+        let bytecode = compiler::compile_program(&program).unwrap();
+        assert_eq!(
+            bytecode.disassemble(),
+            "\
+main:
+0000 Closure 2 0      ; fn countdown
+0004 SetGlobal 0
+0007 GetGlobal 0
+0010 Constant 3       ; \"ten\"
+0013 Call 1
+0015 Pop
+
+constant 2, fn countdown:
+0000 GetLocal 0
+0002 Constant 0       ; 0
+0005 GreaterThan
+0006 JumpFalse L0
+0009 CurrentClosure
+0010 GetLocal 0
+0012 Constant 1       ; 1
+0015 Sub
+0016 TailCall 1
+0018 Jump L1
+L0:
+0021 Null
+L1:
+0022 ReturnValue
+"
+        );
+    }
 }
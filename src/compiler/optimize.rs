@@ -0,0 +1,329 @@
+use crate::ast::visit::walk_expression_mut;
+use crate::ast::{self, Expression, InfixOperator, PrefixOperator, VisitorMut};
+use crate::code::{self, Instruction};
+use crate::logic;
+use crate::object::Object;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptimizationLevel {
+    /// Compile the program exactly as written.
+    #[default]
+    None,
+    /// Fold constant expressions, share equal constants and tidy up the
+    /// instructions. Programs give the same results, in fewer steps.
+    Full,
+}
+
+impl std::str::FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::None),
+            "1" => Ok(Self::Full),
+            _ => Err("Not a valid optimization level".into()),
+        }
+    }
+}
+
+/**
+ * Replaces arithmetic, comparisons and concatenation of literals with
+ * their results, e.g. `60 * 60 * 24` with `86400`. Anything that would
+ * fail (`1 / 0`, `"a" - 1`) is left for the program to fail on as usual.
+ */
+pub fn fold_program(program: &ast::Program) -> ast::Program {
+    let mut program = program.clone();
+    ConstantFolder.visit_program_mut(&mut program);
+    program
+}
+
+struct ConstantFolder;
+
+impl VisitorMut for ConstantFolder {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        // Operands first, so that folding works from the inside out.
+        walk_expression_mut(self, expression);
+        let folded = match expression {
+            Expression::Prefix {
+                operator, right, ..
+            } => fold_prefix(operator, right),
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => fold_infix(left, operator, right),
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            *expression = folded;
+        }
+    }
+}
+
+fn fold_prefix(operator: &PrefixOperator, right: &Expression) -> Option<Expression> {
+    let result = logic::eval_prefix(&literal_value(right)?, operator).ok()?;
+    to_literal(result)
+}
+
+fn fold_infix(
+    left: &Expression,
+    operator: &InfixOperator,
+    right: &Expression,
+) -> Option<Expression> {
+    let left = literal_value(left)?;
+    let right = literal_value(right)?;
+    let result = logic::eval_infix(&left, operator, &right).ok()?;
+    to_literal(result)
+}
+
+fn literal_value(expression: &Expression) -> Option<Object> {
+    match expression {
+        Expression::IntegerLiteral { value } => Some(Object::Integer(*value)),
+        Expression::Boolean { value } => Some(Object::Boolean(*value)),
+        Expression::StringLiteral { value } => Some(Object::String(value.clone())),
+        _ => None,
+    }
+}
+
+fn to_literal(object: Object) -> Option<Expression> {
+    match object {
+        Object::Integer(value) => Some(Expression::IntegerLiteral { value }),
+        Object::Boolean(value) => Some(Expression::Boolean { value }),
+        Object::String(value) => Some(Expression::StringLiteral { value }),
+        _ => None,
+    }
+}
+
+/**
+ * Tidies up compiled instructions: jumps to jumps go straight to where the
+ * last one leads, jumps to the next instruction are dropped, and so are
+ * values pushed only to be popped straight off again. With `keep_result`,
+ * a pop at the very end is kept, as it gives the main program its value.
+ * Also returns where each instruction that was kept moved to, as
+ * `code::relayout` does.
+ */
+pub fn peephole(bytes: &[u8], keep_result: bool) -> (Vec<u8>, code::Moves) {
+    let (instructions, jump_targets) = code::decode_with_targets(bytes).unwrap();
+    let end = instructions.len();
+
+    // Jump targets threaded through other jumps.
+    let mut targets = jump_targets.clone();
+    for target in targets.iter_mut().flatten() {
+        // Bounded, in case of a loop of jumps.
+        for _ in 0..end {
+            match instructions.get(*target) {
+                Some(next) if next.is_unconditional_jump() => {
+                    *target = jump_targets[*target].unwrap()
+                }
+                _ => break,
+            }
+        }
+    }
+
+    let mut is_target = vec![false; end + 1];
+    for target in targets.iter().flatten() {
+        is_target[*target] = true;
+    }
+    let mut keep = vec![true; end];
+    for index in 1..end {
+        let pushes_constant = matches!(
+            instructions[index - 1],
+            Instruction::Constant(_)
+                | Instruction::ConstantWide(_)
+                | Instruction::True
+                | Instruction::False
+                | Instruction::Null
+        );
+        let is_result = keep_result && index == end - 1;
+        if pushes_constant
+            && instructions[index] == Instruction::Pop
+            && keep[index - 1]
+            && !is_target[index]
+            && !is_result
+        {
+            keep[index - 1] = false;
+            keep[index] = false;
+        }
+    }
+    // Where a jump to each instruction ends up once the dropped ones are
+    // gone. Going backwards, a run of jumps to the same place all go.
+    let next_kept = |index: usize, keep: &[bool]| (index..end).find(|i| keep[*i]).unwrap_or(end);
+    for index in (0..end).rev() {
+        if let (true, Some(target)) = (instructions[index].is_unconditional_jump(), targets[index])
+        {
+            keep[index] = false;
+            if next_kept(index + 1, &keep) != next_kept(target, &keep) {
+                keep[index] = true;
+            }
+        }
+    }
+
+    // The index each instruction has among the kept ones.
+    let mut new_indexes = vec![0; end + 1];
+    let mut new_index = 0;
+    for index in 0..end {
+        new_indexes[index] = new_index;
+        if keep[index] {
+            new_index += 1;
+        }
+    }
+    new_indexes[end] = new_index;
+
+    let mut kept = Vec::with_capacity(new_index);
+    let mut kept_targets = Vec::with_capacity(new_index);
+    let mut kept_offsets = Vec::with_capacity(new_index);
+    let old_offsets = code::offsets(&instructions);
+    for (index, instruction) in instructions.iter().enumerate() {
+        if keep[index] {
+            kept.push(instruction.clone());
+            kept_targets.push(targets[index].map(|target| new_indexes[next_kept(target, &keep)]));
+            kept_offsets.push(old_offsets[index]);
+        }
+    }
+    let (optimized, new_offsets) = code::assemble(&kept, &kept_targets);
+    (
+        optimized,
+        kept_offsets.into_iter().zip(new_offsets).collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{peephole, OptimizationLevel};
+    use crate::code::Instruction;
+    use crate::compiler::{Bytecode, Compiler};
+    use crate::{lexer, parser, vm};
+
+    fn compile(input: &str, level: OptimizationLevel) -> Bytecode {
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_optimization_level(level);
+        compiler.compile_program(&program).unwrap()
+    }
+
+    fn run(bytecode: &Bytecode) -> String {
+        format!("{:?}", vm::Vm::new(bytecode).run())
+    }
+
+    fn assemble(instructions: &[Instruction]) -> Vec<u8> {
+        instructions.iter().flat_map(|i| i.to_bytes()).collect()
+    }
+
+    #[test]
+    fn test_same_results() {
+        let inputs = [
+            "1 + 2 * 3 - 4 / 2",
+            "-(5 + 5) * -2",
+            "!(1 < 2) == !true",
+            r#""mon" + "key""#,
+            r#"len("a" + "bc") + 1"#,
+            "1; 2; 3",
+            "5; let a = 1;",
+            "let a = 2 * 3; a * 7",
+            "if (1 > 2) { 10 } else { 20 }",
+            "if (1 < 2) { 10 }",
+            "if (false) { 10 }",
+            "if (true) { 1; 2 } else { 3; 4 }",
+            "let f = fn(x) { 1; if (x) { 2; x } else { 3 } }; [f(1), f(false)]",
+            "let f = fn() { 1; 2; }; f()",
+            "let f = fn() { return 1; 2 }; f() + 1",
+            "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(100)",
+            "let add = fn(a) { fn(b) { a + b + 0 * 9 } }; add(1)(2)",
+            "{ 1 + 1; { 2 * 2 } }",
+            "[1 + 1, 2 + 2, 1 + 1][2]",
+            r#""a" - 1"#,
+            "-true",
+            "1 + true",
+            "let x = 1; let y = 1; x + y",
+        ];
+        for input in inputs.iter() {
+            let plain = compile(input, OptimizationLevel::None);
+            let optimized = compile(input, OptimizationLevel::Full);
+            optimized.verify().unwrap();
+            assert!(
+                optimized.instructions.len() <= plain.instructions.len(),
+                "{}",
+                input
+            );
+            assert!(
+                optimized.constants.len() <= plain.constants.len(),
+                "{}",
+                input
+            );
+            assert_eq!(run(&optimized), run(&plain), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_folding() {
+        let bytecode = compile(
+            r#"let x = 60 * 60 * 24; "a" + "b" == "ab""#,
+            OptimizationLevel::Full,
+        );
+        assert_eq!(
+            bytecode.disassemble(),
+            "main:\n\
+             0000 Constant 0       ; 86400\n\
+             0003 SetGlobal 0\n\
+             0006 True\n\
+             0007 Pop\n"
+        );
+        // Failures are left to happen when the program runs.
+        let bytecode = compile(r#"1 + "a""#, OptimizationLevel::Full);
+        assert_eq!(bytecode.constants.len(), 2);
+    }
+
+    #[test]
+    fn test_shared_constants() {
+        let bytecode = compile(
+            r#"let a = [1, 1, "s"]; let f = fn() { [1, "s", 2] };"#,
+            OptimizationLevel::Full,
+        );
+        // 1, "s", 2 and the function.
+        assert_eq!(bytecode.constants.len(), 4);
+    }
+
+    #[test]
+    fn test_peephole() {
+        // A pushed value that is popped straight off goes, unless it is the
+        // program's result.
+        assert_eq!(
+            peephole(
+                &assemble(&[
+                    Instruction::Constant(0),
+                    Instruction::Pop,
+                    Instruction::True,
+                    Instruction::Pop,
+                ]),
+                true
+            )
+            .0,
+            assemble(&[Instruction::True, Instruction::Pop])
+        );
+        // Jumps are threaded, and those to the next instruction dropped.
+        let (optimized, moves) = peephole(
+            &assemble(&[
+                Instruction::True,         // 0
+                Instruction::JumpFalse(8), // 1
+                Instruction::Null,         // 4
+                Instruction::Jump(8),      // 5
+                Instruction::Jump(11),     // 8
+                Instruction::ReturnValue,  // 11
+            ]),
+            false,
+        );
+        assert_eq!(
+            optimized,
+            assemble(&[
+                Instruction::True,
+                Instruction::JumpFalse(5),
+                Instruction::Null,
+                Instruction::ReturnValue,
+            ])
+        );
+        assert_eq!(moves, vec![(0, 0), (1, 1), (4, 4), (11, 5)]);
+    }
+}
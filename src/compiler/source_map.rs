@@ -0,0 +1,53 @@
+use crate::ast::Position;
+use std::collections::BTreeMap;
+
+/**
+ * Where in the source the instructions of one function came from. Only the
+ * instructions that can fail at runtime are recorded: operators, calls,
+ * indexing and the jump testing an if's condition.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    /// Instruction offsets and their positions, in order of offset.
+    pub entries: Vec<(usize, Position)>,
+}
+
+impl LineTable {
+    /**
+     * The position recorded for the last instruction starting before `ip`,
+     * along with that instruction's offset. The VM checks that this is the
+     * instruction it was running, as it may have had no entry of its own.
+     */
+    pub fn before(&self, ip: usize) -> Option<(usize, Position)> {
+        let index = self.entries.partition_point(|(offset, _)| *offset < ip);
+        index.checked_sub(1).map(|index| self.entries[index])
+    }
+
+    /**
+     * Moves the entries to where their instructions went when the code was
+     * laid out again, dropping those whose instructions were removed.
+     */
+    pub(crate) fn relocate(&mut self, moves: &[(usize, usize)]) {
+        let mut moves = moves.iter().peekable();
+        self.entries.retain_mut(|(offset, _)| {
+            while moves.next_if(|(old, _)| old < offset).is_some() {}
+            match moves.peek() {
+                Some((old, new)) if old == offset => {
+                    *offset = *new;
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+}
+
+/**
+ * Line tables for the main program and for each compiled function, keyed by
+ * the function's index in the constant pool.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub main: LineTable,
+    pub functions: BTreeMap<usize, LineTable>,
+}
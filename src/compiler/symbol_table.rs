@@ -0,0 +1,387 @@
+use crate::ast::visit::{walk_expression, Visitor};
+use crate::ast::{Expression, Statement};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Builtin,
+    Free,
+    /// The function currently being compiled, referred to by its own name.
+    Function,
+    /// A local kept in a cell, as a closure uses it before it is bound.
+    Cell,
+    /// A free variable that is a cell, holding the variable's value.
+    FreeCell,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+/**
+ * There is one symbol table per function being compiled (plus one for the
+ * top level). Within a table, `blocks` mirrors the inner environments the
+ * interpreter creates for `if` and block expressions: a `let` inside a block
+ * gets a fresh slot so it shadows, rather than overwrites, an outer binding.
+ *
+ * As in `resolve`, the names a block binds are declared before it is
+ * compiled, so that functions can refer to bindings made after them.
+ */
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    outer: Option<Box<SymbolTable>>,
+    blocks: Vec<HashMap<String, Symbol>>,
+    // The names each block declares but hasn't bound yet.
+    later: Vec<HashMap<String, Symbol>>,
+    builtins: HashMap<String, usize>,
+    pub num_definitions: usize,
+    pub free_symbols: Vec<Symbol>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable {
+            outer: None,
+            blocks: vec![HashMap::new()],
+            later: vec![HashMap::new()],
+            builtins: HashMap::new(),
+            num_definitions: 0,
+            free_symbols: vec![],
+        }
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> Self {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            ..SymbolTable::default()
+        }
+    }
+
+    /**
+     * Detaches and returns the table this one was enclosed in.
+     */
+    pub fn take_outer(&mut self) -> Option<SymbolTable> {
+        self.outer.take().map(|outer| *outer)
+    }
+
+    pub fn push_block(&mut self) {
+        self.blocks.push(HashMap::new());
+        self.later.push(HashMap::new());
+    }
+
+    pub fn pop_block(&mut self) {
+        self.blocks.pop();
+        self.later.pop();
+    }
+
+    /**
+     * Declares the names the statements bind in the innermost block,
+     * returning the symbols of those not bound there already. A local that
+     * a function literal refers to before (or in) its last `let` is a
+     * cell, as the function may be made before the `let` runs.
+     */
+    pub fn declare(&mut self, statements: &[Statement]) -> Vec<Symbol> {
+        let mut captured = Captured::default();
+        let mut names: Vec<(&str, bool)> = vec![];
+        for statement in statements {
+            captured.visit_statement(statement);
+            if let Statement::Let { name, .. } = statement {
+                let cell = captured.names.contains(name.as_str());
+                match names.iter_mut().find(|(declared, _)| declared == name) {
+                    Some((_, is_cell)) => *is_cell = cell,
+                    None => names.push((name, cell)),
+                }
+            }
+        }
+        let mut symbols = vec![];
+        for (name, cell) in names {
+            if self.bound_in_innermost_block(name).is_some() {
+                continue;
+            }
+            let scope = match (&self.outer, cell) {
+                (None, _) => SymbolScope::Global,
+                (Some(_), false) => SymbolScope::Local,
+                (Some(_), true) => SymbolScope::Cell,
+            };
+            let symbol = Symbol {
+                scope,
+                index: self.num_definitions,
+            };
+            self.num_definitions += 1;
+            self.later
+                .last_mut()
+                .unwrap()
+                .insert(String::from(name), symbol.clone());
+            symbols.push(symbol);
+        }
+        symbols
+    }
+
+    /**
+     * Binds the name in the innermost block, to the symbol it was declared
+     * with if it was, or else the one it is already bound to there.
+     */
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let symbol = match self.later.last_mut().unwrap().remove(name) {
+            Some(symbol) => symbol,
+            None => match self.bound_in_innermost_block(name) {
+                Some(symbol) => return symbol,
+                None => {
+                    let scope = if self.outer.is_some() {
+                        SymbolScope::Local
+                    } else {
+                        SymbolScope::Global
+                    };
+                    self.num_definitions += 1;
+                    Symbol {
+                        scope,
+                        index: self.num_definitions - 1,
+                    }
+                }
+            },
+        };
+        self.innermost_block()
+            .insert(String::from(name), symbol.clone());
+        symbol
+    }
+
+    /**
+     * The symbol `define` would bind the name to, if it is declared in the
+     * innermost block or already bound there.
+     */
+    pub fn declared(&mut self, name: &str) -> Option<Symbol> {
+        match self.later.last().unwrap().get(name) {
+            Some(symbol) => Some(symbol.clone()),
+            None => self.bound_in_innermost_block(name),
+        }
+    }
+
+    fn bound_in_innermost_block(&mut self, name: &str) -> Option<Symbol> {
+        match self.innermost_block().get(name) {
+            Some(
+                symbol @ Symbol {
+                    scope: SymbolScope::Global | SymbolScope::Local | SymbolScope::Cell,
+                    ..
+                },
+            ) => Some(symbol.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) {
+        self.builtins.insert(String::from(name), index);
+    }
+
+    pub fn clear_builtins(&mut self) {
+        self.builtins.clear();
+    }
+
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol {
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+        self.blocks[0].insert(String::from(name), symbol.clone());
+        symbol
+    }
+
+    /**
+     * Finds the symbol a name refers to, by the same rules as `resolve`:
+     * code can refer to the bindings made before it, and a function also to
+     * the bindings its enclosing functions make later on, but only when
+     * nothing outside is bound already.
+     */
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        // The first binding made later, and how many tables out it is.
+        let mut later: Option<(usize, Symbol)> = None;
+        let mut table = Some(&*self);
+        let mut depth = 0;
+        while let Some(current) = table {
+            let bound = current
+                .blocks
+                .iter()
+                .rev()
+                .find_map(|block| block.get(name));
+            if let Some(symbol) = bound {
+                let symbol = symbol.clone();
+                return Some(self.capture(name, depth, symbol));
+            }
+            if depth > 0 && later.is_none() {
+                later = current
+                    .later
+                    .iter()
+                    .rev()
+                    .find_map(|block| block.get(name))
+                    .map(|symbol| (depth, symbol.clone()));
+            }
+            if current.outer.is_none() {
+                match &later {
+                    // Looked up when used, by which time it may be set.
+                    Some((_, symbol)) if symbol.scope == SymbolScope::Global => {
+                        return Some(symbol.clone());
+                    }
+                    _ => {}
+                }
+                if let Some(index) = current.builtins.get(name) {
+                    return Some(Symbol {
+                        scope: SymbolScope::Builtin,
+                        index: *index,
+                    });
+                }
+            }
+            table = current.outer.as_deref();
+            depth += 1;
+        }
+        let (depth, symbol) = later?;
+        Some(self.capture(name, depth, symbol))
+    }
+
+    /**
+     * Makes a symbol found `depth` tables out available in this one, as a
+     * free variable of each function in between.
+     */
+    fn capture(&mut self, name: &str, depth: usize, symbol: Symbol) -> Symbol {
+        match (depth, &symbol.scope, &mut self.outer) {
+            (0, ..) | (_, SymbolScope::Global, _) | (_, SymbolScope::Builtin, _) => symbol,
+            (_, _, Some(outer)) => {
+                let original = outer.capture(name, depth - 1, symbol);
+                self.define_free(name, original)
+            }
+            (_, _, None) => unreachable!("No table that far out"),
+        }
+    }
+
+    fn define_free(&mut self, name: &str, original: Symbol) -> Symbol {
+        let scope = match original.scope {
+            SymbolScope::Cell | SymbolScope::FreeCell => SymbolScope::FreeCell,
+            _ => SymbolScope::Free,
+        };
+        self.free_symbols.push(original);
+        let symbol = Symbol {
+            scope,
+            index: self.free_symbols.len() - 1,
+        };
+        self.blocks[0].insert(String::from(name), symbol.clone());
+        symbol
+    }
+
+    fn innermost_block(&mut self) -> &mut HashMap<String, Symbol> {
+        // There is always at least the function's own block.
+        self.blocks.last_mut().unwrap()
+    }
+}
+
+/**
+ * Collects the names used inside function literals.
+ */
+#[derive(Default)]
+struct Captured {
+    depth: usize,
+    names: HashSet<String>,
+}
+
+impl Visitor for Captured {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier { value, .. } if self.depth > 0 => {
+                self.names.insert(value.clone());
+            }
+            Expression::FnLiteral { .. } => {
+                self.depth += 1;
+                walk_expression(self, expression);
+                self.depth -= 1;
+            }
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Symbol, SymbolScope, SymbolTable};
+    use crate::ast::Statement;
+    use crate::{lexer, parser};
+
+    fn statements(source: &str) -> Vec<Statement> {
+        let mut lexer = lexer::new(source);
+        let mut parser = parser::Parser::new(&mut lexer);
+        parser.parse_program().unwrap().statements
+    }
+
+    fn symbol(scope: SymbolScope, index: usize) -> Symbol {
+        Symbol { scope, index }
+    }
+
+    #[test]
+    fn test_resolve_nested() {
+        let mut global = SymbolTable::new();
+        global.define("a");
+        global.define_builtin(0, "len");
+        let mut first = SymbolTable::new_enclosed(global);
+        first.define("b");
+        let mut second = SymbolTable::new_enclosed(first);
+        second.define("c");
+
+        assert_eq!(second.resolve("a"), Some(symbol(SymbolScope::Global, 0)));
+        assert_eq!(second.resolve("len"), Some(symbol(SymbolScope::Builtin, 0)));
+        assert_eq!(second.resolve("c"), Some(symbol(SymbolScope::Local, 0)));
+        assert_eq!(second.resolve("b"), Some(symbol(SymbolScope::Free, 0)));
+        assert_eq!(second.free_symbols, vec![symbol(SymbolScope::Local, 0)]);
+        assert_eq!(second.resolve("d"), None);
+    }
+
+    #[test]
+    fn test_blocks_shadow() {
+        let mut table = SymbolTable::new();
+        table.define("a");
+        table.push_block();
+        assert_eq!(table.define("a"), symbol(SymbolScope::Global, 1));
+        assert_eq!(table.resolve("a"), Some(symbol(SymbolScope::Global, 1)));
+        table.pop_block();
+        assert_eq!(table.resolve("a"), Some(symbol(SymbolScope::Global, 0)));
+    }
+
+    #[test]
+    fn test_declare_later_bindings() {
+        let mut global = SymbolTable::new();
+        let declared = global.declare(&statements("let f = fn() { g() }; let g = 1;"));
+        assert_eq!(
+            declared,
+            vec![
+                symbol(SymbolScope::Global, 0),
+                symbol(SymbolScope::Global, 1)
+            ]
+        );
+        global.define("f");
+        let mut function = SymbolTable::new_enclosed(global);
+        assert_eq!(function.resolve("g"), Some(symbol(SymbolScope::Global, 1)));
+
+        let mut function = SymbolTable::new_enclosed(function.take_outer().unwrap());
+        let declared = function.declare(&statements(
+            "let a = 1; let f = fn() { b }; let b = a; let a = 2;",
+        ));
+        assert_eq!(
+            declared,
+            vec![
+                symbol(SymbolScope::Local, 0),
+                symbol(SymbolScope::Local, 1),
+                symbol(SymbolScope::Cell, 2),
+            ]
+        );
+        assert_eq!(function.define("a"), symbol(SymbolScope::Local, 0));
+        assert_eq!(function.define("a"), symbol(SymbolScope::Local, 0));
+        let mut inner = SymbolTable::new_enclosed(function);
+        assert_eq!(inner.resolve("b"), Some(symbol(SymbolScope::FreeCell, 0)));
+        assert_eq!(inner.free_symbols, vec![symbol(SymbolScope::Cell, 2)]);
+    }
+}
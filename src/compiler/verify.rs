@@ -0,0 +1,437 @@
+use super::Bytecode;
+use crate::code::Instruction;
+use crate::object::Object;
+use std::collections::HashMap;
+use std::fmt;
+
+/**
+ * Something about a piece of bytecode that could make the VM misbehave.
+ */
+#[derive(Debug, PartialEq)]
+pub struct VerifyError {
+    /// Where the function is in the constant pool, or `None` for the main
+    /// program.
+    pub function: Option<usize>,
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.function {
+            Some(index) => write!(f, "In constant {}", index)?,
+            None => write!(f, "In the main program")?,
+        }
+        write!(f, " at {}: {}", self.position, self.message)
+    }
+}
+
+impl Bytecode {
+    /**
+     * Checks that the VM can run the bytecode without panicking or reading
+     * past the end of its stack. The compiler only produces bytecode that
+     * passes; this is for bytecode from anywhere else, such as a file.
+     */
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let mut code = vec![Code::decode(None, &self.instructions, 0)?];
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let Object::CompiledFunction(function) = &**constant {
+                let error = |message: String| VerifyError {
+                    function: Some(index),
+                    position: 0,
+                    message,
+                };
+                if function.num_parameters > function.num_locals {
+                    return Err(error(format!(
+                        "{} parameters but only {} locals",
+                        function.num_parameters, function.num_locals
+                    )));
+                }
+                if function.num_locals > u8::MAX as usize + 1 {
+                    return Err(error(format!("{} locals", function.num_locals)));
+                }
+                code.push(Code::decode(
+                    Some(index),
+                    &function.instructions,
+                    function.num_locals,
+                )?);
+            }
+        }
+
+        // How many free variables each function is given by the closures
+        // made from it.
+        let mut num_free: HashMap<usize, u8> = HashMap::new();
+        for code in &code {
+            for (position, instruction) in &code.instructions {
+                let closure = match instruction {
+                    Instruction::Closure(index, free) => Some((*index as usize, free)),
+                    Instruction::ClosureWide(index, free) => Some((*index as usize, free)),
+                    _ => None,
+                };
+                if let Some((index, free)) = closure {
+                    match self.constants.get(index).map(|c| &**c) {
+                        Some(Object::CompiledFunction(_)) => {}
+                        _ => return Err(code.error(*position, "Makes a closure of a non-function")),
+                    }
+                    if *num_free.entry(index).or_insert(*free) != *free {
+                        return Err(code.error(
+                            *position,
+                            "Closes over a different number of variables than elsewhere",
+                        ));
+                    }
+                }
+            }
+        }
+
+        for code in &code {
+            let free = code
+                .function
+                .and_then(|index| num_free.get(&index).copied())
+                .unwrap_or(0);
+            code.check_operands(self.constants.len(), free)?;
+            code.check_stack()?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The decoded instructions of the main program or of one function.
+ */
+struct Code {
+    function: Option<usize>,
+    instructions: Vec<(usize, Instruction)>,
+    length: usize,
+    num_locals: usize,
+}
+
+impl Code {
+    fn decode(
+        function: Option<usize>,
+        bytes: &[u8],
+        num_locals: usize,
+    ) -> Result<Self, VerifyError> {
+        let mut code = Code {
+            function,
+            instructions: vec![],
+            length: bytes.len(),
+            num_locals,
+        };
+        let mut position = 0;
+        loop {
+            match Instruction::read_at(bytes, position) {
+                Ok(Some((instruction, next))) => {
+                    code.instructions.push((position, instruction));
+                    position = next;
+                }
+                Ok(None) => return Ok(code),
+                Err(error) => return Err(code.error(position, &error.to_string())),
+            }
+        }
+    }
+
+    fn error(&self, position: usize, message: &str) -> VerifyError {
+        VerifyError {
+            function: self.function,
+            position,
+            message: String::from(message),
+        }
+    }
+
+    /**
+     * The index of the instruction at `position`, which can also be the
+     * end of the code.
+     */
+    fn index_of(&self, position: usize) -> Option<usize> {
+        if position == self.length {
+            return Some(self.instructions.len());
+        }
+        self.instructions
+            .binary_search_by_key(&position, |(start, _)| *start)
+            .ok()
+    }
+
+    fn check_operands(&self, num_constants: usize, num_free: u8) -> Result<(), VerifyError> {
+        for (position, instruction) in &self.instructions {
+            let error = match instruction {
+                Instruction::Constant(index) if *index as usize >= num_constants => {
+                    format!("There is no constant {}", index)
+                }
+                Instruction::ConstantWide(index) if *index as usize >= num_constants => {
+                    format!("There is no constant {}", index)
+                }
+                Instruction::GetLocal(index)
+                | Instruction::SetLocal(index)
+                | Instruction::NewCell(index)
+                | Instruction::GetCell(index)
+                | Instruction::SetCell(index)
+                    if *index as usize >= self.num_locals =>
+                {
+                    format!("There is no local {}", index)
+                }
+                Instruction::GetFree(index) | Instruction::GetFreeCell(index)
+                    if *index >= num_free =>
+                {
+                    format!("There is no free variable {}", index)
+                }
+                _ => match instruction.jump_target() {
+                    Some(target) if self.index_of(target).is_none() => {
+                        format!("Jumps into the middle of an instruction at {}", target)
+                    }
+                    _ => continue,
+                },
+            };
+            return Err(self.error(*position, &error));
+        }
+        Ok(())
+    }
+
+    /**
+     * Follows every path through the code, checking that nothing pops more
+     * than has been pushed, that each instruction always runs with the same
+     * stack depth, and that functions return rather than running off their
+     * end.
+     */
+    fn check_stack(&self) -> Result<(), VerifyError> {
+        let mut depths: Vec<Option<usize>> = vec![None; self.instructions.len() + 1];
+        let mut pending = vec![(0, 0)];
+        while let Some((index, depth)) = pending.pop() {
+            match depths[index] {
+                Some(known) if known == depth => continue,
+                Some(_) => {
+                    let position = self.position_of(index);
+                    return Err(self.error(position, "Reached with different stack depths"));
+                }
+                None => depths[index] = Some(depth),
+            }
+            let (position, instruction) = match self.instructions.get(index) {
+                Some((position, instruction)) => (*position, instruction),
+                None if self.function.is_none() => continue,
+                None => return Err(self.error(self.length, "Runs off the end of the function")),
+            };
+            let (pops, pushes) = stack_effect(instruction);
+            let depth = depth
+                .checked_sub(pops)
+                .ok_or_else(|| self.error(position, "Pops from an empty stack"))?
+                + pushes;
+            if let Some(target) = instruction.jump_target() {
+                pending.push((self.index_of(target).unwrap(), depth));
+            }
+            if !matches!(instruction, Instruction::ReturnValue)
+                && !instruction.is_unconditional_jump()
+            {
+                pending.push((index + 1, depth));
+            }
+        }
+        Ok(())
+    }
+
+    fn position_of(&self, index: usize) -> usize {
+        self.instructions
+            .get(index)
+            .map_or(self.length, |(position, _)| *position)
+    }
+}
+
+/**
+ * How many values an instruction takes off the stack, and how many it
+ * then pushes.
+ */
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Constant(_)
+        | Instruction::ConstantWide(_)
+        | Instruction::True
+        | Instruction::False
+        | Instruction::Null
+        | Instruction::GetGlobal(_)
+        | Instruction::GetLocal(_)
+        | Instruction::GetBuiltin(_)
+        | Instruction::GetFree(_)
+        | Instruction::GetCell(_)
+        | Instruction::GetFreeCell(_)
+        | Instruction::CurrentClosure => (0, 1),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Equal
+        | Instruction::NotEqual
+        | Instruction::GreaterThan
+        | Instruction::LessThan
+        | Instruction::Index => (2, 1),
+        Instruction::Minus | Instruction::Bang => (1, 1),
+        Instruction::Pop
+        | Instruction::JumpFalse(_)
+        | Instruction::JumpFalseWide(_)
+        | Instruction::SetGlobal(_)
+        | Instruction::SetLocal(_)
+        | Instruction::SetCell(_)
+        | Instruction::ReturnValue => (1, 0),
+        Instruction::Jump(_) | Instruction::JumpWide(_) | Instruction::NewCell(_) => (0, 0),
+        Instruction::Array(length) => (*length as usize, 1),
+        Instruction::Call(num_args) | Instruction::TailCall(num_args) => {
+            (*num_args as usize + 1, 1)
+        }
+        Instruction::Closure(_, num_free) | Instruction::ClosureWide(_, num_free) => {
+            (*num_free as usize, 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VerifyError;
+    use crate::code::Instruction;
+    use crate::compiler::{self, Bytecode};
+    use crate::object::{CompiledFunction, Object};
+    use crate::{lexer, parser};
+    use std::rc::Rc;
+
+    fn assemble(instructions: &[Instruction]) -> Vec<u8> {
+        instructions.iter().flat_map(|i| i.to_bytes()).collect()
+    }
+
+    fn verify(instructions: &[Instruction], constants: Vec<Object>) -> Result<(), VerifyError> {
+        Bytecode {
+            instructions: assemble(instructions),
+            constants: constants.into_iter().map(Rc::new).collect(),
+            source_map: Default::default(),
+        }
+        .verify()
+    }
+
+    fn function(instructions: &[Instruction], num_locals: usize) -> Object {
+        Object::CompiledFunction(Rc::new(CompiledFunction {
+            instructions: assemble(instructions),
+            num_locals,
+            num_parameters: 0,
+            name: None,
+        }))
+    }
+
+    fn message(result: Result<(), VerifyError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_compiled_code_verifies() {
+        let input = r#"
+            let rest = fn(xs) { [xs[1]] };
+            let push = fn(xs, x) { [x] };
+            let map = fn(xs, f) {
+                let iter = fn(xs, acc) {
+                    if (len(xs) == 0) { return acc; }
+                    iter(rest(xs), push(acc, f(xs[0])))
+                };
+                iter(xs, [])
+            };
+            let n = 3;
+            { let m = n * 2; if (!(m > n)) { -1 } else { "ok" } };
+            map([1, 2], fn(x) { x + n });
+        "#;
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        compiler::compile_program(&program)
+            .unwrap()
+            .verify()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            message(verify(&[Instruction::Add], vec![])),
+            "In the main program at 0: Pops from an empty stack"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::Constant(1), Instruction::Pop],
+                vec![Object::Integer(1)]
+            )),
+            "In the main program at 0: There is no constant 1"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::ConstantWide(70000), Instruction::Pop],
+                vec![Object::Integer(1)]
+            )),
+            "In the main program at 0: There is no constant 70000"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::True, Instruction::JumpWide(2)],
+                vec![]
+            )),
+            "In the main program at 1: Jumps into the middle of an instruction at 2"
+        );
+        assert_eq!(
+            message(verify(&[Instruction::True, Instruction::Jump(2)], vec![])),
+            "In the main program at 1: Jumps into the middle of an instruction at 2"
+        );
+        // One path leaves a value on the stack and the other doesn't.
+        assert_eq!(
+            message(verify(
+                &[
+                    Instruction::True,
+                    Instruction::JumpFalse(5),
+                    Instruction::Null,
+                    Instruction::Null,
+                    Instruction::Pop,
+                ],
+                vec![]
+            )),
+            "In the main program at 5: Reached with different stack depths"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::Closure(0, 0), Instruction::Pop],
+                vec![function(&[Instruction::Null], 0)]
+            )),
+            "In constant 0 at 1: Runs off the end of the function"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::Closure(0, 0), Instruction::Pop],
+                vec![function(
+                    &[Instruction::GetLocal(1), Instruction::ReturnValue],
+                    1
+                )]
+            )),
+            "In constant 0 at 0: There is no local 1"
+        );
+        assert_eq!(
+            message(verify(
+                &[
+                    Instruction::Null,
+                    Instruction::Closure(0, 1),
+                    Instruction::Pop
+                ],
+                vec![function(
+                    &[Instruction::GetFree(1), Instruction::ReturnValue],
+                    0
+                )]
+            )),
+            "In constant 0 at 0: There is no free variable 1"
+        );
+        assert_eq!(
+            message(verify(
+                &[Instruction::Closure(0, 0), Instruction::Pop],
+                vec![Object::Integer(1)]
+            )),
+            "In the main program at 0: Makes a closure of a non-function"
+        );
+        assert_eq!(
+            message(
+                Bytecode {
+                    instructions: vec![6, 13],
+                    constants: vec![],
+                    source_map: Default::default(),
+                }
+                .verify()
+            ),
+            "In the main program at 1: The instruction is cut short"
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use crate::ast::{self, BlockStatement, Expression, InfixOperator, PrefixOperator, Statement};
+use std::rc::Rc;
+
+/**
+ * What an expression is meant to evaluate to. The generator mostly keeps to
+ * it, so that programs get somewhere before failing, but now and then
+ * ignores it to make sure the backends agree on errors too.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Integer,
+    Boolean,
+    String,
+    Array,
+    /// A function taking this many arguments.
+    Function(usize),
+    /// Whatever is to hand, such as a parameter.
+    Any,
+}
+
+struct Binding {
+    name: String,
+    ty: Type,
+}
+
+/**
+ * Makes random programs out of `ast` nodes. Each name is bound only once
+ * and calls are only made to functions defined earlier, so programs rarely
+ * recurse; those that do, by passing a function to `map` or `filter`, are
+ * cut off by the limits they are run with.
+ */
+pub struct Generator {
+    state: u64,
+    next_name: usize,
+    // The names in scope, innermost scope last.
+    scopes: Vec<Vec<Binding>>,
+}
+
+const STRINGS: [&str; 5] = ["", "a", "mon", "key", "a b"];
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator {
+            // Xorshift gets stuck at zero.
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+            next_name: 0,
+            scopes: vec![],
+        }
+    }
+
+    pub fn program(&mut self) -> ast::Program {
+        self.scopes = vec![vec![]];
+        let count = 1 + self.below(6);
+        let statements = (0..count).map(|_| self.statement(3)).collect();
+        ast::Program { statements }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn fresh_name(&mut self) -> String {
+        self.next_name += 1;
+        // Identifiers can't contain digits, so they are spelt as letters.
+        let letters: String = self
+            .next_name
+            .to_string()
+            .chars()
+            .map(|digit| (b'a' + digit as u8 - b'0') as char)
+            .collect();
+        format!("v{}", letters)
+    }
+
+    fn define(&mut self, name: &str, ty: Type) {
+        let binding = Binding {
+            name: String::from(name),
+            ty,
+        };
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    /**
+     * A name in scope whose type is `ty`, if there is one.
+     */
+    fn name_of_type(&mut self, ty: Type) -> Option<String> {
+        let names: Vec<String> = self
+            .scopes
+            .iter()
+            .flatten()
+            .filter(|binding| binding.ty == ty)
+            .map(|binding| binding.name.clone())
+            .collect();
+        if names.is_empty() {
+            None
+        } else {
+            Some(names[self.below(names.len())].clone())
+        }
+    }
+
+    fn any_type(&mut self) -> Type {
+        match self.below(6) {
+            0 => Type::Integer,
+            1 => Type::Boolean,
+            2 => Type::String,
+            3 => Type::Array,
+            4 => Type::Function(self.below(3)),
+            _ => Type::Any,
+        }
+    }
+
+    fn statement(&mut self, depth: usize) -> Statement {
+        match self.below(8) {
+            0..=3 => {
+                let ty = self.any_type();
+                let name = self.fresh_name();
+                let right = self.expression(ty, depth);
+                // Defined only afterwards, so a function can't call itself.
+                self.define(&name, ty);
+                Statement::Let {
+                    name,
+                    right,
+                    position: Default::default(),
+                    slot: None,
+                    annotation: None,
+                }
+            }
+            4 if self.one_in(3) => Statement::Return {
+                value: self.expression(Type::Any, depth),
+            },
+            _ => Statement::Expression {
+                expression: self.expression(Type::Any, depth),
+            },
+        }
+    }
+
+    /**
+     * A few statements ending in an expression of type `ty`, in a scope of
+     * their own.
+     */
+    fn block(&mut self, ty: Type, depth: usize) -> BlockStatement {
+        self.scopes.push(vec![]);
+        let mut statements: Vec<Statement> =
+            (0..self.below(3)).map(|_| self.statement(depth)).collect();
+        if !self.one_in(8) {
+            statements.push(Statement::Expression {
+                expression: self.expression(ty, depth),
+            });
+        }
+        self.scopes.pop();
+        BlockStatement { statements }
+    }
+
+    fn expression(&mut self, ty: Type, depth: usize) -> Expression {
+        let ty = match ty {
+            Type::Any => self.any_type(),
+            _ if self.one_in(30) => self.any_type(),
+            ty => ty,
+        };
+        if depth == 0 || ty == Type::Any || self.one_in(4) {
+            return self.leaf(ty);
+        }
+        let depth = depth - 1;
+        match self.below(12) {
+            0 => Expression::If {
+                condition: Box::new(self.expression(Type::Boolean, depth)),
+                consequence: self.block(ty, depth),
+                alternative: if self.one_in(4) {
+                    None
+                } else {
+                    Some(self.block(ty, depth))
+                },
+                position: Default::default(),
+            },
+            1 => Expression::Block {
+                statements: self.block(ty, depth).statements,
+            },
+            2 => {
+                let element = self.expression(ty, depth);
+                let elements = vec![self.leaf(ty), element];
+                index(
+                    Expression::ArrayLiteral { elements },
+                    integer(self.below(3) as i64),
+                )
+            }
+            3 => self.call(depth),
+            _ => self.typed(ty, depth),
+        }
+    }
+
+    /**
+     * An expression that can only be of type `ty`.
+     */
+    fn typed(&mut self, ty: Type, depth: usize) -> Expression {
+        match ty {
+            Type::Integer => match self.below(6) {
+                0 => prefix(PrefixOperator::Minus, self.expression(ty, depth)),
+                1 => infix(
+                    self.expression(ty, depth),
+                    InfixOperator::Divide,
+                    integer(1 + self.below(5) as i64),
+                ),
+                2 => {
+                    let argument = if self.one_in(2) {
+                        Type::String
+                    } else {
+                        Type::Array
+                    };
+                    builtin("len", vec![self.expression(argument, depth)])
+                }
+                _ => {
+                    let operator = match self.below(3) {
+                        0 => InfixOperator::Plus,
+                        1 => InfixOperator::Minus,
+                        _ => InfixOperator::Multiply,
+                    };
+                    infix(
+                        self.expression(ty, depth),
+                        operator,
+                        self.expression(ty, depth),
+                    )
+                }
+            },
+            Type::Boolean => match self.below(5) {
+                0 => prefix(PrefixOperator::Bang, self.expression(Type::Any, depth)),
+                1 => infix(
+                    self.expression(Type::Any, depth),
+                    InfixOperator::Eq,
+                    self.expression(Type::Any, depth),
+                ),
+                2 => builtin(
+                    "contains",
+                    vec![
+                        self.expression(Type::String, depth),
+                        self.expression(Type::String, depth),
+                    ],
+                ),
+                _ => {
+                    let operator = match self.below(4) {
+                        0 => InfixOperator::Lt,
+                        1 => InfixOperator::Gt,
+                        2 => InfixOperator::Eq,
+                        _ => InfixOperator::NotEq,
+                    };
+                    infix(
+                        self.expression(Type::Integer, depth),
+                        operator,
+                        self.expression(Type::Integer, depth),
+                    )
+                }
+            },
+            Type::String => match self.below(3) {
+                0 => builtin("upper", vec![self.expression(ty, depth)]),
+                1 => builtin(
+                    "join",
+                    vec![
+                        self.expression(Type::Array, depth),
+                        self.expression(ty, depth),
+                    ],
+                ),
+                _ => infix(
+                    self.expression(ty, depth),
+                    InfixOperator::Plus,
+                    self.expression(ty, depth),
+                ),
+            },
+            Type::Array => match self.below(6) {
+                0 => builtin("chars", vec![self.expression(Type::String, depth)]),
+                1 => builtin("enumerate", vec![self.expression(ty, depth)]),
+                2 => builtin(
+                    "map",
+                    vec![
+                        self.expression(ty, depth),
+                        self.expression(Type::Function(1), depth),
+                    ],
+                ),
+                3 => builtin(
+                    "filter",
+                    vec![
+                        self.expression(ty, depth),
+                        self.expression(Type::Function(1), depth),
+                    ],
+                ),
+                _ => {
+                    let length = self.below(4);
+                    Expression::ArrayLiteral {
+                        elements: (0..length)
+                            .map(|_| self.expression(Type::Any, depth))
+                            .collect(),
+                    }
+                }
+            },
+            Type::Function(arity) => self.function(arity, depth),
+            Type::Any => unreachable!("Any is replaced by a type in `expression`"),
+        }
+    }
+
+    fn leaf(&mut self, ty: Type) -> Expression {
+        if let Some(name) = self.name_of_type(ty) {
+            if !self.one_in(3) {
+                return identifier(name);
+            }
+        }
+        match ty {
+            Type::Integer => integer(self.below(21) as i64),
+            Type::Boolean => Expression::Boolean {
+                value: self.one_in(2),
+            },
+            Type::String => Expression::StringLiteral {
+                value: String::from(STRINGS[self.below(STRINGS.len())]),
+            },
+            Type::Array => {
+                let length = self.below(3);
+                Expression::ArrayLiteral {
+                    elements: (0..length)
+                        .map(|_| integer(self.below(10) as i64))
+                        .collect(),
+                }
+            }
+            Type::Function(arity) => self.function(arity, 0),
+            Type::Any => match self.name_of_type(Type::Any) {
+                Some(name) => identifier(name),
+                None => Expression::Boolean { value: true },
+            },
+        }
+    }
+
+    fn function(&mut self, arity: usize, depth: usize) -> Expression {
+        self.scopes.push(vec![]);
+        let param_names: Vec<String> = (0..arity).map(|_| self.fresh_name()).collect();
+        for name in &param_names {
+            self.define(name, Type::Any);
+        }
+        let ty = self.any_type();
+        let body = self.block(ty, depth);
+        self.scopes.pop();
+        Expression::FnLiteral {
+            param_positions: vec![Default::default(); param_names.len()],
+            param_types: vec![None; param_names.len()],
+            return_type: None,
+            param_names,
+            body: Rc::new(body),
+            position: Default::default(),
+        }
+    }
+
+    /**
+     * A call to a function defined earlier, usually with the right number
+     * of arguments.
+     */
+    fn call(&mut self, depth: usize) -> Expression {
+        let arity = self.below(3);
+        let function = match self.name_of_type(Type::Function(arity)) {
+            Some(name) => identifier(name),
+            None => self.function(arity, depth),
+        };
+        let count = if self.one_in(10) { arity + 1 } else { arity };
+        let arguments = (0..count)
+            .map(|_| self.expression(Type::Any, depth))
+            .collect();
+        Expression::CallExpression {
+            left: Box::new(function),
+            arguments,
+            position: Default::default(),
+        }
+    }
+}
+
+fn identifier(value: String) -> Expression {
+    Expression::Identifier {
+        value,
+        position: Default::default(),
+        slot: None,
+    }
+}
+
+fn integer(value: i64) -> Expression {
+    Expression::IntegerLiteral { value }
+}
+
+fn prefix(operator: PrefixOperator, right: Expression) -> Expression {
+    Expression::Prefix {
+        operator,
+        right: Box::new(right),
+        position: Default::default(),
+    }
+}
+
+fn infix(left: Expression, operator: InfixOperator, right: Expression) -> Expression {
+    Expression::Infix {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+        position: Default::default(),
+    }
+}
+
+fn index(left: Expression, index: Expression) -> Expression {
+    Expression::Index {
+        left: Box::new(left),
+        index: Box::new(index),
+        position: Default::default(),
+    }
+}
+
+fn builtin(name: &str, arguments: Vec<Expression>) -> Expression {
+    Expression::CallExpression {
+        left: Box::new(identifier(String::from(name))),
+        arguments,
+        position: Default::default(),
+    }
+}
@@ -0,0 +1,147 @@
+use crate::eval::builtins::BuiltinRegistry;
+use crate::limits::Limits;
+use crate::object::{environment::Environment, Object};
+use crate::{ast, compiler, eval, lexer, parser, register, resolve, vm};
+use core::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+pub(crate) mod generator;
+mod test;
+
+/**
+ * What running a program came to, in a form that every backend can be
+ * compared by. Values are rendered by `render`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Value(String),
+    Error(String),
+    /// The program was rejected before it ran.
+    CompileError(String),
+    /// The backend crashed, which is a bug however the others behave.
+    Panic(String),
+    /// The run was cut off by its limits, so says nothing about the
+    /// program's result. Backends count steps differently, so one may
+    /// finish where another runs out.
+    OutOfLimits,
+}
+
+pub type Runner = fn(&ast::Program, Limits) -> Outcome;
+
+pub const BACKENDS: [(&str, Runner); 3] = [
+    ("interpreter", run_interpreter),
+    ("vm", run_vm),
+    ("register", run_register),
+];
+
+/**
+ * Runs the program on every backend, returning each one's name and
+ * outcome.
+ */
+pub fn run_all(program: &ast::Program, limits: Limits) -> Vec<(&'static str, Outcome)> {
+    BACKENDS
+        .iter()
+        .map(|(name, runner)| {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| runner(program, limits)))
+                .unwrap_or_else(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|message| String::from(*message))
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    Outcome::Panic(message)
+                });
+            (*name, outcome)
+        })
+        .collect()
+}
+
+pub fn parse(source: &str) -> Result<ast::Program, String> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    parser
+        .parse_program()
+        .map_err(|errors| format!("{:?}", errors))
+}
+
+/**
+ * Resolving names is the interpreter's compile step, which fails on just
+ * the names that the compilers can't find either. They don't tell a name
+ * bound too late from one never bound, so neither does this.
+ */
+fn run_interpreter(program: &ast::Program, limits: Limits) -> Outcome {
+    let mut program = program.clone();
+    let errors = resolve::resolve(&mut program, &BuiltinRegistry::with_defaults());
+    if let Some(error) = errors.into_iter().next() {
+        let name = match error {
+            resolve::ResolveError::UnknownIdentifier { name, .. }
+            | resolve::ResolveError::UsedBeforeDefinition { name, .. } => name,
+        };
+        return Outcome::CompileError(compiler::CompilerError::UnboundIdentifier(name).message());
+    }
+    let env = Rc::new(RefCell::new(Environment::new()));
+    match eval::EvalContext::new(limits).eval_program(&program, env) {
+        Ok(result) => value(result),
+        Err(eval::EvalError::Misc(message)) => Outcome::Error(message),
+        Err(_) => Outcome::OutOfLimits,
+    }
+}
+
+fn run_vm(program: &ast::Program, limits: Limits) -> Outcome {
+    let bytecode = match compiler::compile_program(program) {
+        Ok(bytecode) => bytecode,
+        Err(error) => return Outcome::CompileError(error.message()),
+    };
+    let mut vm = vm::Vm::new(&bytecode);
+    vm.set_limits(limits);
+    vm_outcome(vm.run())
+}
+
+fn run_register(program: &ast::Program, limits: Limits) -> Outcome {
+    let bytecode = match register::Compiler::new().compile_program(program) {
+        Ok(bytecode) => bytecode,
+        Err(error) => return Outcome::CompileError(error.message()),
+    };
+    let mut vm = register::Vm::new(&bytecode);
+    vm.set_limits(limits);
+    vm_outcome(vm.run())
+}
+
+fn vm_outcome(result: Result<Option<Rc<Object>>, vm::VmError>) -> Outcome {
+    match result {
+        Ok(result) => value(result),
+        Err(vm::VmError::LimitExceeded(_)) | Err(vm::VmError::Interrupted { .. }) => {
+            Outcome::OutOfLimits
+        }
+        Err(error) => Outcome::Error(error.into_message()),
+    }
+}
+
+fn value(result: Option<Rc<Object>>) -> Outcome {
+    Outcome::Value(result.map_or_else(|| String::from("nothing"), |object| render(&object)))
+}
+
+/**
+ * Shows a value the same way whichever backend made it: strings are
+ * quoted, and functions are just `fn` as their representations differ.
+ */
+pub fn render(object: &Object) -> String {
+    match object {
+        Object::String(value) => format!("{:?}", value),
+        Object::Array(elements) => format!(
+            "[{}]",
+            elements
+                .iter()
+                .map(|element| render(element))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Object::Function { .. } | Object::Closure { .. } | Object::CompiledFunction(_) => {
+            String::from("fn")
+        }
+        Object::BuiltinFunction(_) => String::from("builtin"),
+        Object::ReturnValue(value) => render(value),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,125 @@
+use super::generator::Generator;
+use super::{parse, run_all, Outcome};
+use crate::limits::Limits;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+// Generous enough for every program in the suite, while keeping runaway
+// random programs quick.
+const LIMITS: Limits = Limits {
+    max_depth: Some(100),
+    fuel: Some(200_000),
+    max_allocations: Some(100_000),
+    max_string_length: Some(10_000),
+    timeout: None,
+};
+
+/**
+ * Reads an expectation file: `error: ` or `compile error: ` and a message,
+ * or a rendered value.
+ */
+fn expected_outcome(text: &str) -> Outcome {
+    let text = text.trim_end();
+    if let Some(message) = text.strip_prefix("error: ") {
+        Outcome::Error(String::from(message))
+    } else if let Some(message) = text.strip_prefix("compile error: ") {
+        Outcome::CompileError(String::from(message))
+    } else {
+        Outcome::Value(String::from(text))
+    }
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Value(value) => value.clone(),
+        Outcome::Error(message) => format!("error: {}", message),
+        Outcome::CompileError(message) => format!("compile error: {}", message),
+        other => format!("{:?}", other),
+    }
+}
+
+/**
+ * Runs every program in `tests/conformance` on each backend, checking the
+ * result against the `.expected` file beside it. Setting
+ * `MONKEY_BLESS=1` writes the interpreter's results to the expectation
+ * files instead, for adding new programs.
+ */
+#[test]
+fn test_conformance_suite() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let bless = std::env::var_os("MONKEY_BLESS").is_some();
+    let mut paths: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new("monkey")))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = vec![];
+    for path in &paths {
+        let source = fs::read_to_string(path).unwrap();
+        let program = parse(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let outcomes = run_all(&program, LIMITS);
+        let expected_path = path.with_extension("expected");
+        if bless {
+            fs::write(&expected_path, describe(&outcomes[0].1) + "\n").unwrap();
+        }
+        let expected = match fs::read_to_string(&expected_path) {
+            Ok(text) => expected_outcome(&text),
+            Err(e) => panic!("{}: {}", expected_path.display(), e),
+        };
+        for (backend, outcome) in outcomes {
+            if outcome != expected {
+                failures.push(format!(
+                    "{} on {}: expected {}, got {}",
+                    path.display(),
+                    backend,
+                    describe(&expected),
+                    describe(&outcome)
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/**
+ * Checks that every backend agrees on random programs. Set
+ * `MONKEY_CONFORMANCE_SEEDS` to try more than the default number.
+ */
+#[test]
+fn test_random_programs() {
+    let seeds = std::env::var("MONKEY_CONFORMANCE_SEEDS")
+        .ok()
+        .and_then(|seeds| seeds.parse().ok())
+        .unwrap_or(500u64);
+    let mut finished = 0u64;
+    for seed in 0..seeds {
+        let program = Generator::new(seed).program();
+        let outcomes = run_all(&program, LIMITS);
+        if outcomes
+            .iter()
+            .any(|(_, outcome)| *outcome == Outcome::OutOfLimits)
+        {
+            continue;
+        }
+        finished += 1;
+        let (_, first) = &outcomes[0];
+        assert!(
+            outcomes.iter().all(|(_, outcome)| outcome == first),
+            "The backends disagree on seed {}:\n{}\n{:#?}",
+            seed,
+            program,
+            outcomes
+        );
+    }
+    // Most programs should run to the end rather than being cut off.
+    assert!(
+        finished * 10 > seeds * 9,
+        "{} of {} finished",
+        finished,
+        seeds
+    );
+}
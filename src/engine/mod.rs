@@ -0,0 +1,366 @@
+use crate::errors::MonkeyError;
+use crate::eval::builtins::{Arity, BuiltinRegistry};
+use crate::limits::{InterruptHandle, Limits};
+use crate::object::heap::{self, HeapStats};
+use crate::object::{environment::Environment, BuiltinFunction, Object};
+use crate::{ast, compiler, eval, lexer, parser, register, resolve, vm};
+use core::cell::RefCell;
+use std::rc::Rc;
+
+#[cfg(test)]
+mod test;
+mod value;
+
+pub use value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// The tree-walking interpreter in `eval`.
+    Interpreter,
+    /// `compiler` followed by `vm`.
+    Vm,
+    /// `register::Compiler` followed by `register::Vm`.
+    Register,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(Self::Interpreter),
+            "vm" => Ok(Self::Vm),
+            "register" => Ok(Self::Register),
+            _ => Err("Not a valid backend".into()),
+        }
+    }
+}
+
+/**
+ * Runs Monkey source on behalf of a host application. Each engine has one
+ * persistent global environment, so a `let` in one call to `eval_str` is
+ * visible to the next.
+ *
+ * ```
+ * use monkey::engine::{Backend, Engine, Value};
+ *
+ * let mut engine = Engine::new(Backend::Vm);
+ * engine.set_global("limit", Value::Integer(10)).unwrap();
+ * engine.eval_str("let over = fn(x) { x > limit };").unwrap();
+ * let result = engine.call_function("over", &[Value::Integer(12)]).unwrap();
+ * assert_eq!(result, Value::Boolean(true));
+ * ```
+ *
+ * Hosts can expose their own functions to scripts, or take builtins such
+ * as `print` away:
+ *
+ * ```
+ * use monkey::engine::{Backend, Engine, Value};
+ * use monkey::eval::builtins::Arity;
+ *
+ * let mut engine = Engine::new(Backend::Interpreter);
+ * engine.register_fn("double", Arity::Exactly(1), |args| match &args[0] {
+ *     Value::Integer(n) => Ok(Value::Integer(n * 2)),
+ *     other => Err(format!("Can't double a {}", other.type_name())),
+ * });
+ * engine.remove_builtin("print");
+ * assert_eq!(engine.eval_str("double(21)").unwrap(), Some(Value::Integer(42)));
+ * assert!(engine.eval_str("print(1)").is_err());
+ * ```
+ */
+pub struct Engine {
+    backend: Backend,
+    builtins: Rc<BuiltinRegistry>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    // Used by the interpreter.
+    env: Rc<RefCell<Environment>>,
+    // Used by the VMs.
+    compiler: compiler::Compiler,
+    register_compiler: register::Compiler,
+    globals: Vec<Rc<Object>>,
+}
+
+impl Engine {
+    pub fn new(backend: Backend) -> Self {
+        Engine::with_builtins(backend, BuiltinRegistry::with_defaults())
+    }
+
+    /**
+     * Creates an engine whose scripts can only call the given builtins.
+     */
+    pub fn with_builtins(backend: Backend, builtins: BuiltinRegistry) -> Self {
+        let builtins = Rc::new(builtins);
+        Engine {
+            backend,
+            env: Rc::new(RefCell::new(Environment::with_builtins(Rc::clone(
+                &builtins,
+            )))),
+            compiler: compiler::Compiler::with_builtins(&builtins),
+            register_compiler: register::Compiler::with_builtins(&builtins),
+            globals: vec![],
+            builtins,
+            limits: Limits::default(),
+            interrupt: InterruptHandle::new(),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /**
+     * Sets the limits that each later call to `eval_str` or `call_function`
+     * is held to. Exceeding one fails that call, leaving the engine usable.
+     */
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /**
+     * How hard the VM backend's compiler works on each later script. The
+     * results are the same at every level.
+     */
+    pub fn set_optimization_level(&mut self, level: compiler::OptimizationLevel) {
+        self.compiler.set_optimization_level(level);
+    }
+
+    /**
+     * A handle that can stop whatever this engine is running from another
+     * thread. Once used, it has to be reset before the engine can run
+     * anything else.
+     */
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        &self.builtins
+    }
+
+    /**
+     * Makes a host closure callable from scripts as `name`, replacing any
+     * builtin already called that.
+     */
+    pub fn register_fn<F>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        self.update_builtins(|builtins| builtins.register_fn(name, arity, function));
+    }
+
+    pub fn register_builtin<F: BuiltinFunction + 'static>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: F,
+    ) {
+        self.update_builtins(|builtins| builtins.register(name, arity, function));
+    }
+
+    /**
+     * Stops scripts from calling the builtin `name`. Returns whether there
+     * was one.
+     */
+    pub fn remove_builtin(&mut self, name: &str) -> bool {
+        self.update_builtins(|builtins| builtins.remove(name))
+    }
+
+    fn update_builtins<T, F: FnOnce(&mut BuiltinRegistry) -> T>(&mut self, update: F) -> T {
+        let result = update(Rc::make_mut(&mut self.builtins));
+        self.env
+            .borrow_mut()
+            .set_builtins(Rc::clone(&self.builtins));
+        self.compiler.set_builtins(&self.builtins);
+        self.register_compiler.set_builtins(&self.builtins);
+        result
+    }
+
+    /**
+     * Runs the source, returning the value of its last expression statement
+     * (or of a top level `return`), or `None` if it doesn't produce one.
+     */
+    pub fn eval_str(&mut self, source: &str) -> Result<Option<Value>, MonkeyError> {
+        let mut program = parse(source)?;
+        let object = match self.backend {
+            Backend::Interpreter => {
+                // Names that can't be resolved are left to be looked up by
+                // name, and fail if the code using them runs.
+                resolve::resolve(&mut program, &self.builtins);
+                self.eval_context()
+                    .eval_program(&program, Rc::clone(&self.env))
+                    .map_err(MonkeyError::Eval)?
+            }
+            Backend::Vm => {
+                let bytecode = self
+                    .compiler
+                    .compile_program(&program)
+                    .map_err(MonkeyError::Compiler)?;
+                self.run_vm(&bytecode, |vm| vm.run())?
+            }
+            Backend::Register => {
+                let bytecode = self
+                    .register_compiler
+                    .compile_program(&program)
+                    .map_err(MonkeyError::Compiler)?;
+                self.run_register_vm(&bytecode, |vm| vm.run())?
+            }
+        };
+        Ok(object.map(|o| Value::from(&*o)))
+    }
+
+    /**
+     * Calls the global function `name` with the given arguments.
+     */
+    pub fn call_function(&mut self, name: &str, arguments: &[Value]) -> Result<Value, MonkeyError> {
+        let function = self
+            .get_global_object(name)
+            .ok_or_else(|| MonkeyError::Engine(format!("There is no global named '{}'", name)))?;
+        let mut objects = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            objects.push(Rc::new(to_object(argument)?));
+        }
+        let result = match self.backend {
+            Backend::Interpreter => self
+                .eval_context()
+                .apply_function(&function, objects)
+                .map_err(MonkeyError::Eval)?,
+            Backend::Vm => {
+                // Nothing new to run, but the VM needs the constant pool
+                // for any closures the function creates.
+                let bytecode = self
+                    .compiler
+                    .compile_program(&ast::Program { statements: vec![] })
+                    .map_err(MonkeyError::Compiler)?;
+                self.run_vm(&bytecode, |vm| vm.call_value(&function, objects).map(Some))?
+                    .unwrap()
+            }
+            Backend::Register => {
+                let bytecode = self
+                    .register_compiler
+                    .compile_program(&ast::Program { statements: vec![] })
+                    .map_err(MonkeyError::Compiler)?;
+                self.run_register_vm(&bytecode, |vm| vm.call_value(&function, objects).map(Some))?
+                    .unwrap()
+            }
+        };
+        Ok(Value::from(&*result))
+    }
+
+    /**
+     * Binds `name` in the global environment, replacing any existing value.
+     * Functions can't be passed in this way.
+     */
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), MonkeyError> {
+        let object = Rc::new(to_object(&value)?);
+        match self.backend {
+            Backend::Interpreter => self.env.borrow_mut().set(name, object),
+            Backend::Vm | Backend::Register => {
+                let index = match self.backend {
+                    Backend::Vm => self.compiler.define_global(name),
+                    _ => self.register_compiler.define_global(name),
+                };
+                if index >= self.globals.len() {
+                    self.globals
+                        .resize_with(index + 1, || Rc::new(Object::Null));
+                }
+                self.globals[index] = object;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Frees the environments kept alive only by reference cycles between
+     * closures, returning how many there were. This also happens
+     * automatically as the heap grows.
+     */
+    pub fn collect_garbage(&self) -> usize {
+        heap::collect()
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        heap::stats()
+    }
+
+    pub fn get_global(&mut self, name: &str) -> Option<Value> {
+        self.get_global_object(name).map(|o| Value::from(&*o))
+    }
+
+    fn get_global_object(&mut self, name: &str) -> Option<Rc<Object>> {
+        match self.backend {
+            Backend::Interpreter => self.env.borrow().get(name),
+            Backend::Vm => {
+                let index = self.compiler.resolve_global(name)?;
+                self.globals.get(index).map(Rc::clone)
+            }
+            Backend::Register => {
+                let index = self.register_compiler.resolve_global(name)?;
+                self.globals.get(index).map(Rc::clone)
+            }
+        }
+    }
+
+    fn eval_context(&self) -> eval::EvalContext {
+        let mut context = eval::EvalContext::new(self.limits);
+        context.set_interrupt(self.interrupt.clone());
+        context
+    }
+
+    /**
+     * Hands the globals to a VM for the duration of `run`, taking them back
+     * afterwards even if it fails.
+     */
+    fn run_vm<F>(
+        &mut self,
+        bytecode: &compiler::Bytecode,
+        run: F,
+    ) -> Result<Option<Rc<Object>>, MonkeyError>
+    where
+        F: FnOnce(&mut vm::Vm<'_>) -> Result<Option<Rc<Object>>, vm::VmError>,
+    {
+        let globals = std::mem::take(&mut self.globals);
+        let mut vm = vm::Vm::with_state(bytecode, globals, Rc::clone(&self.builtins));
+        vm.set_limits(self.limits);
+        vm.set_interrupt(self.interrupt.clone());
+        let result = run(&mut vm);
+        self.globals = vm.into_globals();
+        result.map_err(MonkeyError::VmError)
+    }
+
+    fn run_register_vm<F>(
+        &mut self,
+        bytecode: &register::Bytecode,
+        run: F,
+    ) -> Result<Option<Rc<Object>>, MonkeyError>
+    where
+        F: FnOnce(&mut register::Vm<'_>) -> Result<Option<Rc<Object>>, vm::VmError>,
+    {
+        let globals = std::mem::take(&mut self.globals);
+        let mut vm = register::Vm::with_state(bytecode, globals, Rc::clone(&self.builtins));
+        vm.set_limits(self.limits);
+        vm.set_interrupt(self.interrupt.clone());
+        let result = run(&mut vm);
+        self.globals = vm.into_globals();
+        result.map_err(MonkeyError::VmError)
+    }
+}
+
+fn parse(source: &str) -> Result<ast::Program, MonkeyError> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    parser.parse_program().map_err(MonkeyError::Parser)
+}
+
+fn to_object(value: &Value) -> Result<Object, MonkeyError> {
+    value.to_object().ok_or_else(|| {
+        MonkeyError::Engine(format!(
+            "A {} can't be passed into the engine",
+            value.type_name()
+        ))
+    })
+}
@@ -0,0 +1,433 @@
+use crate::compiler::OptimizationLevel;
+use crate::engine::{Backend, Engine, Value};
+use crate::errors::MonkeyError;
+use crate::eval::builtins::{Arity, BuiltinRegistry};
+use crate::eval::EvalError;
+use crate::limits::{LimitExceeded, Limits, DEFAULT_MAX_DEPTH};
+use crate::vm::VmError;
+use std::time::Duration;
+
+const BACKENDS: [Backend; 3] = [Backend::Interpreter, Backend::Vm, Backend::Register];
+
+#[test]
+fn test_persistent_environment() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        assert_eq!(engine.eval_str("let a = 5;").unwrap(), None);
+        assert_eq!(
+            engine.eval_str("let double = fn(x) { x * 2 };").unwrap(),
+            None
+        );
+        assert_eq!(
+            engine.eval_str("double(a)").unwrap(),
+            Some(Value::Integer(10))
+        );
+        assert_eq!(
+            engine.eval_str("[a, \"b\", true]").unwrap(),
+            Some(Value::Array(vec![
+                Value::Integer(5),
+                Value::from("b"),
+                Value::Boolean(true)
+            ]))
+        );
+    }
+}
+
+#[test]
+fn test_failed_snippet_keeps_environment() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine.eval_str("let a = 1;").unwrap();
+        assert!(engine.eval_str("let b = 2; let c = d;").is_err());
+        assert!(engine.eval_str("a + ").is_err());
+        assert_eq!(engine.eval_str("a").unwrap(), Some(Value::Integer(1)));
+    }
+}
+
+#[test]
+fn test_optimization_level() {
+    let mut engine = Engine::new(Backend::Vm);
+    engine.set_optimization_level(OptimizationLevel::Full);
+    assert_eq!(engine.eval_str("5; let a = 2 * 3;").unwrap(), None);
+    // A failed snippet's constants aren't shared with later ones.
+    assert!(engine.eval_str("let b = 7; c").is_err());
+    assert_eq!(
+        engine
+            .eval_str("let f = fn() { 7 * 1 }; [a * 7, f(), \"x\" + \"y\"]")
+            .unwrap(),
+        Some(Value::Array(vec![
+            Value::Integer(42),
+            Value::Integer(7),
+            Value::from("xy")
+        ]))
+    );
+}
+
+#[test]
+fn test_globals() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine.set_global("name", Value::from("monkey")).unwrap();
+        engine
+            .set_global(
+                "limits",
+                Value::from(vec![Value::Integer(1), Value::Integer(2)]),
+            )
+            .unwrap();
+        assert_eq!(
+            engine.eval_str("upper(name)").unwrap(),
+            Some(Value::from("MONKEY"))
+        );
+        assert_eq!(
+            engine.eval_str("limits[1]").unwrap(),
+            Some(Value::Integer(2))
+        );
+        engine.eval_str("let greeting = \"hi \" + name;").unwrap();
+        assert_eq!(
+            engine.get_global("greeting"),
+            Some(Value::from("hi monkey"))
+        );
+        assert_eq!(engine.get_global("missing"), None);
+        engine.set_global("name", Value::from("ape")).unwrap();
+        assert_eq!(engine.eval_str("name").unwrap(), Some(Value::from("ape")));
+        assert!(engine.set_global("f", Value::Function).is_err());
+    }
+}
+
+#[test]
+fn test_call_function() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine
+            .eval_str(
+                "
+                let offset = 10;
+                let adder = fn(x) { fn(y) { x + y + offset } };
+                let add = fn(a, b) { adder(a)(b) };
+                ",
+            )
+            .unwrap();
+        assert_eq!(
+            engine
+                .call_function("add", &[Value::Integer(1), Value::Integer(2)])
+                .unwrap(),
+            Value::Integer(13)
+        );
+        assert_eq!(
+            engine.call_function("adder", &[Value::Integer(1)]).unwrap(),
+            Value::Function
+        );
+        assert_eq!(
+            engine
+                .call_function("missing", &[])
+                .unwrap_err()
+                .to_string(),
+            "Engine error: There is no global named 'missing'"
+        );
+        assert!(engine.call_function("add", &[Value::Integer(1)]).is_err());
+        // The engine is still usable after a failed call.
+        assert_eq!(engine.eval_str("offset").unwrap(), Some(Value::Integer(10)));
+    }
+}
+
+#[test]
+fn test_register_fn() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine.register_fn("sum", Arity::AtLeast(1), |args| {
+            let mut total = 0;
+            for arg in args {
+                match arg {
+                    Value::Integer(n) => total += n,
+                    other => return Err(format!("Can't sum a {}", other.type_name())),
+                }
+            }
+            Ok(Value::Integer(total))
+        });
+        assert_eq!(
+            engine.eval_str("sum(1, 2, 3)").unwrap(),
+            Some(Value::Integer(6))
+        );
+        assert_eq!(
+            engine
+                .eval_str("map([[1], [2, 3]], fn(xs) { reduce(xs, 0, sum) })")
+                .unwrap(),
+            Some(Value::from(vec![Value::Integer(1), Value::Integer(5)]))
+        );
+        // Host functions are found before locals bound later.
+        assert_eq!(
+            engine
+                .eval_str("fn() { let g = fn() { sum(1) }; let r = g(); let sum = 5; r }()")
+                .unwrap(),
+            Some(Value::Integer(1))
+        );
+        let error = engine.eval_str("sum()").unwrap_err().to_string();
+        assert!(
+            error.ends_with("sum takes at least 1 argument"),
+            "{}",
+            error
+        );
+        let error = engine.eval_str("sum(1, true)").unwrap_err().to_string();
+        assert!(error.ends_with("Can't sum a Boolean"), "{}", error);
+    }
+}
+
+#[test]
+fn test_shadow_and_remove_builtins() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine.eval_str("let shout = fn(s) { upper(s) };").unwrap();
+        engine.register_fn("upper", Arity::Exactly(1), |_| Ok(Value::from("quiet")));
+        assert_eq!(
+            engine.eval_str("upper(\"a\")").unwrap(),
+            Some(Value::from("quiet"))
+        );
+        assert!(engine.remove_builtin("print"));
+        assert!(!engine.remove_builtin("print"));
+        assert!(engine.builtins().get("print").is_none());
+        assert!(engine.eval_str("print(1)").is_err());
+        // Other builtins keep working once one is removed.
+        assert_eq!(
+            engine.eval_str("len(\"ab\")").unwrap(),
+            Some(Value::Integer(2))
+        );
+    }
+}
+
+#[test]
+fn test_empty_registry() {
+    for backend in BACKENDS.iter() {
+        let mut registry = BuiltinRegistry::new();
+        registry.register_fn("answer", Arity::Exactly(0), |_| Ok(Value::Integer(42)));
+        let mut engine = Engine::with_builtins(*backend, registry);
+        assert_eq!(
+            engine.eval_str("answer()").unwrap(),
+            Some(Value::Integer(42))
+        );
+        assert!(engine.eval_str("len(\"a\")").is_err());
+        let error = engine.eval_str("answer(1)").unwrap_err().to_string();
+        assert!(
+            error.ends_with("answer takes exactly 0 arguments"),
+            "{}",
+            error
+        );
+    }
+}
+
+#[test]
+fn test_collect_garbage() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        // Start from a clean heap, which is shared by the thread.
+        engine.collect_garbage();
+        engine
+            .eval_str("let make = fn() { let f = fn() { f }; 1 }; make(); make();")
+            .unwrap();
+        let collections = engine.heap_stats().collections;
+        let reclaimed = engine.eval_str("gc()").unwrap();
+        let expected = match backend {
+            // The VMs copy captured values into closures, so can't make
+            // cycles.
+            Backend::Vm | Backend::Register => 0,
+            Backend::Interpreter => 2,
+        };
+        assert_eq!(reclaimed, Some(Value::Integer(expected)));
+        assert_eq!(engine.collect_garbage(), 0);
+        assert_eq!(engine.heap_stats().collections, collections + 2);
+
+        // But a closure over a local bound after it is, with the local kept
+        // in a cell.
+        engine
+            .eval_str("let cycle = fn() { let f = fn() { g }; let g = f; 1 }; cycle(); cycle();")
+            .unwrap();
+        assert_eq!(engine.collect_garbage(), 2);
+    }
+}
+
+fn exceeded_limit(result: Result<Option<Value>, MonkeyError>) -> LimitExceeded {
+    match result {
+        Err(MonkeyError::Eval(EvalError::LimitExceeded(limit)))
+        | Err(MonkeyError::VmError(VmError::LimitExceeded(limit))) => limit,
+        other => panic!("Expected a limit to be exceeded, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_limits() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine
+            .eval_str(
+                "
+                let forever = fn(n) { 1 + forever(n + 1) };
+                let nest = fn(n) { if (n == 0) { [] } else { [n, nest(n - 1)] } };
+                ",
+            )
+            .unwrap();
+
+        engine.set_limits(Limits {
+            max_depth: Some(50),
+            ..Limits::default()
+        });
+        assert_eq!(
+            exceeded_limit(engine.eval_str("forever(0)")),
+            LimitExceeded::Depth(50)
+        );
+        assert_eq!(
+            engine.eval_str("nest(49)[0]").unwrap(),
+            Some(Value::Integer(49))
+        );
+        let error = engine.call_function("forever", &[Value::Integer(0)]);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .ends_with("Limit exceeded: Function calls nested more than 50 deep"));
+
+        // Calls in tail position don't nest.
+        engine
+            .eval_str("let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } };")
+            .unwrap();
+        assert_eq!(
+            engine.eval_str("count(100)").unwrap(),
+            Some(Value::Integer(0))
+        );
+
+        engine.set_limits(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(
+            exceeded_limit(engine.eval_str("forever(0)")),
+            LimitExceeded::Fuel(1000)
+        );
+        // Still told apart from other errors after passing through a builtin.
+        assert_eq!(
+            exceeded_limit(engine.eval_str("map([1], fn(x) { forever(x) })")),
+            LimitExceeded::Fuel(1000)
+        );
+        // Each run gets a full tank.
+        assert_eq!(engine.eval_str("1 + 1").unwrap(), Some(Value::Integer(2)));
+
+        engine.set_limits(Limits {
+            max_allocations: Some(100),
+            ..Limits::default()
+        });
+        assert_eq!(
+            exceeded_limit(engine.eval_str("nest(100)")),
+            LimitExceeded::Allocations(100)
+        );
+
+        engine.set_limits(Limits {
+            max_string_length: Some(10),
+            ..Limits::default()
+        });
+        assert_eq!(
+            exceeded_limit(engine.eval_str("repeat(\"ab\", 1000000000000)")),
+            LimitExceeded::StringLength(10)
+        );
+        assert_eq!(
+            exceeded_limit(engine.eval_str("let s = \"abcd\"; s + s + s")),
+            LimitExceeded::StringLength(10)
+        );
+        for code in &[
+            "replace(\"aaaa\", \"a\", \"xyz\")",
+            "join([\"abcd\", \"abcd\", \"abcd\"], \"\")",
+            "format(\"{}{}{}\", \"abcd\", \"abcd\", \"abcd\")",
+        ] {
+            assert_eq!(
+                exceeded_limit(engine.eval_str(code)),
+                LimitExceeded::StringLength(10),
+                "{}",
+                code
+            );
+        }
+        assert_eq!(
+            engine.eval_str("repeat(\"ab\", 5)").unwrap(),
+            Some(Value::from("ababababab"))
+        );
+
+        engine.set_limits(Limits::default());
+        assert_eq!(
+            engine.eval_str("nest(100)[0]").unwrap(),
+            Some(Value::Integer(100))
+        );
+    }
+}
+
+#[test]
+fn test_default_depth() {
+    // A debug build of the interpreter needs more stack than a test thread
+    // has to get that deep.
+    let runner = std::thread::Builder::new().stack_size(64 * 1024 * 1024);
+    runner
+        .spawn(|| {
+            for backend in BACKENDS.iter() {
+                let mut engine = Engine::new(*backend);
+                assert_eq!(
+                    exceeded_limit(engine.eval_str("let f = fn(n) { 1 + f(n + 1) }; f(0)")),
+                    LimitExceeded::Depth(DEFAULT_MAX_DEPTH)
+                );
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+// Far too long to ever finish, without recursing deeply.
+const SPIN: &str = "
+    let inner = fn(x) { x };
+    let outer = fn(xs) { each(xs, fn(x) { each(xs, inner) }) };
+    outer(chars(repeat(\"a\", 100000)))
+";
+
+fn interrupted_trace(result: Result<Option<Value>, MonkeyError>) -> Vec<String> {
+    match result {
+        Err(MonkeyError::Eval(EvalError::Interrupted { stack_trace }))
+        | Err(MonkeyError::VmError(VmError::Interrupted { stack_trace })) => stack_trace,
+        other => panic!("Expected the run to be interrupted, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_interrupt() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        let handle = engine.interrupt_handle();
+        let interrupter = std::thread::spawn({
+            let handle = handle.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                handle.interrupt();
+            }
+        });
+        let stack_trace = interrupted_trace(engine.eval_str(SPIN));
+        interrupter.join().unwrap();
+        assert_eq!(stack_trace.last().map(String::as_str), Some("outer"));
+        assert!(stack_trace.len() >= 2, "{:?}", stack_trace);
+
+        // Still interrupted until reset.
+        assert!(engine.eval_str("1 + 1").is_err());
+        handle.reset();
+        assert_eq!(engine.eval_str("1 + 1").unwrap(), Some(Value::Integer(2)));
+    }
+}
+
+#[test]
+fn test_timeout() {
+    for backend in BACKENDS.iter() {
+        let mut engine = Engine::new(*backend);
+        engine.set_limits(Limits {
+            timeout: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        });
+        let error = engine.eval_str(SPIN).unwrap_err();
+        assert!(
+            error.to_string().contains("Interrupted\n    in "),
+            "{}",
+            error
+        );
+        // Each run gets the full time.
+        assert_eq!(engine.eval_str("1 + 1").unwrap(), Some(Value::Integer(2)));
+    }
+}
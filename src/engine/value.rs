@@ -0,0 +1,132 @@
+use crate::object::Object;
+use std::fmt;
+use std::rc::Rc;
+
+/**
+ * An owned copy of a Monkey value, for handing results to (and taking
+ * arguments from) a host application. Unlike `object::Object`, it is plain
+ * data that shares nothing with the engine that produced it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Value>),
+    Null,
+    /// Functions can't be copied out of the engine, but can still be called
+    /// by name with `Engine::call_function`.
+    Function,
+}
+
+impl Value {
+    pub fn type_name(&self) -> String {
+        let string = match self {
+            Value::Integer(_) => "Integer",
+            Value::Boolean(_) => "Boolean",
+            Value::String(_) => "String",
+            Value::Array(_) => "Array",
+            Value::Null => "Null",
+            Value::Function => "Function",
+        };
+        String::from(string)
+    }
+
+    /**
+     * Converts back into an object that scripts can use. Returns `None` for
+     * functions, which only exist inside an engine.
+     */
+    pub fn to_object(&self) -> Option<Object> {
+        let object = match self {
+            Value::Integer(value) => Object::Integer(*value),
+            Value::Boolean(value) => Object::Boolean(*value),
+            Value::String(value) => Object::String(value.clone()),
+            Value::Array(elements) => {
+                let mut objects = Vec::with_capacity(elements.len());
+                for element in elements {
+                    objects.push(Rc::new(element.to_object()?));
+                }
+                Object::Array(objects)
+            }
+            Value::Null => Object::Null,
+            Value::Function => return None,
+        };
+        Some(object)
+    }
+}
+
+impl From<&Object> for Value {
+    fn from(object: &Object) -> Self {
+        match object {
+            Object::Integer(value) => Value::Integer(*value),
+            Object::Boolean(value) => Value::Boolean(*value),
+            Object::String(value) => Value::String(value.clone()),
+            Object::Array(elements) => {
+                Value::Array(elements.iter().map(|e| Value::from(&**e)).collect())
+            }
+            Object::Null => Value::Null,
+            Object::ReturnValue(value) => Value::from(&**value),
+            // Cells stay inside the VMs, but read through one all the same.
+            Object::Cell(value) => value
+                .borrow()
+                .as_ref()
+                .map_or(Value::Null, |value| Value::from(&**value)),
+            Object::Function { .. }
+            | Object::BuiltinFunction(_)
+            | Object::CompiledFunction(_)
+            | Object::Closure { .. } => Value::Function,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(String::from(value))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Self {
+        Value::Array(elements)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let repr: String = match self {
+            Value::Integer(value) => value.to_string(),
+            Value::Boolean(value) => value.to_string(),
+            Value::String(value) => value.clone(),
+            Value::Array(elements) => format!(
+                "[{}]",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Value::Null => String::from("null"),
+            Value::Function => String::from("Function"),
+        };
+        write!(f, "{}", repr)?;
+        Ok(())
+    }
+}
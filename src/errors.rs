@@ -1,3 +1,4 @@
+use crate::compiler::CompilerError;
 use crate::eval::EvalError;
 use crate::parser::ParserError;
 use crate::vm::VmError;
@@ -7,6 +8,8 @@ use core::fmt::Display;
 pub enum MonkeyError {
     Parser(ParserError),
     Eval(EvalError),
+    Compiler(CompilerError),
+    Engine(String),
     VmError(VmError),
 }
 
@@ -24,23 +27,96 @@ impl Display for MonkeyError {
                         expected,
                         actual.token_type()
                     ),
+                    ParserError::IntegerTooLarge { literal } => {
+                        format!("The integer {} is too large", literal)
+                    }
+                    ParserError::NestedTooDeeply { token } => format!(
+                        "Expressions are nested too deeply at token type {}",
+                        token.token_type()
+                    ),
+                    ParserError::UnknownType { name } => format!("There is no type {}", name),
                 };
                 write!(f, "Parser error: {}", message)?;
                 Ok(())
             }
             MonkeyError::Eval(eval_err) => {
-                let EvalError::Misc(message) = eval_err;
-                write!(f, "Eval error: {}", message)?;
+                match eval_err {
+                    EvalError::Misc(message) => write!(f, "Eval error: {}", message)?,
+                    EvalError::LimitExceeded(limit) => {
+                        write!(f, "Eval error: Limit exceeded: {}", limit)?
+                    }
+                    EvalError::Interrupted { stack_trace } => {
+                        write!(f, "Eval error: Interrupted")?;
+                        write_stack_trace(f, stack_trace)?;
+                    }
+                }
                 Ok(())
             }
-            MonkeyError::VmError(err) => {
-                let message = match err {
-                    VmError::PopEmptyStack => "Cannot pop from an empty stack",
-                    VmError::Misc(msg) => msg,
-                };
-                write!(f, "VM Error: {}", message)?;
+            MonkeyError::Compiler(err) => {
+                write!(f, "Compiler error: {}", err.message())?;
                 Ok(())
             }
+            MonkeyError::Engine(message) => {
+                write!(f, "Engine error: {}", message)?;
+                Ok(())
+            }
+            MonkeyError::VmError(err) => write_vm_error(f, err),
+        }
+    }
+}
+
+fn write_vm_error(
+    f: &mut std::fmt::Formatter<'_>,
+    err: &VmError,
+) -> std::result::Result<(), std::fmt::Error> {
+    match err {
+        VmError::Interrupted { stack_trace } => {
+            write!(f, "VM Error: Interrupted")?;
+            write_stack_trace(f, stack_trace)
         }
+        VmError::Located { error, stack_trace } => {
+            write!(f, "VM Error")?;
+            if let Some(position) = stack_trace.first().and_then(|frame| frame.position) {
+                write!(f, " at {}", position)?;
+            }
+            write!(f, ": {}", vm_error_message(error))?;
+            // A trace of just the main program says nothing more.
+            if stack_trace.len() > 1 {
+                write_stack_trace(f, stack_trace)?;
+            }
+            Ok(())
+        }
+        _ => write!(f, "VM Error: {}", vm_error_message(err)),
+    }
+}
+
+fn vm_error_message(err: &VmError) -> String {
+    match err {
+        VmError::PopEmptyStack => String::from("Cannot pop from an empty stack"),
+        VmError::Misc(msg) => msg.clone(),
+        VmError::LimitExceeded(limit) => format!("Limit exceeded: {}", limit),
+        VmError::Interrupted { .. } => String::from("Interrupted"),
+        VmError::Located { error, .. } => vm_error_message(error),
+    }
+}
+
+// Deep recursion can leave a very long trace, so only the innermost frames
+// are shown.
+const MAX_TRACE_LINES: usize = 10;
+
+fn write_stack_trace<T: Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    stack_trace: &[T],
+) -> std::result::Result<(), std::fmt::Error> {
+    for frame in stack_trace.iter().take(MAX_TRACE_LINES) {
+        write!(f, "\n    in {}", frame)?;
+    }
+    if stack_trace.len() > MAX_TRACE_LINES {
+        write!(
+            f,
+            "\n    ... and {} more",
+            stack_trace.len() - MAX_TRACE_LINES
+        )?;
     }
+    Ok(())
 }
@@ -0,0 +1,246 @@
+use super::{array_arg, check_arg_count, function_arg};
+use crate::object::{BuiltinFunction, CallContext, Object};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+// These take the array first and the function last, so that
+// `map(items, fn(x) { ... })` reads in the order it runs.
+
+fn array(elements: Vec<Rc<Object>>) -> Result<Rc<Object>, String> {
+    Ok(Rc::new(Object::Array(elements)))
+}
+
+fn predicate_result(name: &str, result: &Object) -> Result<bool, String> {
+    match result {
+        Object::Boolean(value) => Ok(*value),
+        other => Err(format!(
+            "The function passed to {} must return a Boolean, got {}",
+            name,
+            other.type_name()
+        )),
+    }
+}
+
+#[derive(Debug)]
+struct Map;
+impl BuiltinFunction for Map {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("map", arguments, 2)?;
+        let elements = array_arg("map", arguments, 0)?;
+        let function = function_arg("map", arguments, 1)?;
+        let mut results = Vec::with_capacity(elements.len());
+        for element in elements {
+            results.push(context.call(function, vec![Rc::clone(element)])?);
+        }
+        array(results)
+    }
+}
+
+#[derive(Debug)]
+struct Filter;
+impl BuiltinFunction for Filter {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("filter", arguments, 2)?;
+        let elements = array_arg("filter", arguments, 0)?;
+        let function = function_arg("filter", arguments, 1)?;
+        let mut results = vec![];
+        for element in elements {
+            let keep = context.call(function, vec![Rc::clone(element)])?;
+            if predicate_result("filter", &keep)? {
+                results.push(Rc::clone(element));
+            }
+        }
+        array(results)
+    }
+}
+
+/**
+ * reduce(array, initial, fn(accumulator, element) { ... })
+ */
+#[derive(Debug)]
+struct Reduce;
+impl BuiltinFunction for Reduce {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("reduce", arguments, 3)?;
+        let elements = array_arg("reduce", arguments, 0)?;
+        let function = function_arg("reduce", arguments, 2)?;
+        let mut accumulator = Rc::clone(&arguments[1]);
+        for element in elements {
+            accumulator = context.call(function, vec![accumulator, Rc::clone(element)])?;
+        }
+        Ok(accumulator)
+    }
+}
+
+#[derive(Debug)]
+struct Each;
+impl BuiltinFunction for Each {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("each", arguments, 2)?;
+        let elements = array_arg("each", arguments, 0)?;
+        let function = function_arg("each", arguments, 1)?;
+        for element in elements {
+            context.call(function, vec![Rc::clone(element)])?;
+        }
+        Ok(Rc::new(Object::Null))
+    }
+}
+
+/**
+ * sort_by(array, fn(element) { key }). The keys must be all Integers or all
+ * Strings, and elements with equal keys keep their order.
+ */
+#[derive(Debug)]
+struct SortBy;
+impl BuiltinFunction for SortBy {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("sort_by", arguments, 2)?;
+        let elements = array_arg("sort_by", arguments, 0)?;
+        let function = function_arg("sort_by", arguments, 1)?;
+        let mut keyed = Vec::with_capacity(elements.len());
+        for element in elements {
+            let key = context.call(function, vec![Rc::clone(element)])?;
+            keyed.push((key, Rc::clone(element)));
+        }
+        let comparable = keyed
+            .iter()
+            .all(|(key, _)| matches!(**key, Object::Integer(_)))
+            || keyed
+                .iter()
+                .all(|(key, _)| matches!(**key, Object::String(_)));
+        if !comparable {
+            return Err(String::from(
+                "The keys returned to sort_by must be all Integers or all Strings",
+            ));
+        }
+        keyed.sort_by(|(left, _), (right, _)| match (&**left, &**right) {
+            (Object::Integer(l), Object::Integer(r)) => l.cmp(r),
+            (Object::String(l), Object::String(r)) => l.cmp(r),
+            _ => Ordering::Equal,
+        });
+        array(keyed.into_iter().map(|(_, element)| element).collect())
+    }
+}
+
+#[derive(Debug)]
+struct Any;
+impl BuiltinFunction for Any {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("any", arguments, 2)?;
+        let elements = array_arg("any", arguments, 0)?;
+        let function = function_arg("any", arguments, 1)?;
+        for element in elements {
+            let result = context.call(function, vec![Rc::clone(element)])?;
+            if predicate_result("any", &result)? {
+                return Ok(Rc::new(Object::Boolean(true)));
+            }
+        }
+        Ok(Rc::new(Object::Boolean(false)))
+    }
+}
+
+#[derive(Debug)]
+struct All;
+impl BuiltinFunction for All {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("all", arguments, 2)?;
+        let elements = array_arg("all", arguments, 0)?;
+        let function = function_arg("all", arguments, 1)?;
+        for element in elements {
+            let result = context.call(function, vec![Rc::clone(element)])?;
+            if !predicate_result("all", &result)? {
+                return Ok(Rc::new(Object::Boolean(false)));
+            }
+        }
+        Ok(Rc::new(Object::Boolean(true)))
+    }
+}
+
+/**
+ * Pairs up the elements of two arrays, stopping at the end of the shorter.
+ */
+#[derive(Debug)]
+struct Zip;
+impl BuiltinFunction for Zip {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("zip", arguments, 2)?;
+        let left = array_arg("zip", arguments, 0)?;
+        let right = array_arg("zip", arguments, 1)?;
+        array(
+            left.iter()
+                .zip(right)
+                .map(|(l, r)| Rc::new(Object::Array(vec![Rc::clone(l), Rc::clone(r)])))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Enumerate;
+impl BuiltinFunction for Enumerate {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("enumerate", arguments, 1)?;
+        let elements = array_arg("enumerate", arguments, 0)?;
+        array(
+            elements
+                .iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    let index = Rc::new(Object::Integer(i as i64));
+                    Rc::new(Object::Array(vec![index, Rc::clone(element)]))
+                })
+                .collect(),
+        )
+    }
+}
+
+pub fn get_collection_fn(name: &str) -> Option<Box<dyn BuiltinFunction>> {
+    match name {
+        "map" => Some(Box::new(Map)),
+        "filter" => Some(Box::new(Filter)),
+        "reduce" => Some(Box::new(Reduce)),
+        "each" => Some(Box::new(Each)),
+        "sort_by" => Some(Box::new(SortBy)),
+        "any" => Some(Box::new(Any)),
+        "all" => Some(Box::new(All)),
+        "zip" => Some(Box::new(Zip)),
+        "enumerate" => Some(Box::new(Enumerate)),
+        _ => None,
+    }
+}
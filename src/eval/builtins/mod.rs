@@ -0,0 +1,198 @@
+use crate::object::{heap, BuiltinFunction, CallContext, Object};
+use std::convert::TryInto;
+use std::rc::Rc;
+
+mod collections;
+mod registry;
+mod strings;
+
+pub use registry::{Arity, BuiltinRegistry, RegisteredBuiltin};
+
+/**
+ * The builtins every registry starts with, in a fixed order so that
+ * bytecode compiled against the defaults refers to the same indexes, with
+ * the type of each as it would be annotated. Any arguments past those in
+ * the type of a builtin taking at least some number aren't checked.
+ */
+const DEFAULT_BUILTINS: &[(&str, Arity, &str)] = &[
+    ("len", Arity::Exactly(1), "fn(any) -> int"),
+    ("print", Arity::Exactly(1), "fn(any) -> null"),
+    ("split", Arity::Exactly(2), "fn(string, string) -> [string]"),
+    ("join", Arity::Exactly(2), "fn([string], string) -> string"),
+    ("trim", Arity::Exactly(1), "fn(string) -> string"),
+    ("upper", Arity::Exactly(1), "fn(string) -> string"),
+    ("lower", Arity::Exactly(1), "fn(string) -> string"),
+    (
+        "replace",
+        Arity::Exactly(3),
+        "fn(string, string, string) -> string",
+    ),
+    ("contains", Arity::Exactly(2), "fn(string, string) -> bool"),
+    (
+        "starts_with",
+        Arity::Exactly(2),
+        "fn(string, string) -> bool",
+    ),
+    ("ends_with", Arity::Exactly(2), "fn(string, string) -> bool"),
+    ("index_of", Arity::Exactly(2), "fn(string, string) -> int"),
+    (
+        "substr",
+        Arity::Exactly(3),
+        "fn(string, int, int) -> string",
+    ),
+    ("chars", Arity::Exactly(1), "fn(string) -> [string]"),
+    ("repeat", Arity::Exactly(2), "fn(string, int) -> string"),
+    ("format", Arity::AtLeast(1), "fn(string) -> string"),
+    ("map", Arity::Exactly(2), "fn([A], fn(A) -> B) -> [B]"),
+    ("filter", Arity::Exactly(2), "fn([A], fn(A) -> bool) -> [A]"),
+    (
+        "reduce",
+        Arity::Exactly(3),
+        "fn([A], B, fn(B, A) -> B) -> B",
+    ),
+    ("each", Arity::Exactly(2), "fn([A], fn(A) -> any) -> null"),
+    ("sort_by", Arity::Exactly(2), "fn([A], fn(A) -> any) -> [A]"),
+    ("any", Arity::Exactly(2), "fn([A], fn(A) -> bool) -> bool"),
+    ("all", Arity::Exactly(2), "fn([A], fn(A) -> bool) -> bool"),
+    ("zip", Arity::Exactly(2), "fn([A], [B]) -> [[any]]"),
+    ("enumerate", Arity::Exactly(1), "fn([A]) -> [[any]]"),
+    ("gc", Arity::Exactly(0), "fn() -> int"),
+];
+
+#[derive(Debug)]
+struct Len;
+impl BuiltinFunction for Len {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("len", arguments, 1)?;
+        let arg: &Rc<Object> = arguments.first().unwrap();
+        match arg.as_ref() {
+            Object::String(string) => Ok(Rc::new(Object::Integer(
+                string.chars().count().try_into().unwrap(),
+            ))),
+            Object::Array(elements) => {
+                Ok(Rc::new(Object::Integer(elements.len().try_into().unwrap())))
+            }
+            _ => Err(String::from("Only strings and arrays can be passed to len")),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Print;
+impl BuiltinFunction for Print {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("print", arguments, 1)?;
+        let arg0 = arguments.first().unwrap();
+        println!("{}", arg0);
+        Ok(Rc::new(Object::Null))
+    }
+}
+
+/**
+ * Runs the cycle collector, returning the number of environments it freed.
+ */
+#[derive(Debug)]
+struct Gc;
+impl BuiltinFunction for Gc {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("gc", arguments, 0)?;
+        let reclaimed = heap::collect();
+        Ok(Rc::new(Object::Integer(reclaimed.try_into().unwrap())))
+    }
+}
+
+/**
+ * The type of a default builtin, such as `fn(string) -> int`.
+ */
+pub fn signature(name: &str) -> Option<&'static str> {
+    DEFAULT_BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|(_, _, signature)| *signature)
+}
+
+fn get_builtin_fn(name: &str) -> Option<Box<dyn BuiltinFunction>> {
+    match name {
+        "len" => Some(Box::new(Len)),
+        "print" => Some(Box::new(Print)),
+        "gc" => Some(Box::new(Gc)),
+        _ => strings::get_string_fn(name).or_else(|| collections::get_collection_fn(name)),
+    }
+}
+
+fn check_arg_count(name: &str, arguments: &[Rc<Object>], expected: usize) -> Result<(), String> {
+    if arguments.len() == expected {
+        Ok(())
+    } else {
+        let plural = if expected == 1 { "" } else { "s" };
+        Err(format!(
+            "{} takes exactly {} argument{}",
+            name, expected, plural
+        ))
+    }
+}
+
+fn type_error(name: &str, position: usize, expected: &str, actual: &Object) -> String {
+    format!(
+        "Argument {} to {} must be of type {}, got {}",
+        position + 1,
+        name,
+        expected,
+        actual.type_name()
+    )
+}
+
+fn string_arg<'b>(
+    name: &str,
+    arguments: &'b [Rc<Object>],
+    position: usize,
+) -> Result<&'b str, String> {
+    match arguments[position].as_ref() {
+        Object::String(string) => Ok(string),
+        other => Err(type_error(name, position, "String", other)),
+    }
+}
+
+fn integer_arg(name: &str, arguments: &[Rc<Object>], position: usize) -> Result<i64, String> {
+    match arguments[position].as_ref() {
+        Object::Integer(value) => Ok(*value),
+        other => Err(type_error(name, position, "Integer", other)),
+    }
+}
+
+fn array_arg<'b>(
+    name: &str,
+    arguments: &'b [Rc<Object>],
+    position: usize,
+) -> Result<&'b [Rc<Object>], String> {
+    match arguments[position].as_ref() {
+        Object::Array(elements) => Ok(elements),
+        other => Err(type_error(name, position, "Array", other)),
+    }
+}
+
+fn function_arg<'b>(
+    name: &str,
+    arguments: &'b [Rc<Object>],
+    position: usize,
+) -> Result<&'b Rc<Object>, String> {
+    let argument = &arguments[position];
+    match argument.as_ref() {
+        Object::Function { .. } | Object::Closure { .. } | Object::BuiltinFunction(_) => {
+            Ok(argument)
+        }
+        other => Err(type_error(name, position, "Function", other)),
+    }
+}
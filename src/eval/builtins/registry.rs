@@ -0,0 +1,200 @@
+use super::{get_builtin_fn, DEFAULT_BUILTINS};
+use crate::engine::Value;
+use crate::object::{BuiltinFunction, CallContext, Object};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, num_args: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => num_args == *n,
+            Arity::AtLeast(n) => num_args >= *n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (qualifier, n) = match self {
+            Arity::Exactly(n) => ("exactly", n),
+            Arity::AtLeast(n) => ("at least", n),
+        };
+        let plural = if *n == 1 { "" } else { "s" };
+        write!(f, "{} {} argument{}", qualifier, n, plural)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegisteredBuiltin {
+    pub name: String,
+    pub arity: Arity,
+    pub function: Rc<dyn BuiltinFunction>,
+}
+
+/**
+ * The functions that scripts can call without defining them. Both backends
+ * look builtins up here: the interpreter by name and the compiler/VM by
+ * index, so an entry keeps its index when it is replaced, and removing one
+ * leaves a gap rather than shifting the rest.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct BuiltinRegistry {
+    entries: Vec<Option<RegisteredBuiltin>>,
+    indexes: HashMap<String, usize>,
+}
+
+impl BuiltinRegistry {
+    /**
+     * An empty registry, for sandboxes that should only see what the host
+     * chooses to register.
+     */
+    pub fn new() -> Self {
+        BuiltinRegistry::default()
+    }
+
+    pub fn with_defaults() -> Self {
+        let mut registry = BuiltinRegistry::new();
+        for (name, arity, _) in DEFAULT_BUILTINS {
+            registry.insert(name, *arity, Rc::from(get_builtin_fn(name).unwrap()));
+        }
+        registry
+    }
+
+    /**
+     * Adds a builtin, replacing any existing one with the same name.
+     * Calls with the wrong number of arguments are rejected before
+     * reaching `function`.
+     */
+    pub fn register<F: BuiltinFunction + 'static>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: F,
+    ) {
+        self.insert(name, arity, Rc::new(function));
+    }
+
+    /**
+     * Adds a builtin implemented by a host closure working on owned values.
+     */
+    pub fn register_fn<F>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let host_function = HostFunction {
+            name: String::from(name),
+            function: Box::new(function),
+        };
+        self.insert(name, arity, Rc::new(host_function));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.indexes.remove(name) {
+            Some(index) => {
+                self.entries[index] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredBuiltin> {
+        self.index_of(name)
+            .and_then(|index| self.get_by_index(index))
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.indexes.get(name).copied()
+    }
+
+    pub fn get_by_index(&self, index: usize) -> Option<&RegisteredBuiltin> {
+        self.entries.get(index).and_then(|entry| entry.as_ref())
+    }
+
+    /**
+     * The builtins that are currently registered, with their indexes.
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &RegisteredBuiltin)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| entry.as_ref().map(|entry| (index, entry)))
+    }
+
+    fn insert(&mut self, name: &str, arity: Arity, function: Rc<dyn BuiltinFunction>) {
+        let entry = RegisteredBuiltin {
+            name: String::from(name),
+            arity,
+            function: Rc::new(ArityChecked {
+                name: String::from(name),
+                arity,
+                inner: function,
+            }),
+        };
+        match self.indexes.get(name) {
+            Some(index) => self.entries[*index] = Some(entry),
+            None => {
+                self.indexes.insert(String::from(name), self.entries.len());
+                self.entries.push(Some(entry));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ArityChecked {
+    name: String,
+    arity: Arity,
+    inner: Rc<dyn BuiltinFunction>,
+}
+
+impl BuiltinFunction for ArityChecked {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        if !self.arity.accepts(arguments.len()) {
+            return Err(format!("{} takes {}", self.name, self.arity));
+        }
+        self.inner.run(arguments, context)
+    }
+}
+
+type HostFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+struct HostFunction {
+    name: String,
+    function: Box<HostFn>,
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+impl BuiltinFunction for HostFunction {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        let values: Vec<Value> = arguments.iter().map(|a| Value::from(&**a)).collect();
+        let result = (self.function)(&values)?;
+        result.to_object().map(Rc::new).ok_or_else(|| {
+            format!(
+                "{} returned a {}, which can't be passed into a script",
+                self.name,
+                result.type_name()
+            )
+        })
+    }
+}
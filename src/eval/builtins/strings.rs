@@ -0,0 +1,340 @@
+use super::{array_arg, check_arg_count, integer_arg, string_arg};
+use crate::object::{BuiltinFunction, CallContext, Object};
+use std::convert::TryInto;
+use std::rc::Rc;
+
+// Positions and lengths in these functions count characters rather than
+// bytes, so that they behave sensibly on non-ascii strings.
+
+fn string(value: String) -> Result<Rc<Object>, String> {
+    Ok(Rc::new(Object::String(value)))
+}
+
+fn boolean(value: bool) -> Result<Rc<Object>, String> {
+    Ok(Rc::new(Object::Boolean(value)))
+}
+
+fn string_array<I: Iterator<Item = String>>(strings: I) -> Result<Rc<Object>, String> {
+    let elements = strings.map(|s| Rc::new(Object::String(s))).collect();
+    Ok(Rc::new(Object::Array(elements)))
+}
+
+#[derive(Debug)]
+struct Split;
+impl BuiltinFunction for Split {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("split", arguments, 2)?;
+        let input = string_arg("split", arguments, 0)?;
+        let separator = string_arg("split", arguments, 1)?;
+        if separator.is_empty() {
+            string_array(input.chars().map(String::from))
+        } else {
+            string_array(input.split(separator).map(String::from))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Join;
+impl BuiltinFunction for Join {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("join", arguments, 2)?;
+        let elements = array_arg("join", arguments, 0)?;
+        let separator = string_arg("join", arguments, 1)?;
+        let mut parts: Vec<&str> = vec![];
+        for element in elements {
+            match element.as_ref() {
+                Object::String(part) => parts.push(part),
+                other => {
+                    return Err(format!(
+                        "join can only join an Array of Strings, found {}",
+                        other.type_name()
+                    ))
+                }
+            }
+        }
+        let separators = separator
+            .len()
+            .saturating_mul(parts.len().saturating_sub(1));
+        let length = parts
+            .iter()
+            .map(|part| part.len())
+            .fold(separators, usize::saturating_add);
+        context.check_string_length(length)?;
+        string(parts.join(separator))
+    }
+}
+
+#[derive(Debug)]
+struct Trim;
+impl BuiltinFunction for Trim {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("trim", arguments, 1)?;
+        string(string_arg("trim", arguments, 0)?.trim().to_string())
+    }
+}
+
+#[derive(Debug)]
+struct Upper;
+impl BuiltinFunction for Upper {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("upper", arguments, 1)?;
+        string(string_arg("upper", arguments, 0)?.to_uppercase())
+    }
+}
+
+#[derive(Debug)]
+struct Lower;
+impl BuiltinFunction for Lower {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("lower", arguments, 1)?;
+        string(string_arg("lower", arguments, 0)?.to_lowercase())
+    }
+}
+
+#[derive(Debug)]
+struct Replace;
+impl BuiltinFunction for Replace {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("replace", arguments, 3)?;
+        let input = string_arg("replace", arguments, 0)?;
+        let from = string_arg("replace", arguments, 1)?;
+        let to = string_arg("replace", arguments, 2)?;
+        if from.is_empty() {
+            return Err(String::from("replace cannot replace an empty string"));
+        }
+        let matches = input.matches(from).count();
+        let length =
+            (input.len() - matches * from.len()).saturating_add(matches.saturating_mul(to.len()));
+        context.check_string_length(length)?;
+        string(input.replace(from, to))
+    }
+}
+
+#[derive(Debug)]
+struct Contains;
+impl BuiltinFunction for Contains {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("contains", arguments, 2)?;
+        let input = string_arg("contains", arguments, 0)?;
+        let needle = string_arg("contains", arguments, 1)?;
+        boolean(input.contains(needle))
+    }
+}
+
+#[derive(Debug)]
+struct StartsWith;
+impl BuiltinFunction for StartsWith {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("starts_with", arguments, 2)?;
+        let input = string_arg("starts_with", arguments, 0)?;
+        let prefix = string_arg("starts_with", arguments, 1)?;
+        boolean(input.starts_with(prefix))
+    }
+}
+
+#[derive(Debug)]
+struct EndsWith;
+impl BuiltinFunction for EndsWith {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("ends_with", arguments, 2)?;
+        let input = string_arg("ends_with", arguments, 0)?;
+        let suffix = string_arg("ends_with", arguments, 1)?;
+        boolean(input.ends_with(suffix))
+    }
+}
+
+/**
+ * Returns the character position of the first occurrence of the second
+ * argument, or -1 if it doesn't occur.
+ */
+#[derive(Debug)]
+struct IndexOf;
+impl BuiltinFunction for IndexOf {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("index_of", arguments, 2)?;
+        let input = string_arg("index_of", arguments, 0)?;
+        let needle = string_arg("index_of", arguments, 1)?;
+        let position = match input.find(needle) {
+            Some(byte_index) => input[..byte_index].chars().count().try_into().unwrap(),
+            None => -1,
+        };
+        Ok(Rc::new(Object::Integer(position)))
+    }
+}
+
+/**
+ * substr(string, start, length). The result is cut short if the string
+ * ends before `length` characters have been taken.
+ */
+#[derive(Debug)]
+struct Substr;
+impl BuiltinFunction for Substr {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("substr", arguments, 3)?;
+        let input = string_arg("substr", arguments, 0)?;
+        let start = integer_arg("substr", arguments, 1)?;
+        let length = integer_arg("substr", arguments, 2)?;
+        if start < 0 || length < 0 {
+            return Err(String::from(
+                "substr cannot take a negative start or length",
+            ));
+        }
+        string(
+            input
+                .chars()
+                .skip(start as usize)
+                .take(length as usize)
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Chars;
+impl BuiltinFunction for Chars {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        _context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("chars", arguments, 1)?;
+        let input = string_arg("chars", arguments, 0)?;
+        string_array(input.chars().map(String::from))
+    }
+}
+
+// The longest string repeat will build, whatever the limits, so that a huge
+// count fails with an error rather than aborting the process.
+const MAX_REPEAT_LENGTH: usize = 1 << 30;
+
+#[derive(Debug)]
+struct Repeat;
+impl BuiltinFunction for Repeat {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        check_arg_count("repeat", arguments, 2)?;
+        let input = string_arg("repeat", arguments, 0)?;
+        let count = integer_arg("repeat", arguments, 1)?;
+        if count < 0 {
+            return Err(String::from("repeat cannot take a negative count"));
+        }
+        // Checked up front, as the result could be too big to build.
+        let length = input.len().saturating_mul(count as usize);
+        context.check_string_length(length)?;
+        if length > MAX_REPEAT_LENGTH {
+            return Err(format!(
+                "repeat cannot make a string longer than {} bytes",
+                MAX_REPEAT_LENGTH
+            ));
+        }
+        string(input.repeat(count as usize))
+    }
+}
+
+/**
+ * format("{} and {}", a, b). Each `{}` in the template is replaced by the
+ * next argument, and there must be exactly one argument per placeholder.
+ */
+#[derive(Debug)]
+struct Format;
+impl BuiltinFunction for Format {
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String> {
+        if arguments.is_empty() {
+            return Err(String::from("format takes at least 1 argument"));
+        }
+        let template = string_arg("format", arguments, 0)?;
+        let pieces: Vec<&str> = template.split("{}").collect();
+        let values = &arguments[1..];
+        if values.len() < pieces.len() - 1 {
+            return Err(String::from("format has more placeholders than values"));
+        }
+        if values.len() > pieces.len() - 1 {
+            return Err(String::from("format has more values than placeholders"));
+        }
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        let length = pieces
+            .iter()
+            .map(|piece| piece.len())
+            .chain(values.iter().map(String::len))
+            .fold(0, usize::saturating_add);
+        context.check_string_length(length)?;
+        let mut result = String::from(pieces[0]);
+        for (value, piece) in values.iter().zip(&pieces[1..]) {
+            result.push_str(value);
+            result.push_str(piece);
+        }
+        string(result)
+    }
+}
+
+pub fn get_string_fn(name: &str) -> Option<Box<dyn BuiltinFunction>> {
+    match name {
+        "split" => Some(Box::new(Split)),
+        "join" => Some(Box::new(Join)),
+        "trim" => Some(Box::new(Trim)),
+        "upper" => Some(Box::new(Upper)),
+        "lower" => Some(Box::new(Lower)),
+        "replace" => Some(Box::new(Replace)),
+        "contains" => Some(Box::new(Contains)),
+        "starts_with" => Some(Box::new(StartsWith)),
+        "ends_with" => Some(Box::new(EndsWith)),
+        "index_of" => Some(Box::new(IndexOf)),
+        "substr" => Some(Box::new(Substr)),
+        "chars" => Some(Box::new(Chars)),
+        "repeat" => Some(Box::new(Repeat)),
+        "format" => Some(Box::new(Format)),
+        _ => None,
+    }
+}
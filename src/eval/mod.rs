@@ -1,136 +1,495 @@
-use crate::object::{environment::Environment, Object};
-use crate::{ast, logic};
+use crate::limits::{InterruptHandle, LimitExceeded, Limits, Meter};
+use crate::object::{environment::Environment, heap, CallContext, Object};
+use crate::{ast, logic, resolve};
+use builtins::BuiltinRegistry;
 use core::cell::RefCell;
 use std::rc::Rc;
 
-mod builtins;
+pub mod builtins;
 #[cfg(test)]
 mod test;
 
-use builtins::get_builtin_fn;
-
 #[derive(Debug)]
 pub enum EvalError {
     Misc(String),
+    LimitExceeded(LimitExceeded),
+    /// The names of the functions that were running, innermost first.
+    Interrupted {
+        stack_trace: Vec<String>,
+    },
 }
 
 const EMPTY_BLOCK: ast::BlockStatement = ast::BlockStatement { statements: vec![] };
 const EMPTY_BLOCK_REF: &ast::BlockStatement = &EMPTY_BLOCK;
 
-pub fn eval_expression<'a>(
-    expression: &'a ast::Expression,
-    env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Rc<Object<'a>>, String> {
+fn eval_expression(
+    expression: &ast::Expression,
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Rc<Object>, String> {
+    context.step()?;
     match expression {
-        ast::Expression::IntegerLiteral { value } => Ok(Rc::new(Object::Integer(*value))),
+        ast::Expression::IntegerLiteral { value } => context.allocate(Object::Integer(*value)),
         ast::Expression::Infix {
             left,
             operator,
             right,
+            ..
         } => {
-            let left = eval_expression(left, Rc::clone(&env))?;
-            let right = eval_expression(right, Rc::clone(&env))?;
-            logic::eval_infix(left, operator, right).map(Rc::new)
+            let left = eval_expression(left, Rc::clone(&env), context)?;
+            if is_return(&left) {
+                return Ok(left);
+            }
+            let right = eval_expression(right, Rc::clone(&env), context)?;
+            if is_return(&right) {
+                return Ok(right);
+            }
+            context
+                .meter
+                .check_infix(&left, operator, &right)
+                .map_err(|e| e.to_string())?;
+            let result = logic::eval_infix(&left, operator, &right)?;
+            context.allocate(result)
         }
-        ast::Expression::Boolean { value } => Ok(Rc::new(Object::Boolean(*value))),
-        ast::Expression::Prefix { operator, right } => {
-            let object = eval_expression(right, env)?;
-            logic::eval_prefix(object, operator).map(Rc::new)
+        ast::Expression::Boolean { value } => context.allocate(Object::Boolean(*value)),
+        ast::Expression::Prefix {
+            operator, right, ..
+        } => {
+            let object = eval_expression(right, env, context)?;
+            if is_return(&object) {
+                return Ok(object);
+            }
+            let result = logic::eval_prefix(&object, operator)?;
+            context.allocate(result)
         }
         ast::Expression::If {
             condition,
             consequence,
             alternative,
+            ..
         } => {
-            let condition = eval_expression(condition, Rc::clone(&env))?;
-            let condition = if let Object::Boolean(value) = *condition {
-                value
-            } else {
-                return Err(format!(
-                    "The condition in an if statement must be a bool. Got {}",
-                    condition.type_name()
-                ));
-            };
-            // Pattern matching is cool.
-            let block_to_eval = match (condition, alternative) {
-                (true, _) => consequence,
-                (false, Some(alternative)) => alternative,
-                (false, None) => EMPTY_BLOCK_REF,
-            };
-            let evaluated_block =
-                eval_statements_with_inner_env(&block_to_eval.statements, Rc::clone(&env))?;
+            let condition = eval_expression(condition, Rc::clone(&env), context)?;
+            if is_return(&condition) {
+                return Ok(condition);
+            }
+            let block_to_eval = select_branch(&condition, consequence, alternative)?;
+            let evaluated_block = eval_statements_with_inner_env(
+                &block_to_eval.statements,
+                Rc::clone(&env),
+                context,
+            )?;
             Ok(evaluated_block.unwrap_or_else(|| Rc::new(Object::Null)))
         }
-        ast::Expression::Identifier { value } => {
-            let obj = read_from_env(&env.borrow(), &value)?;
+        ast::Expression::Identifier { value, slot, .. } => {
+            let obj = read_from_env(&env.borrow(), value, *slot)?;
             Ok(obj)
         }
-        ast::Expression::FnLiteral { param_names, body } => Ok(Rc::new(Object::Function {
-            body,
-            parameter_names: param_names.clone(),
-            env: Rc::clone(&env),
-        })),
-        ast::Expression::CallExpression { left, arguments } => {
-            let left_evaluated = eval_expression(left, Rc::clone(&env))?;
-            let evaluated_arguments = eval_expressions(arguments, Rc::clone(&env))?;
-            match &*left_evaluated {
-                Object::Function {
-                    parameter_names,
-                    body,
-                    env,
-                } => call_function(evaluated_arguments, &parameter_names, body, Rc::clone(env)),
-                Object::BuiltinFunction(builtin) => builtin.run(&evaluated_arguments),
-                _ => Err(format!("Cannot call {}", left_evaluated)),
-            }
-        }
-        ast::Expression::StringLiteral { value } => Ok(Rc::new(Object::String(value.clone()))),
-        ast::Expression::Block { statements } => eval_statements_with_inner_env(statements, env)
-            .map(|opt| opt.unwrap_or_else(|| Rc::new(Object::Null))),
-    }
-}
-
-fn eval_expressions<'a>(
-    expressions: &'a [ast::Expression],
-    env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Vec<Rc<Object<'a>>>, String> {
+        ast::Expression::FnLiteral {
+            param_names, body, ..
+        } => eval_function_literal(None, param_names, body, &env, context),
+        ast::Expression::CallExpression {
+            left, arguments, ..
+        } => {
+            let left_evaluated = eval_expression(left, Rc::clone(&env), context)?;
+            if is_return(&left_evaluated) {
+                return Ok(left_evaluated);
+            }
+            let evaluated_arguments = eval_expressions(arguments, Rc::clone(&env), context)?;
+            if let Some(returned) = returned(&evaluated_arguments) {
+                return Ok(returned);
+            }
+            apply(&left_evaluated, evaluated_arguments, context)
+        }
+        ast::Expression::StringLiteral { value } => context.allocate(Object::String(value.clone())),
+        ast::Expression::Block { statements } => {
+            eval_statements_with_inner_env(statements, env, context)
+                .map(|opt| opt.unwrap_or_else(|| Rc::new(Object::Null)))
+        }
+        ast::Expression::ArrayLiteral { elements } => {
+            let elements = eval_expressions(elements, env, context)?;
+            if let Some(returned) = returned(&elements) {
+                return Ok(returned);
+            }
+            context.allocate(Object::Array(elements))
+        }
+        ast::Expression::Index { left, index, .. } => {
+            let left = eval_expression(left, Rc::clone(&env), context)?;
+            if is_return(&left) {
+                return Ok(left);
+            }
+            let index = eval_expression(index, env, context)?;
+            if is_return(&index) {
+                return Ok(index);
+            }
+            logic::eval_index(&left, &index)
+        }
+    }
+}
+
+fn select_branch<'a>(
+    condition: &Object,
+    consequence: &'a ast::BlockStatement,
+    alternative: &'a Option<ast::BlockStatement>,
+) -> Result<&'a ast::BlockStatement, String> {
+    let condition = if let Object::Boolean(value) = *condition {
+        value
+    } else {
+        return Err(format!(
+            "The condition in an if statement must be a bool. Got {}",
+            condition.type_name()
+        ));
+    };
+    // Pattern matching is cool.
+    Ok(match (condition, alternative) {
+        (true, _) => consequence,
+        (false, Some(alternative)) => alternative,
+        (false, None) => EMPTY_BLOCK_REF,
+    })
+}
+
+/**
+ * What a function body evaluates to: either its value, or a call in tail
+ * position that is left for `call_function` to make.
+ */
+enum Tail {
+    Value(Rc<Object>),
+    Call(Rc<Object>, Vec<Rc<Object>>),
+}
+
+fn eval_tail_expression(
+    expression: &ast::Expression,
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Tail, String> {
+    match expression {
+        ast::Expression::CallExpression {
+            left, arguments, ..
+        } => {
+            context.step()?;
+            let function = eval_expression(left, Rc::clone(&env), context)?;
+            if is_return(&function) {
+                return Ok(Tail::Value(function));
+            }
+            let arguments = eval_expressions(arguments, env, context)?;
+            if let Some(returned) = returned(&arguments) {
+                return Ok(Tail::Value(returned));
+            }
+            Ok(Tail::Call(function, arguments))
+        }
+        ast::Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            context.step()?;
+            let condition = eval_expression(condition, Rc::clone(&env), context)?;
+            if is_return(&condition) {
+                return Ok(Tail::Value(condition));
+            }
+            let block_to_eval = select_branch(&condition, consequence, alternative)?;
+            eval_tail_statements(&block_to_eval.statements, enclose(&env), context)
+        }
+        ast::Expression::Block { statements } => {
+            context.step()?;
+            eval_tail_statements(statements, enclose(&env), context)
+        }
+        _ => eval_expression(expression, env, context).map(Tail::Value),
+    }
+}
+
+/**
+ * Evaluates the statements of a function body, or of a block in tail
+ * position within one, where the last statement's value (returned or not)
+ * is what the function returns.
+ */
+fn eval_tail_statements(
+    statements: &[ast::Statement],
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Tail, String> {
+    let (last, rest) = match statements.split_last() {
+        Some(split) => split,
+        None => return Ok(Tail::Value(Rc::new(Object::Null))),
+    };
+    for statement in rest {
+        context.poll()?;
+        if let Some(result) = eval_statement(statement, Rc::clone(&env), context)? {
+            if matches!(&*result, Object::ReturnValue(_)) {
+                return Ok(Tail::Value(result));
+            }
+        }
+    }
+    context.poll()?;
+    match last {
+        ast::Statement::Expression { expression }
+        | ast::Statement::Return { value: expression } => {
+            eval_tail_expression(expression, env, context)
+        }
+        ast::Statement::Let { .. } => {
+            let returned = eval_statement(last, env, context)?;
+            Ok(Tail::Value(
+                returned.unwrap_or_else(|| Rc::new(Object::Null)),
+            ))
+        }
+    }
+}
+
+fn eval_function_literal(
+    name: Option<&str>,
+    param_names: &[String],
+    body: &Rc<ast::BlockStatement>,
+    env: &Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Rc<Object>, String> {
+    heap::track(env);
+    context.allocate(Object::Function {
+        name: name.map(String::from),
+        body: Rc::clone(body),
+        parameter_names: param_names.to_vec(),
+        env: Rc::clone(env),
+    })
+}
+
+/**
+ * Evaluates each expression in turn, stopping after any that returns.
+ */
+fn eval_expressions(
+    expressions: &[ast::Expression],
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Vec<Rc<Object>>, String> {
     // TODO: use iterators
     let mut results: Vec<Rc<Object>> = vec![];
     for expression in expressions {
-        let obj = eval_expression(expression, Rc::clone(&env))?;
+        let obj = eval_expression(expression, Rc::clone(&env), context)?;
+        let stop = is_return(&obj);
         results.push(obj);
+        if stop {
+            break;
+        }
     }
     Ok(results)
 }
 
-fn call_function<'a>(
-    args: Vec<Rc<Object<'a>>>,
-    expected_param_names: &[String],
-    body: &'a ast::BlockStatement,
-    parent_env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Rc<Object<'a>>, String> {
-    if args.len() != expected_param_names.len() {
-        return Err(format!(
-            "Expected {} args, got {}",
-            expected_param_names.len(),
-            args.len()
-        ));
+/**
+ * A return statement can be inside a block anywhere in an expression, and
+ * leaves the function as soon as it runs, just as in the VM. Its value is
+ * handed up through each enclosing expression as a `ReturnValue`.
+ */
+fn is_return(object: &Object) -> bool {
+    matches!(object, Object::ReturnValue(_))
+}
+
+/**
+ * The return that stopped `eval_expressions`, if one did.
+ */
+fn returned(objects: &[Rc<Object>]) -> Option<Rc<Object>> {
+    objects.last().filter(|object| is_return(object)).cloned()
+}
+
+fn apply(
+    function: &Rc<Object>,
+    args: Vec<Rc<Object>>,
+    context: &mut EvalContext,
+) -> Result<Rc<Object>, String> {
+    match &**function {
+        Object::Function { .. } => {
+            context.call_stack.push(Rc::clone(function));
+            let result = context
+                .check_depth()
+                .and_then(|_| call_function(Rc::clone(function), args, context));
+            context.call_stack.pop();
+            result
+        }
+        Object::BuiltinFunction(builtin) => {
+            let result = builtin.run(&args, context)?;
+            if Rc::strong_count(&result) == 1 {
+                // Made by the builtin rather than passed through it.
+                context.meter.allocate(&result).map_err(|e| e.to_string())?;
+            }
+            Ok(result)
+        }
+        _ => Err(format!("Cannot call {}", function)),
+    }
+}
+
+/**
+ * The state of one run of the interpreter, mostly how much of its limits
+ * it has used. It is handed to builtins as their `CallContext`, so that
+ * functions they call back into count towards the same limits.
+ */
+pub struct EvalContext {
+    meter: Meter,
+    // The functions being run, innermost last.
+    call_stack: Vec<Rc<Object>>,
+    // Taken when the run is interrupted, as the call stack then unwinds.
+    stack_trace: Vec<String>,
+}
+
+impl EvalContext {
+    pub fn new(limits: Limits) -> Self {
+        EvalContext {
+            meter: Meter::new(limits),
+            call_stack: vec![],
+            stack_trace: vec![],
+        }
+    }
+
+    pub fn set_interrupt(&mut self, interrupt: InterruptHandle) {
+        self.meter.set_interrupt(interrupt);
+    }
+
+    /**
+     * Runs a program that has been through `resolve::resolve`, which tells
+     * the interpreter where each local is kept.
+     */
+    pub fn eval_program(
+        &mut self,
+        program: &ast::Program,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Option<Rc<Object>>, EvalError> {
+        let evaluated =
+            eval_statements(&program.statements, env, self).map_err(|e| self.error(e))?;
+        let evaluated: Option<Rc<Object>> = evaluated.map(|o| {
+            if let Object::ReturnValue(value) = &*o {
+                Rc::clone(value)
+            } else {
+                o
+            }
+        });
+        Ok(evaluated)
+    }
+
+    pub fn apply_function(
+        &mut self,
+        function: &Rc<Object>,
+        args: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, EvalError> {
+        apply(function, args, self).map_err(|e| self.error(e))
+    }
+
+    fn error(&mut self, message: String) -> EvalError {
+        if self.meter.interrupted() {
+            return EvalError::Interrupted {
+                stack_trace: std::mem::take(&mut self.stack_trace),
+            };
+        }
+        match self.meter.exceeded() {
+            Some(limit) => EvalError::LimitExceeded(limit),
+            None => EvalError::Misc(message),
+        }
+    }
+
+    fn poll(&mut self) -> Result<(), String> {
+        self.meter.poll().map_err(|interrupted| {
+            if self.stack_trace.is_empty() {
+                self.stack_trace = self
+                    .call_stack
+                    .iter()
+                    .rev()
+                    .map(|function| String::from(function.function_name()))
+                    .collect();
+            }
+            interrupted.to_string()
+        })
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.meter.step().map_err(|e| e.to_string())
+    }
+
+    fn check_depth(&mut self) -> Result<(), String> {
+        self.meter
+            .check_depth(self.call_stack.len())
+            .map_err(|e| e.to_string())
+    }
+
+    fn allocate(&mut self, object: Object) -> Result<Rc<Object>, String> {
+        self.meter.allocate(&object).map_err(|e| e.to_string())?;
+        Ok(Rc::new(object))
+    }
+}
+
+impl CallContext for EvalContext {
+    fn call(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, String> {
+        apply(function, arguments, self)
     }
-    let mut call_env = Environment::new_enclosed(Rc::clone(&parent_env));
 
-    for (name, obj) in expected_param_names.iter().zip(args) {
-        call_env.set(name, Rc::clone(&obj));
+    fn check_string_length(&mut self, length: usize) -> Result<(), String> {
+        self.meter
+            .check_string_length(length)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/**
+ * Runs a Monkey function. Calls it makes in tail position are made here in
+ * a loop rather than by recursing, so they take up neither the Rust stack
+ * nor a place on the call stack.
+ */
+fn call_function(
+    mut function: Rc<Object>,
+    mut args: Vec<Rc<Object>>,
+    context: &mut EvalContext,
+) -> Result<Rc<Object>, String> {
+    loop {
+        let (body, call_env) = match &*function {
+            Object::Function {
+                parameter_names,
+                body,
+                env,
+                ..
+            } => {
+                if args.len() != parameter_names.len() {
+                    return Err(format!(
+                        "Expected {} args, got {}",
+                        parameter_names.len(),
+                        args.len()
+                    ));
+                }
+                let mut call_env = Environment::new_enclosed(Rc::clone(env));
+                for (index, obj) in args.into_iter().enumerate() {
+                    call_env.set_slot(index, obj);
+                }
+                (Rc::clone(body), Rc::new(RefCell::new(call_env)))
+            }
+            // A builtin called in tail position.
+            _ => return apply(&function, args, context),
+        };
+        match eval_tail_statements(&body.statements, call_env, context)? {
+            Tail::Value(value) => {
+                return Ok(match &*value {
+                    Object::ReturnValue(value) => Rc::clone(value),
+                    _ => value,
+                })
+            }
+            Tail::Call(next, next_args) => {
+                if let Object::Function { .. } = &*next {
+                    if let Some(top) = context.call_stack.last_mut() {
+                        *top = Rc::clone(&next);
+                    }
+                }
+                function = next;
+                args = next_args;
+            }
+        }
     }
-    let result = eval_statements(&body.statements, Rc::new(RefCell::new(call_env)))?;
-    Ok(result.unwrap_or_else(|| Rc::new(Object::Null)))
 }
 
-fn eval_statements<'a>(
-    statements: &'a [ast::Statement],
-    env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Option<Rc<Object<'a>>>, String> {
+fn eval_statements(
+    statements: &[ast::Statement],
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Option<Rc<Object>>, String> {
     let mut result: Option<Rc<Object>> = None;
     for statement in statements {
-        result = eval_statement(statement, Rc::clone(&env))?;
+        context.poll()?;
+        result = eval_statement(statement, Rc::clone(&env), context)?;
         if let Some(evaluated_statement) = &result {
             if matches!(&**evaluated_statement, Object::ReturnValue(_)) {
                 break;
@@ -140,57 +499,98 @@ fn eval_statements<'a>(
     Ok(result)
 }
 
-fn eval_statements_with_inner_env<'a>(
-    statements: &'a [ast::Statement],
-    parent_env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Option<Rc<Object<'a>>>, String> {
-    let inner_env = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(
-        &parent_env,
-    ))));
-    eval_statements(statements, inner_env)
+fn eval_statements_with_inner_env(
+    statements: &[ast::Statement],
+    parent_env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Option<Rc<Object>>, String> {
+    eval_statements(statements, enclose(&parent_env), context)
 }
 
-fn eval_statement<'a>(
-    statement: &'a ast::Statement,
-    env: Rc<RefCell<Environment<'a>>>,
-) -> Result<Option<Rc<Object<'a>>>, String> {
+fn enclose(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(env))))
+}
+
+fn eval_statement(
+    statement: &ast::Statement,
+    env: Rc<RefCell<Environment>>,
+    context: &mut EvalContext,
+) -> Result<Option<Rc<Object>>, String> {
     match statement {
         ast::Statement::Expression { expression } => {
-            let object = eval_expression(expression, Rc::clone(&env))?;
+            let object = eval_expression(expression, Rc::clone(&env), context)?;
             Ok(Some(object))
         }
         ast::Statement::Return { value } => {
-            let contained_value = eval_expression(value, Rc::clone(&env))?;
+            let contained_value = eval_expression(value, Rc::clone(&env), context)?;
+            if is_return(&contained_value) {
+                return Ok(Some(contained_value));
+            }
             Ok(Some(Rc::new(Object::ReturnValue(contained_value))))
         }
-        ast::Statement::Let { name, right } => {
-            let right_obj = eval_expression(right, Rc::clone(&env))?;
-            env.borrow_mut().set(&name, right_obj);
+        ast::Statement::Let {
+            name, right, slot, ..
+        } => {
+            let right_obj = match right {
+                // Functions remember the name they were first bound to.
+                ast::Expression::FnLiteral {
+                    param_names, body, ..
+                } => {
+                    context.step()?;
+                    eval_function_literal(Some(name), param_names, body, &env, context)?
+                }
+                _ => eval_expression(right, Rc::clone(&env), context)?,
+            };
+            if is_return(&right_obj) {
+                return Ok(Some(right_obj));
+            }
+            match slot {
+                Some(index) => env.borrow_mut().set_slot(*index, right_obj),
+                None => env.borrow_mut().set(name, right_obj),
+            }
             Ok(None)
         }
     }
 }
 
-pub fn eval_program<'prog, 'env>(
-    program: &'prog ast::Program,
-    env: Rc<RefCell<Environment<'env>>>,
-) -> Result<Option<Rc<Object<'env>>>, EvalError>
-where
-    'prog: 'env,
-{
-    let evaluated = eval_statements(&program.statements, env).map_err(EvalError::Misc)?;
-    let evaluated: Option<Rc<Object>> = evaluated.map(|o| {
-        if let Object::ReturnValue(value) = &*o {
-            Rc::clone(value)
-        } else {
-            o
-        }
-    });
-    Ok(evaluated)
+/**
+ * Resolves and runs a program with the default limits. It can be straight
+ * from the parser, as the program is resolved here against the default
+ * builtins.
+ */
+pub fn eval_program(
+    program: &ast::Program,
+    env: Rc<RefCell<Environment>>,
+) -> Result<Option<Rc<Object>>, EvalError> {
+    let mut program = program.clone();
+    resolve::resolve(&mut program, &BuiltinRegistry::with_defaults());
+    EvalContext::new(Limits::default()).eval_program(&program, env)
+}
+
+/**
+ * Calls a function with no limits on what it may do.
+ */
+pub fn apply_function(
+    function: &Rc<Object>,
+    args: Vec<Rc<Object>>,
+) -> Result<Rc<Object>, EvalError> {
+    EvalContext::new(Limits::default()).apply_function(function, args)
 }
 
-fn read_from_env<'a>(env: &Environment<'a>, identifier: &str) -> Result<Rc<Object<'a>>, String> {
-    env.get(identifier)
-        .or_else(|| get_builtin_fn(identifier).map(|f| Rc::new(Object::BuiltinFunction(f))))
+fn read_from_env(
+    env: &Environment,
+    identifier: &str,
+    slot: Option<ast::Slot>,
+) -> Result<Rc<Object>, String> {
+    let found = match slot {
+        // Empty if a function is called before a binding it uses is made.
+        Some(slot) => env.get_slot(slot),
+        None => env.get(identifier),
+    };
+    found
+        .or_else(|| {
+            env.get_builtin(identifier)
+                .map(|f| Rc::new(Object::BuiltinFunction(f)))
+        })
         .ok_or_else(|| format!("The identifier '{}' has not been bound", identifier))
 }
@@ -1,7 +1,7 @@
 use crate::errors;
 use crate::eval;
 use crate::lexer;
-use crate::object::{environment::Environment, Object};
+use crate::object::{environment::Environment, heap, Object};
 use crate::parser;
 use core::cell::RefCell;
 use std::rc::Rc;
@@ -151,6 +151,37 @@ fn test_return_statement() {
                 ",
             10,
         ),
+        TestCase::int("let f = fn() { return 1; 5 }; f() + 1", 2),
+        TestCase::int(
+            "let f = fn(x) { if (x > 1) { return x; } 0 }; f(3) + f(1)",
+            3,
+        ),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
+#[test]
+fn test_tail_calls() {
+    let tests: Vec<TestCase> = vec![
+        TestCase::int(
+            "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+            count(1000000, 0)",
+            1000000,
+        ),
+        TestCase::int(
+            "let count = fn(n) { if (n == 0) { return 0; } return count(n - 1); }; count(1000000)",
+            0,
+        ),
+        TestCase::bool(
+            "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+            let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+            even(100001)",
+            false,
+        ),
+        // A builtin called in tail position.
+        TestCase::int("let f = fn(x) { len(x) }; f([1, 2])", 2),
     ];
     for test in tests {
         run_test_case(test);
@@ -196,6 +227,54 @@ fn test_errors() {
             input: "foobar;",
             error_message: "Eval error: The identifier 'foobar' has not been bound",
         },
+        TestErrorCase {
+            input: "let f = fn() { let g = fn() { h }; let r = g(); let h = 1; r }; f();",
+            error_message: "Eval error: The identifier 'h' has not been bound",
+        },
+        TestErrorCase {
+            input: "\"a\" < 1;",
+            error_message: "Eval error: Cannot evaluate infix expression a < 1",
+        },
+        TestErrorCase {
+            input: "1[0];",
+            error_message: "Eval error: Cannot index Integer with Integer",
+        },
+        TestErrorCase {
+            input: "upper(1);",
+            error_message: "Eval error: Argument 1 to upper must be of type String, got Integer",
+        },
+        TestErrorCase {
+            input: "split(\"a,b\", 1);",
+            error_message: "Eval error: Argument 2 to split must be of type String, got Integer",
+        },
+        TestErrorCase {
+            input: "join(\"ab\", \",\");",
+            error_message: "Eval error: Argument 1 to join must be of type Array, got String",
+        },
+        TestErrorCase {
+            input: "join([1, 2], \",\");",
+            error_message: "Eval error: join can only join an Array of Strings, found Integer",
+        },
+        TestErrorCase {
+            input: "substr(\"abc\", \"1\", 1);",
+            error_message: "Eval error: Argument 2 to substr must be of type Integer, got String",
+        },
+        TestErrorCase {
+            input: "trim(\"a\", \"b\");",
+            error_message: "Eval error: trim takes exactly 1 argument",
+        },
+        TestErrorCase {
+            input: "replace(\"a\", \"b\");",
+            error_message: "Eval error: replace takes exactly 3 arguments",
+        },
+        TestErrorCase {
+            input: "format(\"{} {}\", 1);",
+            error_message: "Eval error: format has more placeholders than values",
+        },
+        TestErrorCase {
+            input: "format(\"{}\", 1, 2);",
+            error_message: "Eval error: format has more values than placeholders",
+        },
     ];
     for test in tests {
         let mut lexer = lexer::new(test.input);
@@ -260,6 +339,27 @@ fn test_fn_application() {
     }
 }
 
+#[test]
+fn test_scoping() {
+    let tests: Vec<TestCase> = vec![
+        // Functions see what is bound after them, by the time they're called.
+        TestCase::int("let f = fn() { g() }; let g = fn() { 3 }; f()", 3),
+        // Until a name is bound in a scope, it refers to the outer binding.
+        TestCase::int(
+            "let x = 1; let f = fn() { let y = x; let x = 2; y + x * 10 }; f()",
+            21,
+        ),
+        TestCase::int("let x = 1; { let y = x; let x = 2; y }", 1),
+        TestCase::int("let x = 1; if (true) { let x = x + 1; x }", 2),
+        TestCase::int("let x = 1; let f = fn() { x }; let x = 2; f()", 2),
+        TestCase::int("let f = fn(a, a) { a }; f(1, 2)", 2),
+        TestCase::int("let f = fn(a) { let a = a * 2; a }; f(4)", 8),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
 #[test]
 fn test_string_literals() {
     let tests: Vec<TestCase> = vec![
@@ -296,8 +396,276 @@ fn test_block_expressions() {
 
 #[test]
 fn test_builtins() {
-    let tests: Vec<TestCase> = vec![TestCase::int("len(\"ahoy\")", 4)];
+    let tests: Vec<TestCase> = vec![
+        TestCase::int("len(\"ahoy\")", 4),
+        TestCase::int("len(\"héllo\")", 5),
+        TestCase::int("len([1, 2, 3])", 3),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
+#[test]
+fn test_arrays() {
+    let tests: Vec<TestCase> = vec![
+        TestCase::int("[1, 2 * 2, 3][1]", 4),
+        TestCase::int("let a = [1, 2, 3]; a[0] + a[2]", 4),
+        TestCase::int("let first = fn(a) { a[0] }; first([5])", 5),
+        TestCase::null("[1, 2][2]"),
+        TestCase::null("[1, 2][-1]"),
+        TestCase::bool("[1, [2]] == [1, [2]]", true),
+        TestCase::bool("[1, 2] == [2, 1]", false),
+    ];
     for test in tests {
         run_test_case(test);
     }
 }
+
+#[test]
+fn test_string_builtins() {
+    let tests: Vec<TestCase> = vec![
+        TestCase::int("len(split(\"a,b,c\", \",\"))", 3),
+        TestCase::string("split(\"a,b,c\", \",\")[1]", String::from("b")),
+        TestCase::string("split(\"abc\", \"\")[2]", String::from("c")),
+        TestCase::string(
+            "join(split(\"a b c\", \" \"), \"-\")",
+            String::from("a-b-c"),
+        ),
+        TestCase::string("join([], \",\")", String::from("")),
+        TestCase::string("trim(\"  hi there \")", String::from("hi there")),
+        TestCase::string("upper(\"Monkey\")", String::from("MONKEY")),
+        TestCase::string("lower(\"Monkey\")", String::from("monkey")),
+        TestCase::string("replace(\"a-b-c\", \"-\", \"+\")", String::from("a+b+c")),
+        TestCase::bool("contains(\"monkey\", \"key\")", true),
+        TestCase::bool("contains(\"monkey\", \"ape\")", false),
+        TestCase::bool("starts_with(\"monkey\", \"mon\")", true),
+        TestCase::bool("starts_with(\"monkey\", \"key\")", false),
+        TestCase::bool("ends_with(\"monkey\", \"key\")", true),
+        TestCase::bool("ends_with(\"monkey\", \"mon\")", false),
+        TestCase::int("index_of(\"monkey\", \"key\")", 3),
+        TestCase::int("index_of(\"héllo\", \"l\")", 2),
+        TestCase::int("index_of(\"monkey\", \"ape\")", -1),
+        TestCase::string("substr(\"monkey\", 1, 3)", String::from("onk")),
+        TestCase::string("substr(\"monkey\", 4, 10)", String::from("ey")),
+        TestCase::string("substr(\"monkey\", 10, 1)", String::from("")),
+        TestCase::string("chars(\"héllo\")[1]", String::from("é")),
+        TestCase::int("len(chars(\"abc\"))", 3),
+        TestCase::string("repeat(\"ab\", 3)", String::from("ababab")),
+        TestCase::string("repeat(\"ab\", 0)", String::from("")),
+        TestCase::string(
+            "format(\"{} + {} = {}\", 1, 2, 1 + 2)",
+            String::from("1 + 2 = 3"),
+        ),
+        TestCase::string(
+            "format(\"no placeholders\")",
+            String::from("no placeholders"),
+        ),
+        TestCase::string("format(\"{}\", [1, \"a\"])", String::from("[1, a]")),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
+#[test]
+fn test_repeat_too_long() {
+    for code in &[
+        "repeat(\"ab\", 4611686018427387904)",
+        "repeat(\"ab\", 100000000000)",
+    ] {
+        let mut lexer = lexer::new(code);
+        let program = parser::Parser::new(&mut lexer).parse_program().unwrap();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        assert!(eval::eval_program(&program, env).is_err(), "{}", code);
+    }
+}
+
+#[test]
+fn test_string_comparison() {
+    let tests: Vec<TestCase> = vec![
+        TestCase::bool("\"a\" < \"b\"", true),
+        TestCase::bool("\"b\" < \"a\"", false),
+        TestCase::bool("\"apple\" > \"apricot\"", false),
+        TestCase::bool("\"b\" > \"abc\"", true),
+        TestCase::bool("\"a\" < \"a\"", false),
+        TestCase::bool("\"a\" == \"a\"", true),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
+#[test]
+fn test_collection_builtins() {
+    let tests: Vec<TestCase> = vec![
+        TestCase::int("map([1, 2, 3], fn(x) { x * 2 })[2]", 6),
+        TestCase::int("len(map([], fn(x) { x }))", 0),
+        TestCase::int("len(filter([1, 2, 3, 4], fn(x) { x > 2 }))", 2),
+        TestCase::int("filter([1, 2, 3, 4], fn(x) { x > 2 })[0]", 3),
+        TestCase::int("reduce([1, 2, 3], 10, fn(acc, x) { acc + x })", 16),
+        TestCase::int("reduce([], 10, fn(acc, x) { acc + x })", 10),
+        TestCase::null("each([1, 2], fn(x) { x })"),
+        TestCase::string(
+            "join(sort_by([\"pear\", \"fig\", \"apple\"], fn(x) { len(x) }), \",\")",
+            String::from("fig,pear,apple"),
+        ),
+        TestCase::string(
+            "join(sort_by([\"b\", \"c\", \"a\"], fn(x) { x }), \"\")",
+            String::from("abc"),
+        ),
+        TestCase::bool("any([1, 2], fn(x) { x == 2 })", true),
+        TestCase::bool("any([], fn(x) { true })", false),
+        TestCase::bool("all([1, 2], fn(x) { x == 2 })", false),
+        TestCase::bool("all([], fn(x) { false })", true),
+        TestCase::int("zip([1, 2], [3, 4, 5])[1][1]", 4),
+        TestCase::int("len(zip([1, 2], [3, 4, 5]))", 2),
+        TestCase::int("enumerate([7, 8])[1][0]", 1),
+        TestCase::int("enumerate([7, 8])[1][1]", 8),
+        TestCase::string("map([\"a\"], upper)[0]", String::from("A")),
+        TestCase::int(
+            "let n = 5; reduce(map([1, 2], fn(x) { x + n }), 0, fn(a, b) { a + b })",
+            13,
+        ),
+    ];
+    for test in tests {
+        run_test_case(test);
+    }
+}
+
+#[test]
+fn test_collection_builtin_errors() {
+    let tests: Vec<TestErrorCase> = vec![
+        TestErrorCase {
+            input: "map([1], 2)",
+            error_message: "Eval error: Argument 2 to map must be of type Function, got Integer",
+        },
+        TestErrorCase {
+            input: "filter([1], fn(x) { x })",
+            error_message:
+                "Eval error: The function passed to filter must return a Boolean, got Integer",
+        },
+        TestErrorCase {
+            input: "map([1], fn(x, y) { x })",
+            error_message: "Eval error: Expected 2 args, got 1",
+        },
+        TestErrorCase {
+            input: "sort_by([1, 2], fn(x) { if (x > 1) { 1 } else { \"a\" } })",
+            error_message:
+                "Eval error: The keys returned to sort_by must be all Integers or all Strings",
+        },
+    ];
+    for test in tests {
+        let mut lexer = lexer::new(test.input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        let env = Environment::new();
+        let evaluation_result = eval::eval_program(&program, Rc::new(RefCell::new(env)))
+            .map_err(errors::MonkeyError::Eval)
+            .unwrap_err();
+        assert_eq!(
+            evaluation_result.to_string(),
+            String::from(test.error_message)
+        );
+    }
+}
+
+#[test]
+fn test_values_outlive_program() {
+    // Parses and evaluates in a helper, so the AST is dropped before the
+    // returned function is called.
+    fn eval_source(source: &str, env: &Rc<RefCell<Environment>>) -> Rc<Object> {
+        let mut lexer = lexer::new(source);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        eval::eval_program(&program, Rc::clone(env))
+            .unwrap()
+            .unwrap()
+    }
+
+    let env = Rc::new(RefCell::new(Environment::new()));
+    let adder = eval_source("let n = 10; fn(x) { x + n }", &env);
+    let cache: Vec<Rc<Object>> = vec![Rc::clone(&adder)];
+    eval_source("let n = 20; n", &env);
+    let result = eval::apply_function(&cache[0], vec![Rc::new(Object::Integer(1))]).unwrap();
+    assert_eq!(*result, Object::Integer(21));
+}
+
+#[test]
+fn test_eval_unresolved_program() {
+    // Straight from the parser, without the slots the resolver fills in.
+    let mut lexer = lexer::new("let f = fn(x) { let y = x + 1; y }; f(1)");
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program().unwrap();
+    let env = Rc::new(RefCell::new(Environment::new()));
+    let result = eval::eval_program(&program, env).unwrap().unwrap();
+    assert_eq!(*result, Object::Integer(2));
+}
+
+fn eval_in(source: &str, env: &Rc<RefCell<Environment>>) -> Option<Rc<Object>> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program().unwrap();
+    eval::eval_program(&program, Rc::clone(env)).unwrap()
+}
+
+#[test]
+fn test_recursive_closures_are_collected() {
+    let env = Rc::new(RefCell::new(Environment::new()));
+    eval_in(
+        "let countdown = fn(x) { if (x == 0) { 0 } else { countdown(x - 1) } }; countdown(3);",
+        &env,
+    );
+    let weak = Rc::downgrade(&env);
+    drop(env);
+    // The environment holds `countdown`, which holds the environment.
+    assert!(weak.upgrade().is_some());
+    assert_eq!(heap::collect(), 1);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_referenced_closures_are_kept() {
+    let env = Rc::new(RefCell::new(Environment::new()));
+    eval_in(
+        "let make = fn() { let inner = fn() { inner }; inner }; let kept = make();",
+        &env,
+    );
+    let kept = env.borrow().get("kept").unwrap();
+    let weak = Rc::downgrade(&env);
+    drop(env);
+    // `kept` is still referenced from here, and its environment encloses
+    // the top level one.
+    heap::collect();
+    assert!(weak.upgrade().is_some());
+    let result = eval::apply_function(&kept, vec![]).unwrap();
+    assert!(Rc::ptr_eq(&result, &kept));
+    drop(result);
+    drop(kept);
+    assert!(weak.upgrade().is_some());
+    heap::collect();
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_gc_builtin() {
+    let env = Rc::new(RefCell::new(Environment::new()));
+    let before = heap::stats();
+    eval_in(
+        "let loop = fn(n) { if (n > 0) { let f = fn() { f }; loop(n - 1) } };
+         loop(10);",
+        &env,
+    );
+    // Each call leaves behind its own environment and that of the `if`
+    // block, which `f` closes over.
+    let reclaimed = eval_in("gc()", &env).unwrap();
+    assert_eq!(*reclaimed, Object::Integer(20));
+    let after = heap::stats();
+    assert_eq!(after.collections, before.collections + 1);
+    assert_eq!(
+        after.reclaimed_environments,
+        before.reclaimed_environments + 20
+    );
+    // The root environment is still in use, so survives.
+    assert_eq!(*eval_in("loop(0); 1", &env).unwrap(), Object::Integer(1));
+}
@@ -0,0 +1,421 @@
+#[cfg(test)]
+mod test;
+
+use crate::ast::{Expression, Position, PrefixOperator, Program, Statement};
+use crate::lexer::{self, Comment};
+use crate::parser::{self, Layout, ParserError, Precedence};
+use std::vec;
+
+const INDENT: &str = "    ";
+
+/**
+ * Lays out Monkey source the one canonical way, keeping its comments and
+ * single blank lines between statements.
+ */
+pub fn format_source(source: &str) -> Result<String, ParserError> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program()?;
+    let layout = parser.into_layout();
+    let mut formatter = Formatter::new(lexer.comments(), layout);
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+/**
+ * Lays out a program that has no source, such as one that was generated.
+ */
+pub fn format_program(program: &Program) -> String {
+    let mut formatter = Formatter::new(&[], Layout::default());
+    formatter.program(program);
+    formatter.out
+}
+
+struct Formatter<'a> {
+    out: String,
+    depth: usize,
+    // The comments not yet written, in order.
+    comments: &'a [Comment],
+    // Consumed in the same order the parser recorded them, which is the
+    // order they are met in walking the program.
+    statements: vec::IntoIter<(Position, Position)>,
+    block_ends: vec::IntoIter<Position>,
+    operands: vec::IntoIter<Position>,
+    list_ends: vec::IntoIter<Position>,
+    // Whether a comment has just broken a line partway through, so that
+    // the rest of it is indented one more level.
+    continued: bool,
+    // The source line of the last thing written, or `None` at the start of
+    // a block, where no blank line is kept.
+    last_line: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(comments: &'a [Comment], layout: Layout) -> Self {
+        Formatter {
+            out: String::new(),
+            depth: 0,
+            comments,
+            statements: layout.statements.into_iter(),
+            block_ends: layout.block_ends.into_iter(),
+            operands: layout.operands.into_iter(),
+            list_ends: layout.list_ends.into_iter(),
+            continued: false,
+            last_line: None,
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.statements(&program.statements, false);
+        self.comments_before(None);
+    }
+
+    fn write(&mut self, text: &str) {
+        let mut text = text;
+        if self.out.ends_with('\n') {
+            for _ in 0..self.depth + self.continued as usize {
+                self.out.push_str(INDENT);
+            }
+            if self.continued {
+                text = text.trim_start();
+                self.continued = false;
+            }
+        }
+        self.out.push_str(text);
+    }
+
+    fn line_break(&mut self) {
+        self.out.push('\n');
+    }
+
+    fn blank_line_before(&mut self, line: usize) {
+        if matches!(self.last_line, Some(last) if line > last + 1) {
+            self.line_break();
+        }
+    }
+
+    /**
+     * Writes, each on its own line, the comments that come before
+     * `position`, or all that are left if there is no position.
+     */
+    fn comments_before(&mut self, position: Option<Position>) {
+        while let Some((comment, rest)) = self.comments.split_first() {
+            if matches!(position, Some(position) if !is_before(comment.position, position)) {
+                break;
+            }
+            self.comments = rest;
+            self.blank_line_before(comment.position.line);
+            self.write(&comment.text);
+            self.line_break();
+            self.last_line = Some(comment.position.line);
+        }
+    }
+
+    /**
+     * Writes the comments that come before `position` where the formatter
+     * has got to, which may be partway through a line. As a comment runs
+     * to the end of its line, what follows carries on on the next.
+     */
+    fn comments_within(&mut self, position: Position) {
+        while let Some((comment, rest)) = self.comments.split_first() {
+            if !is_before(comment.position, position) {
+                break;
+            }
+            self.comments = rest;
+            if !self.out.is_empty() && !self.out.ends_with('\n') {
+                self.out.truncate(self.out.trim_end_matches(' ').len());
+                self.out.push(' ');
+            }
+            self.write(&comment.text);
+            self.line_break();
+            self.continued = true;
+            self.last_line = Some(comment.position.line);
+        }
+    }
+
+    /**
+     * Writes the comments before the next operand or closing bracket.
+     */
+    fn comments_before_operand(&mut self) {
+        if let Some(position) = self.operands.next() {
+            self.comments_within(position);
+        }
+    }
+
+    fn comments_before_list_end(&mut self) {
+        if let Some(position) = self.list_ends.next() {
+            self.comments_within(position);
+        }
+    }
+
+    /**
+     * Writes the comments left on or before the line a statement ended on
+     * after it, the first on the same line.
+     */
+    fn trailing_comments(&mut self, end: Position) {
+        self.last_line = Some(end.line);
+        let mut first = true;
+        while let Some((comment, rest)) = self.comments.split_first() {
+            if comment.position.line > end.line {
+                break;
+            }
+            self.comments = rest;
+            if first {
+                self.write(" ");
+                first = false;
+            } else {
+                self.line_break();
+            }
+            self.write(&comment.text);
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement], in_block: bool) {
+        for (index, statement) in statements.iter().enumerate() {
+            let span = self.statements.next();
+            if let Some((start, _)) = span {
+                self.comments_before(Some(start));
+                self.blank_line_before(start.line);
+            }
+            let terminator = match statement {
+                Statement::Let {
+                    name,
+                    right,
+                    annotation,
+                    ..
+                } => {
+                    match annotation {
+                        Some(annotation) => self.write(&format!("let {}: {} = ", name, annotation)),
+                        None => self.write(&format!("let {} = ", name)),
+                    }
+                    self.expression(right);
+                    ";"
+                }
+                Statement::Return { value } => {
+                    self.write("return ");
+                    self.expression(value);
+                    ";"
+                }
+                Statement::Expression { expression } => {
+                    self.expression(expression);
+                    // The last statement of a block needs no semicolon, nor
+                    // does an if or block that nothing could be read as
+                    // carrying on.
+                    let next = statements.get(index + 1);
+                    let last = in_block && next.is_none();
+                    if last || ends_with_block(expression) && !next.is_some_and(continues_previous)
+                    {
+                        ""
+                    } else {
+                        ";"
+                    }
+                }
+            };
+            self.write(terminator);
+            if let Some((_, end)) = span {
+                self.trailing_comments(end);
+            }
+            self.line_break();
+        }
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.write("{");
+        self.line_break();
+        let empty_length = self.out.len();
+        self.depth += 1;
+        self.last_line = None;
+        self.statements(statements, true);
+        if let Some(end) = self.block_ends.next() {
+            self.comments_before(Some(end));
+        }
+        self.depth -= 1;
+        if self.out.len() == empty_length {
+            self.out.pop();
+        }
+        self.write("}");
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        if !matches!(
+            expression,
+            Expression::Infix { .. } | Expression::CallExpression { .. } | Expression::Index { .. }
+        ) {
+            self.comments_before_operand();
+        }
+        match expression {
+            Expression::Identifier { value, .. } => self.write(value),
+            Expression::IntegerLiteral { value } => self.write(&value.to_string()),
+            Expression::StringLiteral { value } => self.write(&format!("\"{}\"", value)),
+            Expression::Boolean { value } => self.write(&value.to_string()),
+            Expression::Prefix {
+                operator, right, ..
+            } => {
+                // Brackets keep nested operators apart, so that `-(-x)`
+                // isn't written as `--x`.
+                self.write(&operator.to_string());
+                self.operand(right, precedence(right) <= Precedence::Prefix);
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                position,
+            } => {
+                // Operators of equal precedence group from the left, so
+                // only the right operand needs brackets to keep its place.
+                let operator_precedence = parser::infix_precedence(operator);
+                self.operand(left, precedence(left) < operator_precedence);
+                self.comments_within(*position);
+                self.write(&format!(" {} ", operator));
+                self.operand(right, precedence(right) <= operator_precedence);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.write("if (");
+                self.expression(condition);
+                self.write(") ");
+                self.block(&consequence.statements);
+                if let Some(alternative) = alternative {
+                    self.write(" else ");
+                    self.block(&alternative.statements);
+                }
+            }
+            Expression::FnLiteral {
+                param_names,
+                param_positions,
+                param_types,
+                return_type,
+                body,
+                ..
+            } => {
+                self.write("fn(");
+                for (index, name) in param_names.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    if let Some(position) = param_positions.get(index) {
+                        self.comments_within(*position);
+                    }
+                    match param_types.get(index) {
+                        Some(Some(ty)) => self.write(&format!("{}: {}", name, ty)),
+                        _ => self.write(name),
+                    }
+                }
+                self.comments_before_list_end();
+                self.write(") ");
+                if let Some(return_type) = return_type {
+                    self.write(&format!("-> {} ", return_type));
+                }
+                self.block(&body.statements);
+            }
+            Expression::CallExpression {
+                left,
+                arguments,
+                position,
+            } => {
+                self.operand(left, precedence(left) < Precedence::Call);
+                self.comments_within(*position);
+                self.write("(");
+                self.list(arguments);
+                self.comments_before_list_end();
+                self.write(")");
+            }
+            Expression::Block { statements } => self.block(statements),
+            Expression::ArrayLiteral { elements } => {
+                self.write("[");
+                self.list(elements);
+                self.comments_before_list_end();
+                self.write("]");
+            }
+            Expression::Index {
+                left,
+                index,
+                position,
+            } => {
+                self.operand(left, precedence(left) < Precedence::Call);
+                self.comments_within(*position);
+                self.write("[");
+                self.expression(index);
+                self.comments_before_list_end();
+                self.write("]");
+            }
+        }
+    }
+
+    fn operand(&mut self, expression: &Expression, bracketed: bool) {
+        if bracketed {
+            self.write("(");
+            self.expression(expression);
+            self.write(")");
+        } else {
+            self.expression(expression);
+        }
+    }
+
+    fn list(&mut self, expressions: &[Expression]) {
+        for (index, expression) in expressions.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.expression(expression);
+        }
+    }
+}
+
+/**
+ * How tightly an expression holds together when written out: anything
+ * that isn't an operator, call or index is never split.
+ */
+fn precedence(expression: &Expression) -> Precedence {
+    match expression {
+        Expression::Infix { operator, .. } => parser::infix_precedence(operator),
+        Expression::Prefix { .. } => Precedence::Prefix,
+        Expression::CallExpression { .. } => Precedence::Call,
+        _ => Precedence::Index,
+    }
+}
+
+fn ends_with_block(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::If { .. } | Expression::FnLiteral { .. } | Expression::Block { .. }
+    )
+}
+
+/**
+ * Whether a statement, written straight after an expression, would be
+ * parsed as carrying that expression on: as a call, an index or a
+ * subtraction.
+ */
+fn continues_previous(statement: &Statement) -> bool {
+    match statement {
+        Statement::Expression { expression } => starts_with_operator(expression),
+        _ => false,
+    }
+}
+
+fn starts_with_operator(expression: &Expression) -> bool {
+    match expression {
+        Expression::Prefix {
+            operator: PrefixOperator::Minus,
+            ..
+        }
+        | Expression::ArrayLiteral { .. } => true,
+        Expression::Infix { left, operator, .. } => {
+            precedence(left) < parser::infix_precedence(operator) || starts_with_operator(left)
+        }
+        Expression::CallExpression { left, .. } | Expression::Index { left, .. } => {
+            precedence(left) < Precedence::Call || starts_with_operator(left)
+        }
+        _ => false,
+    }
+}
+
+fn is_before(a: Position, b: Position) -> bool {
+    (a.line, a.column) < (b.line, b.column)
+}
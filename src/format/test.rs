@@ -0,0 +1,194 @@
+use super::{format_program, format_source};
+use crate::ast::VisitorMut;
+use crate::conformance::generator::Generator;
+use crate::{ast, lexer, parser};
+use pretty_assertions::assert_eq;
+use std::ffi::OsStr;
+use std::fs;
+
+fn parse(source: &str) -> ast::Program {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    parser.parse_program().unwrap()
+}
+
+/**
+ * Checks that formatted source means the same as what it came from, and
+ * that formatting it again changes nothing.
+ */
+fn assert_canonical(program: &ast::Program, formatted: &str) {
+    let mut program = program.clone();
+    let mut reparsed = parse(formatted);
+    ast::ClearPositions.visit_program_mut(&mut program);
+    ast::ClearPositions.visit_program_mut(&mut reparsed);
+    assert_eq!(reparsed, program, "Reparsing:\n{}", formatted);
+    assert_eq!(format_source(formatted).unwrap(), formatted);
+}
+
+#[test]
+fn test_layout() {
+    let source = "let add=fn(a,b){a+b};let x = if (add(1, 2) > 2) { let y = 3; y } else { fn(){} };
+    [1,2][0]; puts(\"done\")";
+    let expected = r#"let add = fn(a, b) {
+    a + b
+};
+let x = if (add(1, 2) > 2) {
+    let y = 3;
+    y
+} else {
+    fn() {}
+};
+[1, 2][0];
+puts("done");
+"#;
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert_canonical(&parse(source), expected);
+}
+
+#[test]
+fn test_minimal_parentheses() {
+    let tests = [
+        ("((1 + 2) + 3)", "1 + 2 + 3;\n"),
+        ("1 + (2 + 3)", "1 + (2 + 3);\n"),
+        ("(1 + 2) * 3", "(1 + 2) * 3;\n"),
+        ("1 + (2 * 3)", "1 + 2 * 3;\n"),
+        ("-(1 + 2)", "-(1 + 2);\n"),
+        ("(-a)(1)", "(-a)(1);\n"),
+        ("-(a(1))", "-a(1);\n"),
+        ("(a + b)[0]", "(a + b)[0];\n"),
+        ("(f(1))[0](2)", "f(1)[0](2);\n"),
+        ("(a == b) == (c < d)", "a == b == c < d;\n"),
+        ("!(-(a))", "!(-a);\n"),
+        ("-(-x)", "-(-x);\n"),
+        ("!!x", "!(!x);\n"),
+    ];
+    for (source, expected) in tests.iter() {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(&formatted, expected, "Formatting {}", source);
+        assert_canonical(&parse(source), &formatted);
+    }
+}
+
+#[test]
+fn test_semicolons_after_blocks() {
+    // An if followed by something that could carry it on keeps its
+    // semicolon; otherwise it needs none.
+    let source = "if (x) { 1 }; -1; if (x) { 2 }; [3]; if (x) { 4 } puts(5)";
+    let expected = "if (x) {
+    1
+};
+-1;
+if (x) {
+    2
+};
+[3];
+if (x) {
+    4
+}
+puts(5);
+";
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert_canonical(&parse(source), expected);
+}
+
+#[test]
+fn test_comments() {
+    let source = "// Adds things.
+let add = fn(a, b) { // Two numbers.
+  a + b // The sum.
+  // Nothing after.
+};
+
+
+// Used twice.
+let x = add(1,
+  // The second.
+  2);
+let empty = fn() {
+  // Not yet.
+}; // The end.
+// Really.
+";
+    let expected = "// Adds things.
+let add = fn(a, b) {
+    // Two numbers.
+    a + b // The sum.
+    // Nothing after.
+};
+
+// Used twice.
+let x = add(1, // The second.
+    2);
+let empty = fn() {
+    // Not yet.
+}; // The end.
+// Really.
+";
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert_canonical(&parse(source), expected);
+}
+
+#[test]
+fn test_comments_within_expressions() {
+    // A comment goes before the token it came before, however deep in an
+    // expression that is.
+    let source = "let f = fn(a, // first
+  b) { a };
+let xs = [
+  1, // one
+  2 // two
+];
+let y = xs[ // index
+  0] // last
+  + 1;
+";
+    let expected = "let f = fn(a, // first
+    b) {
+    a
+};
+let xs = [1, // one
+    2 // two
+    ];
+let y = xs[ // index
+    0] // last
+    + 1;
+";
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert_canonical(&parse(source), expected);
+}
+
+#[test]
+fn test_type_annotations() {
+    let source = "let f:fn(int)->[A]=fn(a:int,b)->[A]{[b]};";
+    let expected = "let f: fn(int) -> [A] = fn(a: int, b) -> [A] {
+    [b]
+};
+";
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert_canonical(&parse(source), expected);
+}
+
+#[test]
+fn test_conformance_programs() {
+    let mut count = 0;
+    for entry in fs::read_dir("tests/conformance").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some(OsStr::new("monkey")) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let formatted = format_source(&source).unwrap();
+        assert_canonical(&parse(&source), &formatted);
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn test_generated_programs() {
+    for seed in 0..500 {
+        let program = Generator::new(seed).program();
+        let formatted = format_program(&program);
+        assert_canonical(&program, &formatted);
+    }
+}
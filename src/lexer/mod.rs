@@ -1,43 +1,106 @@
 #[cfg(test)]
 mod test;
 
-use crate::token;
+use crate::token::{self, Position};
 
 pub struct Lexer {
-    input: String,
+    // Held as chars rather than a String so that positions are character
+    // offsets, which keeps multi-byte characters in string literals intact.
+    input: Vec<char>,
     position: usize,
     read_position: usize,
     ch: char,
+    // The line and column of `ch`.
+    line: usize,
+    column: usize,
+    token_position: Position,
+    comments: Vec<Comment>,
+}
+
+/**
+ * A `//` comment, which runs to the end of its line. The parser never sees
+ * comments; they are kept only so that the formatter can put them back.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment's text, from the slashes up to the end of the line.
+    pub text: String,
+    pub position: Position,
 }
 
 pub fn new(input: &str) -> Lexer {
     let mut l = Lexer {
-        input: String::from(input),
+        input: input.chars().collect(),
         position: 0,
         read_position: 0,
         ch: '\0',
+        line: 1,
+        column: 0,
+        token_position: Position::default(),
+        comments: vec![],
     };
     l.read_char();
     l
 }
 
 fn is_letter(ch: char) -> bool {
-    ('a' <= ch && ch <= 'z') || ('A' <= ch && ch <= 'Z') || ch == '_'
+    ch.is_ascii_lowercase() || ch.is_ascii_uppercase() || ch == '_'
 }
 
 fn is_digit(ch: char) -> bool {
-    '0' <= ch && ch <= '9'
+    ch.is_ascii_digit()
 }
 
 impl Lexer {
     fn skip_whitespace(&mut self) {
-        while self.ch == ' ' || self.ch == '\t' || self.ch == '\n' || self.ch == '\r' {
+        loop {
+            while self.ch == ' ' || self.ch == '\t' || self.ch == '\n' || self.ch == '\r' {
+                self.read_char();
+            }
+            if self.ch == '/' && self.peek_char() == '/' {
+                self.read_comment();
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn read_comment(&mut self) {
+        let position = Position {
+            line: self.line,
+            column: self.column,
+        };
+        let start_pos = self.position;
+        while self.ch != '\n' && self.position < self.input.len() {
             self.read_char();
         }
+        let text: String = self.input[start_pos..self.position].iter().collect();
+        self.comments.push(Comment {
+            text: String::from(text.trim_end()),
+            position,
+        });
+    }
+
+    /**
+     * The comments passed over so far, in the order they appear.
+     */
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /**
+     * Where the token last returned by `next_token` starts.
+     */
+    pub fn token_position(&self) -> Position {
+        self.token_position
     }
 
     pub fn next_token(&mut self) -> token::Token {
         self.skip_whitespace();
+        self.token_position = Position {
+            line: self.line,
+            column: self.column,
+        };
         let token = match self.ch {
             '=' => {
                 if self.peek_char() == '=' {
@@ -52,7 +115,15 @@ impl Lexer {
             ')' => token::Token::RParen,
             ',' => token::Token::Comma,
             '+' => token::Token::Plus,
-            '-' => token::Token::Minus,
+            '-' => {
+                if self.peek_char() == '>' {
+                    self.read_char();
+                    token::Token::Arrow
+                } else {
+                    token::Token::Minus
+                }
+            }
+            ':' => token::Token::Colon,
             '/' => token::Token::Slash,
             '!' => {
                 if self.peek_char() == '=' {
@@ -67,6 +138,8 @@ impl Lexer {
             '*' => token::Token::Asterisk,
             '{' => token::Token::LBrace,
             '}' => token::Token::RBrace,
+            '[' => token::Token::LBracket,
+            ']' => token::Token::RBracket,
             '\0' => token::Token::Eof,
             _ => {
                 if is_letter(self.ch) {
@@ -77,12 +150,11 @@ impl Lexer {
                     let literal = self.read_number();
                     return token::Token::Int { literal };
                 } else if self.ch == '"' {
-                    let string = self.read_string();
-                    return token::Token::String { literal: string };
+                    return self.read_string();
                 } else {
-                    return token::Token::Illegal {
+                    token::Token::Illegal {
                         literal: self.ch.to_string(),
-                    };
+                    }
                 }
             }
         };
@@ -92,11 +164,16 @@ impl Lexer {
     }
 
     fn read_char(&mut self) {
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         if self.read_position >= self.input.len() {
             self.ch = '\0';
         } else {
-            let all_chars: Vec<char> = self.input.chars().collect();
-            self.ch = all_chars[self.read_position];
+            self.ch = self.input[self.read_position];
         }
         self.position = self.read_position;
         self.read_position += 1;
@@ -107,7 +184,7 @@ impl Lexer {
         while is_letter(self.ch) {
             self.read_char();
         }
-        self.input[start_pos..self.position].to_string()
+        self.input[start_pos..self.position].iter().collect()
     }
 
     fn read_number(&mut self) -> String {
@@ -115,26 +192,34 @@ impl Lexer {
         while is_digit(self.ch) {
             self.read_char();
         }
-        self.input[start_pos..self.position].to_string()
+        self.input[start_pos..self.position].iter().collect()
     }
 
-    fn read_string(&mut self) -> String {
-        self.read_char(); // consume the opening quote
+    /**
+     * Reads a string literal, or an illegal token holding the rest of the
+     * input if the string is never closed.
+     */
+    fn read_string(&mut self) -> token::Token {
         let start_pos = self.position;
+        self.read_char(); // consume the opening quote
         while self.ch != '"' {
+            if self.position >= self.input.len() {
+                return token::Token::Illegal {
+                    literal: self.input[start_pos..].iter().collect(),
+                };
+            }
             self.read_char();
         }
-        let string = self.input[start_pos..self.position].to_string();
+        let literal = self.input[start_pos + 1..self.position].iter().collect();
         self.read_char(); // consume the closing quote
-        string
+        token::Token::String { literal }
     }
 
     fn peek_char(&self) -> char {
-        let chars: Vec<char> = self.input.chars().collect();
-        if self.read_position >= chars.len() {
+        if self.read_position >= self.input.len() {
             '\0'
         } else {
-            chars[self.read_position]
+            self.input[self.read_position]
         }
     }
 }
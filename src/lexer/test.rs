@@ -25,6 +25,7 @@ fn test_next_token() {
         10 != 9;
 
         "hi";
+        [1, 2];
         "#,
     );
 
@@ -152,6 +153,16 @@ fn test_next_token() {
             literal: String::from("hi"),
         },
         Token::Semicolon,
+        Token::LBracket,
+        Token::Int {
+            literal: String::from("1"),
+        },
+        Token::Comma,
+        Token::Int {
+            literal: String::from("2"),
+        },
+        Token::RBracket,
+        Token::Semicolon,
         Token::Eof,
     ];
     let mut lexer = lexer::new(&input);
@@ -160,3 +171,121 @@ fn test_next_token() {
         assert_eq!(&tok, test);
     }
 }
+
+#[test]
+fn test_token_positions() {
+    let mut lexer = lexer::new("let x = 5;\n  x == \"é\" + 1");
+    let mut positions = vec![];
+    loop {
+        let token = lexer.next_token();
+        let position = lexer.token_position();
+        positions.push((position.line, position.column));
+        if token == Token::Eof {
+            break;
+        }
+    }
+    assert_eq!(
+        positions,
+        vec![
+            (1, 1),
+            (1, 5),
+            (1, 7),
+            (1, 9),
+            (1, 10),
+            (2, 3),
+            (2, 5),
+            (2, 8),
+            (2, 12),
+            (2, 14),
+            (2, 15)
+        ]
+    );
+}
+
+#[test]
+fn test_malformed_input() {
+    let mut lexer = lexer::new("@ 1 \"open");
+    assert_eq!(
+        lexer.next_token(),
+        Token::Illegal {
+            literal: String::from("@"),
+        }
+    );
+    assert_eq!(
+        lexer.next_token(),
+        Token::Int {
+            literal: String::from("1"),
+        }
+    );
+    assert_eq!(
+        lexer.next_token(),
+        Token::Illegal {
+            literal: String::from("\"open"),
+        }
+    );
+    assert_eq!(lexer.next_token(), Token::Eof);
+    assert_eq!(lexer.next_token(), Token::Eof);
+}
+
+#[test]
+fn test_comments() {
+    let mut lexer = lexer::new("// first\nx / 2; // second \"\n  // third");
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token();
+        if token == Token::Eof {
+            break;
+        }
+        tokens.push(token);
+    }
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident {
+                literal: String::from("x"),
+            },
+            Token::Slash,
+            Token::Int {
+                literal: String::from("2"),
+            },
+            Token::Semicolon,
+        ]
+    );
+    let comments: Vec<(&str, usize, usize)> = lexer
+        .comments()
+        .iter()
+        .map(|comment| {
+            let position = comment.position;
+            (comment.text.as_str(), position.line, position.column)
+        })
+        .collect();
+    assert_eq!(
+        comments,
+        vec![
+            ("// first", 1, 1),
+            ("// second \"", 2, 8),
+            ("// third", 3, 3)
+        ]
+    );
+}
+
+#[test]
+fn test_annotation_tokens() {
+    let mut lexer = lexer::new("let f: fn(int) -> int; a - >b");
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token();
+        if token == Token::Eof {
+            break;
+        }
+        tokens.push(token.token_type());
+    }
+    use token::TokenType::*;
+    assert_eq!(
+        tokens,
+        vec![
+            Let, Ident, Colon, Function, LParen, Ident, RParen, Arrow, Ident, Semicolon, Ident,
+            Minus, Gt, Ident
+        ]
+    );
+}
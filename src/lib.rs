@@ -1,11 +1,21 @@
 pub mod ast;
 pub mod code;
 pub mod compiler;
+#[cfg(test)]
+mod conformance;
+pub mod engine;
 pub mod errors;
 pub mod eval;
+pub mod format;
 pub mod lexer;
+pub mod limits;
+pub mod lint;
 pub mod logic;
+pub mod lsp;
 pub mod object;
 pub mod parser;
+pub mod register;
+pub mod resolve;
 pub mod token;
+pub mod typecheck;
 pub mod vm;
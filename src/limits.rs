@@ -0,0 +1,256 @@
+use crate::logic::InfixOperator;
+use crate::object::Object;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/**
+ * How deeply function calls may nest unless told otherwise. The interpreter
+ * recurses on the native stack, and this is about as deep as a release build
+ * can go on an 8 MB stack with room to spare. A debug build needs more.
+ */
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/**
+ * Bounds on what one run of a script may do, for running code that isn't
+ * trusted. Both the interpreter and the VM enforce them. By default only
+ * the depth is limited, so that runaway recursion fails cleanly rather than
+ * overflowing the stack.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// How deeply function calls may nest.
+    pub max_depth: Option<usize>,
+    /// How many steps a run may take: expressions evaluated by the
+    /// interpreter, or instructions executed by the VM.
+    pub fuel: Option<u64>,
+    /// How many allocations a run may make in all. This is a budget
+    /// rather than a bound on memory in use: objects that have since been
+    /// freed still count. An array also counts one for each of its
+    /// elements.
+    pub max_allocations: Option<usize>,
+    /// The longest string, in bytes, that a run may create.
+    pub max_string_length: Option<usize>,
+    /// How long a run may take before it is interrupted.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            fuel: None,
+            max_allocations: None,
+            max_string_length: None,
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    Depth(usize),
+    Fuel(u64),
+    Allocations(usize),
+    StringLength(usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Depth(max) => write!(f, "Function calls nested more than {} deep", max),
+            LimitExceeded::Fuel(max) => write!(f, "Ran out of fuel after {} steps", max),
+            LimitExceeded::Allocations(max) => {
+                write!(f, "Made more than {} allocations", max)
+            }
+            LimitExceeded::StringLength(max) => {
+                write!(f, "Created a string longer than {} bytes", max)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+/**
+ * Lets a host stop a running script, typically from another thread. The run
+ * notices within a few hundred steps and fails with an `Interrupted` error.
+ * The handle stays interrupted, stopping any later runs it is given to as
+ * well, until it is reset.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        InterruptHandle::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    pub fn reset(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+}
+
+// Checking the clock on every step would slow runs down noticeably.
+const POLL_INTERVAL: u32 = 256;
+
+/**
+ * Keeps track of how much of its limits a run has used. The first limit to
+ * be exceeded is remembered, so that the error can still be told apart from
+ * others after passing through code that only deals in messages, such as a
+ * builtin calling back into a script.
+ */
+#[derive(Debug, Default)]
+pub struct Meter {
+    limits: Limits,
+    steps: u64,
+    allocations: usize,
+    exceeded: Option<LimitExceeded>,
+    interrupt: Option<InterruptHandle>,
+    deadline: Option<Instant>,
+    polls: u32,
+    interrupted: bool,
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Self {
+        Meter {
+            limits,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            ..Meter::default()
+        }
+    }
+
+    pub fn set_interrupt(&mut self, interrupt: InterruptHandle) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /**
+     * Called regularly by a running program; fails if it has been
+     * interrupted or has run out of time.
+     */
+    pub fn poll(&mut self) -> Result<(), Interrupted> {
+        // Counts down, so that the first poll of a run checks.
+        if self.polls > 0 {
+            self.polls -= 1;
+            return Ok(());
+        }
+        self.polls = POLL_INTERVAL;
+        let interrupted = self
+            .interrupt
+            .as_ref()
+            .is_some_and(InterruptHandle::is_interrupted);
+        let timed_out = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if interrupted || timed_out {
+            self.interrupted = true;
+            return Err(Interrupted);
+        }
+        Ok(())
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn exceeded(&self) -> Option<LimitExceeded> {
+        self.exceeded
+    }
+
+    pub fn step(&mut self) -> Result<(), LimitExceeded> {
+        self.steps += 1;
+        match self.limits.fuel {
+            Some(fuel) if self.steps > fuel => self.exceed(LimitExceeded::Fuel(fuel)),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Checks the depth a function call is about to run at, counting from 1
+     * for a call made by the top level of the program.
+     */
+    pub fn check_depth(&mut self, depth: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_depth {
+            Some(max) if depth > max => self.exceed(LimitExceeded::Depth(max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_string_length(&mut self, length: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_string_length {
+            Some(max) if length > max => self.exceed(LimitExceeded::StringLength(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Checks the string an infix expression is about to build, if it
+     * builds one, before the memory is spent.
+     */
+    pub fn check_infix(
+        &mut self,
+        left: &Object,
+        operator: &InfixOperator,
+        right: &Object,
+    ) -> Result<(), LimitExceeded> {
+        match (left, operator, right) {
+            (Object::String(left), InfixOperator::Plus, Object::String(right)) => {
+                self.check_string_length(left.len().saturating_add(right.len()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Counts an object that the run has just created against its
+     * allocation budget.
+     */
+    pub fn allocate(&mut self, object: &Object) -> Result<(), LimitExceeded> {
+        match object {
+            Object::String(string) => self.check_string_length(string.len())?,
+            Object::Array(elements) => return self.count(1 + elements.len()),
+            _ => {}
+        }
+        self.count(1)
+    }
+
+    /**
+     * Counts objects the run has made without allocating them one by one,
+     * such as elements added to an array.
+     */
+    pub fn count(&mut self, allocations: usize) -> Result<(), LimitExceeded> {
+        self.allocations += allocations;
+        match self.limits.max_allocations {
+            Some(max) if self.allocations > max => self.exceed(LimitExceeded::Allocations(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn exceed(&mut self, limit: LimitExceeded) -> Result<(), LimitExceeded> {
+        self.exceeded.get_or_insert(limit);
+        Err(limit)
+    }
+}
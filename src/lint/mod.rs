@@ -0,0 +1,449 @@
+#[cfg(test)]
+mod test;
+
+use crate::ast::visit::{walk_expression, walk_statement};
+use crate::ast::{BlockStatement, Expression, InfixOperator, Position, Span, Statement, Visitor};
+use crate::eval::builtins::{Arity, BuiltinRegistry};
+use crate::lexer;
+use crate::parser::{self, ParserError};
+use std::collections::HashMap;
+use std::fmt;
+use std::vec;
+
+/**
+ * Something the linter looks for. Each rule can be allowed, warned about
+ * or denied on its own.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedBinding,
+    UnusedParameter,
+    ShadowedBuiltin,
+    UnreachableCode,
+    ConstantCondition,
+    WrongArity,
+    SelfComparison,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UnusedBinding,
+        Rule::UnusedParameter,
+        Rule::ShadowedBuiltin,
+        Rule::UnreachableCode,
+        Rule::ConstantCondition,
+        Rule::WrongArity,
+        Rule::SelfComparison,
+    ];
+
+    /**
+     * The name the rule goes by on the command line and in reports.
+     */
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::UnusedBinding => "unused-binding",
+            Rule::UnusedParameter => "unused-parameter",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::WrongArity => "wrong-arity",
+            Rule::SelfComparison => "self-comparison",
+        }
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::ALL
+            .iter()
+            .find(|rule| rule.id() == s)
+            .copied()
+            .ok_or_else(|| {
+                let ids: Vec<&str> = Rule::ALL.iter().map(|rule| rule.id()).collect();
+                format!("Not a lint rule. Expected one of {}", ids.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string = match self {
+            Level::Allow => "allowed",
+            Level::Warn => "warning",
+            Level::Deny => "error",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+/**
+ * How seriously to take each rule. Every rule warns unless told otherwise.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    levels: HashMap<Rule, Level>,
+}
+
+impl Config {
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.insert(rule, level);
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels.get(&rule).copied().unwrap_or(Level::Warn)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.span.start;
+        write!(
+            f,
+            "{}:{}: {}[{}]: {}",
+            start.line,
+            start.column,
+            self.level,
+            self.rule.id(),
+            self.message
+        )
+    }
+}
+
+/**
+ * Parses the source and reports what the enabled rules find in it, in the
+ * order it appears.
+ */
+pub fn lint(source: &str, config: &Config) -> Result<Vec<Diagnostic>, ParserError> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program()?;
+    let layout = parser.into_layout();
+    let mut linter = Linter {
+        config,
+        builtins: BuiltinRegistry::with_defaults(),
+        statement_spans: layout.statements.into_iter(),
+        scopes: vec![],
+        diagnostics: vec![],
+    };
+    linter.scoped(|linter| linter.statements(&program.statements));
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| {
+        let start = diagnostic.span.start;
+        (start.line, start.column)
+    });
+    Ok(diagnostics)
+}
+
+enum Kind {
+    Let,
+    Parameter,
+}
+
+struct Binding {
+    name: String,
+    span: Span,
+    kind: Kind,
+    /// How many arguments it takes, if it's bound straight to a function.
+    arity: Option<usize>,
+    used: bool,
+}
+
+struct Linter<'a> {
+    config: &'a Config,
+    builtins: BuiltinRegistry,
+    // The start and end of each statement, in the order they are walked.
+    statement_spans: vec::IntoIter<(Position, Position)>,
+    // The bindings of each enclosing scope, innermost last.
+    scopes: Vec<Vec<Binding>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, span: Span, message: String) {
+        let level = self.config.level(rule);
+        if level != Level::Allow {
+            self.diagnostics.push(Diagnostic {
+                rule,
+                level,
+                message,
+                span,
+            });
+        }
+    }
+
+    /**
+     * Runs `walk` in a scope of its own, then reports what it bound but
+     * never used. Names starting with an underscore are meant to be unused.
+     */
+    fn scoped<F: FnOnce(&mut Self)>(&mut self, walk: F) {
+        self.scopes.push(vec![]);
+        walk(self);
+        for binding in self.scopes.pop().unwrap() {
+            if binding.used || binding.name.starts_with('_') {
+                continue;
+            }
+            match binding.kind {
+                Kind::Let => self.report(
+                    Rule::UnusedBinding,
+                    binding.span,
+                    format!("'{}' is never used", binding.name),
+                ),
+                Kind::Parameter => self.report(
+                    Rule::UnusedParameter,
+                    binding.span,
+                    format!("The parameter '{}' is never used", binding.name),
+                ),
+            }
+        }
+    }
+
+    fn bind(&mut self, name: &str, position: Position, kind: Kind, arity: Option<usize>) {
+        let span = Span::new(position, name.chars().count());
+        if self.builtins.get(name).is_some() {
+            self.report(
+                Rule::ShadowedBuiltin,
+                span,
+                format!("'{}' hides the builtin of the same name", name),
+            );
+        }
+        self.scopes.last_mut().unwrap().push(Binding {
+            name: String::from(name),
+            span,
+            kind,
+            arity,
+            used: false,
+        });
+    }
+
+    /**
+     * Marks the binding a name refers to, if there is one, as used.
+     */
+    fn use_name(&mut self, name: &str) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|binding| binding.name == name);
+        if let Some(binding) = binding {
+            binding.used = true;
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        let mut unreachable: Option<Span> = None;
+        let mut returned = false;
+        for statement in statements {
+            let span = self.statement_spans.next().unwrap_or_default();
+            if returned {
+                let start = unreachable.map_or(span.0, |span| span.start);
+                unreachable = Some(Span {
+                    start,
+                    end: Position {
+                        line: span.1.line,
+                        column: span.1.column + 1,
+                    },
+                });
+            }
+            self.visit_statement(statement);
+            returned |= matches!(statement, Statement::Return { .. });
+        }
+        if let Some(span) = unreachable {
+            self.report(
+                Rule::UnreachableCode,
+                span,
+                String::from("This code comes after a return, so never runs"),
+            );
+        }
+    }
+
+    /**
+     * Checks a call to a function bound by name, or to a builtin, is given
+     * as many arguments as it takes.
+     */
+    fn arity(&mut self, function: &Expression, num_args: usize, position: Position) {
+        let (name, start) = match function {
+            Expression::Identifier {
+                value, position, ..
+            } => (value, *position),
+            _ => return,
+        };
+        // Looked up without marking the binding used, which walking the
+        // function expression will do.
+        let bound = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|binding| &binding.name == name);
+        let arity = match bound {
+            Some(binding) => binding.arity.map(Arity::Exactly),
+            None => self.builtins.get(name).map(|builtin| builtin.arity),
+        };
+        if let Some(arity) = arity {
+            if !arity.accepts(num_args) {
+                let span = Span {
+                    start,
+                    end: Position {
+                        line: position.line,
+                        column: position.column + 1,
+                    },
+                };
+                self.report(
+                    Rule::WrongArity,
+                    span,
+                    format!("'{}' takes {} but is given {}", name, arity, num_args),
+                );
+            }
+        }
+    }
+
+    fn self_comparison(
+        &mut self,
+        left: &Expression,
+        operator: &InfixOperator,
+        right: &Expression,
+        position: Position,
+    ) {
+        let comparison = matches!(
+            operator,
+            InfixOperator::Eq | InfixOperator::NotEq | InfixOperator::Lt | InfixOperator::Gt
+        );
+        if comparison && left.same_shape(right) && is_pure(left) {
+            let length = operator.to_string().len();
+            self.report(
+                Rule::SelfComparison,
+                Span::new(position, length),
+                format!("Both sides of {} are the same", operator),
+            );
+        }
+    }
+}
+
+impl Visitor for Linter<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                name,
+                right,
+                position,
+                ..
+            } => {
+                if let Expression::FnLiteral { param_names, .. } = right {
+                    // Bound before its body, so that it can call itself.
+                    self.bind(name, *position, Kind::Let, Some(param_names.len()));
+                    self.visit_expression(right);
+                } else {
+                    self.visit_expression(right);
+                    self.bind(name, *position, Kind::Let, None);
+                }
+            }
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier { value, .. } => self.use_name(value),
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                position,
+            } => {
+                self.self_comparison(left, operator, right, *position);
+                walk_expression(self, expression);
+            }
+            Expression::If {
+                condition,
+                position,
+                ..
+            } => {
+                if is_constant(condition) {
+                    self.report(
+                        Rule::ConstantCondition,
+                        Span::new(*position, 2),
+                        String::from("The condition is the same every time"),
+                    );
+                }
+                walk_expression(self, expression);
+            }
+            Expression::FnLiteral {
+                param_names,
+                param_positions,
+                body,
+                ..
+            } => self.scoped(|linter| {
+                for (name, position) in param_names.iter().zip(param_positions) {
+                    linter.bind(name, *position, Kind::Parameter, None);
+                }
+                linter.statements(&body.statements);
+            }),
+            Expression::CallExpression {
+                left,
+                arguments,
+                position,
+            } => {
+                self.arity(left, arguments.len(), *position);
+                walk_expression(self, expression);
+            }
+            Expression::Block { statements } => self.scoped(|linter| linter.statements(statements)),
+            _ => walk_expression(self, expression),
+        }
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.scoped(|linter| linter.statements(&block.statements));
+    }
+}
+
+/**
+ * Whether an expression has the same value however the program got to it.
+ */
+fn is_constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::IntegerLiteral { .. }
+        | Expression::StringLiteral { .. }
+        | Expression::Boolean { .. }
+        | Expression::FnLiteral { .. } => true,
+        Expression::Prefix { right, .. } => is_constant(right),
+        Expression::Infix { left, right, .. } => is_constant(left) && is_constant(right),
+        Expression::ArrayLiteral { elements } => elements.iter().all(is_constant),
+        _ => false,
+    }
+}
+
+/**
+ * Whether evaluating an expression twice must give the same value, as it
+ * calls nothing.
+ */
+fn is_pure(expression: &Expression) -> bool {
+    match expression {
+        Expression::Identifier { .. }
+        | Expression::IntegerLiteral { .. }
+        | Expression::StringLiteral { .. }
+        | Expression::Boolean { .. } => true,
+        Expression::Prefix { right, .. } => is_pure(right),
+        Expression::Infix { left, right, .. } => is_pure(left) && is_pure(right),
+        Expression::ArrayLiteral { elements } => elements.iter().all(is_pure),
+        Expression::Index { left, index, .. } => is_pure(left) && is_pure(index),
+        _ => false,
+    }
+}
@@ -0,0 +1,160 @@
+use super::{lint, Config, Level, Rule};
+use pretty_assertions::assert_eq;
+
+// A rule id, and where what it found starts and ends as (line, column).
+type Finding = (&'static str, (usize, usize), (usize, usize));
+
+/**
+ * Lints the source with every rule on.
+ */
+fn findings(source: &str) -> Vec<Finding> {
+    lint(source, &Config::default())
+        .unwrap()
+        .iter()
+        .map(|diagnostic| {
+            let span = diagnostic.span;
+            (
+                diagnostic.rule.id(),
+                (span.start.line, span.start.column),
+                (span.end.line, span.end.column),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_clean_program() {
+    let source = "let double = fn(x) { x * 2 };
+let _ignored = 1;
+let count = fn(items, _unused) { len(items) };
+print(double(count([1, 2], 0)));";
+    assert_eq!(findings(source), vec![]);
+}
+
+#[test]
+fn test_unused_bindings() {
+    let source = "let a = 1;
+let f = fn(x, y) {
+    let inner = x;
+    let a = 2;
+    a
+};
+f(1, 2);
+{ let b = 3; }";
+    assert_eq!(
+        findings(source),
+        vec![
+            ("unused-binding", (1, 5), (1, 6)),
+            ("unused-parameter", (2, 15), (2, 16)),
+            ("unused-binding", (3, 9), (3, 14)),
+            ("unused-binding", (8, 7), (8, 8)),
+        ]
+    );
+}
+
+#[test]
+fn test_recursive_function_is_bound_in_its_body() {
+    let source = "let count = fn(n) { if (n > 0) { count(n - 1) } else { 0 } }; count(3)";
+    assert_eq!(findings(source), vec![]);
+}
+
+#[test]
+fn test_shadowed_builtins() {
+    let source = "let len = fn(map) { map }; len(1)";
+    assert_eq!(
+        findings(source),
+        vec![
+            ("shadowed-builtin", (1, 5), (1, 8)),
+            ("shadowed-builtin", (1, 14), (1, 17)),
+        ]
+    );
+}
+
+#[test]
+fn test_unreachable_code() {
+    let source = "let f = fn() {
+    return 1;
+    print(2);
+    3
+};
+f()";
+    assert_eq!(findings(source), vec![("unreachable-code", (3, 5), (4, 6))]);
+}
+
+#[test]
+fn test_constant_conditions() {
+    let source = "let x = 1;
+if (true) { x }
+if (1 < 2) { x }
+if (x < 2) { x }
+if (fn() { x }) { x }";
+    assert_eq!(
+        findings(source),
+        vec![
+            ("constant-condition", (2, 1), (2, 3)),
+            ("constant-condition", (3, 1), (3, 3)),
+            ("constant-condition", (5, 1), (5, 3)),
+        ]
+    );
+}
+
+#[test]
+fn test_wrong_arity() {
+    let source = "let add = fn(a, b) { a + b };
+add(1);
+add(1, 2);
+len(\"a\", \"b\");
+format(\"{}\", 1, 2);
+let g = add;
+g(1)";
+    assert_eq!(
+        findings(source),
+        vec![
+            ("wrong-arity", (2, 1), (2, 5)),
+            ("wrong-arity", (4, 1), (4, 5)),
+        ]
+    );
+    let message = &lint(source, &Config::default()).unwrap()[0].message;
+    assert_eq!(message, "'add' takes exactly 2 arguments but is given 1");
+}
+
+#[test]
+fn test_self_comparison() {
+    let source = "let a = [1];
+a == a;
+a[0] != a[0];
+a < a;
+a == [1];
+rest(a) == rest(a)";
+    assert_eq!(
+        findings(source),
+        vec![
+            ("self-comparison", (2, 3), (2, 5)),
+            ("self-comparison", (3, 6), (3, 8)),
+            ("self-comparison", (4, 3), (4, 4)),
+        ]
+    );
+}
+
+#[test]
+fn test_configured_levels() {
+    let source = "let unused = 1; let len = 2; len";
+    let mut config = Config::default();
+    config.set(Rule::UnusedBinding, Level::Allow);
+    config.set(Rule::ShadowedBuiltin, Level::Deny);
+    let diagnostics = lint(source, &config).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].level, Level::Deny);
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "1:21: error[shadowed-builtin]: 'len' hides the builtin of the same name"
+    );
+}
+
+#[test]
+fn test_rule_ids() {
+    for rule in Rule::ALL.iter() {
+        assert_eq!(rule.id().parse::<Rule>(), Ok(*rule));
+    }
+    assert!("no-such-rule".parse::<Rule>().is_err());
+}
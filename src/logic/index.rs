@@ -0,0 +1,22 @@
+use crate::object::Object;
+use std::rc::Rc;
+
+pub fn eval_index(left: &Object, index: &Object) -> Result<Rc<Object>, String> {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(i)) => {
+            let element = if *i < 0 {
+                None
+            } else {
+                elements.get(*i as usize)
+            };
+            Ok(element
+                .map(Rc::clone)
+                .unwrap_or_else(|| Rc::new(Object::Null)))
+        }
+        _ => Err(format!(
+            "Cannot index {} with {}",
+            left.type_name(),
+            index.type_name()
+        )),
+    }
+}
@@ -1,9 +1,8 @@
 use crate::object;
 use object::Object;
 use std::fmt;
-use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InfixOperator {
     Plus,
     Minus,
@@ -31,25 +30,26 @@ impl fmt::Display for InfixOperator {
     }
 }
 
-pub fn eval_infix<'a>(
-    left: Rc<Object<'a>>,
-    op: &InfixOperator,
-    right: Rc<Object<'a>>,
-) -> Result<Object<'a>, String> {
-    match (&*left, &op, &*right) {
+pub fn eval_infix(left: &Object, op: &InfixOperator, right: &Object) -> Result<Object, String> {
+    match (left, &op, right) {
         (_, InfixOperator::Eq, _) => Ok(Object::Boolean(left == right)),
         (_, InfixOperator::NotEq, _) => Ok(Object::Boolean(left != right)),
-        (Object::Integer(left), InfixOperator::Plus, Object::Integer(right)) => {
-            Ok(Object::Integer(left + right))
+        (Object::Integer(left), InfixOperator::Divide, Object::Integer(0)) => {
+            Err(format!("Cannot divide {} by zero", left))
         }
-        (Object::Integer(left), InfixOperator::Minus, Object::Integer(right)) => {
-            Ok(Object::Integer(left - right))
-        }
-        (Object::Integer(left), InfixOperator::Multiply, Object::Integer(right)) => {
-            Ok(Object::Integer(left * right))
-        }
-        (Object::Integer(left), InfixOperator::Divide, Object::Integer(right)) => {
-            Ok(Object::Integer(left / right))
+        (Object::Integer(l), InfixOperator::Plus, Object::Integer(r))
+        | (Object::Integer(l), InfixOperator::Minus, Object::Integer(r))
+        | (Object::Integer(l), InfixOperator::Multiply, Object::Integer(r))
+        | (Object::Integer(l), InfixOperator::Divide, Object::Integer(r)) => {
+            let result = match op {
+                InfixOperator::Plus => l.checked_add(*r),
+                InfixOperator::Minus => l.checked_sub(*r),
+                InfixOperator::Multiply => l.checked_mul(*r),
+                _ => l.checked_div(*r),
+            };
+            result
+                .map(Object::Integer)
+                .ok_or_else(|| format!("Integer overflow evaluating {} {} {}", l, op, r))
         }
         (Object::Integer(left), InfixOperator::Gt, Object::Integer(right)) => {
             Ok(Object::Boolean(left > right))
@@ -60,6 +60,12 @@ pub fn eval_infix<'a>(
         (Object::String(left), InfixOperator::Plus, Object::String(right)) => {
             Ok(Object::String(format!("{}{}", left, right)))
         }
+        (Object::String(left), InfixOperator::Gt, Object::String(right)) => {
+            Ok(Object::Boolean(left > right))
+        }
+        (Object::String(left), InfixOperator::Lt, Object::String(right)) => {
+            Ok(Object::Boolean(left < right))
+        }
         (left, op, right) => Err(format!(
             "Cannot evaluate infix expression {} {} {}",
             left, op, right
@@ -1,5 +1,7 @@
+mod index;
 mod infix;
 mod prefix;
 
+pub use index::*;
 pub use infix::*;
 pub use prefix::*;
@@ -1,9 +1,8 @@
 use std::fmt;
-use std::rc::Rc;
 
 use crate::object::Object;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PrefixOperator {
     Bang,
     Minus,
@@ -18,12 +17,12 @@ impl fmt::Display for PrefixOperator {
     }
 }
 
-pub fn eval_prefix<'a>(
-    operand: Rc<Object<'a>>,
-    operator: &PrefixOperator,
-) -> Result<Object<'a>, String> {
-    match (operator, &*operand) {
-        (PrefixOperator::Minus, Object::Integer(value)) => Ok(Object::Integer(-value)),
+pub fn eval_prefix(operand: &Object, operator: &PrefixOperator) -> Result<Object, String> {
+    match (operator, operand) {
+        (PrefixOperator::Minus, Object::Integer(value)) => value
+            .checked_neg()
+            .map(Object::Integer)
+            .ok_or_else(|| format!("Integer overflow negating {}", value)),
         (PrefixOperator::Bang, Object::Boolean(value)) => Ok(Object::Boolean(!value)),
         _ => Err(format!(
             "The prefix {} cannot appear before type {}",
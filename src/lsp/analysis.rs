@@ -0,0 +1,272 @@
+use crate::ast::visit::{walk_expression, walk_statement};
+use crate::ast::{BlockStatement, Expression, Position, Program, Span, Statement, Visitor};
+use crate::parser::Layout;
+use crate::resolve::Resolution;
+use std::vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindingKind {
+    Let,
+    Parameter,
+}
+
+/**
+ * A name bound by a `let` or a parameter.
+ */
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub span: Span,
+    pub kind: BindingKind,
+    /// Whether it's bound straight to a function literal.
+    pub function: bool,
+    /// The closing brace of the block or function it's bound in, or None at
+    /// the top level.
+    pub scope_end: Option<Position>,
+}
+
+/**
+ * A name used in an expression.
+ */
+#[derive(Debug, Clone)]
+pub struct Use {
+    pub name: String,
+    pub span: Span,
+    /// The index of the binding it refers to, if it's one in the program.
+    pub binding: Option<usize>,
+}
+
+/**
+ * A `let`, for outlining the program, with the `let`s in what it's bound
+ * to.
+ */
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// Where its name is.
+    pub span: Span,
+    /// Where its whole statement is.
+    pub range: Span,
+    pub function: bool,
+    pub children: Vec<Symbol>,
+}
+
+/**
+ * What an editor needs to know about the names in a program.
+ */
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// In the order they're bound.
+    pub bindings: Vec<Binding>,
+    /// In the order they appear.
+    pub uses: Vec<Use>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Analysis {
+    /**
+     * Gathers what the resolver found about the program's names, along
+     * with where each binding's scope ends and an outline of the `let`s.
+     */
+    pub fn new(program: &Program, layout: Layout, resolution: Resolution) -> Self {
+        let mut indexer = Indexer {
+            bindings: vec![],
+            statement_spans: layout.statements.into_iter(),
+            block_ends: layout.block_ends.into_iter(),
+            scopes: vec![],
+            symbols: vec![vec![]],
+        };
+        indexer.scoped(&[], &program.statements);
+        // Both walk the program in the same order.
+        debug_assert_eq!(indexer.bindings.len(), resolution.bindings.len());
+        let uses = resolution
+            .uses
+            .into_iter()
+            .map(|(name, position, binding)| Use {
+                span: Span::new(position, name.chars().count()),
+                name,
+                binding,
+            })
+            .collect();
+        Analysis {
+            bindings: indexer.bindings,
+            uses,
+            symbols: indexer.symbols.pop().unwrap(),
+        }
+    }
+
+    /**
+     * The binding the name at a position refers to, or is the name of.
+     */
+    pub fn binding_at(&self, position: Position) -> Option<usize> {
+        let used = self
+            .uses
+            .iter()
+            .find(|used| contains(used.span, position))
+            .and_then(|used| used.binding);
+        used.or_else(|| {
+            self.bindings
+                .iter()
+                .position(|binding| contains(binding.span, position))
+        })
+    }
+
+    /**
+     * The name used at a position.
+     */
+    pub fn use_at(&self, position: Position) -> Option<&Use> {
+        self.uses.iter().find(|used| contains(used.span, position))
+    }
+
+    /**
+     * Where the binding is used, in the order the uses appear.
+     */
+    pub fn references(&self, binding: usize) -> Vec<Span> {
+        self.uses
+            .iter()
+            .filter(|used| used.binding == Some(binding))
+            .map(|used| used.span)
+            .collect()
+    }
+
+    /**
+     * The bindings that code at a position can use, innermost first and
+     * each name only once.
+     */
+    pub fn in_scope(&self, position: Position) -> Vec<&Binding> {
+        let mut names: Vec<&Binding> = vec![];
+        for binding in self.bindings.iter().rev() {
+            let visible = before(binding.span.start, position)
+                && binding.scope_end.is_none_or(|end| !before(end, position));
+            if visible && !names.iter().any(|other| other.name == binding.name) {
+                names.push(binding);
+            }
+        }
+        names
+    }
+}
+
+fn key(position: Position) -> (usize, usize) {
+    (position.line, position.column)
+}
+
+fn before(a: Position, b: Position) -> bool {
+    key(a) < key(b)
+}
+
+/**
+ * Whether a position is in a span, or just after its end, where an editor
+ * puts the cursor after typing a name.
+ */
+fn contains(span: Span, position: Position) -> bool {
+    key(span.start) <= key(position) && key(position) <= key(span.end)
+}
+
+struct Indexer {
+    // In the order the resolver reaches them.
+    bindings: Vec<Binding>,
+    // The start and end of each statement, in the order they are walked.
+    statement_spans: vec::IntoIter<(Position, Position)>,
+    // The closing brace of each block, in the order they end.
+    block_ends: vec::IntoIter<Position>,
+    // The bindings made in each enclosing scope, innermost last.
+    scopes: Vec<Vec<usize>>,
+    // The symbols found in each enclosing `let`, innermost last.
+    symbols: Vec<Vec<Symbol>>,
+}
+
+impl Indexer {
+    /**
+     * Walks statements in a scope of their own. Bindings made by a block
+     * or function are visible until its closing brace.
+     */
+    fn scoped(&mut self, params: &[(String, Position)], statements: &[Statement]) {
+        self.scopes.push(vec![]);
+        for (name, position) in params {
+            self.bind(name, *position, BindingKind::Parameter, false);
+        }
+        for statement in statements {
+            self.visit_statement(statement);
+        }
+        let bindings = self.scopes.pop().unwrap();
+        // The program itself has no closing brace.
+        let end = if self.scopes.is_empty() {
+            None
+        } else {
+            self.block_ends.next()
+        };
+        for index in bindings {
+            self.bindings[index].scope_end = end;
+        }
+    }
+
+    fn bind(&mut self, name: &str, position: Position, kind: BindingKind, function: bool) {
+        self.scopes.last_mut().unwrap().push(self.bindings.len());
+        self.bindings.push(Binding {
+            name: String::from(name),
+            span: Span::new(position, name.chars().count()),
+            kind,
+            function,
+            scope_end: None,
+        });
+    }
+}
+
+impl Visitor for Indexer {
+    fn visit_statement(&mut self, statement: &Statement) {
+        let span = self.statement_spans.next().unwrap_or_default();
+        match statement {
+            Statement::Let {
+                name,
+                right,
+                position,
+                ..
+            } => {
+                let function = matches!(right, Expression::FnLiteral { .. });
+                self.symbols.push(vec![]);
+                self.bind(name, *position, BindingKind::Let, function);
+                self.visit_expression(right);
+                let children = self.symbols.pop().unwrap();
+                let end = Position {
+                    line: span.1.line,
+                    column: span.1.column + 1,
+                };
+                self.symbols.last_mut().unwrap().push(Symbol {
+                    name: name.clone(),
+                    span: Span::new(*position, name.chars().count()),
+                    range: Span { start: span.0, end },
+                    function,
+                    children,
+                });
+            }
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::FnLiteral {
+                param_names,
+                param_positions,
+                body,
+                ..
+            } => {
+                let params: Vec<(String, Position)> = param_names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let position = param_positions.get(i).copied().unwrap_or_default();
+                        (name.clone(), position)
+                    })
+                    .collect();
+                self.scoped(&params, &body.statements);
+            }
+            Expression::Block { statements } => self.scoped(&[], statements),
+            _ => walk_expression(self, expression),
+        }
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.scoped(&[], &block.statements);
+    }
+}
@@ -0,0 +1,678 @@
+#[cfg(test)]
+mod test;
+
+pub mod analysis;
+pub mod json;
+
+use crate::ast::{Position, Span};
+use crate::errors::MonkeyError;
+use crate::eval::builtins::{self, BuiltinRegistry};
+use crate::lint::{self, Level};
+use crate::token::KEYWORDS;
+use crate::{format, lexer, parser, resolve, typecheck};
+use analysis::{Analysis, Binding, BindingKind, Symbol};
+use json::Json;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+// The protocol's error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// The protocol's numbers for kinds of things.
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+
+/**
+ * Serves the Language Server Protocol over a pair of streams until the
+ * client says to exit. Returns whether it was asked to shut down first, as
+ * the protocol expects the process to exit with an error otherwise.
+ */
+pub fn run<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<bool> {
+    let mut server = Server::default();
+    while let Some(content) = read_message(input)? {
+        let message = match Json::parse(&content) {
+            Ok(message) => message,
+            Err(error) => {
+                let response = error_response(Json::Null, PARSE_ERROR, error);
+                write_message(output, &response)?;
+                continue;
+            }
+        };
+        if message.get("method").as_str() == Some("exit") {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+    }
+    Ok(server.shut_down)
+}
+
+/**
+ * Reads the content of the next message, or None at the end of the input.
+ */
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Message has no Content-Length")
+    })?;
+    // Read as it arrives rather than all allocated up front, as the length
+    // could be anything.
+    let mut content = vec![];
+    input.take(length as u64).read_to_end(&mut content)?;
+    if content.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Message ended early",
+        ));
+    }
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+/**
+ * An open document, and what was found in it when it last parsed.
+ */
+struct Document {
+    text: String,
+    analysis: Option<Analysis>,
+}
+
+/**
+ * Answers the client's requests about the documents it has open.
+ */
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    encoding: Encoding,
+    shut_down: bool,
+}
+
+/**
+ * How the client counts the characters in a line: in UTF-16 code units
+ * unless it says it can count whole characters, as the source does.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Encoding {
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl Server {
+    /**
+     * Handles one message from the client, returning the messages to send
+     * back: a response to a request, and any notifications.
+     */
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = match message.get("method").as_str() {
+            Some(method) => method,
+            // A response to something we asked, which we never do.
+            None => return vec![],
+        };
+        let params = message.get("params");
+        let id = message.get("id");
+        if *id == Json::Null {
+            return self.notification(method, params);
+        }
+        let result = if self.shut_down {
+            // Only exit is expected now, and that never gets here.
+            Err((INVALID_REQUEST, String::from("Shut down")))
+        } else {
+            self.request(method, params)
+        };
+        vec![match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            Err((code, message)) => error_response(id.clone(), code, message),
+        }]
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => Err((METHOD_NOT_FOUND, format!("No method {}", method))),
+        }
+    }
+
+    /**
+     * Agrees how to count characters with the client, and says what the
+     * server can do.
+     */
+    fn initialize(&mut self, params: &Json) -> Json {
+        let offered = params
+            .get("capabilities")
+            .get("general")
+            .get("positionEncodings")
+            .as_array()
+            .unwrap_or_default();
+        self.encoding = if offered
+            .iter()
+            .any(|encoding| encoding.as_str() == Some("utf-32"))
+        {
+            Encoding::Utf32
+        } else {
+            Encoding::Utf16
+        };
+        capabilities(self.encoding)
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = match params.get("textDocument").get("uri").as_str() {
+            Some(uri) => String::from(uri),
+            None => return vec![],
+        };
+        let text = match method {
+            "textDocument/didOpen" => params.get("textDocument").get("text").as_str(),
+            // Only ever sent whole, as the server asks.
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text").as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, vec![])];
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => {
+                let diagnostics = self.update(&uri, text);
+                vec![publish_diagnostics(&uri, diagnostics)]
+            }
+            None => vec![],
+        }
+    }
+
+    /**
+     * Takes in the new text of a document, returning what's wrong with it.
+     * If it doesn't parse, what was found when it last did is kept, so that
+     * an editor can still get around while the code is being typed.
+     */
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        let previous = self
+            .documents
+            .remove(uri)
+            .and_then(|document| document.analysis);
+        let positions = Positions::new(text, self.encoding);
+        let diagnostic = |span, severity, code, message: &dyn ToString| {
+            diagnostic(positions.range(span), severity, code, message.to_string())
+        };
+        let mut lexer = lexer::new(text);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let (analysis, diagnostics) = match parser.parse_program() {
+            Ok(mut program) => {
+                let mut diagnostics = vec![];
+                let resolution =
+                    resolve::resolve_names(&mut program, &BuiltinRegistry::with_defaults());
+                for error in &resolution.errors {
+                    let message = error.message();
+                    diagnostics.push(diagnostic(error.span(), SEVERITY_ERROR, None, &message));
+                }
+                for error in typecheck::check_program(&program) {
+                    let message = &error.message;
+                    diagnostics.push(diagnostic(error.span, SEVERITY_ERROR, None, message));
+                }
+                for found in lint::lint(text, &lint::Config::default()).unwrap_or_default() {
+                    let severity = match found.level {
+                        Level::Deny => SEVERITY_ERROR,
+                        _ => SEVERITY_WARNING,
+                    };
+                    let rule = Some(found.rule.id());
+                    diagnostics.push(diagnostic(found.span, severity, rule, &found.message));
+                }
+                let analysis = Analysis::new(&program, parser.into_layout(), resolution);
+                (Some(analysis), diagnostics)
+            }
+            Err(error) => {
+                let span = Span::new(parser.position(), 1);
+                let message = MonkeyError::Parser(error);
+                (
+                    previous,
+                    vec![diagnostic(span, SEVERITY_ERROR, None, &message)],
+                )
+            }
+        };
+        self.documents.insert(
+            String::from(uri),
+            Document {
+                text: String::from(text),
+                analysis,
+            },
+        );
+        diagnostics
+    }
+
+    /**
+     * The document a request is about, what was found in it, and the
+     * position it's about.
+     */
+    fn at<'a>(&'a self, params: &'a Json) -> Result<At<'a>, (i64, String)> {
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .ok_or_else(|| (INVALID_PARAMS, String::from("No document")))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("{} isn't open", uri)))?;
+        let positions = Positions::new(&document.text, self.encoding);
+        let position = positions
+            .source_position(params.get("position"))
+            .ok_or_else(|| (INVALID_PARAMS, String::from("No position")))?;
+        let analysis = document
+            .analysis
+            .as_ref()
+            .ok_or_else(|| (INVALID_PARAMS, format!("{} hasn't parsed", uri)))?;
+        Ok(At {
+            uri,
+            analysis,
+            positions,
+            position,
+        })
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let At {
+            uri,
+            analysis,
+            positions,
+            position,
+        } = self.at(params)?;
+        Ok(match analysis.binding_at(position) {
+            Some(binding) => positions.location(uri, analysis.bindings[binding].span),
+            None => Json::Null,
+        })
+    }
+
+    fn references(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let At {
+            uri,
+            analysis,
+            positions,
+            position,
+        } = self.at(params)?;
+        let binding = match analysis.binding_at(position) {
+            Some(binding) => binding,
+            None => return Ok(Json::Null),
+        };
+        let mut spans = analysis.references(binding);
+        let declaration = params
+            .get("context")
+            .get("includeDeclaration")
+            .as_bool()
+            .unwrap_or(false);
+        if declaration {
+            spans.insert(0, analysis.bindings[binding].span);
+        }
+        Ok(Json::from(
+            spans
+                .into_iter()
+                .map(|span| positions.location(uri, span))
+                .collect::<Vec<Json>>(),
+        ))
+    }
+
+    /**
+     * Shows the signature of the builtin under the cursor.
+     */
+    fn hover(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let At {
+            analysis,
+            positions,
+            position,
+            ..
+        } = self.at(params)?;
+        let used = match analysis.use_at(position) {
+            Some(used) if used.binding.is_none() => used,
+            _ => return Ok(Json::Null),
+        };
+        Ok(match builtins::signature(&used.name) {
+            Some(signature) => Json::object(vec![
+                (
+                    "contents",
+                    Json::object(vec![
+                        ("kind", Json::from("markdown")),
+                        (
+                            "value",
+                            Json::from(format!("```\n{}: {}\n```", used.name, signature)),
+                        ),
+                    ]),
+                ),
+                ("range", positions.range(used.span)),
+            ]),
+            None => Json::Null,
+        })
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let uri = params.get("textDocument").get("uri").as_str();
+        let document = uri.and_then(|uri| self.documents.get(uri));
+        Ok(match document {
+            Some(Document {
+                text,
+                analysis: Some(analysis),
+            }) => symbols(&Positions::new(text, self.encoding), &analysis.symbols),
+            _ => Json::Null,
+        })
+    }
+
+    /**
+     * Offers the names in scope at the cursor, then the builtins, then the
+     * keywords. The client picks out those matching what's been typed.
+     */
+    fn completion(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let At {
+            analysis, position, ..
+        } = self.at(params)?;
+        let in_scope = analysis.in_scope(position);
+        let mut items: Vec<Json> = in_scope
+            .iter()
+            .map(|binding| {
+                let kind = if binding.function {
+                    COMPLETION_FUNCTION
+                } else {
+                    COMPLETION_VARIABLE
+                };
+                completion_item(&binding.name, kind, detail(binding))
+            })
+            .collect();
+        let hidden = |name: &str| in_scope.iter().any(|binding| binding.name == name);
+        for (_, builtin) in BuiltinRegistry::with_defaults().iter() {
+            if !hidden(&builtin.name) {
+                let signature = builtins::signature(&builtin.name).map(String::from);
+                items.push(completion_item(
+                    &builtin.name,
+                    COMPLETION_FUNCTION,
+                    signature,
+                ));
+            }
+        }
+        for keyword in KEYWORDS.iter() {
+            items.push(completion_item(keyword, COMPLETION_KEYWORD, None));
+        }
+        Ok(Json::from(items))
+    }
+
+    /**
+     * Replaces the whole document with it formatted, unless it doesn't
+     * parse.
+     */
+    fn formatting(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let document = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+            .ok_or_else(|| (INVALID_PARAMS, String::from("No such document")))?;
+        let formatted = match format::format_source(&document.text) {
+            Ok(formatted) => formatted,
+            Err(_) => return Ok(Json::Null),
+        };
+        if formatted == document.text {
+            return Ok(Json::from(vec![]));
+        }
+        let whole = Json::object(vec![
+            ("start", lsp_position(0, 0)),
+            ("end", Positions::new(&document.text, self.encoding).end()),
+        ]);
+        Ok(Json::from(vec![Json::object(vec![
+            ("range", whole),
+            ("newText", Json::from(formatted)),
+        ])]))
+    }
+}
+
+fn capabilities(encoding: Encoding) -> Json {
+    let encoding = match encoding {
+        Encoding::Utf16 => "utf-16",
+        Encoding::Utf32 => "utf-32",
+    };
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("positionEncoding", Json::from(encoding)),
+                // Sent whole on every change.
+                ("textDocumentSync", Json::from(1)),
+                ("definitionProvider", Json::from(true)),
+                ("referencesProvider", Json::from(true)),
+                ("hoverProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+                ("completionProvider", Json::object(vec![])),
+                ("documentFormattingProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", Json::from("monkey_lsp"))]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Json::object(vec![
+                ("code", Json::Number(code as f64)),
+                ("message", Json::from(message)),
+            ]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", Json::from(uri)),
+                ("diagnostics", Json::from(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn diagnostic(range: Json, severity: usize, code: Option<&str>, message: String) -> Json {
+    let mut fields = vec![
+        ("range", range),
+        ("severity", Json::from(severity)),
+        ("source", Json::from("monkey")),
+        ("message", Json::from(message)),
+    ];
+    if let Some(code) = code {
+        fields.push(("code", Json::from(code)));
+    }
+    Json::object(fields)
+}
+
+fn symbols(positions: &Positions, symbols: &[Symbol]) -> Json {
+    Json::from(
+        symbols
+            .iter()
+            .map(|symbol| {
+                let kind = if symbol.function {
+                    SYMBOL_FUNCTION
+                } else {
+                    SYMBOL_VARIABLE
+                };
+                Json::object(vec![
+                    ("name", Json::from(symbol.name.as_str())),
+                    ("kind", Json::from(kind)),
+                    ("range", positions.range(symbol.range)),
+                    ("selectionRange", positions.range(symbol.span)),
+                    ("children", self::symbols(positions, &symbol.children)),
+                ])
+            })
+            .collect::<Vec<Json>>(),
+    )
+}
+
+fn completion_item(label: &str, kind: usize, detail: Option<String>) -> Json {
+    let mut fields = vec![("label", Json::from(label)), ("kind", Json::from(kind))];
+    if let Some(detail) = detail {
+        fields.push(("detail", Json::from(detail)));
+    }
+    Json::object(fields)
+}
+
+fn detail(binding: &Binding) -> Option<String> {
+    match binding.kind {
+        BindingKind::Parameter => Some(String::from("parameter")),
+        BindingKind::Let => None,
+    }
+}
+
+/**
+ * What a request about a position in a document is about.
+ */
+struct At<'a> {
+    uri: &'a str,
+    analysis: &'a Analysis,
+    positions: Positions<'a>,
+    position: Position,
+}
+
+/**
+ * Converts between positions in a document's source and the protocol's.
+ * The protocol counts lines and characters from zero, where the source
+ * counts them from one, and may count characters in UTF-16 code units
+ * where the source counts whole characters.
+ */
+struct Positions<'a> {
+    lines: Vec<&'a str>,
+    encoding: Encoding,
+}
+
+impl<'a> Positions<'a> {
+    fn new(text: &'a str, encoding: Encoding) -> Self {
+        Positions {
+            lines: text.split('\n').collect(),
+            encoding,
+        }
+    }
+
+    fn location(&self, uri: &str, span: Span) -> Json {
+        Json::object(vec![("uri", Json::from(uri)), ("range", self.range(span))])
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object(vec![
+            ("start", self.to_lsp(span.start)),
+            ("end", self.to_lsp(span.end)),
+        ])
+    }
+
+    fn to_lsp(&self, position: Position) -> Json {
+        let line = position.line.saturating_sub(1);
+        let column = position.column.saturating_sub(1);
+        let character = match (self.encoding, self.lines.get(line)) {
+            (Encoding::Utf16, Some(text)) => {
+                let mut chars = text.chars();
+                let units: usize = chars.by_ref().take(column).map(char::len_utf16).sum();
+                // Past the end of the line, each column is one more.
+                units + column.saturating_sub(text.chars().count())
+            }
+            _ => column,
+        };
+        lsp_position(line, character)
+    }
+
+    /**
+     * Where the text ends: at the end of its last line.
+     */
+    fn end(&self) -> Json {
+        let line = self.lines.len();
+        let columns = self.lines.last().map_or(0, |text| text.chars().count());
+        self.to_lsp(Position {
+            line,
+            column: columns + 1,
+        })
+    }
+
+    fn source_position(&self, position: &Json) -> Option<Position> {
+        let line = position.get("line").as_usize()?;
+        let character = position.get("character").as_usize()?;
+        let column = match (self.encoding, self.lines.get(line)) {
+            (Encoding::Utf16, Some(text)) => {
+                let mut units = 0;
+                let mut column = 0;
+                for ch in text.chars() {
+                    if units >= character {
+                        break;
+                    }
+                    units += ch.len_utf16();
+                    column += 1;
+                }
+                column + character.saturating_sub(units)
+            }
+            _ => character,
+        };
+        Some(Position {
+            line: line + 1,
+            column: column + 1,
+        })
+    }
+}
+
+fn lsp_position(line: usize, character: usize) -> Json {
+    Json::object(vec![
+        ("line", Json::from(line)),
+        ("character", Json::from(character)),
+    ])
+}
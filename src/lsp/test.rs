@@ -0,0 +1,344 @@
+use super::json::Json;
+use super::{run, Server};
+use crate::token::{token_from_word, KEYWORDS};
+use pretty_assertions::assert_eq;
+
+const URI: &str = "file:///test.monkey";
+
+fn message(text: &str) -> Json {
+    Json::parse(text).unwrap()
+}
+
+/**
+ * Opens a document in the server, returning the diagnostics published for
+ * it.
+ */
+fn open(server: &mut Server, text: &str) -> Vec<Json> {
+    let text = Json::from(text).to_string();
+    let replies = server.handle(&message(&format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"monkey","version":1,"text":{}}}}}}}"#,
+        URI, text
+    )));
+    assert_eq!(replies.len(), 1);
+    let params = replies[0].get("params");
+    assert_eq!(params.get("uri").as_str(), Some(URI));
+    params.get("diagnostics").as_array().unwrap().to_vec()
+}
+
+/**
+ * Asks the server about a position in the document, returning the result.
+ */
+fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Json {
+    let replies = server.handle(&message(&format!(
+        r#"{{"jsonrpc":"2.0","id":7,"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}},"context":{{"includeDeclaration":true}}}}}}"#,
+        method, URI, line, character
+    )));
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].get("id").as_usize(), Some(7));
+    assert_eq!(replies[0].get("error"), &Json::Null);
+    replies[0].get("result").clone()
+}
+
+// A range as (start line, start character, end line, end character).
+type Range = (usize, usize, usize, usize);
+
+fn range(range: &Json) -> Range {
+    let start = range.get("start");
+    let end = range.get("end");
+    (
+        start.get("line").as_usize().unwrap(),
+        start.get("character").as_usize().unwrap(),
+        end.get("line").as_usize().unwrap(),
+        end.get("character").as_usize().unwrap(),
+    )
+}
+
+fn ranges(locations: &Json) -> Vec<Range> {
+    locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| range(location.get("range")))
+        .collect()
+}
+
+const PROGRAM: &str = "let double = fn(x) {
+    let twice = x * 2;
+    twice
+};
+let a = double(2);
+print(double(a));";
+
+#[test]
+fn test_diagnostics() {
+    let mut server = Server::default();
+    assert_eq!(open(&mut server, PROGRAM), vec![]);
+    let diagnostics = open(&mut server, "let unused = 1;\n\"a\" - missing;\nlet x = ;");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].get("message").as_str(),
+        Some("Parser error: An expression cannot begin with token type Semicolon")
+    );
+    assert_eq!(range(diagnostics[0].get("range")), (2, 8, 2, 9));
+    let diagnostics = open(&mut server, "let unused = 1;\n\"a\" - missing;");
+    let found: Vec<(&str, Range, usize)> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.get("message").as_str().unwrap(),
+                range(diagnostic.get("range")),
+                diagnostic.get("severity").as_usize().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                "The identifier 'missing' has not been bound",
+                (1, 6, 1, 13),
+                1
+            ),
+            ("Cannot apply - to string and any", (1, 4, 1, 5), 1),
+            ("'unused' is never used", (0, 4, 0, 10), 2),
+        ]
+    );
+}
+
+#[test]
+fn test_definition_and_references() {
+    let mut server = Server::default();
+    open(&mut server, PROGRAM);
+    // From the use of `double` in `print(double(a))`.
+    let definition = request(&mut server, "textDocument/definition", 5, 7);
+    assert_eq!(definition.get("uri").as_str(), Some(URI));
+    assert_eq!(range(definition.get("range")), (0, 4, 0, 10));
+    // A parameter.
+    let definition = request(&mut server, "textDocument/definition", 1, 16);
+    assert_eq!(range(definition.get("range")), (0, 16, 0, 17));
+    // A builtin has none.
+    let definition = request(&mut server, "textDocument/definition", 5, 1);
+    assert_eq!(definition, Json::Null);
+    let references = request(&mut server, "textDocument/references", 0, 5);
+    assert_eq!(
+        ranges(&references),
+        vec![(0, 4, 0, 10), (4, 8, 4, 14), (5, 6, 5, 12)]
+    );
+}
+
+#[test]
+fn test_functions_can_refer_to_later_bindings() {
+    let mut server = Server::default();
+    open(&mut server, "let f = fn() { g() };\nlet g = fn() { 1 };");
+    let definition = request(&mut server, "textDocument/definition", 0, 15);
+    assert_eq!(range(definition.get("range")), (1, 4, 1, 5));
+}
+
+#[test]
+fn test_utf16_positions() {
+    // The emoji is two UTF-16 code units, but one character in the source.
+    let text = "let s = \"😀\"; let t = s;\nt";
+    let mut server = Server::default();
+    let diagnostics = open(&mut server, &format!("{} + missing", text));
+    assert_eq!(range(diagnostics[0].get("range")), (1, 4, 1, 11));
+    open(&mut server, text);
+    let definition = request(&mut server, "textDocument/definition", 0, 22);
+    assert_eq!(range(definition.get("range")), (0, 4, 0, 5));
+    let references = request(&mut server, "textDocument/references", 1, 0);
+    assert_eq!(ranges(&references), vec![(0, 18, 0, 19), (1, 0, 1, 1)]);
+
+    // Unless the client can count whole characters.
+    server.handle(&message(
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{"general":{"positionEncodings":["utf-16","utf-32"]}}}}"#,
+    ));
+    let references = request(&mut server, "textDocument/references", 1, 0);
+    assert_eq!(ranges(&references), vec![(0, 17, 0, 18), (1, 0, 1, 1)]);
+}
+
+#[test]
+fn test_hover() {
+    let mut server = Server::default();
+    open(&mut server, PROGRAM);
+    let hover = request(&mut server, "textDocument/hover", 5, 2);
+    assert_eq!(
+        hover.get("contents").get("value").as_str(),
+        Some("```\nprint: fn(any) -> null\n```")
+    );
+    assert_eq!(range(hover.get("range")), (5, 0, 5, 5));
+    assert_eq!(request(&mut server, "textDocument/hover", 4, 9), Json::Null);
+}
+
+#[test]
+fn test_document_symbols() {
+    let mut server = Server::default();
+    open(&mut server, PROGRAM);
+    let symbols = request(&mut server, "textDocument/documentSymbol", 0, 0);
+    let symbols = symbols.as_array().unwrap();
+    let names: Vec<&str> = symbols
+        .iter()
+        .map(|symbol| symbol.get("name").as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["double", "a"]);
+    assert_eq!(symbols[0].get("kind").as_usize(), Some(12));
+    assert_eq!(range(symbols[0].get("range")), (0, 0, 3, 2));
+    assert_eq!(range(symbols[0].get("selectionRange")), (0, 4, 0, 10));
+    let children = symbols[0].get("children").as_array().unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].get("name").as_str(), Some("twice"));
+    assert_eq!(children[0].get("kind").as_usize(), Some(13));
+}
+
+fn labels(completion: &Json) -> Vec<&str> {
+    completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item.get("label").as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_completion() {
+    let mut server = Server::default();
+    open(&mut server, PROGRAM);
+    // Inside the function, after `twice` is bound.
+    let completion = request(&mut server, "textDocument/completion", 2, 4);
+    let names = labels(&completion);
+    assert_eq!(&names[..3], &["twice", "x", "double"]);
+    assert!(names.contains(&"map"));
+    assert!(!names.contains(&"a"));
+    for keyword in KEYWORDS.iter() {
+        assert!(token_from_word(keyword).is_some());
+        assert!(names.contains(keyword));
+    }
+    // After the function, its locals are gone.
+    let completion = request(&mut server, "textDocument/completion", 5, 0);
+    assert_eq!(&labels(&completion)[..2], &["a", "double"]);
+}
+
+#[test]
+fn test_completion_while_typing() {
+    let mut server = Server::default();
+    open(&mut server, "let total = 1;\n");
+    open(&mut server, "let total = 1;\nlet x = ");
+    let completion = request(&mut server, "textDocument/completion", 1, 8);
+    assert_eq!(labels(&completion)[0], "total");
+}
+
+#[test]
+fn test_formatting() {
+    let mut server = Server::default();
+    open(&mut server, "let  a=1;\nprint( a )");
+    let edits = request(&mut server, "textDocument/formatting", 0, 0);
+    let edits = edits.as_array().unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(range(edits[0].get("range")), (0, 0, 1, 10));
+    assert_eq!(
+        edits[0].get("newText").as_str(),
+        Some("let a = 1;\nprint(a);\n")
+    );
+    // The edit ends at the end of the last line, in UTF-16 code units.
+    open(&mut server, "let  s = \"😀\";");
+    let edits = request(&mut server, "textDocument/formatting", 0, 0);
+    assert_eq!(
+        range(edits.as_array().unwrap()[0].get("range")),
+        (0, 0, 0, 14)
+    );
+    open(&mut server, "let a = 1;\n");
+    let edits = request(&mut server, "textDocument/formatting", 0, 0);
+    assert_eq!(edits, Json::from(vec![]));
+}
+
+/**
+ * Frames messages as a client would send them.
+ */
+fn framed(messages: &[&str]) -> Vec<u8> {
+    messages
+        .iter()
+        .map(|message| format!("Content-Length: {}\r\n\r\n{}", message.len(), message))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/**
+ * Splits what the server wrote back into messages.
+ */
+fn unframed(mut output: &[u8]) -> Vec<Json> {
+    let mut messages = vec![];
+    while let Some(content) = super::read_message(&mut output).unwrap() {
+        messages.push(Json::parse(&content).unwrap());
+    }
+    messages
+}
+
+#[test]
+fn test_session() {
+    let input = framed(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.monkey","languageId":"monkey","version":1,"text":"let a = 1;\na"}}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.monkey","version":2},"contentChanges":[{"text":"b"}]}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"no/such/method"}"#,
+        "not json",
+        r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.monkey"}}}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]);
+    let mut output = vec![];
+    assert_eq!(run(&mut input.as_slice(), &mut output).unwrap(), true);
+    let replies = unframed(&output);
+    assert_eq!(replies.len(), 7);
+    let capabilities = replies[0].get("result").get("capabilities");
+    assert_eq!(capabilities.get("textDocumentSync").as_usize(), Some(1));
+    assert_eq!(capabilities.get("hoverProvider").as_bool(), Some(true));
+    assert_eq!(
+        capabilities.get("positionEncoding").as_str(),
+        Some("utf-16")
+    );
+    assert_eq!(
+        replies[1].get("params").get("diagnostics"),
+        &Json::from(vec![])
+    );
+    let diagnostics = replies[2].get("params").get("diagnostics");
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(replies[3].get("error").get("code"), &Json::Number(-32601.0));
+    assert_eq!(replies[4].get("error").get("code"), &Json::Number(-32700.0));
+    assert_eq!(replies[5].get("id").as_usize(), Some(3));
+    assert_eq!(replies[5].get("result"), &Json::Null);
+    // Nothing but exit is answered after shutting down.
+    assert_eq!(replies[6].get("error").get("code"), &Json::Number(-32600.0));
+
+    // A length longer than the message fails rather than being allocated.
+    let input = b"Content-Length: 1000000000000\r\n\r\n{}";
+    assert!(super::read_message(&mut input.as_slice()).is_err());
+
+    // Exiting without shutting down first is an error.
+    let input = framed(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+    assert_eq!(run(&mut input.as_slice(), &mut vec![]).unwrap(), false);
+}
+
+#[test]
+fn test_json_round_trip() {
+    let text = r#"{"id":1,"name":"a \"quoted\"\nline","items":[true,false,null,-2.5],"empty":{}}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("id").as_usize(), Some(1));
+    assert_eq!(json.get("name").as_str(), Some("a \"quoted\"\nline"));
+    assert_eq!(json.get("items").as_array().unwrap().len(), 4);
+    assert_eq!(json.get("missing").get("deeper"), &Json::Null);
+    assert_eq!(json.to_string(), text);
+}
+
+#[test]
+fn test_json_escapes() {
+    let json = Json::parse(r#" "\u00e9\ud83d\ude00\t" "#).unwrap();
+    assert_eq!(json.as_str(), Some("é😀\t"));
+    assert_eq!(Json::from("\u{1}").to_string(), r#""\u0001""#);
+}
+
+#[test]
+fn test_malformed_json() {
+    for text in ["", "{", "[1,]", "{\"a\" 1}", "\"\\x\"", "nul", "1 2"] {
+        assert!(Json::parse(text).is_err(), "{}", text);
+    }
+    assert!(Json::parse(&"[".repeat(1000)).is_err());
+}
@@ -1,60 +1,304 @@
 mod repl;
 
 use clap::Clap;
-use core::cell::RefCell;
-use monkey::{eval, lexer, object::environment, parser};
+use monkey::ast::Span;
+use monkey::compiler::{Bytecode, OptimizationLevel};
+use monkey::errors::MonkeyError;
+use monkey::eval::builtins::BuiltinRegistry;
+use monkey::limits::Limits;
+use monkey::{
+    ast, compiler, engine, format, lexer, lint, parser, register, resolve, typecheck, vm,
+};
+use std::fmt::Display;
 use std::fs;
 use std::io;
-use std::rc::Rc;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-enum EvalMethod {
-    Interpreter,
-    Vm,
-}
-impl std::str::FromStr for EvalMethod {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "interpreter" => Ok(Self::Interpreter),
-            "vm" => Ok(Self::Vm),
-            _ => Err("Not a valid eval method".into()),
-        }
-    }
-}
+const BYTECODE_EXTENSION: &str = "mkc";
+// Enough for the interpreter to reach the default depth limit in a debug
+// build, which takes far more stack per call than a release build.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
 
 #[derive(Clap)]
 struct Opts {
     source_file: Option<String>,
     #[clap(long)]
     use_interpreter: Option<bool>,
+    /// What to run programs with: interpreter, vm or register. Overrides
+    /// --use-interpreter.
+    #[clap(long)]
+    backend: Option<engine::Backend>,
+    /// Stop any program (or REPL line) that runs for longer than this many
+    /// seconds.
+    #[clap(long)]
+    timeout: Option<f64>,
+    /// Stop any program whose function calls nest more than this deep.
+    #[clap(long)]
+    max_depth: Option<usize>,
+    /// Stop any program that takes more than this many steps: expressions
+    /// evaluated by the interpreter, or instructions run by a VM.
+    #[clap(long)]
+    fuel: Option<u64>,
+    /// How much to optimize compiled code: 0 for none, 1 for everything.
+    #[clap(short = "O", long, default_value = "0")]
+    opt_level: compiler::OptimizationLevel,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// Print the bytecode that a source (or .mkc) file compiles to, for the
+    /// register VM with --backend register.
+    Disasm { file: String },
+    /// Compile a source file to a .mkc bytecode file.
+    Compile {
+        source_file: String,
+        /// Where to write the bytecode. Defaults to the source file with
+        /// its extension changed to .mkc.
+        #[clap(short, long)]
+        output: Option<String>,
+        /// Leave out debug info, such as the names of functions.
+        #[clap(long)]
+        strip: bool,
+    },
+    /// Run a source file, or a .mkc file on the VM.
+    Run { file: String },
+    /// Rewrite source files in the canonical layout.
+    Fmt {
+        #[clap(required = true)]
+        files: Vec<String>,
+        /// Change nothing, but fail if any file isn't already formatted.
+        #[clap(long)]
+        check: bool,
+    },
+    /// Report likely mistakes in source files.
+    Lint {
+        #[clap(required = true)]
+        files: Vec<String>,
+        /// Don't report this rule, such as unused-binding.
+        #[clap(long, number_of_values = 1)]
+        allow: Vec<lint::Rule>,
+        /// Fail if this rule finds anything.
+        #[clap(long, number_of_values = 1)]
+        deny: Vec<lint::Rule>,
+    },
+    /// Check source files for names that aren't bound where they're used,
+    /// and for values of the wrong type, without running them.
+    Check {
+        #[clap(required = true)]
+        files: Vec<String>,
+    },
 }
 
 fn main() {
     let opts: Opts = Opts::parse();
-    let use_interpreter = opts.use_interpreter.unwrap_or(true);
-    if let Some(source_file) = opts.source_file {
-        let source_code = fs::read_to_string(source_file).unwrap();
-        run_program(source_code, use_interpreter)
-    } else {
-        repl::start(
-            &mut io::stdin().lock(),
-            &mut io::stdout(),
-            &mut io::stderr(),
-            use_interpreter,
-        )
-        .expect("Repl failed");
+    let runner = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(opts))
+        .unwrap_or_else(|e| fail(e));
+    if runner.join().is_err() {
+        // The panic has been reported already.
+        process::exit(101);
     }
 }
 
-fn run_program(source_code: String, use_interpreter: bool) {
-    let env = Rc::new(RefCell::new(environment::Environment::new()));
-    let mut lexer = lexer::new(&source_code);
-    let mut parser = parser::Parser::new(&mut lexer);
-    let program = parser.parse_program().unwrap();
-    if use_interpreter {
-        eval::eval_program(&program, env).unwrap();
+fn run(opts: Opts) {
+    let defaults = Limits::default();
+    let limits = Limits {
+        timeout: opts.timeout.map(Duration::from_secs_f64),
+        max_depth: opts.max_depth.or(defaults.max_depth),
+        fuel: opts.fuel,
+        ..defaults
+    };
+    let backend = match (opts.backend, opts.use_interpreter.unwrap_or(true)) {
+        (Some(backend), _) => backend,
+        (None, true) => engine::Backend::Interpreter,
+        (None, false) => engine::Backend::Vm,
+    };
+    let mut engine = engine::Engine::new(backend);
+    engine.set_limits(limits);
+    engine.set_optimization_level(opts.opt_level);
+    match (opts.command, opts.source_file) {
+        (Some(Command::Disasm { file }), _) if backend == engine::Backend::Register => {
+            let program = parse_source(&read_source(&file));
+            let bytecode = register::Compiler::new()
+                .compile_program(&program)
+                .unwrap_or_else(|e| fail(MonkeyError::Compiler(e)));
+            print!("{}", bytecode.disassemble());
+        }
+        (Some(Command::Disasm { file }), _) => {
+            print!("{}", load_bytecode(&file, opts.opt_level).disassemble());
+        }
+        (
+            Some(Command::Compile {
+                source_file,
+                output,
+                strip,
+            }),
+            _,
+        ) => {
+            let output = output.unwrap_or_else(|| {
+                let path = Path::new(&source_file).with_extension(BYTECODE_EXTENSION);
+                path.to_string_lossy().into_owned()
+            });
+            compile_file(&source_file, &output, strip, opts.opt_level);
+        }
+        (Some(Command::Run { file }), _) | (None, Some(file)) => run_file(&file, engine, limits),
+        (Some(Command::Fmt { files, check }), _) => format_files(&files, check),
+        (Some(Command::Lint { files, allow, deny }), _) => {
+            let mut config = lint::Config::default();
+            for rule in allow {
+                config.set(rule, lint::Level::Allow);
+            }
+            for rule in deny {
+                config.set(rule, lint::Level::Deny);
+            }
+            lint_files(&files, &config);
+        }
+        (Some(Command::Check { files }), _) => check_files(&files),
+        (None, None) => {
+            repl::start(
+                &mut io::stdin().lock(),
+                &mut io::stdout(),
+                &mut io::stderr(),
+                engine,
+            )
+            .expect("Repl failed");
+        }
+    }
+}
+
+fn run_file(file: &str, mut engine: engine::Engine, limits: Limits) {
+    if is_bytecode_file(file) {
+        let bytecode = load_bytecode(file, OptimizationLevel::None);
+        let mut vm = vm::Vm::new(&bytecode);
+        vm.set_limits(limits);
+        if let Err(error) = vm.run() {
+            fail(MonkeyError::VmError(error));
+        }
+    } else if let Err(error) = engine.eval_str(&read_source(file)) {
+        fail(error);
+    }
+}
+
+fn format_files(files: &[String], check: bool) {
+    let mut unformatted = false;
+    for file in files {
+        let source = read_source(file);
+        let formatted = format::format_source(&source)
+            .unwrap_or_else(|e| fail(format!("{}: {}", file, MonkeyError::Parser(e))));
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", file);
+            unformatted = true;
+        } else {
+            fs::write(file, formatted).unwrap_or_else(|e| fail(e));
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn lint_files(files: &[String], config: &lint::Config) {
+    let mut denied = false;
+    for file in files {
+        let diagnostics = lint::lint(&read_source(file), config)
+            .unwrap_or_else(|e| fail(format!("{}: {}", file, MonkeyError::Parser(e))));
+        for diagnostic in diagnostics {
+            println!("{}:{}", file, diagnostic);
+            denied |= diagnostic.level == lint::Level::Deny;
+        }
+    }
+    if denied {
+        process::exit(1);
+    }
+}
+
+fn check_files(files: &[String]) {
+    let mut failed = false;
+    for file in files {
+        let mut lexer = lexer::new(&read_source(file));
+        let mut parser = parser::Parser::new(&mut lexer);
+        let mut program = parser
+            .parse_program()
+            .unwrap_or_else(|e| fail(format!("{}: {}", file, MonkeyError::Parser(e))));
+        let builtins = BuiltinRegistry::with_defaults();
+        let mut errors: Vec<(Span, String)> = resolve::resolve(&mut program, &builtins)
+            .iter()
+            .map(|error| (error.span(), error.to_string()))
+            .chain(
+                typecheck::check_program(&program)
+                    .iter()
+                    .map(|error| (error.span, error.to_string())),
+            )
+            .collect();
+        errors.sort_by_key(|(span, _)| (span.start.line, span.start.column));
+        for (_, error) in errors {
+            println!("{}:{}", file, error);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn compile_file(source_file: &str, output: &str, strip: bool, level: OptimizationLevel) {
+    let bytecode = compile_source(&read_source(source_file), level);
+    let mut writer = io::BufWriter::new(fs::File::create(output).unwrap_or_else(|e| fail(e)));
+    let written = if strip {
+        bytecode.write_stripped_to(&mut writer)
     } else {
-        unimplemented!("VM is not yet supported")
+        bytecode.write_to(&mut writer)
+    };
+    written.unwrap_or_else(|e| fail(e));
+}
+
+/**
+ * Reads a .mkc file, or compiles a source file.
+ */
+fn load_bytecode(file: &str, level: OptimizationLevel) -> Bytecode {
+    if is_bytecode_file(file) {
+        let mut reader = fs::File::open(file).unwrap_or_else(|e| fail(e));
+        Bytecode::read_from(&mut reader).unwrap_or_else(|e| fail(e))
+    } else {
+        compile_source(&read_source(file), level)
     }
 }
+
+fn compile_source(source_code: &str, level: OptimizationLevel) -> Bytecode {
+    let program = parse_source(source_code);
+    let mut compiler = compiler::Compiler::new();
+    compiler.set_optimization_level(level);
+    compiler
+        .compile_program(&program)
+        .unwrap_or_else(|e| fail(MonkeyError::Compiler(e)))
+}
+
+fn parse_source(source_code: &str) -> ast::Program {
+    let mut lexer = lexer::new(source_code);
+    let mut parser = parser::Parser::new(&mut lexer);
+    parser
+        .parse_program()
+        .unwrap_or_else(|e| fail(MonkeyError::Parser(e)))
+}
+
+fn read_source(file: &str) -> String {
+    fs::read_to_string(file).unwrap_or_else(|e| fail(e))
+}
+
+fn is_bytecode_file(file: &str) -> bool {
+    Path::new(file).extension() == Some(BYTECODE_EXTENSION.as_ref())
+}
+
+fn fail<E: Display>(error: E) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
@@ -1,47 +1,123 @@
-use crate::object::Object;
+use crate::ast::Slot;
+use crate::eval::builtins::BuiltinRegistry;
+use crate::object::{BuiltinFunction, Object};
 use core::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Debug)]
-pub struct Environment<'a> {
-    map: HashMap<String, Rc<Object<'a>>>,
-    outer: Option<Rc<RefCell<Environment<'a>>>>,
+pub struct Environment {
+    map: HashMap<String, Rc<Object>>,
+    // Bindings the resolver gave a slot to, which are kept here rather
+    // than by name. A slot is empty until its binding runs.
+    slots: Vec<Option<Rc<Object>>>,
+    outer: Option<Rc<RefCell<Environment>>>,
+    // Only the outermost environment has builtins.
+    builtins: Option<Rc<BuiltinRegistry>>,
 }
 
-impl<'a> Default for Environment<'a> {
+impl Default for Environment {
     fn default() -> Self {
-        Environment {
-            map: HashMap::<String, Rc<Object>>::new(),
-            outer: None,
-        }
+        Environment::with_builtins(Rc::new(BuiltinRegistry::with_defaults()))
     }
 }
 
-impl<'a> Environment<'a> {
-    pub fn new() -> Environment<'a> {
+impl Environment {
+    pub fn new() -> Environment {
         Environment::default()
     }
 
+    pub fn with_builtins(builtins: Rc<BuiltinRegistry>) -> Environment {
+        Environment {
+            map: HashMap::new(),
+            slots: vec![],
+            outer: None,
+            builtins: Some(builtins),
+        }
+    }
+
     pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Environment {
-        let mut env = Environment::new();
-        env.outer = Some(outer);
-        env
+        Environment {
+            map: HashMap::new(),
+            slots: vec![],
+            outer: Some(outer),
+            builtins: None,
+        }
     }
 
-    pub fn get(&self, name: &str) -> Option<Rc<Object<'a>>> {
-        let inner: Option<Rc<Object>> = self.map.get(name).map(|obj| Rc::clone(obj));
+    pub fn get(&self, name: &str) -> Option<Rc<Object>> {
+        let inner: Option<Rc<Object>> = self.map.get(name).map(Rc::clone);
 
-        let outer: Option<Rc<Object>> = self
-            .outer
-            .as_ref()
-            .map(|env| env.borrow().get(name))
-            .flatten();
+        let outer: Option<Rc<Object>> = self.outer.as_ref().and_then(|env| env.borrow().get(name));
 
         inner.or(outer)
     }
 
-    pub fn set(&mut self, name: &str, obj: Rc<Object<'a>>) {
+    pub fn set(&mut self, name: &str, obj: Rc<Object>) {
         self.map.insert(String::from(name), obj);
     }
+
+    /**
+     * Reads the slot `slot.index` of the environment `slot.depth` out from
+     * this one.
+     */
+    pub fn get_slot(&self, slot: Slot) -> Option<Rc<Object>> {
+        match (slot.depth, &self.outer) {
+            (0, _) => self.slots.get(slot.index).cloned().flatten(),
+            (depth, Some(outer)) => outer.borrow().get_slot(Slot {
+                depth: depth - 1,
+                index: slot.index,
+            }),
+            (_, None) => None,
+        }
+    }
+
+    pub fn set_slot(&mut self, index: usize, obj: Rc<Object>) {
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, None);
+        }
+        self.slots[index] = Some(obj);
+    }
+
+    pub(super) fn outer(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.outer.as_ref().map(Rc::clone)
+    }
+
+    pub(super) fn values(&self) -> impl Iterator<Item = &Rc<Object>> {
+        self.map.values().chain(self.slots.iter().flatten())
+    }
+
+    /**
+     * Empties the environment, returning what it held so that the caller
+     * decides when it is dropped.
+     */
+    pub(super) fn take_contents(&mut self) -> Environment {
+        Environment {
+            map: std::mem::take(&mut self.map),
+            slots: std::mem::take(&mut self.slots),
+            outer: self.outer.take(),
+            builtins: self.builtins.take(),
+        }
+    }
+
+    pub fn get_builtin(&self, name: &str) -> Option<Rc<dyn BuiltinFunction>> {
+        match &self.outer {
+            Some(outer) => outer.borrow().get_builtin(name),
+            None => self
+                .builtins
+                .as_ref()
+                .and_then(|builtins| builtins.get(name))
+                .map(|builtin| Rc::clone(&builtin.function)),
+        }
+    }
+
+    /**
+     * Replaces the builtins of the outermost environment.
+     */
+    pub fn set_builtins(&mut self, builtins: Rc<BuiltinRegistry>) {
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().set_builtins(builtins),
+            None => self.builtins = Some(builtins),
+        }
+    }
 }
@@ -0,0 +1,331 @@
+use crate::object::{environment::Environment, Object};
+use core::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/**
+ * Objects are reference counted, which can't free a cycle. In Monkey the
+ * only way to make one is for a function to end up in (or below) the
+ * environment it closed over, e.g. `let f = fn() { f };`, or in the VMs for
+ * a closure to end up in a cell it captured. Every environment captured by
+ * a function and every cell is tracked here, and a cycle collector looks
+ * for the ones that nothing outside the heap refers to any more.
+ *
+ * The collector uses trial deletion: any reference it can't account for
+ * by tracing the heap (a local in the interpreter, a value held by a host,
+ * the VM's stack) keeps an environment or cell alive, so collecting is safe at any
+ * point, including from inside a running script.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeapStats {
+    /// Environments captured by functions that haven't been freed yet.
+    pub tracked_environments: usize,
+    pub collections: usize,
+    /// Environments freed by the collector (rather than by reference
+    /// counting) over the lifetime of the thread.
+    pub reclaimed_environments: usize,
+    /// Cells made by the VMs that haven't been freed yet.
+    pub tracked_cells: usize,
+    /// Cells emptied by the collector over the lifetime of the thread.
+    pub reclaimed_cells: usize,
+}
+
+// Collections happen automatically once this many things are tracked,
+// and the threshold then grows with the number that survive.
+const MIN_THRESHOLD: usize = 1000;
+
+struct Heap {
+    environments: HashMap<usize, Weak<RefCell<Environment>>>,
+    // Always `Object::Cell`s.
+    cells: HashMap<usize, Weak<Object>>,
+    threshold: usize,
+    stats: HeapStats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        environments: HashMap::new(),
+        cells: HashMap::new(),
+        threshold: MIN_THRESHOLD,
+        stats: HeapStats::default(),
+    });
+}
+
+/**
+ * Records that a function has captured `env`, running a collection if the
+ * heap has grown enough since the last one.
+ */
+pub fn track(env: &Rc<RefCell<Environment>>) {
+    HEAP.with(|heap| {
+        heap.borrow_mut()
+            .environments
+            .entry(address(env))
+            .or_insert_with(|| Rc::downgrade(env));
+    });
+    collect_if_grown();
+}
+
+/**
+ * Records a cell made by a VM, which is where a closure can end up
+ * referring to itself. Like `track`, it may run a collection.
+ */
+pub fn track_cell(cell: &Rc<Object>) {
+    HEAP.with(|heap| {
+        heap.borrow_mut()
+            .cells
+            .entry(address(cell))
+            .or_insert_with(|| Rc::downgrade(cell));
+    });
+    collect_if_grown();
+}
+
+fn collect_if_grown() {
+    let should_collect = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if heap.len() < heap.threshold {
+            return false;
+        }
+        heap.forget_freed();
+        heap.len() >= heap.threshold
+    });
+    if should_collect {
+        collect();
+        HEAP.with(|heap| {
+            let mut heap = heap.borrow_mut();
+            heap.threshold = MIN_THRESHOLD.max(heap.len() * 2);
+        });
+    }
+}
+
+impl Heap {
+    fn len(&self) -> usize {
+        self.environments.len() + self.cells.len()
+    }
+
+    fn forget_freed(&mut self) {
+        self.environments.retain(|_, env| env.strong_count() > 0);
+        self.cells.retain(|_, cell| cell.strong_count() > 0);
+    }
+}
+
+/**
+ * Frees every tracked environment and cell that is only kept alive by
+ * cycles, and returns how many there were.
+ */
+pub fn collect() -> usize {
+    let (environments, cells) = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.forget_freed();
+        let environments: Vec<Rc<RefCell<Environment>>> = heap
+            .environments
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let cells: Vec<Rc<Object>> = heap.cells.values().filter_map(Weak::upgrade).collect();
+        (environments, cells)
+    });
+
+    let mut graph = Graph::default();
+    for env in environments {
+        graph.add_environment(env);
+    }
+    for cell in &cells {
+        graph.add_object(cell);
+    }
+    drop(cells);
+    graph.trace();
+    let (garbage, garbage_cells) = graph.garbage();
+    let reclaimed = garbage.len() + garbage_cells.len();
+
+    // Emptying the environments and cells breaks the cycles; everything in
+    // them is dropped once the graph (which holds a reference to each) is.
+    let mut contents = Vec::with_capacity(garbage.len());
+    for env in &garbage {
+        contents.push(env.borrow_mut().take_contents());
+    }
+    let mut values = Vec::with_capacity(garbage_cells.len());
+    for cell in &garbage_cells {
+        if let Object::Cell(value) = &**cell {
+            values.push(value.borrow_mut().take());
+        }
+    }
+    let stats = (garbage.len(), garbage_cells.len());
+    drop(garbage);
+    drop(garbage_cells);
+    drop(graph);
+    drop(contents);
+    drop(values);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.forget_freed();
+        heap.stats.collections += 1;
+        heap.stats.reclaimed_environments += stats.0;
+        heap.stats.reclaimed_cells += stats.1;
+    });
+    reclaimed
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        HeapStats {
+            tracked_environments: heap
+                .environments
+                .values()
+                .filter(|env| env.strong_count() > 0)
+                .count(),
+            tracked_cells: heap
+                .cells
+                .values()
+                .filter(|cell| cell.strong_count() > 0)
+                .count(),
+            ..heap.stats
+        }
+    })
+}
+
+#[derive(Clone)]
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Object(Rc<Object>),
+}
+
+impl Node {
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(env) => Rc::strong_count(env),
+            Node::Object(object) => Rc::strong_count(object),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<usize, Node>,
+    // Children of each node, filled in by `trace`.
+    edges: HashMap<usize, Vec<usize>>,
+    // Set if an environment couldn't be traced, in which case nothing is
+    // known to be garbage.
+    incomplete: bool,
+    pending: Vec<usize>,
+}
+
+impl Graph {
+    fn add_environment(&mut self, env: Rc<RefCell<Environment>>) -> usize {
+        let key = address(&env);
+        if let Entry::Vacant(entry) = self.nodes.entry(key) {
+            entry.insert(Node::Environment(env));
+            self.pending.push(key);
+        }
+        key
+    }
+
+    fn add_object(&mut self, object: &Rc<Object>) -> Option<usize> {
+        match &**object {
+            Object::Array(_)
+            | Object::ReturnValue(_)
+            | Object::Function { .. }
+            | Object::Closure { .. }
+            | Object::Cell(_) => {}
+            // Nothing else can refer to an environment or cell.
+            _ => return None,
+        }
+        let key = address(object);
+        if let Entry::Vacant(entry) = self.nodes.entry(key) {
+            entry.insert(Node::Object(Rc::clone(object)));
+            self.pending.push(key);
+        }
+        Some(key)
+    }
+
+    fn trace(&mut self) {
+        while let Some(key) = self.pending.pop() {
+            let mut children = vec![];
+            match self.nodes[&key].clone() {
+                Node::Environment(env) => match env.try_borrow() {
+                    Ok(env) => {
+                        let outer = env.outer();
+                        let values: Vec<Rc<Object>> = env.values().cloned().collect();
+                        drop(env);
+                        if let Some(outer) = outer {
+                            children.push(self.add_environment(outer));
+                        }
+                        children.extend(values.iter().filter_map(|v| self.add_object(v)));
+                    }
+                    Err(_) => self.incomplete = true,
+                },
+                Node::Object(object) => match &*object {
+                    Object::Array(elements) | Object::Closure { free: elements, .. } => {
+                        children.extend(elements.iter().filter_map(|e| self.add_object(e)));
+                    }
+                    Object::ReturnValue(value) => children.extend(self.add_object(value)),
+                    Object::Cell(value) => match value.try_borrow() {
+                        Ok(value) => {
+                            let value = value.clone();
+                            children.extend(value.and_then(|value| self.add_object(&value)));
+                        }
+                        Err(_) => self.incomplete = true,
+                    },
+                    Object::Function { env, .. } => {
+                        children.push(self.add_environment(Rc::clone(env)))
+                    }
+                    _ => {}
+                },
+            }
+            self.edges.insert(key, children);
+        }
+    }
+
+    /**
+     * The environments and cells that can only be reached from inside the
+     * graph.
+     */
+    fn garbage(&self) -> (Vec<Rc<RefCell<Environment>>>, Vec<Rc<Object>>) {
+        if self.incomplete {
+            return (vec![], vec![]);
+        }
+        let mut internal: HashMap<usize, usize> = HashMap::new();
+        for children in self.edges.values() {
+            for child in children {
+                *internal.entry(*child).or_insert(0) += 1;
+            }
+        }
+
+        // The graph holds one reference to every node itself.
+        let mut reachable: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|(key, node)| node.strong_count() - 1 > internal.get(key).copied().unwrap_or(0))
+            .map(|(key, _)| *key)
+            .collect();
+        while let Some(key) = stack.pop() {
+            if reachable.insert(key) {
+                stack.extend(self.edges[&key].iter().copied());
+            }
+        }
+
+        let mut environments = vec![];
+        let mut cells = vec![];
+        for (key, node) in &self.nodes {
+            if reachable.contains(key) {
+                continue;
+            }
+            match node {
+                Node::Environment(env) => environments.push(Rc::clone(env)),
+                Node::Object(object) => {
+                    if let Object::Cell(_) = &**object {
+                        cells.push(Rc::clone(object));
+                    }
+                }
+            }
+        }
+        (environments, cells)
+    }
+}
+
+fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as usize
+}
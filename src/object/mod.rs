@@ -1,4 +1,5 @@
 pub mod environment;
+pub mod heap;
 use crate::ast;
 use core::cell::RefCell;
 use std::fmt;
@@ -6,24 +7,68 @@ use std::fmt::Debug;
 use std::rc::Rc;
 
 pub trait BuiltinFunction: Debug {
-    fn run<'a>(&self, arguments: &[Rc<Object<'a>>]) -> Result<Rc<Object<'a>>, String>;
+    fn run(
+        &self,
+        arguments: &[Rc<Object>],
+        context: &mut dyn CallContext,
+    ) -> Result<Rc<Object>, String>;
+}
+
+/**
+ * Handed to builtins by whichever backend is running them, so that natives
+ * can call back into Monkey functions (both `Function` and `Closure` values).
+ */
+pub trait CallContext {
+    fn call(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, String>;
+
+    /**
+     * Lets a builtin check that a string it is about to build is within
+     * the limits of the run, before spending the memory.
+     */
+    fn check_string_length(&mut self, _length: usize) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CompiledFunction {
+    pub instructions: Vec<u8>,
+    pub num_locals: usize,
+    pub num_parameters: usize,
+    /// The name the function was bound to by a `let`, if any.
+    pub name: Option<String>,
 }
 
 #[derive(Debug)]
-pub enum Object<'ast> {
+pub enum Object {
     Integer(i64),
     Boolean(bool),
     String(String),
+    Array(Vec<Rc<Object>>),
     Null,
-    ReturnValue(Rc<Object<'ast>>),
+    ReturnValue(Rc<Object>),
     Function {
+        /// The name the function was bound to by a `let`, if any.
+        name: Option<String>,
         parameter_names: Vec<String>,
-        body: &'ast ast::BlockStatement,
-        env: Rc<RefCell<environment::Environment<'ast>>>,
+        body: Rc<ast::BlockStatement>,
+        env: Rc<RefCell<environment::Environment>>,
+    },
+    BuiltinFunction(Rc<dyn BuiltinFunction>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure {
+        function: Rc<CompiledFunction>,
+        free: Vec<Rc<Object>>,
     },
-    BuiltinFunction(Box<dyn BuiltinFunction>),
+    /// Where the VMs keep a local that a closure captured before it was
+    /// bound, so that the closure sees it once it is. Empty until then.
+    Cell(RefCell<Option<Rc<Object>>>),
 }
-impl<'ast> Object<'ast> {
+impl Object {
     pub fn type_name(&self) -> String {
         let string = match self {
             Object::Integer(_) => "Integer",
@@ -31,36 +76,67 @@ impl<'ast> Object<'ast> {
             Object::Null => "Null",
             Object::ReturnValue(_) => "Return value",
             Object::String(_) => "String",
+            Object::Array(_) => "Array",
             Object::Function { .. } => "Function",
             Object::BuiltinFunction(..) => "BuiltinFunction",
+            Object::CompiledFunction(..) => "CompiledFunction",
+            // Closures are what the VM has in place of `Function`, so they
+            // share a name to keep error messages the same across backends.
+            Object::Closure { .. } => "Function",
+            Object::Cell(_) => "Cell",
         };
         String::from(string)
     }
+
+    /**
+     * The name to show for a function in a stack trace.
+     */
+    pub fn function_name(&self) -> &str {
+        let name = match self {
+            Object::Function { name, .. } => name.as_deref(),
+            Object::Closure { function, .. } => function.name.as_deref(),
+            _ => None,
+        };
+        name.unwrap_or("<anonymous>")
+    }
 }
 
-impl<'a> PartialEq for Object<'a> {
-    fn eq(&self, rhs: &Object<'a>) -> bool {
+impl PartialEq for Object {
+    fn eq(&self, rhs: &Object) -> bool {
         match (self, rhs) {
             (Object::Integer(l), Object::Integer(r)) => l == r,
             (Object::Boolean(l), Object::Boolean(r)) => l == r,
             (Object::Null, Object::Null) => true,
             (Object::ReturnValue(l), Object::ReturnValue(r)) => l == r,
             (Object::String(l), Object::String(r)) => l == r,
+            (Object::Array(l), Object::Array(r)) => l == r,
+            (Object::CompiledFunction(l), Object::CompiledFunction(r)) => l == r,
             _ => false,
         }
     }
 }
 
-impl fmt::Display for Object<'_> {
+impl fmt::Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         let repr: String = match self {
             Object::Integer(value) => value.to_string(),
             Object::Boolean(value) => value.to_string(),
             Object::Null => String::from("null"),
             Object::String(value) => value.clone(),
+            Object::Array(elements) => format!(
+                "[{}]",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Object::ReturnValue(obj) => format!("Return value: {}", obj),
             Object::Function { .. } => String::from("Function"),
             Object::BuiltinFunction(..) => String::from("Builtin Function"),
+            Object::CompiledFunction(..) => String::from("Compiled Function"),
+            Object::Closure { .. } => String::from("Function"),
+            Object::Cell(_) => String::from("Cell"),
         };
         write!(f, "{}", repr)?;
         Ok(())
@@ -1,36 +1,73 @@
 #[cfg(test)]
 mod test;
 
+use crate::errors::MonkeyError;
 use crate::{
     ast, lexer,
-    token::{Token, TokenType},
+    token::{Position, Token, TokenType},
 };
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum ParserError {
     UnexpectedToken { expected: TokenType, actual: Token },
     InvalidExpression { first_token: Token },
+    IntegerTooLarge { literal: String },
+    NestedTooDeeply { token: Token },
+    UnknownType { name: String },
 }
 
 type ParserResult<T> = Result<T, ParserError>;
 
-#[derive(Debug, PartialOrd, PartialEq)]
-enum Precedence {
-    LOWEST,
-    EQUALS,      // ==
-    LESSGREATER, // > or <
-    MINUS,       // -
-    SUM,         // +
-    PRODUCT,     // *
-    DIVIDE,      // /
-    PREFIX,      // -X or !X
-    CALL,        // myFunction(X)
+// How deeply expressions may nest, so that malformed input such as a long
+// run of opening brackets fails rather than overflowing the stack.
+const MAX_NESTING: usize = 256;
+
+/**
+ * How tightly an operator binds: operators of higher precedence are
+ * grouped first, and those of equal precedence from the left.
+ */
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
+pub enum Precedence {
+    Lowest,
+    Equals,      // ==
+    LessGreater, // > or <
+    Minus,       // -
+    Sum,         // +
+    Product,     // *
+    Divide,      // /
+    Prefix,      // -X or !X
+    Call,        // myFunction(X)
+    Index,       // array[index]
+}
+
+/**
+ * Where the statements and blocks of a parsed program were in the source,
+ * for putting comments back when formatting it.
+ */
+#[derive(Debug, Default)]
+pub struct Layout {
+    /// The first and last token of each statement, in the order the
+    /// statements begin, so an outer statement comes before those inside it.
+    pub statements: Vec<(Position, Position)>,
+    /// The closing brace of each block, in the order the blocks end.
+    pub block_ends: Vec<Position>,
+    /// The first token of each expression that isn't an operator, call or
+    /// index, leaving out brackets around it, in the order they appear.
+    pub operands: Vec<Position>,
+    /// The closing bracket of each parameter, argument and element list,
+    /// and of each index, in the order they appear.
+    pub list_ends: Vec<Position>,
 }
 
 pub struct Parser<'a> {
     lexer: &'a mut lexer::Lexer,
     cur_token: Token,
     peek_token: Token,
+    cur_position: Position,
+    peek_position: Position,
+    nesting: usize,
+    layout: Layout,
 }
 
 enum ParsedInfix {
@@ -41,22 +78,33 @@ enum ParsedInfix {
     Call {
         args: Vec<ast::Expression>,
     },
+    Index {
+        index: ast::Expression,
+    },
 }
 
 impl Parser<'_> {
-    pub fn new(lexer: &mut lexer::Lexer) -> Parser {
+    pub fn new(lexer: &mut lexer::Lexer) -> Parser<'_> {
         let first_token = lexer.next_token();
+        let first_position = lexer.token_position();
         let second_token = lexer.next_token();
+        let second_position = lexer.token_position();
         Parser {
             lexer,
             cur_token: first_token,
             peek_token: second_token,
+            cur_position: first_position,
+            peek_position: second_position,
+            nesting: 0,
+            layout: Layout::default(),
         }
     }
 
     fn next_token(&mut self) {
         std::mem::swap(&mut self.cur_token, &mut self.peek_token);
+        self.cur_position = self.peek_position;
         self.peek_token = self.lexer.next_token();
+        self.peek_position = self.lexer.token_position();
     }
 
     pub fn parse_program(&mut self) -> Result<ast::Program, ParserError> {
@@ -72,12 +120,32 @@ impl Parser<'_> {
         Ok(program)
     }
 
+    /**
+     * Where the current token is. After an error, it's the token the error
+     * is about.
+     */
+    pub fn position(&self) -> Position {
+        self.cur_position
+    }
+
+    /**
+     * Where the statements and blocks parsed so far were.
+     */
+    pub fn into_layout(self) -> Layout {
+        self.layout
+    }
+
     fn parse_statement(&mut self) -> ParserResult<ast::Statement> {
+        let index = self.layout.statements.len();
+        self.layout
+            .statements
+            .push((self.cur_position, self.cur_position));
         let r: ParserResult<ast::Statement> = match &self.cur_token {
             Token::Let => self.parse_let_statement(),
             Token::Return => self.parse_return_statement(),
             _ => self.parse_expression_statement(),
         };
+        self.layout.statements[index].1 = self.cur_position;
         r
     }
 
@@ -91,9 +159,13 @@ impl Parser<'_> {
 
     fn parse_integer_literal(&mut self) -> ParserResult<ast::Expression> {
         if let Token::Int { literal } = &self.cur_token {
-            // It's impossible for this to go wrong as we've already
-            // established it's an integer.
-            let parsed = literal.parse::<i64>().unwrap();
+            // The lexer only lets digits through, so this fails only when
+            // the literal is too large.
+            let parsed = literal
+                .parse::<i64>()
+                .map_err(|_| ParserError::IntegerTooLarge {
+                    literal: literal.clone(),
+                })?;
 
             Ok(ast::Expression::IntegerLiteral { value: parsed })
         } else {
@@ -106,7 +178,7 @@ impl Parser<'_> {
 
         // Next we expect an expression
         self.next_token();
-        let expr = self.parse_expression(Precedence::LOWEST)?;
+        let expr = self.parse_expression(Precedence::Lowest)?;
 
         // end with semi
         self.next_token();
@@ -130,15 +202,26 @@ impl Parser<'_> {
         // Identifier should be next
         self.next_token();
         let identifier_name = self.parse_identifier()?;
+        let position = self.cur_position;
 
-        // Then assign
+        // Then an optional annotation
         self.next_token();
+        let annotation = if self.cur_token == Token::Colon {
+            self.next_token();
+            let annotation = self.parse_type()?;
+            self.next_token();
+            Some(annotation)
+        } else {
+            None
+        };
+
+        // Then assign
         self.assert_cur_token_type(TokenType::Assign)?;
 
         // Now the expression
         self.next_token();
         // skip expresion for now
-        let expr = self.parse_expression(Precedence::LOWEST)?;
+        let expr = self.parse_expression(Precedence::Lowest)?;
 
         // Make sure it was terminated
         self.next_token();
@@ -146,40 +229,133 @@ impl Parser<'_> {
         Ok(ast::Statement::Let {
             name: identifier_name,
             right: expr,
+            position,
+            slot: None,
+            annotation,
         })
     }
 
     fn parse_fn_literal(&mut self) -> ParserResult<ast::Expression> {
         self.assert_cur_token_type(TokenType::Function)?;
+        let position = self.cur_position;
         self.next_token();
 
         self.assert_cur_token_type(TokenType::LParen)?;
         self.next_token();
 
         let mut param_names: Vec<String> = vec![];
+        let mut param_positions: Vec<Position> = vec![];
+        let mut param_types: Vec<Option<ast::Type>> = vec![];
         while self.cur_token.token_type() != TokenType::RParen {
             let name = self.parse_identifier()?;
             param_names.push(name);
+            param_positions.push(self.cur_position);
             self.next_token();
 
+            if self.cur_token == Token::Colon {
+                self.next_token();
+                param_types.push(Some(self.parse_type()?));
+                self.next_token();
+            } else {
+                param_types.push(None);
+            }
+
             if self.cur_token.token_type() == TokenType::Comma {
                 self.next_token();
             }
         }
 
         // Current token is now RParen
+        self.layout.list_ends.push(self.cur_position);
         self.next_token();
 
+        let return_type = if self.cur_token == Token::Arrow {
+            self.next_token();
+            let return_type = self.parse_type()?;
+            self.next_token();
+            Some(return_type)
+        } else {
+            None
+        };
+
         let body = self.parse_block_statement()?;
 
-        Ok(ast::Expression::FnLiteral { param_names, body })
+        Ok(ast::Expression::FnLiteral {
+            param_names,
+            param_positions,
+            param_types,
+            return_type,
+            body: Rc::new(body),
+            position,
+        })
+    }
+
+    /**
+     * Parses a type annotation, leaving its last token as cur token.
+     */
+    fn parse_type(&mut self) -> ParserResult<ast::Type> {
+        match &self.cur_token {
+            Token::Ident { literal } => {
+                ast::Type::from_name(literal).ok_or_else(|| ParserError::UnknownType {
+                    name: literal.clone(),
+                })
+            }
+            Token::LBracket => {
+                self.next_token();
+                let element = self.parse_type()?;
+                self.next_token();
+                self.assert_cur_token_type(TokenType::RBracket)?;
+                Ok(ast::Type::Array(Box::new(element)))
+            }
+            Token::Function => {
+                self.next_token();
+                self.assert_cur_token_type(TokenType::LParen)?;
+                self.next_token();
+                let mut params = vec![];
+                while self.cur_token != Token::RParen {
+                    params.push(self.parse_type()?);
+                    self.next_token();
+                    if self.cur_token == Token::Comma {
+                        self.next_token();
+                    }
+                }
+                self.next_token();
+                self.assert_cur_token_type(TokenType::Arrow)?;
+                self.next_token();
+                let result = self.parse_type()?;
+                Ok(ast::Type::Function {
+                    params,
+                    result: Box::new(result),
+                })
+            }
+            _ => parser_err(TokenType::Ident, &self.cur_token),
+        }
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> ParserResult<ast::Expression> {
+        if self.nesting == MAX_NESTING {
+            return Err(ParserError::NestedTooDeeply {
+                token: self.cur_token.clone(),
+            });
+        }
+        self.nesting += 1;
+        let expression = self.parse_nested_expression(precedence);
+        self.nesting -= 1;
+        expression
+    }
+
+    fn parse_nested_expression(&mut self, precedence: Precedence) -> ParserResult<ast::Expression> {
+        if self.cur_token.token_type() != TokenType::LParen {
+            self.layout.operands.push(self.cur_position);
+        }
         let mut left_exp: ast::Expression = match self.cur_token.token_type() {
             TokenType::Ident => self
                 .parse_identifier()
-                .map(|s| ast::Expression::Identifier { value: s }),
+                .map(|value| ast::Expression::Identifier {
+                    value,
+                    position: self.cur_position,
+                    slot: None,
+                }),
             TokenType::Int => self.parse_integer_literal(),
             TokenType::Bang | TokenType::Minus => self.parse_prefix_expression(),
             TokenType::True | TokenType::False => self.parse_boolean_expression(),
@@ -192,6 +368,7 @@ impl Parser<'_> {
             TokenType::If => self.parse_if_expression(),
             TokenType::Function => self.parse_fn_literal(),
             TokenType::String => self.parse_string_literal(),
+            TokenType::LBracket => self.parse_array_literal(),
             _ => Err(ParserError::InvalidExpression {
                 first_token: self.cur_token.clone(),
             }),
@@ -206,6 +383,7 @@ impl Parser<'_> {
         {
             self.next_token();
             // cur token is a potential infix operator
+            let position = self.cur_position;
 
             if let Some(parsed_infix_result) = self.parse_infix_expression() {
                 let parsed_infix = parsed_infix_result?;
@@ -215,12 +393,21 @@ impl Parser<'_> {
                             left: Box::new(left_exp),
                             operator,
                             right: Box::new(right),
+                            position,
                         }
                     }
                     ParsedInfix::Call { args } => {
                         left_exp = ast::Expression::CallExpression {
                             left: Box::new(left_exp),
                             arguments: args,
+                            position,
+                        }
+                    }
+                    ParsedInfix::Index { index } => {
+                        left_exp = ast::Expression::Index {
+                            left: Box::new(left_exp),
+                            index: Box::new(index),
+                            position,
                         }
                     }
                 }
@@ -234,12 +421,13 @@ impl Parser<'_> {
 
     fn parse_if_expression(&mut self) -> ParserResult<ast::Expression> {
         self.assert_cur_token_type(TokenType::If)?;
+        let position = self.cur_position;
         self.next_token();
 
         self.assert_cur_token_type(TokenType::LParen)?;
         self.next_token();
 
-        let condition = self.parse_expression(Precedence::LOWEST)?;
+        let condition = self.parse_expression(Precedence::Lowest)?;
         self.next_token();
 
         self.assert_cur_token_type(TokenType::RParen)?;
@@ -258,6 +446,7 @@ impl Parser<'_> {
             condition: Box::new(condition),
             consequence,
             alternative,
+            position,
         })
     }
 
@@ -272,11 +461,12 @@ impl Parser<'_> {
             statements.push(statement);
             self.next_token();
         }
+        self.layout.block_ends.push(self.cur_position);
         Ok(ast::BlockStatement { statements })
     }
 
     fn parse_expression_statement(&mut self) -> ParserResult<ast::Statement> {
-        let expression = self.parse_expression(Precedence::LOWEST)?;
+        let expression = self.parse_expression(Precedence::Lowest)?;
 
         // Semicolons are optional at the end of expression statements to make REPL easier.
         if let Token::Semicolon = self.peek_token {
@@ -289,29 +479,61 @@ impl Parser<'_> {
         let operator: ast::PrefixOperator = match self.cur_token {
             Token::Bang => Ok(ast::PrefixOperator::Bang),
             Token::Minus => Ok(ast::PrefixOperator::Minus),
-            _ => unreachable!(),
+            _ => Err(ParserError::InvalidExpression {
+                first_token: self.cur_token.clone(),
+            }),
         }?;
+        let position = self.cur_position;
         self.next_token();
-        let right = self.parse_expression(Precedence::PREFIX)?;
+        let right = self.parse_expression(Precedence::Prefix)?;
         Ok(ast::Expression::Prefix {
             operator,
             right: Box::new(right),
+            position,
         })
     }
 
     fn parse_call_args(&mut self) -> ParserResult<Vec<ast::Expression>> {
-        self.assert_cur_token_type(TokenType::LParen)?;
+        self.parse_expression_list(TokenType::LParen, Token::RParen)
+    }
+
+    /**
+     * Parses a comma separated list of expressions between the given
+     * opening and closing tokens, leaving the closing token as cur token.
+     */
+    fn parse_expression_list(
+        &mut self,
+        open: TokenType,
+        close: Token,
+    ) -> ParserResult<Vec<ast::Expression>> {
+        self.assert_cur_token_type(open)?;
         self.next_token();
-        let mut args: Vec<ast::Expression> = vec![];
-        while self.cur_token != Token::RParen {
-            let expr = self.parse_expression(Precedence::LOWEST)?;
-            args.push(expr);
+        let mut expressions: Vec<ast::Expression> = vec![];
+        while self.cur_token != close {
+            let expr = self.parse_expression(Precedence::Lowest)?;
+            expressions.push(expr);
             self.next_token();
             if self.cur_token == Token::Comma {
                 self.next_token();
             }
         }
-        Ok(args)
+        self.layout.list_ends.push(self.cur_position);
+        Ok(expressions)
+    }
+
+    fn parse_array_literal(&mut self) -> ParserResult<ast::Expression> {
+        let elements = self.parse_expression_list(TokenType::LBracket, Token::RBracket)?;
+        Ok(ast::Expression::ArrayLiteral { elements })
+    }
+
+    fn parse_index(&mut self) -> ParserResult<ast::Expression> {
+        self.assert_cur_token_type(TokenType::LBracket)?;
+        self.next_token();
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.next_token();
+        self.assert_cur_token_type(TokenType::RBracket)?;
+        self.layout.list_ends.push(self.cur_position);
+        Ok(index)
     }
 
     fn parse_infix_expression(&mut self) -> Option<ParserResult<ParsedInfix>> {
@@ -323,6 +545,8 @@ impl Parser<'_> {
                 self.parse_call_args()
                     .map(|args| ParsedInfix::Call { args }),
             )
+        } else if operator_token == &Token::LBracket {
+            Some(self.parse_index().map(|index| ParsedInfix::Index { index }))
         } else {
             let operator = match self.cur_token {
                 Token::Plus => Some(ast::InfixOperator::Plus),
@@ -347,7 +571,9 @@ impl Parser<'_> {
         match self.cur_token {
             Token::True => Ok(ast::Expression::Boolean { value: true }),
             Token::False => Ok(ast::Expression::Boolean { value: false }),
-            _ => unreachable!(),
+            _ => Err(ParserError::InvalidExpression {
+                first_token: self.cur_token.clone(),
+            }),
         }
     }
 
@@ -355,7 +581,7 @@ impl Parser<'_> {
         self.assert_cur_token_type(TokenType::LParen)?;
         self.next_token();
 
-        let expression = self.parse_expression(Precedence::LOWEST)?;
+        let expression = self.parse_expression(Precedence::Lowest)?;
         self.next_token();
         self.assert_cur_token_type(TokenType::RParen)?;
         Ok(expression)
@@ -375,11 +601,29 @@ impl Parser<'_> {
                 value: literal.clone(),
             })
         } else {
-            unreachable!()
+            parser_err(TokenType::String, &self.cur_token)
         }
     }
 }
 
+/**
+ * Parses a type on its own, as written in an annotation.
+ */
+impl std::str::FromStr for ast::Type {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lexer = lexer::new(s);
+        let mut parser = Parser::new(&mut lexer);
+        let parsed = parser.parse_type().and_then(|ty| {
+            parser.next_token();
+            parser.assert_cur_token_type(TokenType::Eof)?;
+            Ok(ty)
+        });
+        parsed.map_err(|e| MonkeyError::Parser(e).to_string())
+    }
+}
+
 fn parser_err<T>(expected_type: TokenType, actual: &Token) -> ParserResult<T> {
     Err(ParserError::UnexpectedToken {
         expected: expected_type,
@@ -387,15 +631,33 @@ fn parser_err<T>(expected_type: TokenType, actual: &Token) -> ParserResult<T> {
     })
 }
 
+/**
+ * The precedence the parser gives an infix operator.
+ */
+pub fn infix_precedence(operator: &ast::InfixOperator) -> Precedence {
+    let token_type = match operator {
+        ast::InfixOperator::Plus => TokenType::Plus,
+        ast::InfixOperator::Minus => TokenType::Minus,
+        ast::InfixOperator::Multiply => TokenType::Asterisk,
+        ast::InfixOperator::Divide => TokenType::Slash,
+        ast::InfixOperator::Gt => TokenType::Gt,
+        ast::InfixOperator::Lt => TokenType::Lt,
+        ast::InfixOperator::Eq => TokenType::Eq,
+        ast::InfixOperator::NotEq => TokenType::NotEq,
+    };
+    precedence_for_token_type(&token_type)
+}
+
 fn precedence_for_token_type(token_type: &TokenType) -> Precedence {
     match token_type {
-        TokenType::Eq | TokenType::NotEq => Precedence::EQUALS,
-        TokenType::Lt | TokenType::Gt => Precedence::LESSGREATER,
-        TokenType::Plus => Precedence::SUM,
-        TokenType::Minus => Precedence::MINUS,
-        TokenType::Slash => Precedence::DIVIDE,
-        TokenType::Asterisk => Precedence::PRODUCT,
-        TokenType::LParen => Precedence::CALL,
-        _ => Precedence::LOWEST,
+        TokenType::Eq | TokenType::NotEq => Precedence::Equals,
+        TokenType::Lt | TokenType::Gt => Precedence::LessGreater,
+        TokenType::Plus => Precedence::Sum,
+        TokenType::Minus => Precedence::Minus,
+        TokenType::Slash => Precedence::Divide,
+        TokenType::Asterisk => Precedence::Product,
+        TokenType::LParen => Precedence::Call,
+        TokenType::LBracket => Precedence::Index,
+        _ => Precedence::Lowest,
     }
 }
@@ -1,5 +1,7 @@
+use crate::ast::VisitorMut;
 use crate::{ast, lexer, parser};
 use pretty_assertions::assert_eq;
+use std::rc::Rc;
 #[test]
 fn test_let_statements() {
     let input = "
@@ -12,14 +14,23 @@ fn test_let_statements() {
 
     let expected: Vec<ast::Statement> = vec![
         ast::Statement::Let {
+            position: Default::default(),
+            slot: None,
+            annotation: None,
             name: String::from("x"),
             right: ast::Expression::IntegerLiteral { value: 5 },
         },
         ast::Statement::Let {
+            position: Default::default(),
+            slot: None,
+            annotation: None,
             name: String::from("y"),
             right: ast::Expression::IntegerLiteral { value: 10 },
         },
         ast::Statement::Let {
+            position: Default::default(),
+            slot: None,
+            annotation: None,
             name: String::from("foobar"),
             right: ast::Expression::IntegerLiteral { value: 83838383 },
         },
@@ -48,12 +59,22 @@ fn test_return_statements() {
         ]
     )
 }
-fn read_program(input: &'static str) -> ast::Program {
+fn parse(input: &str) -> ast::Program {
     let mut lexer = lexer::new(input);
     let mut parser = parser::Parser::new(&mut lexer);
     parser.parse_program().unwrap()
 }
 
+/**
+ * Parses a program with its positions cleared, so that it can be compared
+ * with trees written out without them.
+ */
+fn read_program(input: &'static str) -> ast::Program {
+    let mut program = parse(input);
+    ast::ClearPositions.visit_program_mut(&mut program);
+    program
+}
+
 #[test]
 fn test_identifier_expression() {
     let input = "
@@ -67,7 +88,9 @@ fn test_identifier_expression() {
         vec![
             ast::Statement::Expression {
                 expression: ast::Expression::Identifier {
-                    value: String::from("foobar")
+                    value: String::from("foobar"),
+                    position: Default::default(),
+                    slot: None,
                 }
             },
             ast::Statement::Expression {
@@ -90,15 +113,19 @@ fn test_prefix_expression() {
         vec![
             ast::Statement::Expression {
                 expression: ast::Expression::Prefix {
+                    position: Default::default(),
                     operator: ast::PrefixOperator::Minus,
                     right: Box::new(ast::Expression::IntegerLiteral { value: 3 }),
                 }
             },
             ast::Statement::Expression {
                 expression: ast::Expression::Prefix {
+                    position: Default::default(),
                     operator: ast::PrefixOperator::Bang,
                     right: Box::new(ast::Expression::Identifier {
-                        value: String::from("whatever")
+                        value: String::from("whatever"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                 }
             },
@@ -157,6 +184,7 @@ fn construct_simple_infix_test_case(
 ) -> ast::Statement {
     ast::Statement::Expression {
         expression: ast::Expression::Infix {
+            position: Default::default(),
             left: Box::new(ast::Expression::IntegerLiteral { value: left }),
             operator: op,
             right: Box::new(ast::Expression::IntegerLiteral { value: right }),
@@ -166,7 +194,7 @@ fn construct_simple_infix_test_case(
 
 fn run_paren_infix_test(no_parens: &'static str, with_parens: &'static str) {
     let program_noparens = read_program(no_parens);
-    let first_statement = program_noparens.statements.get(0).unwrap();
+    let first_statement = program_noparens.statements.first().unwrap();
     let expression = match first_statement {
         ast::Statement::Expression { expression } => expression,
         _ => panic!("Expected expression statement"),
@@ -224,19 +252,27 @@ fn test_if_expression() {
         program.statements,
         vec!(ast::Statement::Expression {
             expression: ast::Expression::If {
+                position: Default::default(),
                 condition: Box::new(ast::Expression::Infix {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::Identifier {
-                        value: String::from("x")
+                        value: String::from("x"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                     operator: ast::InfixOperator::Lt,
                     right: Box::new(ast::Expression::Identifier {
-                        value: String::from("y")
+                        value: String::from("y"),
+                        position: Default::default(),
+                        slot: None,
                     })
                 }),
                 consequence: ast::BlockStatement {
                     statements: vec!(ast::Statement::Expression {
                         expression: ast::Expression::Identifier {
-                            value: String::from("x")
+                            value: String::from("x"),
+                            position: Default::default(),
+                            slot: None,
                         }
                     })
                 },
@@ -254,26 +290,36 @@ fn test_if_else_expression() {
         program.statements,
         vec!(ast::Statement::Expression {
             expression: ast::Expression::If {
+                position: Default::default(),
                 condition: Box::new(ast::Expression::Infix {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::Identifier {
-                        value: String::from("x")
+                        value: String::from("x"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                     operator: ast::InfixOperator::Lt,
                     right: Box::new(ast::Expression::Identifier {
-                        value: String::from("y")
+                        value: String::from("y"),
+                        position: Default::default(),
+                        slot: None,
                     })
                 }),
                 consequence: ast::BlockStatement {
                     statements: vec!(ast::Statement::Expression {
                         expression: ast::Expression::Identifier {
-                            value: String::from("x")
+                            value: String::from("x"),
+                            position: Default::default(),
+                            slot: None,
                         }
                     })
                 },
                 alternative: Some(ast::BlockStatement {
                     statements: vec!(ast::Statement::Expression {
                         expression: ast::Expression::Identifier {
-                            value: String::from("y")
+                            value: String::from("y"),
+                            position: Default::default(),
+                            slot: None,
                         }
                     })
                 }),
@@ -301,39 +347,56 @@ fn test_fn_literal() {
             ast::Statement::Expression {
                 expression: ast::Expression::FnLiteral {
                     param_names: vec!(String::from("x"), String::from("y")),
-                    body: ast::BlockStatement {
+                    param_positions: vec![Default::default(); 2],
+                    param_types: vec![None; 2],
+                    return_type: None,
+                    position: Default::default(),
+                    body: Rc::new(ast::BlockStatement {
                         statements: vec!(ast::Statement::Expression {
                             expression: ast::Expression::Infix {
+                                position: Default::default(),
                                 left: Box::new(ast::Expression::Identifier {
-                                    value: String::from("x")
+                                    value: String::from("x"),
+                                    position: Default::default(),
+                                    slot: None,
                                 }),
                                 operator: ast::InfixOperator::Plus,
                                 right: Box::new(ast::Expression::Identifier {
-                                    value: String::from("y")
+                                    value: String::from("y"),
+                                    position: Default::default(),
+                                    slot: None,
                                 }),
                             }
                         })
-                    }
+                    })
                 }
             },
             ast::Statement::Expression {
                 expression: ast::Expression::FnLiteral {
                     param_names: vec!(String::from("x")),
-                    body: ast::BlockStatement {
+                    param_positions: vec![Default::default(); 1],
+                    param_types: vec![None; 1],
+                    return_type: None,
+                    position: Default::default(),
+                    body: Rc::new(ast::BlockStatement {
                         statements: vec!(ast::Statement::Expression {
                             expression: ast::Expression::IntegerLiteral { value: 4 }
                         })
-                    }
+                    })
                 }
             },
             ast::Statement::Expression {
                 expression: ast::Expression::FnLiteral {
                     param_names: vec!(),
-                    body: ast::BlockStatement {
+                    param_positions: vec![],
+                    param_types: vec![],
+                    return_type: None,
+                    position: Default::default(),
+                    body: Rc::new(ast::BlockStatement {
                         statements: vec!(ast::Statement::Expression {
                             expression: ast::Expression::IntegerLiteral { value: 3 },
                         })
-                    }
+                    })
                 }
             }
         )
@@ -354,27 +417,37 @@ fn test_call_expression() {
         vec!(
             ast::Statement::Expression {
                 expression: ast::Expression::CallExpression {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::Identifier {
-                        value: String::from("add")
+                        value: String::from("add"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                     arguments: vec!()
                 }
             },
             ast::Statement::Expression {
                 expression: ast::Expression::CallExpression {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::Identifier {
-                        value: String::from("add")
+                        value: String::from("add"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                     arguments: vec!(ast::Expression::IntegerLiteral { value: 1 })
                 }
             },
             ast::Statement::Expression {
                 expression: ast::Expression::CallExpression {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::Identifier {
-                        value: String::from("add")
+                        value: String::from("add"),
+                        position: Default::default(),
+                        slot: None,
                     }),
                     arguments: vec!(
                         ast::Expression::Infix {
+                            position: Default::default(),
                             left: Box::new(ast::Expression::IntegerLiteral { value: 1 }),
                             operator: ast::InfixOperator::Plus,
                             right: Box::new(ast::Expression::IntegerLiteral { value: 2 }),
@@ -385,9 +458,14 @@ fn test_call_expression() {
             },
             ast::Statement::Expression {
                 expression: ast::Expression::CallExpression {
+                    position: Default::default(),
                     left: Box::new(ast::Expression::FnLiteral {
                         param_names: vec![String::from("x"), String::from("y")],
-                        body: ast::BlockStatement { statements: vec![] },
+                        param_positions: vec![Default::default(); 2],
+                        param_types: vec![None; 2],
+                        return_type: None,
+                        position: Default::default(),
+                        body: Rc::new(ast::BlockStatement { statements: vec![] }),
                     }),
                     arguments: vec!(ast::Expression::IntegerLiteral { value: 2 })
                 }
@@ -408,6 +486,9 @@ fn test_block_expression() {
     assert_eq!(
         program.statements,
         vec!(ast::Statement::Let {
+            position: Default::default(),
+            slot: None,
+            annotation: None,
             name: String::from("a"),
             right: ast::Expression::Block {
                 statements: vec![
@@ -426,3 +507,184 @@ fn test_block_expression() {
         })
     )
 }
+
+#[test]
+fn test_array_literal() {
+    let input = "
+        [];
+        [1, 2 + 3];
+        ";
+    let program = read_program(input);
+    assert_eq!(
+        program.statements,
+        vec!(
+            ast::Statement::Expression {
+                expression: ast::Expression::ArrayLiteral { elements: vec![] }
+            },
+            ast::Statement::Expression {
+                expression: ast::Expression::ArrayLiteral {
+                    elements: vec![
+                        ast::Expression::IntegerLiteral { value: 1 },
+                        ast::Expression::Infix {
+                            position: Default::default(),
+                            left: Box::new(ast::Expression::IntegerLiteral { value: 2 }),
+                            operator: ast::InfixOperator::Plus,
+                            right: Box::new(ast::Expression::IntegerLiteral { value: 3 }),
+                        },
+                    ]
+                }
+            }
+        )
+    )
+}
+
+#[test]
+fn test_index_expression() {
+    let input = "
+        items[1 + 1];
+        ";
+    let program = read_program(input);
+    assert_eq!(
+        program.statements,
+        vec!(ast::Statement::Expression {
+            expression: ast::Expression::Index {
+                position: Default::default(),
+                left: Box::new(ast::Expression::Identifier {
+                    value: String::from("items"),
+                    position: Default::default(),
+                    slot: None,
+                }),
+                index: Box::new(ast::Expression::Infix {
+                    position: Default::default(),
+                    left: Box::new(ast::Expression::IntegerLiteral { value: 1 }),
+                    operator: ast::InfixOperator::Plus,
+                    right: Box::new(ast::Expression::IntegerLiteral { value: 1 }),
+                }),
+            }
+        })
+    );
+    run_paren_infix_test("a * [1, 2][b]", "(a * ([1, 2][b]))");
+    run_paren_infix_test("add(a[0], b)[1]", "(add((a[0]), b)[1])");
+}
+
+#[test]
+fn test_expression_positions() {
+    let program = parse("let a = 1;\n  f(a)[0] + -2");
+    let (left, right, position) = match &program.statements[1] {
+        ast::Statement::Expression {
+            expression:
+                ast::Expression::Infix {
+                    left,
+                    right,
+                    position,
+                    ..
+                },
+        } => (left, right, position),
+        other => panic!("Expected an infix expression, got {}", other),
+    };
+    assert_eq!((position.line, position.column), (2, 11));
+    match &**right {
+        ast::Expression::Prefix { position, .. } => {
+            assert_eq!((position.line, position.column), (2, 13))
+        }
+        other => panic!("Expected a prefix expression, got {}", other),
+    }
+    match &**left {
+        ast::Expression::Index { left, position, .. } => {
+            assert_eq!((position.line, position.column), (2, 7));
+            match &**left {
+                ast::Expression::CallExpression { position, .. } => {
+                    assert_eq!((position.line, position.column), (2, 4))
+                }
+                other => panic!("Expected a call, got {}", other),
+            }
+        }
+        other => panic!("Expected an index expression, got {}", other),
+    }
+}
+
+#[test]
+fn test_malformed_programs() {
+    let parse = |input: &str| {
+        let mut lexer = lexer::new(input);
+        parser::Parser::new(&mut lexer).parse_program()
+    };
+    match parse("let x = \"open;") {
+        Err(parser::ParserError::InvalidExpression { .. }) => {}
+        other => panic!("Expected an invalid expression, got {:?}", other),
+    }
+    match parse("99999999999999999999") {
+        Err(parser::ParserError::IntegerTooLarge { literal }) => {
+            assert_eq!(literal, "99999999999999999999")
+        }
+        other => panic!("Expected a too large integer, got {:?}", other),
+    }
+    match parse(&"(".repeat(100_000)) {
+        Err(parser::ParserError::NestedTooDeeply { .. }) => {}
+        other => panic!("Expected too deep nesting, got {:?}", other),
+    }
+    assert!(parse(&format!("{}1{}", "(".repeat(200), ")".repeat(200))).is_ok());
+}
+
+#[test]
+fn test_name_positions() {
+    let program = parse("let f = fn(a,\n  b) { a };");
+    match &program.statements[0] {
+        ast::Statement::Let {
+            position,
+            right:
+                ast::Expression::FnLiteral {
+                    param_positions,
+                    body,
+                    ..
+                },
+            ..
+        } => {
+            assert_eq!((position.line, position.column), (1, 5));
+            let params: Vec<(usize, usize)> = param_positions
+                .iter()
+                .map(|position| (position.line, position.column))
+                .collect();
+            assert_eq!(params, vec![(1, 12), (2, 3)]);
+            match &body.statements[0] {
+                ast::Statement::Expression {
+                    expression: ast::Expression::Identifier { position, .. },
+                } => assert_eq!((position.line, position.column), (2, 8)),
+                other => panic!("Expected an identifier, got {}", other),
+            }
+        }
+        other => panic!("Expected a function, got {}", other),
+    }
+}
+
+#[test]
+fn test_type_annotations() {
+    let program = read_program(
+        "let x: int = 5; let f = fn(a: [string], b) -> fn(int, A) -> bool { b }; let g: any = 1;",
+    );
+    assert_eq!(
+        program.to_string(),
+        "let x: int = 5;let f = fn(a: [string], b) -> fn(int, A) -> bool {b;};let g: any = 1;"
+    );
+    match &program.statements[0] {
+        ast::Statement::Let { annotation, .. } => assert_eq!(annotation, &Some(ast::Type::Int)),
+        other => panic!("Expected a let, got {}", other),
+    }
+    assert_eq!(
+        "fn([A]) -> null".parse::<ast::Type>(),
+        Ok(ast::Type::Function {
+            params: vec![ast::Type::Array(Box::new(ast::Type::Variable(
+                String::from("A")
+            )))],
+            result: Box::new(ast::Type::Null),
+        })
+    );
+    assert!("fn(int)".parse::<ast::Type>().is_err());
+    assert!("int int".parse::<ast::Type>().is_err());
+
+    let mut lexer = lexer::new("let x: integer = 5;");
+    match parser::Parser::new(&mut lexer).parse_program() {
+        Err(parser::ParserError::UnknownType { name }) => assert_eq!(name, "integer"),
+        other => panic!("Expected an unknown type, got {:?}", other),
+    }
+}
@@ -0,0 +1,289 @@
+use crate::code::DecodeError;
+use std::fmt;
+
+/**
+ * A register in the current frame's window. A function has at most 256.
+ */
+pub type Register = u8;
+
+/**
+ * Three-address instructions: each names the registers it reads and the
+ * register it writes, rather than working on the top of a stack. Jump
+ * targets are byte positions, as in `code::Instruction`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Destination, then the index of the constant.
+    LoadConstant(Register, u16),
+    LoadTrue(Register),
+    LoadFalse(Register),
+    LoadNull(Register),
+    /// Destination, then source.
+    Move(Register, Register),
+    GetGlobal(Register, u16),
+    /// The global's index, then the register holding its new value.
+    SetGlobal(u16, Register),
+    GetBuiltin(Register, u8),
+    GetFree(Register, u8),
+    CurrentClosure(Register),
+    /// Destination, then the left and right operands.
+    Add(Register, Register, Register),
+    Sub(Register, Register, Register),
+    Mul(Register, Register, Register),
+    Div(Register, Register, Register),
+    Equal(Register, Register, Register),
+    NotEqual(Register, Register, Register),
+    GreaterThan(Register, Register, Register),
+    LessThan(Register, Register, Register),
+    /// Destination, then the operand.
+    Minus(Register, Register),
+    Bang(Register, Register),
+    Jump(u16),
+    /// The condition, then where to jump if it is false.
+    JumpFalse(Register, u16),
+    /// Destination, then the first of the consecutive registers holding the
+    /// elements, then how many there are.
+    Array(Register, Register, u8),
+    /// Destination, then what is indexed, then the index.
+    Index(Register, Register, Register),
+    /// Destination, then the function, whose arguments are in the registers
+    /// straight after it, then the number of arguments. The called
+    /// function's registers start at its first argument.
+    Call(Register, Register, u8),
+    /// Like `Call`, but the calling function returns the result, so its
+    /// frame can be reused. A builtin's result is left in the function's
+    /// register, and a return of it always follows.
+    TailCall(Register, u8),
+    /// Destination, then the index of the compiled function in the constant
+    /// pool, then the first of the consecutive registers holding its free
+    /// variables, then how many there are.
+    Closure(Register, u16, Register, u8),
+    Return(Register),
+    /// Puts an empty cell in the register, for a closure to capture before
+    /// the local variable living there is bound.
+    NewCell(Register),
+    /// Destination, then the register holding the cell.
+    GetCell(Register, Register),
+    /// The register holding the cell, then the new value.
+    SetCell(Register, Register),
+    /// Destination, then the index of the free variable that is a cell.
+    GetFreeCell(Register, u8),
+    /// Like `Array`, but adds the elements to the end of the array just
+    /// made in the first register, for arrays too long to make in one go.
+    AppendArray(Register, Register, u8),
+}
+
+impl Instruction {
+    fn opcode_byte(&self) -> u8 {
+        match self {
+            Self::LoadConstant(..) => 0,
+            Self::LoadTrue(_) => 1,
+            Self::LoadFalse(_) => 2,
+            Self::LoadNull(_) => 3,
+            Self::Move(..) => 4,
+            Self::GetGlobal(..) => 5,
+            Self::SetGlobal(..) => 6,
+            Self::GetBuiltin(..) => 7,
+            Self::GetFree(..) => 8,
+            Self::CurrentClosure(_) => 9,
+            Self::Add(..) => 10,
+            Self::Sub(..) => 11,
+            Self::Mul(..) => 12,
+            Self::Div(..) => 13,
+            Self::Equal(..) => 14,
+            Self::NotEqual(..) => 15,
+            Self::GreaterThan(..) => 16,
+            Self::Minus(..) => 17,
+            Self::Bang(..) => 18,
+            Self::Jump(_) => 19,
+            Self::JumpFalse(..) => 20,
+            Self::Array(..) => 21,
+            Self::Index(..) => 22,
+            Self::Call(..) => 23,
+            Self::TailCall(..) => 24,
+            Self::Closure(..) => 25,
+            Self::Return(_) => 26,
+            Self::LessThan(..) => 27,
+            Self::NewCell(_) => 28,
+            Self::GetCell(..) => 29,
+            Self::SetCell(..) => 30,
+            Self::GetFreeCell(..) => 31,
+            Self::AppendArray(..) => 32,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode_byte()];
+        match self {
+            Self::LoadConstant(dest, constant) | Self::GetGlobal(dest, constant) => {
+                bytes.push(*dest);
+                bytes.extend(&constant.to_be_bytes());
+            }
+            Self::SetGlobal(index, source) => {
+                bytes.extend(&index.to_be_bytes());
+                bytes.push(*source);
+            }
+            Self::LoadTrue(register)
+            | Self::LoadFalse(register)
+            | Self::LoadNull(register)
+            | Self::CurrentClosure(register)
+            | Self::Return(register)
+            | Self::NewCell(register) => bytes.push(*register),
+            Self::Move(a, b)
+            | Self::GetBuiltin(a, b)
+            | Self::GetFree(a, b)
+            | Self::Minus(a, b)
+            | Self::Bang(a, b)
+            | Self::TailCall(a, b)
+            | Self::GetCell(a, b)
+            | Self::SetCell(a, b)
+            | Self::GetFreeCell(a, b) => bytes.extend(&[*a, *b]),
+            Self::Add(a, b, c)
+            | Self::Sub(a, b, c)
+            | Self::Mul(a, b, c)
+            | Self::Div(a, b, c)
+            | Self::Equal(a, b, c)
+            | Self::NotEqual(a, b, c)
+            | Self::GreaterThan(a, b, c)
+            | Self::LessThan(a, b, c)
+            | Self::Array(a, b, c)
+            | Self::AppendArray(a, b, c)
+            | Self::Index(a, b, c)
+            | Self::Call(a, b, c) => bytes.extend(&[*a, *b, *c]),
+            Self::Jump(position) => bytes.extend(&position.to_be_bytes()),
+            Self::JumpFalse(condition, position) => {
+                bytes.push(*condition);
+                bytes.extend(&position.to_be_bytes());
+            }
+            Self::Closure(dest, constant, first, num_free) => {
+                bytes.push(*dest);
+                bytes.extend(&constant.to_be_bytes());
+                bytes.extend(&[*first, *num_free]);
+            }
+        }
+        bytes
+    }
+
+    /**
+     * Reads one instruction from the iterator, which must not be empty.
+     */
+    pub fn from_bytes(iter: &mut std::slice::Iter<u8>) -> Result<Self, DecodeError> {
+        let op_byte = *iter.next().ok_or(DecodeError::Truncated)?;
+        let mut byte = || iter.next().copied().ok_or(DecodeError::Truncated);
+        let instruction = match op_byte {
+            0 => Self::LoadConstant(byte()?, u16::from_be_bytes([byte()?, byte()?])),
+            1 => Self::LoadTrue(byte()?),
+            2 => Self::LoadFalse(byte()?),
+            3 => Self::LoadNull(byte()?),
+            4 => Self::Move(byte()?, byte()?),
+            5 => Self::GetGlobal(byte()?, u16::from_be_bytes([byte()?, byte()?])),
+            6 => Self::SetGlobal(u16::from_be_bytes([byte()?, byte()?]), byte()?),
+            7 => Self::GetBuiltin(byte()?, byte()?),
+            8 => Self::GetFree(byte()?, byte()?),
+            9 => Self::CurrentClosure(byte()?),
+            10 => Self::Add(byte()?, byte()?, byte()?),
+            11 => Self::Sub(byte()?, byte()?, byte()?),
+            12 => Self::Mul(byte()?, byte()?, byte()?),
+            13 => Self::Div(byte()?, byte()?, byte()?),
+            14 => Self::Equal(byte()?, byte()?, byte()?),
+            15 => Self::NotEqual(byte()?, byte()?, byte()?),
+            16 => Self::GreaterThan(byte()?, byte()?, byte()?),
+            17 => Self::Minus(byte()?, byte()?),
+            18 => Self::Bang(byte()?, byte()?),
+            19 => Self::Jump(u16::from_be_bytes([byte()?, byte()?])),
+            20 => Self::JumpFalse(byte()?, u16::from_be_bytes([byte()?, byte()?])),
+            21 => Self::Array(byte()?, byte()?, byte()?),
+            22 => Self::Index(byte()?, byte()?, byte()?),
+            23 => Self::Call(byte()?, byte()?, byte()?),
+            24 => Self::TailCall(byte()?, byte()?),
+            25 => Self::Closure(
+                byte()?,
+                u16::from_be_bytes([byte()?, byte()?]),
+                byte()?,
+                byte()?,
+            ),
+            26 => Self::Return(byte()?),
+            27 => Self::LessThan(byte()?, byte()?, byte()?),
+            28 => Self::NewCell(byte()?),
+            29 => Self::GetCell(byte()?, byte()?),
+            30 => Self::SetCell(byte()?, byte()?),
+            31 => Self::GetFreeCell(byte()?, byte()?),
+            32 => Self::AppendArray(byte()?, byte()?, byte()?),
+            _ => return Err(DecodeError::UnknownOpcode(op_byte)),
+        };
+        Ok(instruction)
+    }
+
+    /**
+     * Reads the instruction starting at `position`, returning it along with
+     * the position of the instruction after it, or `None` at the end.
+     */
+    pub fn read_at(
+        instructions: &[u8],
+        position: usize,
+    ) -> Result<Option<(Self, usize)>, DecodeError> {
+        let mut iter = match instructions.get(position..) {
+            Some(rest) if !rest.is_empty() => rest.iter(),
+            _ => return Ok(None),
+        };
+        let instruction = Self::from_bytes(&mut iter)?;
+        Ok(Some((
+            instruction,
+            instructions.len() - iter.as_slice().len(),
+        )))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LoadConstant(dest, constant) => write!(f, "LoadConstant r{} {}", dest, constant),
+            Self::LoadTrue(dest) => write!(f, "LoadTrue r{}", dest),
+            Self::LoadFalse(dest) => write!(f, "LoadFalse r{}", dest),
+            Self::LoadNull(dest) => write!(f, "LoadNull r{}", dest),
+            Self::Move(dest, source) => write!(f, "Move r{} r{}", dest, source),
+            Self::GetGlobal(dest, index) => write!(f, "GetGlobal r{} {}", dest, index),
+            Self::SetGlobal(index, source) => write!(f, "SetGlobal {} r{}", index, source),
+            Self::GetBuiltin(dest, index) => write!(f, "GetBuiltin r{} {}", dest, index),
+            Self::GetFree(dest, index) => write!(f, "GetFree r{} {}", dest, index),
+            Self::CurrentClosure(dest) => write!(f, "CurrentClosure r{}", dest),
+            Self::Add(dest, left, right) => write!(f, "Add r{} r{} r{}", dest, left, right),
+            Self::Sub(dest, left, right) => write!(f, "Sub r{} r{} r{}", dest, left, right),
+            Self::Mul(dest, left, right) => write!(f, "Mul r{} r{} r{}", dest, left, right),
+            Self::Div(dest, left, right) => write!(f, "Div r{} r{} r{}", dest, left, right),
+            Self::Equal(dest, left, right) => write!(f, "Equal r{} r{} r{}", dest, left, right),
+            Self::NotEqual(dest, left, right) => {
+                write!(f, "NotEqual r{} r{} r{}", dest, left, right)
+            }
+            Self::GreaterThan(dest, left, right) => {
+                write!(f, "GreaterThan r{} r{} r{}", dest, left, right)
+            }
+            Self::LessThan(dest, left, right) => {
+                write!(f, "LessThan r{} r{} r{}", dest, left, right)
+            }
+            Self::Minus(dest, operand) => write!(f, "Minus r{} r{}", dest, operand),
+            Self::Bang(dest, operand) => write!(f, "Bang r{} r{}", dest, operand),
+            Self::Jump(position) => write!(f, "Jump {}", position),
+            Self::JumpFalse(condition, position) => {
+                write!(f, "JumpFalse r{} {}", condition, position)
+            }
+            Self::Array(dest, first, length) => write!(f, "Array r{} r{} {}", dest, first, length),
+            Self::AppendArray(array, first, length) => {
+                write!(f, "AppendArray r{} r{} {}", array, first, length)
+            }
+            Self::Index(dest, left, index) => write!(f, "Index r{} r{} r{}", dest, left, index),
+            Self::Call(dest, function, num_args) => {
+                write!(f, "Call r{} r{} {}", dest, function, num_args)
+            }
+            Self::TailCall(function, num_args) => write!(f, "TailCall r{} {}", function, num_args),
+            Self::Closure(dest, constant, first, num_free) => {
+                write!(f, "Closure r{} {} r{} {}", dest, constant, first, num_free)
+            }
+            Self::Return(source) => write!(f, "Return r{}", source),
+            Self::NewCell(dest) => write!(f, "NewCell r{}", dest),
+            Self::GetCell(dest, cell) => write!(f, "GetCell r{} r{}", dest, cell),
+            Self::SetCell(cell, source) => write!(f, "SetCell r{} r{}", cell, source),
+            Self::GetFreeCell(dest, index) => write!(f, "GetFreeCell r{} {}", dest, index),
+        }
+    }
+}
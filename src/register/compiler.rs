@@ -0,0 +1,716 @@
+use super::code::{Instruction, Register};
+use super::Bytecode;
+use crate::compiler::symbol_table::{Symbol, SymbolScope, SymbolTable};
+use crate::compiler::CompilerError;
+use crate::eval::builtins::BuiltinRegistry;
+use crate::{ast, object};
+use std::convert::TryInto;
+use std::rc::Rc;
+
+type CompilerResult = Result<(), CompilerError>;
+
+/// How many elements of an array literal are made at once. Longer arrays
+/// are made a chunk at a time, so need no more registers than this.
+const ARRAY_CHUNK: usize = 64;
+
+/**
+ * The code being generated for one function (or the main program), and
+ * the registers it uses.
+ */
+#[derive(Default)]
+struct Scope {
+    instructions: Vec<u8>,
+    // The register each local variable lives in, by symbol index.
+    locals: Vec<Register>,
+    // Registers below this are in use. They are handed out and given back
+    // in stack order, so a value stays put for as long as it is needed.
+    next_register: usize,
+    num_registers: usize,
+}
+
+/**
+ * Compiles programs for `register::Vm`. Like `compiler::Compiler` it can be
+ * fed several programs in turn, with later ones seeing the globals and
+ * constants of earlier ones.
+ *
+ * Parameters and local variables each get a register of their own for the
+ * whole of the block they are in, and are read straight from it; other
+ * values go through temporary registers above them.
+ */
+pub struct Compiler {
+    constants: Vec<Rc<object::Object>>,
+    symbol_table: SymbolTable,
+    // One scope per function being compiled, innermost last.
+    scopes: Vec<Scope>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler::with_builtins(&BuiltinRegistry::with_defaults())
+    }
+
+    /**
+     * Creates a compiler whose programs can call the builtins in `builtins`.
+     * The VM running them has to be given the same registry.
+     */
+    pub fn with_builtins(builtins: &BuiltinRegistry) -> Self {
+        let mut compiler = Compiler {
+            constants: vec![],
+            symbol_table: SymbolTable::new(),
+            scopes: vec![Scope::default()],
+        };
+        compiler.set_builtins(builtins);
+        compiler
+    }
+
+    /**
+     * Replaces the builtins that later programs can refer to.
+     */
+    pub fn set_builtins(&mut self, builtins: &BuiltinRegistry) {
+        self.symbol_table.clear_builtins();
+        for (index, builtin) in builtins.iter() {
+            self.symbol_table.define_builtin(index, &builtin.name);
+        }
+    }
+
+    /**
+     * Compiles a program on top of whatever has been compiled already. If
+     * it fails, the compiler is left as it was before the call.
+     */
+    pub fn compile_program(&mut self, program: &ast::Program) -> Result<Bytecode, CompilerError> {
+        let symbol_table = self.symbol_table.clone();
+        let num_constants = self.constants.len();
+        self.scopes = vec![Scope::default()];
+        if let Err(err) = self.compile_main(&program.statements) {
+            self.symbol_table = symbol_table;
+            self.constants.truncate(num_constants);
+            return Err(err);
+        }
+        let main = self.scopes.pop().unwrap();
+        Ok(Bytecode {
+            instructions: main.instructions,
+            num_registers: main.num_registers,
+            constants: self.constants.clone(),
+        })
+    }
+
+    /**
+     * Reserves a global slot for `name`, returning its index into the VM's
+     * globals. Used by hosts to give scripts values of their own.
+     */
+    pub fn define_global(&mut self, name: &str) -> usize {
+        self.symbol_table.define(name).index
+    }
+
+    pub fn resolve_global(&mut self, name: &str) -> Option<usize> {
+        match self.symbol_table.resolve(name) {
+            Some(Symbol {
+                scope: SymbolScope::Global,
+                index,
+            }) => Some(index),
+            _ => None,
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn add_constant(&mut self, obj: object::Object) -> Result<u16, CompilerError> {
+        let index = self
+            .constants
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("constants")))?;
+        self.constants.push(Rc::new(obj));
+        Ok(index)
+    }
+
+    /**
+     * Pushes the instruction and returns its position.
+     */
+    fn push_instruction(&mut self, instruction: Instruction) -> usize {
+        let instructions = &mut self.scope().instructions;
+        let position = instructions.len();
+        instructions.extend(instruction.to_bytes());
+        position
+    }
+
+    fn replace_instruction(&mut self, position: usize, instruction: Instruction) {
+        let bytes = instruction.to_bytes();
+        self.scope().instructions[position..position + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    fn current_position(&mut self) -> Result<u16, CompilerError> {
+        self.scope()
+            .instructions
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("instructions to jump over")))
+    }
+
+    /**
+     * Takes `count` consecutive registers, returning the first.
+     */
+    fn allocate(&mut self, count: usize) -> Result<Register, CompilerError> {
+        let scope = self.scope();
+        let first = scope.next_register;
+        scope.next_register += count;
+        scope.num_registers = scope.num_registers.max(scope.next_register);
+        if scope.next_register > u8::MAX as usize + 1 {
+            return Err(CompilerError::TooManyOperands(String::from("registers")));
+        }
+        // When `count` is zero, this can be one past the last register.
+        Ok(first.min(u8::MAX as usize) as Register)
+    }
+
+    /**
+     * The position to give registers back to with `free_to`.
+     */
+    fn mark(&mut self) -> usize {
+        self.scope().next_register
+    }
+
+    fn free_to(&mut self, mark: usize) {
+        self.scope().next_register = mark;
+    }
+
+    fn compile_main(&mut self, statements: &[ast::Statement]) -> CompilerResult {
+        self.symbol_table.declare(statements);
+        let (last, rest) = match statements.split_last() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        for statement in rest {
+            self.compile_statement(statement)?;
+        }
+        match last {
+            // The program's value is that of its last statement.
+            ast::Statement::Expression { expression } => {
+                let register = self.compile_operand(expression)?;
+                self.push_instruction(Instruction::Return(register));
+                Ok(())
+            }
+            _ => self.compile_statement(last),
+        }
+    }
+
+    /**
+     * Declares the names the block binds, giving each local its register
+     * for the whole of the block.
+     */
+    fn declare(&mut self, statements: &[ast::Statement]) -> CompilerResult {
+        for symbol in self.symbol_table.declare(statements) {
+            if symbol.scope == SymbolScope::Global {
+                continue;
+            }
+            let register = self.allocate(1)?;
+            let locals = &mut self.scope().locals;
+            if locals.len() <= symbol.index {
+                locals.resize(symbol.index + 1, 0);
+            }
+            locals[symbol.index] = register;
+            if symbol.scope == SymbolScope::Cell {
+                self.push_instruction(Instruction::NewCell(register));
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, statement: &ast::Statement) -> CompilerResult {
+        let mark = self.mark();
+        match statement {
+            ast::Statement::Let { name, right, .. } => {
+                // A local's value goes straight into its register, anything
+                // else's through a temporary.
+                let register = match self.symbol_table.declared(name) {
+                    Some(Symbol {
+                        scope: SymbolScope::Local,
+                        index,
+                    }) => self.scope().locals[index],
+                    _ => self.allocate(1)?,
+                };
+                if let ast::Expression::FnLiteral {
+                    param_names, body, ..
+                } = right
+                {
+                    self.compile_function(Some(name), param_names, body, register)?;
+                } else {
+                    self.compile_into(right, register)?;
+                }
+                // Defined after compiling the right hand side, so that
+                // `let a = a + 1;` in a block refers to the outer `a`.
+                let symbol = self.symbol_table.define(name);
+                match symbol.scope {
+                    SymbolScope::Global => {
+                        let index = symbol
+                            .index
+                            .try_into()
+                            .map_err(|_| CompilerError::TooManyOperands(String::from("globals")))?;
+                        self.push_instruction(Instruction::SetGlobal(index, register));
+                        self.free_to(mark);
+                    }
+                    SymbolScope::Cell => {
+                        let cell = self.scope().locals[symbol.index];
+                        self.push_instruction(Instruction::SetCell(cell, register));
+                        self.free_to(mark);
+                    }
+                    _ => {
+                        // The register is the variable's until the end of
+                        // its block.
+                        let locals = &mut self.scope().locals;
+                        if locals.len() <= symbol.index {
+                            locals.resize(symbol.index + 1, 0);
+                        }
+                        locals[symbol.index] = register;
+                    }
+                }
+            }
+            ast::Statement::Return { value } => {
+                if self.scopes.len() > 1 {
+                    self.compile_return(value)?;
+                } else {
+                    let register = self.compile_operand(value)?;
+                    self.push_instruction(Instruction::Return(register));
+                }
+                self.free_to(mark);
+            }
+            ast::Statement::Expression { expression } => {
+                self.compile_operand(expression)?;
+                self.free_to(mark);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns a register holding the value of the expression: the variable's
+     * own register for a local, or else a new one it is computed into.
+     */
+    fn compile_operand(&mut self, expression: &ast::Expression) -> Result<Register, CompilerError> {
+        if let ast::Expression::Identifier { value, .. } = expression {
+            let symbol = self.resolve(value)?;
+            if symbol.scope == SymbolScope::Local {
+                return Ok(self.scope().locals[symbol.index]);
+            }
+        }
+        let register = self.allocate(1)?;
+        self.compile_into(expression, register)?;
+        Ok(register)
+    }
+
+    /**
+     * Like `compile_operand`, but computes the value into `dest` when that
+     * is a temporary register, so that a chain like `a + b + c` needs just
+     * one register rather than one per term. A local's register can't be
+     * used, as the rest of the expression may still read the local.
+     */
+    fn compile_operand_into(
+        &mut self,
+        expression: &ast::Expression,
+        dest: Register,
+    ) -> Result<Register, CompilerError> {
+        if let ast::Expression::Identifier { value, .. } = expression {
+            let symbol = self.resolve(value)?;
+            if symbol.scope == SymbolScope::Local {
+                return Ok(self.scope().locals[symbol.index]);
+            }
+        }
+        if !self.is_temporary(dest) {
+            return self.compile_operand(expression);
+        }
+        self.compile_into(expression, dest)?;
+        Ok(dest)
+    }
+
+    fn is_temporary(&mut self, register: Register) -> bool {
+        !self.scope().locals.contains(&register)
+    }
+
+    fn compile_into(&mut self, expression: &ast::Expression, dest: Register) -> CompilerResult {
+        let mark = self.mark();
+        match expression {
+            ast::Expression::IntegerLiteral { value } => {
+                let index = self.add_constant(object::Object::Integer(*value))?;
+                self.push_instruction(Instruction::LoadConstant(dest, index));
+            }
+            ast::Expression::StringLiteral { value } => {
+                let index = self.add_constant(object::Object::String(value.clone()))?;
+                self.push_instruction(Instruction::LoadConstant(dest, index));
+            }
+            ast::Expression::Boolean { value } => {
+                let instruction = if *value {
+                    Instruction::LoadTrue(dest)
+                } else {
+                    Instruction::LoadFalse(dest)
+                };
+                self.push_instruction(instruction);
+            }
+            ast::Expression::Identifier { value, .. } => {
+                let symbol = self.resolve(value)?;
+                self.load_symbol(&symbol, dest)?;
+            }
+            ast::Expression::Prefix {
+                operator, right, ..
+            } => {
+                let operand = self.compile_operand_into(right, dest)?;
+                let instruction = match operator {
+                    ast::PrefixOperator::Bang => Instruction::Bang(dest, operand),
+                    ast::PrefixOperator::Minus => Instruction::Minus(dest, operand),
+                };
+                self.push_instruction(instruction);
+            }
+            ast::Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let left = self.compile_operand_into(left, dest)?;
+                let right = self.compile_operand(right)?;
+                let instruction = match operator {
+                    ast::InfixOperator::Plus => Instruction::Add(dest, left, right),
+                    ast::InfixOperator::Minus => Instruction::Sub(dest, left, right),
+                    ast::InfixOperator::Multiply => Instruction::Mul(dest, left, right),
+                    ast::InfixOperator::Divide => Instruction::Div(dest, left, right),
+                    ast::InfixOperator::Eq => Instruction::Equal(dest, left, right),
+                    ast::InfixOperator::NotEq => Instruction::NotEqual(dest, left, right),
+                    ast::InfixOperator::Gt => Instruction::GreaterThan(dest, left, right),
+                    ast::InfixOperator::Lt => Instruction::LessThan(dest, left, right),
+                };
+                self.push_instruction(instruction);
+            }
+            ast::Expression::Block { statements } => self.compile_block_into(statements, dest)?,
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                let condition = self.compile_operand(condition)?;
+                // The jump targets aren't known yet so are patched in once
+                // the blocks have been compiled.
+                let jump_false_position =
+                    self.push_instruction(Instruction::JumpFalse(condition, 0));
+                self.free_to(mark);
+                self.compile_block_into(&consequence.statements, dest)?;
+                let jump_position = self.push_instruction(Instruction::Jump(0));
+                let alternative_start = self.current_position()?;
+                self.replace_instruction(
+                    jump_false_position,
+                    Instruction::JumpFalse(condition, alternative_start),
+                );
+                match alternative {
+                    Some(alternative) => self.compile_block_into(&alternative.statements, dest)?,
+                    None => {
+                        self.push_instruction(Instruction::LoadNull(dest));
+                    }
+                }
+                let end = self.current_position()?;
+                self.replace_instruction(jump_position, Instruction::Jump(end));
+            }
+            ast::Expression::ArrayLiteral { elements } => {
+                if elements.len() <= ARRAY_CHUNK {
+                    let first = self.compile_elements(elements)?;
+                    let length = elements.len() as u8;
+                    self.push_instruction(Instruction::Array(dest, first, length));
+                } else {
+                    // The array is made in a temporary register, as the
+                    // elements after the first chunk may read a local that
+                    // lives in `dest`.
+                    let array = self.allocate(1)?;
+                    let chunk_mark = self.mark();
+                    for (index, chunk) in elements.chunks(ARRAY_CHUNK).enumerate() {
+                        let first = self.compile_elements(chunk)?;
+                        let length = chunk.len() as u8;
+                        self.push_instruction(if index == 0 {
+                            Instruction::Array(array, first, length)
+                        } else {
+                            Instruction::AppendArray(array, first, length)
+                        });
+                        self.free_to(chunk_mark);
+                    }
+                    self.push_instruction(Instruction::Move(dest, array));
+                }
+            }
+            ast::Expression::Index { left, index, .. } => {
+                let left = self.compile_operand_into(left, dest)?;
+                let index = self.compile_operand(index)?;
+                self.push_instruction(Instruction::Index(dest, left, index));
+            }
+            ast::Expression::FnLiteral {
+                param_names, body, ..
+            } => {
+                self.compile_function(None, param_names, body, dest)?;
+            }
+            ast::Expression::CallExpression {
+                left, arguments, ..
+            } => {
+                let function = self.compile_arguments(left, arguments)?;
+                let num_args = arguments.len() as u8;
+                self.push_instruction(Instruction::Call(dest, function, num_args));
+            }
+        }
+        self.free_to(mark);
+        Ok(())
+    }
+
+    /**
+     * Compiles the statements so that they leave the value of the block in
+     * `dest`: the value of a trailing expression statement, or null.
+     */
+    fn compile_block_into(
+        &mut self,
+        statements: &[ast::Statement],
+        dest: Register,
+    ) -> CompilerResult {
+        self.symbol_table.push_block();
+        let mark = self.mark();
+        self.declare(statements)?;
+        match statements.split_last() {
+            Some((last, rest)) => {
+                for statement in rest {
+                    self.compile_statement(statement)?;
+                }
+                match last {
+                    ast::Statement::Expression { expression } => {
+                        self.compile_into(expression, dest)?;
+                    }
+                    _ => {
+                        self.compile_statement(last)?;
+                        self.push_instruction(Instruction::LoadNull(dest));
+                    }
+                }
+            }
+            None => {
+                self.push_instruction(Instruction::LoadNull(dest));
+            }
+        }
+        self.free_to(mark);
+        self.symbol_table.pop_block();
+        Ok(())
+    }
+
+    /**
+     * Compiles the statements of a block whose value the function returns,
+     * so that every path through them ends in a return.
+     */
+    fn compile_block_return(&mut self, statements: &[ast::Statement]) -> CompilerResult {
+        self.symbol_table.push_block();
+        let mark = self.mark();
+        self.declare(statements)?;
+        match statements.split_last() {
+            Some((last, rest)) => {
+                for statement in rest {
+                    self.compile_statement(statement)?;
+                }
+                match last {
+                    ast::Statement::Expression { expression } => self.compile_return(expression)?,
+                    ast::Statement::Return { .. } => self.compile_statement(last)?,
+                    ast::Statement::Let { .. } => {
+                        self.compile_statement(last)?;
+                        self.return_null()?;
+                    }
+                }
+            }
+            None => self.return_null()?,
+        }
+        self.free_to(mark);
+        self.symbol_table.pop_block();
+        Ok(())
+    }
+
+    /**
+     * Compiles an expression whose value the function returns, so that a
+     * call it ends in can reuse the function's frame.
+     */
+    fn compile_return(&mut self, expression: &ast::Expression) -> CompilerResult {
+        let mark = self.mark();
+        match expression {
+            ast::Expression::CallExpression {
+                left, arguments, ..
+            } => {
+                let function = self.compile_arguments(left, arguments)?;
+                self.push_instruction(Instruction::TailCall(function, arguments.len() as u8));
+                self.push_instruction(Instruction::Return(function));
+            }
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                // Both branches return, so there is no jump over the
+                // alternative.
+                let condition = self.compile_operand(condition)?;
+                let jump_false_position =
+                    self.push_instruction(Instruction::JumpFalse(condition, 0));
+                self.free_to(mark);
+                self.compile_block_return(&consequence.statements)?;
+                let alternative_start = self.current_position()?;
+                self.replace_instruction(
+                    jump_false_position,
+                    Instruction::JumpFalse(condition, alternative_start),
+                );
+                match alternative {
+                    Some(alternative) => self.compile_block_return(&alternative.statements)?,
+                    None => self.return_null()?,
+                }
+            }
+            ast::Expression::Block { statements } => self.compile_block_return(statements)?,
+            _ => {
+                let register = self.compile_operand(expression)?;
+                self.push_instruction(Instruction::Return(register));
+            }
+        }
+        self.free_to(mark);
+        Ok(())
+    }
+
+    fn return_null(&mut self) -> CompilerResult {
+        let mark = self.mark();
+        let register = self.allocate(1)?;
+        self.push_instruction(Instruction::LoadNull(register));
+        self.push_instruction(Instruction::Return(register));
+        self.free_to(mark);
+        Ok(())
+    }
+
+    /**
+     * Puts the elements in consecutive registers, returning the first.
+     */
+    fn compile_elements(
+        &mut self,
+        elements: &[ast::Expression],
+    ) -> Result<Register, CompilerError> {
+        let first = self.allocate(elements.len())?;
+        for (offset, element) in elements.iter().enumerate() {
+            self.compile_into(element, first + offset as Register)?;
+        }
+        Ok(first)
+    }
+
+    /**
+     * Puts the function and its arguments in consecutive registers, as
+     * calls expect them, and returns the function's register.
+     */
+    fn compile_arguments(
+        &mut self,
+        left: &ast::Expression,
+        arguments: &[ast::Expression],
+    ) -> Result<Register, CompilerError> {
+        if arguments.len() > u8::MAX as usize {
+            return Err(CompilerError::TooManyOperands(String::from(
+                "call arguments",
+            )));
+        }
+        let function = self.allocate(arguments.len() + 1)?;
+        self.compile_into(left, function)?;
+        for (offset, argument) in arguments.iter().enumerate() {
+            self.compile_into(argument, function + 1 + offset as Register)?;
+        }
+        Ok(function)
+    }
+
+    fn compile_function(
+        &mut self,
+        name: Option<&str>,
+        param_names: &[String],
+        body: &ast::BlockStatement,
+        dest: Register,
+    ) -> CompilerResult {
+        self.scopes.push(Scope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(name);
+        }
+        // The arguments are passed in the first registers.
+        let result = self.allocate(param_names.len()).and_then(|_| {
+            for (index, param_name) in param_names.iter().enumerate() {
+                self.symbol_table.define(param_name);
+                self.scope().locals.push(index as Register);
+            }
+            self.compile_block_return(&body.statements)
+        });
+        let scope = self.scopes.pop().unwrap();
+        let outer = self.symbol_table.take_outer().unwrap();
+        let symbol_table = std::mem::replace(&mut self.symbol_table, outer);
+        result?;
+
+        let num_free = symbol_table
+            .free_symbols
+            .len()
+            .try_into()
+            .map_err(|_| CompilerError::TooManyOperands(String::from("free variables")))?;
+        let first = self.allocate(symbol_table.free_symbols.len())?;
+        for (offset, free_symbol) in symbol_table.free_symbols.iter().enumerate() {
+            let dest = first + offset as Register;
+            // A closure captures a cell itself, so that it sees the value
+            // once it is set.
+            match free_symbol.scope {
+                SymbolScope::Cell => {
+                    let cell = self.scope().locals[free_symbol.index];
+                    self.push_instruction(Instruction::Move(dest, cell));
+                }
+                SymbolScope::FreeCell => {
+                    let index = free_symbol.index.try_into().map_err(|_| {
+                        CompilerError::TooManyOperands(String::from("free variables"))
+                    })?;
+                    self.push_instruction(Instruction::GetFree(dest, index));
+                }
+                _ => self.load_symbol(free_symbol, dest)?,
+            }
+        }
+        let function = object::CompiledFunction {
+            instructions: scope.instructions,
+            num_locals: scope.num_registers,
+            num_parameters: param_names.len(),
+            name: name.map(String::from),
+        };
+        let index = self.add_constant(object::Object::CompiledFunction(Rc::new(function)))?;
+        self.push_instruction(Instruction::Closure(dest, index, first, num_free));
+        Ok(())
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Symbol, CompilerError> {
+        self.symbol_table
+            .resolve(name)
+            .ok_or_else(|| CompilerError::UnboundIdentifier(String::from(name)))
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol, dest: Register) -> CompilerResult {
+        let operand_error = |_| CompilerError::TooManyOperands(String::from("symbols"));
+        let instruction = match symbol.scope {
+            SymbolScope::Global => {
+                Instruction::GetGlobal(dest, symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Local => {
+                let source = self.scope().locals[symbol.index];
+                if source == dest {
+                    return Ok(());
+                }
+                Instruction::Move(dest, source)
+            }
+            SymbolScope::Builtin => {
+                Instruction::GetBuiltin(dest, symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Free => {
+                Instruction::GetFree(dest, symbol.index.try_into().map_err(operand_error)?)
+            }
+            SymbolScope::Function => Instruction::CurrentClosure(dest),
+            SymbolScope::Cell => Instruction::GetCell(dest, self.scope().locals[symbol.index]),
+            SymbolScope::FreeCell => {
+                Instruction::GetFreeCell(dest, symbol.index.try_into().map_err(operand_error)?)
+            }
+        };
+        self.push_instruction(instruction);
+        Ok(())
+    }
+}
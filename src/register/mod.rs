@@ -0,0 +1,103 @@
+use crate::code::{describe_constant, DecodeError};
+use crate::object::Object;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+mod code;
+mod compiler;
+#[cfg(test)]
+mod test;
+mod vm;
+
+pub use self::code::{Instruction, Register};
+pub use self::compiler::Compiler;
+pub use self::vm::Vm;
+
+pub struct Bytecode {
+    pub instructions: Vec<u8>,
+    /// How many registers the main program uses.
+    pub num_registers: usize,
+    pub constants: Vec<Rc<Object>>,
+}
+
+impl Bytecode {
+    /**
+     * Lists the main program's instructions and then each function's, in
+     * the same way as `compiler::Bytecode::disassemble`.
+     */
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::from("main:\n");
+        listing.push_str(&disassemble(&self.instructions, &self.constants));
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let Object::CompiledFunction(function) = &**constant {
+                listing.push_str(&format!(
+                    "\nconstant {}, fn {}:\n",
+                    index,
+                    function.name.as_deref().unwrap_or("<anonymous>")
+                ));
+                listing.push_str(&disassemble(&function.instructions, &self.constants));
+            }
+        }
+        listing
+    }
+}
+
+/**
+ * Renders register instructions as `code::disassemble` does stack ones.
+ */
+pub fn disassemble(instructions: &[u8], constants: &[Rc<Object>]) -> String {
+    let mut decoded = vec![];
+    let mut position = 0;
+    let mut error: Option<(usize, DecodeError)> = None;
+    loop {
+        match Instruction::read_at(instructions, position) {
+            Ok(Some((instruction, next))) => {
+                decoded.push((position, instruction));
+                position = next;
+            }
+            Ok(None) => break,
+            Err(decode_error) => {
+                error = Some((position, decode_error));
+                break;
+            }
+        }
+    }
+
+    // Labels are numbered in the order they appear.
+    let mut labels: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, instruction) in &decoded {
+        if let Instruction::Jump(target) | Instruction::JumpFalse(_, target) = instruction {
+            labels.insert(*target as usize, 0);
+        }
+    }
+    for (number, label) in labels.values_mut().enumerate() {
+        *label = number;
+    }
+
+    let mut listing = String::new();
+    for (position, instruction) in &decoded {
+        if let Some(label) = labels.get(position) {
+            writeln!(listing, "L{}:", label).unwrap();
+        }
+        let text = match instruction {
+            Instruction::Jump(target) => format!("Jump L{}", labels[&(*target as usize)]),
+            Instruction::JumpFalse(condition, target) => {
+                format!("JumpFalse r{} L{}", condition, labels[&(*target as usize)])
+            }
+            Instruction::LoadConstant(_, index) | Instruction::Closure(_, index, ..) => format!(
+                "{:<20} ; {}",
+                instruction.to_string(),
+                describe_constant(constants, *index as usize)
+            ),
+            _ => instruction.to_string(),
+        };
+        writeln!(listing, "{:04} {}", position, text).unwrap();
+    }
+    if let Some((position, error)) = error {
+        writeln!(listing, "{:04} <{}>", position, error).unwrap();
+    } else if let Some(label) = labels.get(&instructions.len()) {
+        writeln!(listing, "L{}:", label).unwrap();
+    }
+    listing
+}
@@ -0,0 +1,175 @@
+use super::{Bytecode, Compiler, Instruction, Vm};
+use crate::{compiler, lexer, parser, vm};
+
+fn compile(input: &str) -> Bytecode {
+    let mut lexer = lexer::new(input);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program().unwrap();
+    Compiler::new().compile_program(&program).unwrap()
+}
+
+fn run(input: &str) -> String {
+    format!("{:?}", Vm::new(&compile(input)).run())
+}
+
+fn run_stack_vm(input: &str) -> String {
+    let mut lexer = lexer::new(input);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program().unwrap();
+    let bytecode = compiler::compile_program(&program).unwrap();
+    // The register VM doesn't say where errors happened.
+    let result = vm::Vm::new(&bytecode).run().map_err(|error| match error {
+        vm::VmError::Located { error, .. } => *error,
+        error => error,
+    });
+    format!("{:?}", result)
+}
+
+#[test]
+fn test_round_trip() {
+    let instructions = [
+        Instruction::LoadConstant(1, 258),
+        Instruction::SetGlobal(3, 4),
+        Instruction::Add(0, 1, 2),
+        Instruction::JumpFalse(7, 65535),
+        Instruction::Closure(1, 2, 3, 4),
+        Instruction::TailCall(5, 6),
+        Instruction::Return(0),
+    ];
+    for instruction in instructions.iter() {
+        let bytes = instruction.to_bytes();
+        assert_eq!(
+            Instruction::read_at(&bytes, 0).unwrap(),
+            Some((instruction.clone(), bytes.len()))
+        );
+    }
+    assert_eq!(
+        Instruction::LoadConstant(1, 258).to_bytes(),
+        vec![0, 1, 1, 2]
+    );
+    assert!(Instruction::read_at(&[10, 0, 1], 0).is_err());
+}
+
+#[test]
+fn test_same_results_as_stack_vm() {
+    let inputs = [
+        "",
+        "1 + 2 * 3 - 4 / 2",
+        "-(5 + 5) * -2",
+        "!(1 < 2) == !true",
+        r#""mon" + "key""#,
+        "1; 2; 3",
+        "5; let a = 1;",
+        "let a = 2 * 3; a * 7",
+        "if (1 > 2) { 10 } else { 20 }",
+        "if (1 < 2) { 10 }",
+        "if (false) { 10 }",
+        "if (true) { return 1; } 2",
+        "{ let a = 1; { let a = a + 1; a } }",
+        "[1, [2, 3], 4][1][0]",
+        "[][0]",
+        "let f = fn(x) { 1; if (x) { 2; x } else { 3 } }; [f(1), f(false)]",
+        "let f = fn() { 1; 2; }; f()",
+        "let f = fn() { let a = 1; }; f()",
+        "let f = fn() { }; f()",
+        "let f = fn() { return 1; 2 }; f() + 1",
+        "let f = fn(a, b) { let c = a + b; let d = c * 2; [a, b, c, d] }; f(1, 2)",
+        "let f = fn(a) { let a = a * 10; a }; f(2)",
+        "let f = fn(a, b) { a < b }; [f(1, 2), f(2, 1)]",
+        "let add = fn(a) { fn(b) { a + b } }; add(1)(2)",
+        "let adder = fn(a, b) { let c = a + b; fn(d) { fn(e) { a + b + c + d + e } } }; adder(1, 2)(3)(4)",
+        "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(100)",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+        "let f = fn(n) { let g = fn() { f(n - 1) }; if (n == 0) { \"done\" } else { g() } }; f(5)",
+        "map([1, 2, 3], fn(x) { x * x })",
+        "reduce([1, 2, 3], 0, fn(acc, x) { acc + x })",
+        "let n = 10; map([1, 2], fn(x) { x + n })",
+        "let f = fn(xs) { len(xs) }; f([1, 2, 3])",
+        "let f = fn(x) { len(x) }; f(1)",
+        "let g = fn() { len }; g()(\"abc\")",
+        r#""a" - 1"#,
+        "-true",
+        "1 + true",
+        "if (1) { 2 }",
+        "let f = fn(a) { a }; f()",
+        "1(2)",
+        "[1, 2] + [3]",
+        "let x = 1; let y = { let x = 2; x * 10 }; [x, y]",
+        "let s = fn(n, acc) { if (n == 0) { acc } else { s(n - 1, acc + \"x\") } }; s(5, \"\")",
+    ];
+    for input in inputs.iter() {
+        assert_eq!(run(input), run_stack_vm(input), "{}", input);
+    }
+}
+
+#[test]
+fn test_locals_stay_in_registers() {
+    let bytecode = compile("let f = fn(a, b) { let c = a * b; c + a }; f(2, 3)");
+    assert_eq!(
+        bytecode.disassemble(),
+        "main:\n\
+         0000 Closure r0 0 r1 0    ; fn f\n\
+         0006 SetGlobal 0 r0\n\
+         0010 GetGlobal r1 0\n\
+         0014 LoadConstant r2 1    ; 2\n\
+         0018 LoadConstant r3 2    ; 3\n\
+         0022 Call r0 r1 2\n\
+         0026 Return r0\n\
+         \n\
+         constant 0, fn f:\n\
+         0000 Mul r2 r0 r1\n\
+         0004 Add r3 r2 r0\n\
+         0008 Return r3\n"
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    assert_eq!(
+        run("let count = fn(n) { if (n == 0) { \"done\" } else { count(n - 1) } }; count(100000)"),
+        "Ok(Some(String(\"done\")))"
+    );
+    // A builtin in tail position.
+    assert_eq!(
+        run("let f = fn(xs) { len(xs) }; f([1, 2])"),
+        "Ok(Some(Integer(2)))"
+    );
+    // Called from a builtin, recursing in tail position.
+    assert_eq!(
+        run("let down = fn(n) { if (n == 0) { 0 } else { down(n - 1) } }; map([10, 20], down)"),
+        "Ok(Some(Array([Integer(0), Integer(0)])))"
+    );
+}
+
+#[test]
+fn test_long_expressions() {
+    let terms: Vec<String> = (0..400).map(|i| i.to_string()).collect();
+    assert_eq!(run(&terms.join(" + ")), "Ok(Some(Integer(79800)))");
+    assert_eq!(
+        run(&format!("len([{}])", terms.join(", "))),
+        "Ok(Some(Integer(400)))"
+    );
+    // A local rebound to a long array of itself.
+    assert_eq!(
+        run(&format!(
+            "fn(a) {{ let a = [{}]; len(a[299]) }}([1])",
+            vec!["a"; 300].join(", ")
+        )),
+        "Ok(Some(Integer(1)))"
+    );
+}
+
+#[test]
+fn test_too_many_registers() {
+    // Each local needs a register of its own.
+    let lets: Vec<String> = (0..300)
+        .map(|i| format!("let {} = {};", "a".repeat(i + 1), i))
+        .collect();
+    let mut lexer = lexer::new(&format!("fn() {{ {} }}", lets.join(" ")));
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program().unwrap();
+    assert!(matches!(
+        Compiler::new().compile_program(&program),
+        Err(compiler::CompilerError::TooManyOperands(_))
+    ));
+}
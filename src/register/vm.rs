@@ -0,0 +1,525 @@
+use super::code::{Instruction, Register};
+use super::Bytecode;
+use crate::eval::builtins::BuiltinRegistry;
+use crate::limits::{InterruptHandle, Limits, Meter};
+use crate::logic;
+use crate::object::{CallContext, CompiledFunction, Object};
+use crate::vm::{self, VmError};
+use std::rc::Rc;
+
+struct Frame {
+    // Always an `Object::Closure`; kept whole for `CurrentClosure`.
+    closure: Rc<Object>,
+    function: Rc<CompiledFunction>,
+    ip: usize,
+    // Where the frame's registers start in the register file.
+    base: usize,
+    // The register in the caller's frame that the result goes in.
+    return_to: usize,
+}
+
+/**
+ * Runs bytecode from `register::Compiler`. All frames share one register
+ * file, each frame having a window onto it. A call's arguments are already
+ * in place at the start of the called function's window, so calls copy
+ * nothing.
+ */
+pub struct Vm<'bytecode> {
+    bytecode: &'bytecode Bytecode,
+    registers: Vec<Rc<Object>>,
+    globals: Vec<Rc<Object>>,
+    builtins: Rc<BuiltinRegistry>,
+    frames: Vec<Frame>,
+    meter: Meter,
+    // Fills registers that haven't been set yet, which calls would
+    // otherwise spend most of their time allocating.
+    null: Rc<Object>,
+}
+
+impl<'bytecode> Vm<'bytecode> {
+    pub fn new(bytecode: &'bytecode Bytecode) -> Vm<'bytecode> {
+        Vm::with_state(bytecode, vec![], Rc::new(BuiltinRegistry::with_defaults()))
+    }
+
+    /**
+     * Creates a VM that starts with the globals left behind by an earlier
+     * one, for running bytecode compiled by the same `Compiler`. `builtins`
+     * must be the registry the compiler was given.
+     */
+    pub fn with_state(
+        bytecode: &'bytecode Bytecode,
+        globals: Vec<Rc<Object>>,
+        builtins: Rc<BuiltinRegistry>,
+    ) -> Vm<'bytecode> {
+        Vm {
+            bytecode,
+            registers: vec![],
+            globals,
+            builtins,
+            frames: vec![],
+            meter: Meter::default(),
+            null: Rc::new(Object::Null),
+        }
+    }
+
+    /**
+     * Limits what the bytecode can do, for running untrusted scripts.
+     */
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.meter = Meter::new(limits);
+    }
+
+    pub fn set_interrupt(&mut self, interrupt: InterruptHandle) {
+        self.meter.set_interrupt(interrupt);
+    }
+
+    pub fn into_globals(self) -> Vec<Rc<Object>> {
+        self.globals
+    }
+
+    pub fn run(&mut self) -> Result<Option<Rc<Object>>, VmError> {
+        let function = Rc::new(CompiledFunction {
+            instructions: self.bytecode.instructions.clone(),
+            num_locals: self.bytecode.num_registers,
+            num_parameters: 0,
+            name: None,
+        });
+        let closure = Rc::new(Object::Closure {
+            function: Rc::clone(&function),
+            free: vec![],
+        });
+        self.registers
+            .resize(function.num_locals, Rc::clone(&self.null));
+        self.frames.push(Frame {
+            closure,
+            function,
+            ip: 0,
+            base: 0,
+            return_to: 0,
+        });
+        self.execute(0)
+    }
+
+    /**
+     * Calls a function value, such as a closure read out of the globals,
+     * running it to completion.
+     */
+    pub fn call_value(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, VmError> {
+        // Above every frame's registers, as though called by the frame on
+        // top.
+        let position = self.registers.len();
+        let depth = self.frames.len();
+        self.registers.push(Rc::clone(function));
+        let num_args = arguments.len();
+        self.registers.extend(arguments);
+        let result = self.call(position, num_args, position).and_then(|_| {
+            if self.frames.len() == depth {
+                // It was a builtin, so the result is already in place.
+                Ok(Rc::clone(&self.registers[position]))
+            } else {
+                let result = self.execute(depth)?;
+                Ok(result.unwrap_or_else(|| Rc::new(Object::Null)))
+            }
+        });
+        self.registers.truncate(position);
+        result
+    }
+
+    /**
+     * Runs instructions until the frame count drops back to `base_depth`:
+     * either the main program finishing (depth 0) or a function called by a
+     * builtin returning.
+     */
+    fn execute(&mut self, base_depth: usize) -> Result<Option<Rc<Object>>, VmError> {
+        loop {
+            self.meter.step().map_err(VmError::LimitExceeded)?;
+            if self.meter.poll().is_err() {
+                return Err(self.interrupted());
+            }
+            let frame = self.frames.last_mut().unwrap();
+            let instruction = Instruction::read_at(&frame.function.instructions, frame.ip)
+                .map_err(|e| VmError::Misc(format!("Bad instruction at {}: {}", frame.ip, e)))?;
+            let instruction = if let Some((instruction, next_ip)) = instruction {
+                frame.ip = next_ip;
+                instruction
+            } else {
+                // Only the main program can run off the end of its
+                // instructions, as functions always end with a return.
+                self.frames.pop();
+                return Ok(None);
+            };
+            let base = frame.base;
+            match instruction {
+                Instruction::LoadConstant(dest, index) => {
+                    let constant = Rc::clone(&self.bytecode.constants[index as usize]);
+                    self.set(base, dest, constant);
+                }
+                Instruction::LoadTrue(dest) => self.set_new(base, dest, Object::Boolean(true))?,
+                Instruction::LoadFalse(dest) => self.set_new(base, dest, Object::Boolean(false))?,
+                Instruction::LoadNull(dest) => self.set_new(base, dest, Object::Null)?,
+                Instruction::Move(dest, source) => {
+                    let value = self.get(base, source);
+                    self.set(base, dest, value);
+                }
+                Instruction::GetGlobal(dest, index) => {
+                    let global = self.globals.get(index as usize).ok_or_else(|| {
+                        VmError::Misc(format!("Global {} hasn't been set", index))
+                    })?;
+                    let global = Rc::clone(global);
+                    self.set(base, dest, global);
+                }
+                Instruction::SetGlobal(index, source) => {
+                    let index = index as usize;
+                    if index >= self.globals.len() {
+                        self.globals
+                            .resize_with(index + 1, || Rc::new(Object::Null));
+                    }
+                    self.globals[index] = self.get(base, source);
+                }
+                Instruction::GetBuiltin(dest, index) => {
+                    let builtin = self.builtins.get_by_index(index as usize).ok_or_else(|| {
+                        VmError::Misc(format!("There is no builtin with index {}", index))
+                    })?;
+                    let function = Rc::clone(&builtin.function);
+                    self.set_new(base, dest, Object::BuiltinFunction(function))?;
+                }
+                Instruction::GetFree(dest, index) => {
+                    let free_value = match &*self.current_frame().closure {
+                        Object::Closure { free, .. } => Rc::clone(&free[index as usize]),
+                        _ => unreachable!(),
+                    };
+                    self.set(base, dest, free_value);
+                }
+                Instruction::NewCell(dest) => self.set(base, dest, vm::new_cell()),
+                Instruction::GetCell(dest, cell) => {
+                    let value = vm::read_cell(&self.get(base, cell))?;
+                    self.set(base, dest, value);
+                }
+                Instruction::SetCell(cell, source) => {
+                    vm::write_cell(&self.get(base, cell), self.get(base, source))?;
+                }
+                Instruction::GetFreeCell(dest, index) => {
+                    let value = match &*self.current_frame().closure {
+                        Object::Closure { free, .. } => vm::read_cell(&free[index as usize])?,
+                        _ => unreachable!(),
+                    };
+                    self.set(base, dest, value);
+                }
+                Instruction::CurrentClosure(dest) => {
+                    let closure = Rc::clone(&self.current_frame().closure);
+                    self.set(base, dest, closure);
+                }
+                Instruction::Add(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Plus, right)?;
+                }
+                Instruction::Sub(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Minus, right)?;
+                }
+                Instruction::Mul(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Multiply, right)?;
+                }
+                Instruction::Div(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Divide, right)?;
+                }
+                Instruction::Equal(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Eq, right)?;
+                }
+                Instruction::NotEqual(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::NotEq, right)?;
+                }
+                Instruction::GreaterThan(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Gt, right)?;
+                }
+                Instruction::LessThan(dest, left, right) => {
+                    self.infix(base, dest, left, &logic::InfixOperator::Lt, right)?;
+                }
+                Instruction::Minus(dest, operand) => {
+                    self.prefix(base, dest, &logic::PrefixOperator::Minus, operand)?;
+                }
+                Instruction::Bang(dest, operand) => {
+                    self.prefix(base, dest, &logic::PrefixOperator::Bang, operand)?;
+                }
+                Instruction::Jump(position) => {
+                    self.current_frame().ip = position as usize;
+                }
+                Instruction::JumpFalse(condition, position) => {
+                    let condition = self.get(base, condition);
+                    match *condition {
+                        Object::Boolean(true) => {}
+                        Object::Boolean(false) => self.current_frame().ip = position as usize,
+                        _ => {
+                            return Err(VmError::Misc(format!(
+                                "The condition in an if statement must be a bool. Got {}",
+                                condition.type_name()
+                            )))
+                        }
+                    }
+                }
+                Instruction::Array(dest, first, length) => {
+                    let first = base + first as usize;
+                    let elements = self.registers[first..first + length as usize].to_vec();
+                    self.set_new(base, dest, Object::Array(elements))?;
+                }
+                Instruction::AppendArray(array, first, length) => {
+                    let first = base + first as usize;
+                    let elements = &self.registers[first..first + length as usize];
+                    self.meter
+                        .count(elements.len())
+                        .map_err(VmError::LimitExceeded)?;
+                    let elements = elements.to_vec();
+                    // Nothing else has seen the array yet.
+                    match Rc::get_mut(&mut self.registers[base + array as usize]) {
+                        Some(Object::Array(existing)) => existing.extend(elements),
+                        _ => return Err(VmError::Misc(String::from("Not a new array"))),
+                    }
+                }
+                Instruction::Index(dest, left, index) => {
+                    let result = logic::eval_index(&self.get(base, left), &self.get(base, index))
+                        .map_err(VmError::Misc)?;
+                    self.set(base, dest, result);
+                }
+                Instruction::Call(dest, function, num_args) => {
+                    let function = base + function as usize;
+                    self.call(function, num_args as usize, base + dest as usize)?;
+                }
+                Instruction::TailCall(function, num_args) => {
+                    self.tail_call(base + function as usize, num_args as usize)?;
+                }
+                Instruction::Closure(dest, index, first, num_free) => {
+                    let constant = &self.bytecode.constants[index as usize];
+                    let function = if let Object::CompiledFunction(function) = &**constant {
+                        Rc::clone(function)
+                    } else {
+                        return Err(VmError::Misc(format!(
+                            "Expected a compiled function, got {}",
+                            constant.type_name()
+                        )));
+                    };
+                    let first = base + first as usize;
+                    let free = self.registers[first..first + num_free as usize].to_vec();
+                    self.set_new(base, dest, Object::Closure { function, free })?;
+                }
+                Instruction::Return(source) => {
+                    let value = self.get(base, source);
+                    let frame = self.frames.pop().unwrap();
+                    if frame.base == 0 {
+                        // A return statement in the main program ends it.
+                        return Ok(Some(value));
+                    }
+                    if self.frames.len() == base_depth {
+                        return Ok(Some(value));
+                    }
+                    // The caller's temporaries above the call may still
+                    // hold the returning function's values, but nothing
+                    // past the end of its window does.
+                    let caller = self.current_frame();
+                    let end = caller.base + caller.function.num_locals;
+                    self.registers.truncate(end);
+                    self.registers.resize(end, Rc::clone(&self.null));
+                    self.registers[frame.return_to] = value;
+                }
+            }
+        }
+    }
+
+    fn current_frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn get(&self, base: usize, register: Register) -> Rc<Object> {
+        Rc::clone(&self.registers[base + register as usize])
+    }
+
+    fn set(&mut self, base: usize, register: Register, value: Rc<Object>) {
+        self.registers[base + register as usize] = value;
+    }
+
+    /**
+     * Stores an object the VM has just made, counting it against the limits.
+     */
+    fn set_new(&mut self, base: usize, register: Register, object: Object) -> Result<(), VmError> {
+        self.meter
+            .allocate(&object)
+            .map_err(VmError::LimitExceeded)?;
+        self.set(base, register, Rc::new(object));
+        Ok(())
+    }
+
+    fn prefix(
+        &mut self,
+        base: usize,
+        dest: Register,
+        operator: &logic::PrefixOperator,
+        operand: Register,
+    ) -> Result<(), VmError> {
+        let operand = &self.registers[base + operand as usize];
+        let result = logic::eval_prefix(operand, operator).map_err(VmError::Misc)?;
+        self.set_new(base, dest, result)
+    }
+
+    fn infix(
+        &mut self,
+        base: usize,
+        dest: Register,
+        left: Register,
+        operator: &logic::InfixOperator,
+        right: Register,
+    ) -> Result<(), VmError> {
+        let left = &self.registers[base + left as usize];
+        let right = &self.registers[base + right as usize];
+        self.meter
+            .check_infix(left, operator, right)
+            .map_err(VmError::LimitExceeded)?;
+        let result = logic::eval_infix(left, operator, right).map_err(VmError::Misc)?;
+        self.set_new(base, dest, result)
+    }
+
+    /**
+     * Calls the function in register `function` (counting from the start of
+     * the register file) with the `num_args` registers after it as its
+     * arguments. Closures get a new frame which the dispatch loop then runs;
+     * builtins are run straight away. Either way the result ends up in
+     * register `return_to`.
+     */
+    fn call(&mut self, function: usize, num_args: usize, return_to: usize) -> Result<(), VmError> {
+        let callee = Rc::clone(&self.registers[function]);
+        match &*callee {
+            Object::Closure {
+                function: compiled, ..
+            } => {
+                check_arguments(compiled, num_args)?;
+                // The main program's frame isn't a call.
+                let depth = match self.frames.first() {
+                    Some(frame) if frame.base == 0 => self.frames.len(),
+                    _ => self.frames.len() + 1,
+                };
+                self.meter
+                    .check_depth(depth)
+                    .map_err(VmError::LimitExceeded)?;
+                let base = function + 1;
+                self.reserve(base, compiled);
+                self.frames.push(Frame {
+                    closure: Rc::clone(&callee),
+                    function: Rc::clone(compiled),
+                    ip: 0,
+                    base,
+                    return_to,
+                });
+            }
+            Object::BuiltinFunction(builtin) => {
+                let arguments = self.registers[function + 1..function + 1 + num_args].to_vec();
+                let result = builtin.run(&arguments, self).map_err(|m| self.error(m))?;
+                if Rc::strong_count(&result) == 1 {
+                    // Made by the builtin rather than passed through it.
+                    self.meter
+                        .allocate(&result)
+                        .map_err(VmError::LimitExceeded)?;
+                }
+                self.registers[return_to] = result;
+            }
+            _ => return Err(VmError::Misc(format!("Cannot call {}", callee))),
+        }
+        Ok(())
+    }
+
+    /**
+     * Like `call`, but a closure replaces the current frame instead of
+     * getting a new one on top of it, so recursion in tail position runs in
+     * constant space. Anything else is called as usual, with the result
+     * left in the function's register.
+     */
+    fn tail_call(&mut self, function: usize, num_args: usize) -> Result<(), VmError> {
+        let callee = Rc::clone(&self.registers[function]);
+        let base = self.current_frame().base;
+        let compiled = match &*callee {
+            // The main program's frame can't be replaced.
+            Object::Closure { function, .. } if base != 0 => Rc::clone(function),
+            _ => return self.call(function, num_args, function),
+        };
+        check_arguments(&compiled, num_args)?;
+        // The callee and its arguments move down to where the current
+        // function and its arguments were.
+        for offset in 0..=num_args {
+            let value = Rc::clone(&self.registers[function + offset]);
+            self.registers[base - 1 + offset] = value;
+        }
+        self.reserve(base, &compiled);
+        let frame = self.current_frame();
+        frame.closure = callee;
+        frame.function = compiled;
+        frame.ip = 0;
+        Ok(())
+    }
+
+    /**
+     * Makes sure the register file is long enough for a frame of `function`
+     * starting at `base`.
+     */
+    fn reserve(&mut self, base: usize, function: &CompiledFunction) {
+        let end = base + function.num_locals;
+        if self.registers.len() < end {
+            self.registers.resize(end, Rc::clone(&self.null));
+        }
+    }
+
+    /**
+     * Turns an error message from a builtin back into a `VmError`, which
+     * is a `LimitExceeded` if a function it called ran out.
+     */
+    fn error(&self, message: String) -> VmError {
+        if self.meter.interrupted() {
+            return self.interrupted();
+        }
+        match self.meter.exceeded() {
+            Some(limit) => VmError::LimitExceeded(limit),
+            None => VmError::Misc(message),
+        }
+    }
+
+    fn interrupted(&self) -> VmError {
+        let stack_trace = self
+            .frames
+            .iter()
+            .rev()
+            // The main program's frame isn't a call.
+            .filter(|frame| frame.base != 0)
+            .map(|frame| String::from(frame.closure.function_name()))
+            .collect();
+        VmError::Interrupted { stack_trace }
+    }
+}
+
+fn check_arguments(function: &CompiledFunction, num_args: usize) -> Result<(), VmError> {
+    if num_args != function.num_parameters {
+        return Err(VmError::Misc(format!(
+            "Expected {} args, got {}",
+            function.num_parameters, num_args
+        )));
+    }
+    Ok(())
+}
+
+/**
+ * Lets builtins call back into the VM, running the function above every
+ * frame's registers until it returns.
+ */
+impl<'bytecode> CallContext for Vm<'bytecode> {
+    fn call(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, String> {
+        self.call_value(function, arguments)
+            .map_err(VmError::into_message)
+    }
+
+    fn check_string_length(&mut self, length: usize) -> Result<(), String> {
+        self.meter
+            .check_string_length(length)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,338 @@
+#[cfg(test)]
+mod test;
+
+use crate::ast::visit::{walk_expression_mut, walk_statement_mut};
+use crate::ast::{
+    BlockStatement, Expression, Position, Program, Slot, Span, Statement, VisitorMut,
+};
+use crate::eval::builtins::BuiltinRegistry;
+use crate::lexer;
+use crate::parser::{self, ParserError};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/**
+ * A name that can't be found where it's used, so would fail if the code
+ * using it ever ran.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    UnknownIdentifier {
+        name: String,
+        span: Span,
+    },
+    /// Used by code that runs before the name is bound.
+    UsedBeforeDefinition {
+        name: String,
+        span: Span,
+        /// Where it's bound.
+        definition: Position,
+    },
+}
+
+impl ResolveError {
+    pub fn span(&self) -> Span {
+        match self {
+            ResolveError::UnknownIdentifier { span, .. }
+            | ResolveError::UsedBeforeDefinition { span, .. } => *span,
+        }
+    }
+}
+
+impl ResolveError {
+    /**
+     * What's wrong, without where.
+     */
+    pub fn message(&self) -> String {
+        match self {
+            ResolveError::UnknownIdentifier { name, .. } => {
+                format!("The identifier '{}' has not been bound", name)
+            }
+            ResolveError::UsedBeforeDefinition {
+                name, definition, ..
+            } => format!(
+                "'{}' is used before it is bound on line {}",
+                name, definition.line
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.span().start;
+        write!(f, "{}:{}: {}", start.line, start.column, self.message())
+    }
+}
+
+/**
+ * Parses the source and reports every name in it that can't be resolved,
+ * in the order they appear.
+ */
+pub fn check(source: &str) -> Result<Vec<ResolveError>, ParserError> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let mut program = parser.parse_program()?;
+    Ok(resolve(&mut program, &BuiltinRegistry::with_defaults()))
+}
+
+/**
+ * Works out which binding each name in the program refers to, noting on
+ * each local where the interpreter keeps it, and reports the names that
+ * refer to nothing. Anything bound at the top level is left to be looked
+ * up by name, as it may also be bound by the host or by earlier programs.
+ */
+pub fn resolve(program: &mut Program, builtins: &BuiltinRegistry) -> Vec<ResolveError> {
+    resolve_names(program, builtins).errors
+}
+
+/**
+ * Which binding each name in a program refers to, for tools that need to
+ * know more than where the interpreter finds it.
+ */
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// Each name bound by a `let` or a parameter, and where, in the order
+    /// they're reached: a function's parameters as it is, and a `let` as
+    /// its statement starts.
+    pub bindings: Vec<(String, Position)>,
+    /// Each name used, and where, in the order they appear, with the index
+    /// of the binding it refers to if the program makes it.
+    pub uses: Vec<(String, Position, Option<usize>)>,
+    pub errors: Vec<ResolveError>,
+}
+
+/**
+ * Resolves the program as `resolve` does, also returning which binding each
+ * name refers to.
+ */
+pub fn resolve_names(program: &mut Program, builtins: &BuiltinRegistry) -> Resolution {
+    let mut resolver = Resolver {
+        builtins,
+        scopes: vec![],
+        resolution: Resolution::default(),
+    };
+    resolver.scoped(Kind::Global, &[], &mut program.statements);
+    resolver.resolution
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Global,
+    Function,
+    Block,
+}
+
+/**
+ * The bindings made by one environment of the interpreter: the program's,
+ * a function call's or a block's.
+ */
+struct Scope {
+    kind: Kind,
+    // Every name bound anywhere in the scope, with its slot and where it's
+    // first bound. Binding a name again reuses its slot.
+    slots: HashMap<String, (usize, Position)>,
+    // The names bound by the code walked so far, each with its latest
+    // binding.
+    defined: HashMap<String, usize>,
+    // The first binding of each name reached so far, which may still be
+    // being walked.
+    first: HashMap<String, usize>,
+    // Uses by functions of names the scope binds later, by index, to be
+    // filled in once the binding is reached.
+    pending: Vec<(String, usize)>,
+}
+
+impl Scope {
+    fn new(kind: Kind, params: &[(String, Position)], statements: &[Statement]) -> Self {
+        let mut scope = Scope {
+            kind,
+            slots: HashMap::new(),
+            defined: HashMap::new(),
+            first: HashMap::new(),
+            pending: vec![],
+        };
+        // Parameters take the first slots in order, so that a call can
+        // fill them in without knowing their names.
+        for (index, (name, position)) in params.iter().enumerate() {
+            scope.slots.insert(name.clone(), (index, *position));
+        }
+        let mut next = params.len();
+        for statement in statements {
+            if let Statement::Let { name, position, .. } = statement {
+                scope.slots.entry(name.clone()).or_insert_with(|| {
+                    next += 1;
+                    (next - 1, *position)
+                });
+            }
+        }
+        scope
+    }
+}
+
+struct Resolver<'a> {
+    builtins: &'a BuiltinRegistry,
+    // Innermost last.
+    scopes: Vec<Scope>,
+    resolution: Resolution,
+}
+
+impl Resolver<'_> {
+    fn scoped(&mut self, kind: Kind, params: &[(String, Position)], statements: &mut [Statement]) {
+        self.scopes.push(Scope::new(kind, params, statements));
+        for (name, position) in params {
+            let binding = self.bind(name, *position);
+            let scope = self.scopes.last_mut().unwrap();
+            scope.defined.insert(name.clone(), binding);
+        }
+        for statement in statements {
+            self.visit_statement_mut(statement);
+        }
+        self.scopes.pop();
+    }
+
+    /**
+     * Finds the binding a name refers to, just as the interpreter would
+     * when the code using it runs. Code in a function runs when it is
+     * called, by which time the scopes around the function may have bound
+     * more names, so it can refer to their later bindings, but only when
+     * nothing outside is bound yet: a call made before the later binding
+     * runs would find its slot empty. Other code can only refer to the
+     * bindings made before it.
+     */
+    fn lookup(&mut self, name: &str, position: Position) -> Option<Slot> {
+        let used = self.resolution.uses.len();
+        self.resolution
+            .uses
+            .push((String::from(name), position, None));
+        let mut in_function = false;
+        let mut bound_later: Option<Position> = None;
+        // The scope binding the name later, and the slot it will be in.
+        let mut later: Option<(usize, Option<Slot>)> = None;
+        let innermost = self.scopes.len() - 1;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some((index, definition)) = scope.slots.get(name) {
+                let slot = match scope.kind {
+                    Kind::Global => None,
+                    _ => Some(Slot {
+                        depth,
+                        index: *index,
+                    }),
+                };
+                if let Some(binding) = scope.defined.get(name) {
+                    self.resolution.uses[used].2 = Some(*binding);
+                    return slot;
+                }
+                if in_function && later.is_none() {
+                    later = Some((innermost - depth, slot));
+                    // Globals are looked up by name when used.
+                    if slot.is_none() {
+                        break;
+                    }
+                }
+                // Not bound yet, so an outer binding is found instead.
+                bound_later = bound_later.or(Some(*definition));
+            }
+            in_function |= scope.kind == Kind::Function;
+        }
+        let builtin = self.builtins.get(name).is_some();
+        match later {
+            Some((scope, slot)) if !builtin || slot.is_none() => {
+                let scope = &mut self.scopes[scope];
+                match scope.first.get(name) {
+                    Some(binding) => self.resolution.uses[used].2 = Some(*binding),
+                    None => scope.pending.push((String::from(name), used)),
+                }
+                return slot;
+            }
+            _ if builtin => return None,
+            _ => {}
+        }
+        let name = String::from(name);
+        let span = Span::new(position, name.chars().count());
+        self.resolution.errors.push(match bound_later {
+            Some(definition) => ResolveError::UsedBeforeDefinition {
+                name,
+                span,
+                definition,
+            },
+            None => ResolveError::UnknownIdentifier { name, span },
+        });
+        None
+    }
+
+    /**
+     * Notes a binding made in the innermost scope, returning its index.
+     */
+    fn bind(&mut self, name: &str, position: Position) -> usize {
+        let bindings = &mut self.resolution.bindings;
+        let binding = bindings.len();
+        bindings.push((String::from(name), position));
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.first.contains_key(name) {
+            return binding;
+        }
+        scope.first.insert(String::from(name), binding);
+        let uses = &mut self.resolution.uses;
+        scope.pending.retain(|(pending, used)| {
+            if pending != name {
+                return true;
+            }
+            uses[*used].2 = Some(binding);
+            false
+        });
+        binding
+    }
+}
+
+impl VisitorMut for Resolver<'_> {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        let binding = match statement {
+            Statement::Let { name, position, .. } => Some(self.bind(name, *position)),
+            _ => None,
+        };
+        walk_statement_mut(self, statement);
+        if let (Statement::Let { name, slot, .. }, Some(binding)) = (statement, binding) {
+            let scope = self.scopes.last_mut().unwrap();
+            scope.defined.insert(name.clone(), binding);
+            *slot = match scope.kind {
+                Kind::Global => None,
+                _ => scope.slots.get(name).map(|(index, _)| *index),
+            };
+        }
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Identifier {
+                value,
+                position,
+                slot,
+            } => *slot = self.lookup(value, *position),
+            Expression::FnLiteral {
+                param_names,
+                param_positions,
+                body,
+                ..
+            } => {
+                let params: Vec<(String, Position)> = param_names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let position = param_positions.get(i).copied().unwrap_or_default();
+                        (name.clone(), position)
+                    })
+                    .collect();
+                let body = Rc::make_mut(body);
+                self.scoped(Kind::Function, &params, &mut body.statements);
+            }
+            Expression::Block { statements } => self.scoped(Kind::Block, &[], statements),
+            _ => walk_expression_mut(self, expression),
+        }
+    }
+
+    fn visit_block_statement_mut(&mut self, block: &mut BlockStatement) {
+        self.scoped(Kind::Block, &[], &mut block.statements);
+    }
+}
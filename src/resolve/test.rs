@@ -0,0 +1,212 @@
+use super::{check, resolve, resolve_names, ResolveError};
+use crate::ast::visit::walk_expression;
+use crate::ast::{Expression, Slot, Visitor};
+use crate::eval::builtins::BuiltinRegistry;
+use crate::{lexer, parser};
+use pretty_assertions::assert_eq;
+
+/**
+ * Each error found in the source, as what it is, the name it's about and
+ * where that starts as (line, column).
+ */
+fn errors(source: &str) -> Vec<(&'static str, String, (usize, usize))> {
+    check(source)
+        .unwrap()
+        .into_iter()
+        .map(|error| {
+            let start = error.span().start;
+            let (kind, name) = match error {
+                ResolveError::UnknownIdentifier { name, .. } => ("unknown", name),
+                ResolveError::UsedBeforeDefinition { name, .. } => ("before", name),
+            };
+            (kind, name, (start.line, start.column))
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Slots {
+    slots: Vec<(String, Option<Slot>)>,
+}
+
+impl Visitor for Slots {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Identifier { value, slot, .. } = expression {
+            self.slots.push((value.clone(), *slot));
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/**
+ * Resolves the source, returning where each name in it is found, in the
+ * order they appear.
+ */
+fn slots(source: &str) -> Vec<(String, Option<Slot>)> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let mut program = parser.parse_program().unwrap();
+    resolve(&mut program, &BuiltinRegistry::with_defaults());
+    let mut slots = Slots::default();
+    slots.visit_program(&program);
+    slots.slots
+}
+
+fn local(depth: usize, index: usize) -> Option<Slot> {
+    Some(Slot { depth, index })
+}
+
+#[test]
+fn test_resolvable_program() {
+    let source = "let f = fn(a) { g(a) };
+let g = fn(b) { let c = len(b); if (c > 0) { f(filter(b, fn(x) { x > 1 })) } else { c } };
+print(f([1, 2]));";
+    assert_eq!(errors(source), vec![]);
+}
+
+#[test]
+fn test_unknown_identifiers() {
+    let source = "let f = fn(a) { a + b };
+print(f(c));
+if (true) { let d = 1; }
+d;
+fn() { nope() }";
+    assert_eq!(
+        errors(source),
+        vec![
+            ("unknown", String::from("b"), (1, 21)),
+            ("unknown", String::from("c"), (2, 9)),
+            ("unknown", String::from("d"), (4, 1)),
+            ("unknown", String::from("nope"), (5, 8)),
+        ]
+    );
+    assert_eq!(
+        check("b").unwrap()[0].to_string(),
+        "1:1: The identifier 'b' has not been bound"
+    );
+}
+
+#[test]
+fn test_use_before_definition() {
+    let source = "print(x);
+let x = 1;
+let f = fn() { let y = z; let z = 2; y };
+{ w; let w = 3; }
+let v = v;";
+    assert_eq!(
+        errors(source),
+        vec![
+            ("before", String::from("x"), (1, 7)),
+            ("before", String::from("z"), (3, 24)),
+            ("before", String::from("w"), (4, 3)),
+            ("before", String::from("v"), (5, 9)),
+        ]
+    );
+    assert_eq!(
+        check("x; let x = 1;").unwrap()[0].to_string(),
+        "1:1: 'x' is used before it is bound on line 1"
+    );
+}
+
+#[test]
+fn test_outer_binding_until_shadowed() {
+    let source = "let x = 1; { let y = x; let x = 2; x }";
+    assert_eq!(errors(source), vec![]);
+    assert_eq!(
+        slots(source),
+        vec![(String::from("x"), None), (String::from("x"), local(0, 1)),]
+    );
+}
+
+#[test]
+fn test_slots() {
+    let source = "let top = 1;
+let f = fn(a, b) {
+    let c = a;
+    let g = fn() { if (c) { [b, top, len, g] } };
+    let c = g;
+    c
+};";
+    assert_eq!(
+        slots(source),
+        vec![
+            (String::from("a"), local(0, 0)),
+            (String::from("c"), local(1, 2)),
+            (String::from("b"), local(2, 1)),
+            (String::from("top"), None),
+            (String::from("len"), None),
+            (String::from("g"), local(2, 3)),
+            (String::from("g"), local(0, 3)),
+            (String::from("c"), local(0, 2)),
+        ]
+    );
+}
+
+#[test]
+fn test_later_local_only_without_outer_binding() {
+    let source = "let x = 1;
+let f = fn() {
+    let g = fn() { [x, h] };
+    if (true) { let k = fn() { x }; let x = 3; }
+    let x = 2;
+    let h = 4;
+    g
+};";
+    assert_eq!(errors(source), vec![]);
+    assert_eq!(
+        slots(source),
+        vec![
+            (String::from("x"), None),
+            (String::from("h"), local(1, 2)),
+            (String::from("x"), None),
+            (String::from("g"), local(0, 0)),
+        ]
+    );
+}
+
+/**
+ * Resolves the source, returning each name used and the name and line of
+ * the binding it refers to.
+ */
+fn uses(source: &str) -> Vec<(String, Option<(String, usize)>)> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let mut program = parser.parse_program().unwrap();
+    let resolution = resolve_names(&mut program, &BuiltinRegistry::with_defaults());
+    let bindings = resolution.bindings;
+    resolution
+        .uses
+        .into_iter()
+        .map(|(name, _, binding)| {
+            let binding = binding.map(|index| {
+                let (name, position) = &bindings[index];
+                (name.clone(), position.line)
+            });
+            (name, binding)
+        })
+        .collect()
+}
+
+#[test]
+fn test_bindings() {
+    let source = "let f = fn(n) { g(n) };
+let g = fn(n) { len(f) };
+let n = 1;
+let n = n + 1;
+let h = fn() { let k = fn() { n }; let n = 3; k };
+n";
+    let bound = |name: &str, line| Some((String::from(name), line));
+    assert_eq!(
+        uses(source),
+        vec![
+            (String::from("g"), bound("g", 2)),
+            (String::from("n"), bound("n", 1)),
+            (String::from("len"), None),
+            (String::from("f"), bound("f", 1)),
+            (String::from("n"), bound("n", 3)),
+            (String::from("n"), bound("n", 4)),
+            (String::from("k"), bound("k", 5)),
+            (String::from("n"), bound("n", 4)),
+        ]
+    );
+}
@@ -1,5 +1,10 @@
 use std::fmt;
 
+/**
+ * The words `token_from_word` makes keywords of.
+ */
+pub const KEYWORDS: [&str; 7] = ["fn", "let", "if", "else", "return", "true", "false"];
+
 pub fn token_from_word(literal: &str) -> Option<Token> {
     match literal {
         "fn" => Some(Token::Function),
@@ -13,6 +18,45 @@ pub fn token_from_word(literal: &str) -> Option<Token> {
     }
 }
 
+/**
+ * Where something is in the source: a line and a column, both counted
+ * from one, the column in characters.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/**
+ * A stretch of source from `start` up to, but not including, `end`.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /**
+     * The span of something `length` characters long, which can't run
+     * over a line.
+     */
+    pub fn new(start: Position, length: usize) -> Self {
+        let end = Position {
+            line: start.line,
+            column: start.column + length,
+        };
+        Span { start, end }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Illegal { literal: String },
@@ -24,11 +68,15 @@ pub enum Token {
     Plus,
     Minus,
     Comma,
+    Colon,
+    Arrow,
     Semicolon,
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Function,
     Let,
     Lt,
@@ -57,11 +105,15 @@ impl Token {
             Token::Plus => TokenType::Plus,
             Token::Minus => TokenType::Minus,
             Token::Comma => TokenType::Comma,
+            Token::Colon => TokenType::Colon,
+            Token::Arrow => TokenType::Arrow,
             Token::Semicolon => TokenType::Semicolon,
             Token::LParen => TokenType::LParen,
             Token::RParen => TokenType::RParen,
             Token::LBrace => TokenType::LBrace,
             Token::RBrace => TokenType::RBrace,
+            Token::LBracket => TokenType::LBracket,
+            Token::RBracket => TokenType::RBracket,
             Token::Function => TokenType::Function,
             Token::Let => TokenType::Let,
             Token::Lt => TokenType::Lt,
@@ -91,11 +143,15 @@ pub enum TokenType {
     Plus,
     Minus,
     Comma,
+    Colon,
+    Arrow,
     Semicolon,
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Function,
     Let,
     Lt,
@@ -124,11 +180,15 @@ impl fmt::Display for TokenType {
             TokenType::Plus => "Plus",
             TokenType::Minus => "Minus",
             TokenType::Comma => "Comma",
+            TokenType::Colon => "Colon",
+            TokenType::Arrow => "Arrow",
             TokenType::Semicolon => "Semicolon",
             TokenType::LParen => "LParen",
             TokenType::RParen => "RParen",
             TokenType::LBrace => "LBrace",
             TokenType::RBrace => "RBrace",
+            TokenType::LBracket => "LBracket",
+            TokenType::RBracket => "RBracket",
             TokenType::Function => "Function",
             TokenType::Let => "Let",
             TokenType::Lt => "Lt",
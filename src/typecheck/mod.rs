@@ -0,0 +1,841 @@
+#[cfg(test)]
+mod test;
+
+use crate::ast::{
+    self, BlockStatement, Expression, InfixOperator, Position, PrefixOperator, Program, Span,
+    Statement,
+};
+use crate::eval::builtins::{self, Arity, BuiltinRegistry};
+use crate::lexer;
+use crate::parser::{self, ParserError};
+use std::collections::HashMap;
+use std::fmt;
+
+/**
+ * Something that would fail when it ran, as the types of its values can't
+ * be what it needs.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.span.start;
+        write!(f, "{}:{}: {}", start.line, start.column, self.message)
+    }
+}
+
+/**
+ * Parses the source and reports the type errors in it, in the order they
+ * appear.
+ */
+pub fn check(source: &str) -> Result<Vec<TypeError>, ParserError> {
+    let mut lexer = lexer::new(source);
+    let mut parser = parser::Parser::new(&mut lexer);
+    let program = parser.parse_program()?;
+    Ok(check_program(&program))
+}
+
+/**
+ * Infers the type of everything in the program, taking annotations as
+ * given, and reports where the types don't fit. Where inference can't tell
+ * what a value is, such as a name bound by the host, or the result of an
+ * `if` whose branches disagree, the value is left unchecked, just as if it
+ * were annotated `any`.
+ */
+pub fn check_program(program: &Program) -> Vec<TypeError> {
+    let mut checker = Checker {
+        vars: vec![],
+        trail: vec![],
+        level: 0,
+        scopes: vec![],
+        functions: vec![],
+        errors: vec![],
+    };
+    let builtins = checker.builtins(&BuiltinRegistry::with_defaults());
+    checker.scopes.push(builtins);
+    checker.scoped(&program.statements);
+    let mut errors = checker.errors;
+    errors.sort_by_key(|error| (error.span.start.line, error.span.start.column));
+    errors
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Int,
+    Bool,
+    String,
+    Null,
+    /// Unchecked: fits anything.
+    Any,
+    Array(Box<Ty>),
+    Function(Vec<Ty>, Box<Ty>),
+    Var(usize),
+}
+
+#[derive(Clone)]
+enum Var {
+    Bound(Ty),
+    // Made while checking the `let` this many deep, and not bound yet.
+    Unbound { level: usize },
+}
+
+/**
+ * The type of a name, in which `vars` can stand for a different type at
+ * each use.
+ */
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Ty,
+    // Whether it's a builtin that takes more arguments than its type says.
+    variadic: bool,
+}
+
+impl Scheme {
+    fn monomorphic(ty: Ty) -> Self {
+        Scheme {
+            vars: vec![],
+            ty,
+            variadic: false,
+        }
+    }
+}
+
+/**
+ * What a function being checked returns: what it's annotated with, or if
+ * it isn't, what it has been seen to return so far.
+ */
+enum Returns {
+    Declared(Ty),
+    Found(Option<Ty>),
+}
+
+struct Checker {
+    vars: Vec<Var>,
+    // The vars changed by the unification in progress, with what they were
+    // before, so that a failed one can be undone.
+    trail: Vec<(usize, Var)>,
+    level: usize,
+    // Innermost last. The outermost holds the builtins.
+    scopes: Vec<HashMap<String, Scheme>>,
+    // For each function being checked, innermost last, with where its `fn`
+    // keyword is.
+    functions: Vec<(Returns, Position)>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn builtins(&mut self, registry: &BuiltinRegistry) -> HashMap<String, Scheme> {
+        let mut scope = HashMap::new();
+        // Made a level deeper, so that their type variables are generalized.
+        self.level += 1;
+        for (_, builtin) in registry.iter() {
+            let annotation = builtins::signature(&builtin.name).and_then(|s| s.parse().ok());
+            let ty = match annotation {
+                Some(annotation) => self.annotated(&annotation, &mut HashMap::new()),
+                None => Ty::Any,
+            };
+            let mut vars = vec![];
+            self.collect_vars(&ty, 0, &mut vars);
+            scope.insert(
+                builtin.name.clone(),
+                Scheme {
+                    vars,
+                    ty,
+                    variadic: matches!(builtin.arity, Arity::AtLeast(_)),
+                },
+            );
+        }
+        self.level -= 1;
+        scope
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(TypeError { message, span });
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.vars.push(Var::Unbound { level: self.level });
+        Ty::Var(self.vars.len() - 1)
+    }
+
+    /**
+     * Follows bound vars until reaching a type that isn't one.
+     */
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(var) => match &self.vars[*var] {
+                Var::Bound(bound) => self.resolve(bound),
+                Var::Unbound { .. } => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    fn set_var(&mut self, var: usize, state: Var) {
+        let previous = std::mem::replace(&mut self.vars[var], state);
+        self.trail.push((var, previous));
+    }
+
+    /**
+     * Makes two types the same, binding vars as needed, or changes nothing
+     * and returns false if they can't be.
+     */
+    fn unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let unified = self.unify_parts(a, b);
+        if !unified {
+            while let Some((var, previous)) = self.trail.pop() {
+                self.vars[var] = previous;
+            }
+        }
+        self.trail.clear();
+        unified
+    }
+
+    fn unify_parts(&mut self, a: &Ty, b: &Ty) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (Ty::Any, _) | (_, Ty::Any) => true,
+            (Ty::Var(a), Ty::Var(b)) if a == b => true,
+            (Ty::Var(var), other) | (other, Ty::Var(var)) => {
+                self.bind(var, other);
+                true
+            }
+            (Ty::Array(a), Ty::Array(b)) => self.unify_parts(&a, &b),
+            (Ty::Function(a_params, a_result), Ty::Function(b_params, b_result)) => {
+                a_params.len() == b_params.len()
+                    && a_params
+                        .iter()
+                        .zip(&b_params)
+                        .all(|(a, b)| self.unify_parts(a, b))
+                    && self.unify_parts(&a_result, &b_result)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn bind(&mut self, var: usize, ty: Ty) {
+        let level = match self.vars[var] {
+            Var::Unbound { level } => level,
+            Var::Bound(_) => unreachable!("Binding a bound var"),
+        };
+        if self.occurs(var, &ty) {
+            // A value that would have to contain itself, such as a
+            // function passed to itself, which is left unchecked.
+            self.set_var(var, Var::Bound(Ty::Any));
+            return;
+        }
+        self.lower_levels(&ty, level);
+        self.set_var(var, Var::Bound(ty));
+    }
+
+    fn occurs(&self, var: usize, ty: &Ty) -> bool {
+        match self.resolve(ty) {
+            Ty::Var(other) => other == var,
+            Ty::Array(element) => self.occurs(var, &element),
+            Ty::Function(params, result) => {
+                params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &result)
+            }
+            _ => false,
+        }
+    }
+
+    /**
+     * Keeps the vars in a type from being generalized any further out than
+     * the var it's being bound to.
+     */
+    fn lower_levels(&mut self, ty: &Ty, level: usize) {
+        match self.resolve(ty) {
+            Ty::Var(var) => {
+                if let Var::Unbound { level: own } = self.vars[var] {
+                    if own > level {
+                        self.set_var(var, Var::Unbound { level });
+                    }
+                }
+            }
+            Ty::Array(element) => self.lower_levels(&element, level),
+            Ty::Function(params, result) => {
+                for param in &params {
+                    self.lower_levels(param, level);
+                }
+                self.lower_levels(&result, level);
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * The type of a value that may be either of two, such as the result of
+     * an `if`: their common type if they have one, or else `any`.
+     */
+    fn join(&mut self, a: Ty, b: &Ty) -> Ty {
+        if self.unify(&a, b) {
+            a
+        } else {
+            Ty::Any
+        }
+    }
+
+    fn free_vars(&self, ty: &Ty) -> Vec<usize> {
+        let mut vars = vec![];
+        self.collect_vars(ty, self.level, &mut vars);
+        vars
+    }
+
+    /**
+     * Collects the unbound vars in a type made deeper than `level`.
+     */
+    fn collect_vars(&self, ty: &Ty, level: usize, vars: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Ty::Var(var)
+                if matches!(self.vars[var], Var::Unbound { level: own } if own > level)
+                    && !vars.contains(&var) =>
+            {
+                vars.push(var);
+            }
+            Ty::Array(element) => self.collect_vars(&element, level, vars),
+            Ty::Function(params, result) => {
+                for param in &params {
+                    self.collect_vars(param, level, vars);
+                }
+                self.collect_vars(&result, level, vars);
+            }
+            _ => {}
+        }
+    }
+
+    fn instantiate(&mut self, scheme_vars: &[usize], ty: &Ty) -> Ty {
+        if scheme_vars.is_empty() {
+            return ty.clone();
+        }
+        let fresh: HashMap<usize, Ty> =
+            scheme_vars.iter().map(|var| (*var, self.fresh())).collect();
+        self.substitute(ty, &fresh)
+    }
+
+    fn substitute(&self, ty: &Ty, fresh: &HashMap<usize, Ty>) -> Ty {
+        match self.resolve(ty) {
+            Ty::Var(var) => fresh.get(&var).cloned().unwrap_or(Ty::Var(var)),
+            Ty::Array(element) => Ty::Array(Box::new(self.substitute(&element, fresh))),
+            Ty::Function(params, result) => Ty::Function(
+                params.iter().map(|p| self.substitute(p, fresh)).collect(),
+                Box::new(self.substitute(&result, fresh)),
+            ),
+            other => other,
+        }
+    }
+
+    /**
+     * The type an annotation stands for. Its type variables are looked up
+     * in, and added to, `names`.
+     */
+    fn annotated(&mut self, annotation: &ast::Type, names: &mut HashMap<String, Ty>) -> Ty {
+        match annotation {
+            ast::Type::Int => Ty::Int,
+            ast::Type::Bool => Ty::Bool,
+            ast::Type::String => Ty::String,
+            ast::Type::Null => Ty::Null,
+            ast::Type::Any => Ty::Any,
+            ast::Type::Array(element) => Ty::Array(Box::new(self.annotated(element, names))),
+            ast::Type::Function { params, result } => Ty::Function(
+                params
+                    .iter()
+                    .map(|param| self.annotated(param, names))
+                    .collect(),
+                Box::new(self.annotated(result, names)),
+            ),
+            ast::Type::Variable(name) => match names.get(name) {
+                Some(ty) => ty.clone(),
+                None => {
+                    let ty = self.fresh();
+                    names.insert(name.clone(), ty.clone());
+                    ty
+                }
+            },
+        }
+    }
+
+    /**
+     * Writes types as they would be annotated, naming the vars in them in
+     * the order they appear.
+     */
+    fn describe(&self, types: &[&Ty]) -> Vec<String> {
+        let mut names = HashMap::new();
+        types
+            .iter()
+            .map(|ty| self.to_annotation(ty, &mut names).to_string())
+            .collect()
+    }
+
+    fn to_annotation(&self, ty: &Ty, names: &mut HashMap<usize, String>) -> ast::Type {
+        match self.resolve(ty) {
+            Ty::Int => ast::Type::Int,
+            Ty::Bool => ast::Type::Bool,
+            Ty::String => ast::Type::String,
+            Ty::Null => ast::Type::Null,
+            Ty::Any => ast::Type::Any,
+            Ty::Array(element) => ast::Type::Array(Box::new(self.to_annotation(&element, names))),
+            Ty::Function(params, result) => ast::Type::Function {
+                params: params
+                    .iter()
+                    .map(|param| self.to_annotation(param, names))
+                    .collect(),
+                result: Box::new(self.to_annotation(&result, names)),
+            },
+            Ty::Var(var) => {
+                let next = names.len();
+                let name = names.entry(var).or_insert_with(|| var_name(next)).clone();
+                ast::Type::Variable(name)
+            }
+        }
+    }
+
+    fn bind_name(&mut self, name: &str, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(String::from(name), scheme);
+    }
+
+    fn lookup(&mut self, name: &str) -> Ty {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .map(|scheme| (scheme.vars.clone(), scheme.ty.clone()));
+        match found {
+            Some((vars, ty)) => self.instantiate(&vars, &ty),
+            // Not bound yet, or bound by the host: the resolver reports
+            // names that are never bound.
+            None => Ty::Any,
+        }
+    }
+
+    fn is_variadic(&self, function: &Expression) -> bool {
+        match function {
+            Expression::Identifier { value, .. } => self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(value))
+                .is_some_and(|scheme| scheme.variadic),
+            _ => false,
+        }
+    }
+
+    /**
+     * Checks statements in a scope of their own, returning the type of the
+     * value they leave.
+     */
+    fn scoped(&mut self, statements: &[Statement]) -> Ty {
+        self.scopes.push(HashMap::new());
+        let ty = self.statements(statements);
+        self.scopes.pop();
+        ty
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Ty {
+        let mut last = Ty::Null;
+        for statement in statements {
+            last = match statement {
+                Statement::Let {
+                    name,
+                    right,
+                    position,
+                    annotation,
+                    ..
+                } => {
+                    self.let_statement(name, right, *position, annotation.as_ref());
+                    Ty::Null
+                }
+                Statement::Return { value } => {
+                    let ty = self.infer(value);
+                    self.returned(ty, Some(value));
+                    // Nothing follows a return, so whatever would have
+                    // come next fits.
+                    self.fresh()
+                }
+                Statement::Expression { expression } => self.infer(expression),
+            };
+        }
+        last
+    }
+
+    fn let_statement(
+        &mut self,
+        name: &str,
+        right: &Expression,
+        position: Position,
+        annotation: Option<&ast::Type>,
+    ) {
+        self.level += 1;
+        let declared = annotation.map(|annotation| self.annotated(annotation, &mut HashMap::new()));
+        let ty = if let Expression::FnLiteral { .. } = right {
+            // Bound before its body, so that it can call itself.
+            let itself = declared.clone().unwrap_or_else(|| self.fresh());
+            self.bind_name(name, Scheme::monomorphic(itself.clone()));
+            let ty = self.infer(right);
+            self.unify(&itself, &ty);
+            ty
+        } else {
+            self.infer(right)
+        };
+        if let Some(declared) = &declared {
+            if !self.unify(declared, &ty) {
+                let described = self.describe(&[declared, &ty]);
+                self.error(
+                    Span::new(position, name.chars().count()),
+                    format!(
+                        "'{}' is declared as {} but bound to {}",
+                        name, described[0], described[1]
+                    ),
+                );
+            }
+        }
+        self.level -= 1;
+        let ty = declared.unwrap_or(ty);
+        let vars = self.free_vars(&ty);
+        self.bind_name(
+            name,
+            Scheme {
+                vars,
+                ty,
+                variadic: false,
+            },
+        );
+    }
+
+    /**
+     * Records a value returned from the function being checked, if there
+     * is one.
+     */
+    fn returned(&mut self, ty: Ty, value: Option<&Expression>) {
+        let (returns, position) = match self.functions.pop() {
+            Some(function) => function,
+            None => return,
+        };
+        let returns = match returns {
+            Returns::Declared(declared) => {
+                if !self.unify(&declared, &ty) {
+                    let described = self.describe(&[&declared, &ty]);
+                    self.error(
+                        value
+                            .and_then(span_of)
+                            .unwrap_or_else(|| Span::new(position, 2)),
+                        format!(
+                            "The function should return {} but returns {}",
+                            described[0], described[1]
+                        ),
+                    );
+                }
+                Returns::Declared(declared)
+            }
+            Returns::Found(None) => Returns::Found(Some(ty)),
+            Returns::Found(Some(found)) => Returns::Found(Some(self.join(found, &ty))),
+        };
+        self.functions.push((returns, position));
+    }
+
+    fn infer(&mut self, expression: &Expression) -> Ty {
+        match expression {
+            Expression::IntegerLiteral { .. } => Ty::Int,
+            Expression::StringLiteral { .. } => Ty::String,
+            Expression::Boolean { .. } => Ty::Bool,
+            Expression::Identifier { value, .. } => self.lookup(value),
+            Expression::Prefix {
+                operator,
+                right,
+                position,
+            } => {
+                let operand = self.infer(right);
+                let expected = match operator {
+                    PrefixOperator::Minus => Ty::Int,
+                    PrefixOperator::Bang => Ty::Bool,
+                };
+                if !self.unify(&operand, &expected) {
+                    let described = self.describe(&[&operand]);
+                    self.error(
+                        Span::new(*position, 1),
+                        format!("Cannot apply {} to {}", operator, described[0]),
+                    );
+                }
+                expected
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                position,
+            } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                self.infix(&left, operator, &right, *position)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                position,
+            } => {
+                let condition = self.infer(condition);
+                if !self.unify(&condition, &Ty::Bool) {
+                    let described = self.describe(&[&condition]);
+                    self.error(
+                        Span::new(*position, 2),
+                        format!("The condition must be a bool, not {}", described[0]),
+                    );
+                }
+                let consequence = self.block(consequence);
+                let alternative = match alternative {
+                    Some(alternative) => self.block(alternative),
+                    None => Ty::Null,
+                };
+                self.join(consequence, &alternative)
+            }
+            Expression::FnLiteral {
+                param_names,
+                param_types,
+                return_type,
+                body,
+                position,
+                ..
+            } => self.function(
+                param_names,
+                param_types,
+                return_type.as_ref(),
+                body,
+                *position,
+            ),
+            Expression::CallExpression {
+                left,
+                arguments,
+                position,
+            } => self.call(left, arguments, *position),
+            Expression::Block { statements } => self.scoped(statements),
+            Expression::ArrayLiteral { elements } => {
+                let mut element: Option<Ty> = None;
+                for expression in elements {
+                    let ty = self.infer(expression);
+                    element = Some(match element {
+                        None => ty,
+                        Some(element) => self.join(element, &ty),
+                    });
+                }
+                let element = element.unwrap_or_else(|| self.fresh());
+                Ty::Array(Box::new(element))
+            }
+            Expression::Index {
+                left,
+                index,
+                position,
+            } => {
+                let left = self.infer(left);
+                let index = self.infer(index);
+                let span = Span::new(*position, 1);
+                if !self.unify(&index, &Ty::Int) {
+                    let described = self.describe(&[&index]);
+                    self.error(span, format!("Cannot index with {}", described[0]));
+                }
+                if self.resolve(&left) == Ty::Any {
+                    return Ty::Any;
+                }
+                let element = self.fresh();
+                if !self.unify(&left, &Ty::Array(Box::new(element.clone()))) {
+                    let described = self.describe(&[&left]);
+                    self.error(span, format!("Cannot index {}", described[0]));
+                    return Ty::Any;
+                }
+                element
+            }
+        }
+    }
+
+    fn infix(&mut self, left: &Ty, operator: &InfixOperator, right: &Ty, position: Position) -> Ty {
+        let span = Span::new(position, operator.to_string().len());
+        match operator {
+            InfixOperator::Eq | InfixOperator::NotEq => Ty::Bool,
+            InfixOperator::Minus | InfixOperator::Multiply | InfixOperator::Divide => {
+                let left_fits = self.unify(left, &Ty::Int);
+                let right_fits = self.unify(right, &Ty::Int);
+                if !(left_fits && right_fits) {
+                    let described = self.describe(&[left, right]);
+                    self.error(
+                        span,
+                        format!(
+                            "Cannot apply {} to {} and {}",
+                            operator, described[0], described[1]
+                        ),
+                    );
+                }
+                Ty::Int
+            }
+            InfixOperator::Plus | InfixOperator::Lt | InfixOperator::Gt => {
+                // Both integers or both strings.
+                let fits = self.unify(left, right)
+                    && matches!(
+                        self.resolve(left),
+                        Ty::Int | Ty::String | Ty::Var(_) | Ty::Any
+                    );
+                if !fits {
+                    let described = self.describe(&[left, right]);
+                    self.error(
+                        span,
+                        format!(
+                            "Cannot apply {} to {} and {}",
+                            operator, described[0], described[1]
+                        ),
+                    );
+                }
+                match operator {
+                    InfixOperator::Plus if fits => left.clone(),
+                    InfixOperator::Plus => Ty::Any,
+                    _ => Ty::Bool,
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) -> Ty {
+        self.scoped(&block.statements)
+    }
+
+    fn function(
+        &mut self,
+        param_names: &[String],
+        param_types: &[Option<ast::Type>],
+        return_type: Option<&ast::Type>,
+        body: &BlockStatement,
+        position: Position,
+    ) -> Ty {
+        // Type variables written in the annotations are shared between them.
+        let mut names = HashMap::new();
+        let mut scope = HashMap::new();
+        let mut params = vec![];
+        for (i, name) in param_names.iter().enumerate() {
+            let ty = match param_types.get(i) {
+                Some(Some(annotation)) => self.annotated(annotation, &mut names),
+                _ => self.fresh(),
+            };
+            scope.insert(name.clone(), Scheme::monomorphic(ty.clone()));
+            params.push(ty);
+        }
+        let returns = match return_type {
+            Some(annotation) => Returns::Declared(self.annotated(annotation, &mut names)),
+            None => Returns::Found(None),
+        };
+        self.functions.push((returns, position));
+        self.scopes.push(scope);
+        let value = self.statements(&body.statements);
+        self.scopes.pop();
+        // The value of the body is returned like any other.
+        if let Some(Statement::Expression { expression }) = body.statements.last() {
+            self.returned(value, Some(expression));
+        } else if !matches!(body.statements.last(), Some(Statement::Return { .. })) {
+            self.returned(value, None);
+        }
+        let result = match self.functions.pop().unwrap().0 {
+            Returns::Declared(ty) => ty,
+            Returns::Found(ty) => ty.unwrap_or(Ty::Null),
+        };
+        Ty::Function(params, Box::new(result))
+    }
+
+    fn call(&mut self, left: &Expression, arguments: &[Expression], position: Position) -> Ty {
+        let function = self.infer(left);
+        let args: Vec<Ty> = arguments.iter().map(|a| self.infer(a)).collect();
+        let name = match left {
+            Expression::Identifier { value, .. } => format!("'{}'", value),
+            _ => String::from("The function"),
+        };
+        match self.resolve(&function) {
+            Ty::Any => Ty::Any,
+            Ty::Var(_) => {
+                let result = self.fresh();
+                self.unify(&function, &Ty::Function(args, Box::new(result.clone())));
+                result
+            }
+            Ty::Function(params, result) => {
+                let variadic = self.is_variadic(left);
+                let arity_fits = if variadic {
+                    args.len() >= params.len()
+                } else {
+                    args.len() == params.len()
+                };
+                if !arity_fits {
+                    let plural = if params.len() == 1 { "" } else { "s" };
+                    self.error(
+                        Span::new(position, 1),
+                        format!(
+                            "{} takes {} argument{} but is given {}",
+                            name,
+                            params.len(),
+                            plural,
+                            args.len()
+                        ),
+                    );
+                    return *result;
+                }
+                for (i, (param, arg)) in params.iter().zip(&args).enumerate() {
+                    if !self.unify(param, arg) {
+                        let described = self.describe(&[param, arg]);
+                        self.error(
+                            span_of(&arguments[i]).unwrap_or_else(|| Span::new(position, 1)),
+                            format!(
+                                "Argument {} to {} should be {} but is {}",
+                                i + 1,
+                                name,
+                                described[0],
+                                described[1]
+                            ),
+                        );
+                    }
+                }
+                *result
+            }
+            other => {
+                let described = self.describe(&[&other]);
+                self.error(
+                    Span::new(position, 1),
+                    format!("Cannot call {}", described[0]),
+                );
+                Ty::Any
+            }
+        }
+    }
+}
+
+/**
+ * Names a type variable as it would be written: A to Z, then A1 and so on.
+ */
+fn var_name(index: usize) -> String {
+    let letter = (b'A' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => letter.to_string(),
+        n => format!("{}{}", letter, n),
+    }
+}
+
+/**
+ * Where to point at an expression in an error, if it knows where it is.
+ */
+fn span_of(expression: &Expression) -> Option<Span> {
+    let span = match expression {
+        Expression::Identifier {
+            value, position, ..
+        } => Span::new(*position, value.chars().count()),
+        Expression::Infix {
+            operator, position, ..
+        } => Span::new(*position, operator.to_string().len()),
+        Expression::If { position, .. } | Expression::FnLiteral { position, .. } => {
+            Span::new(*position, 2)
+        }
+        Expression::Prefix { position, .. }
+        | Expression::CallExpression { position, .. }
+        | Expression::Index { position, .. } => Span::new(*position, 1),
+        _ => return None,
+    };
+    Some(span)
+}
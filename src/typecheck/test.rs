@@ -0,0 +1,188 @@
+use super::check;
+use crate::eval::builtins;
+use crate::eval::builtins::BuiltinRegistry;
+use pretty_assertions::assert_eq;
+
+/**
+ * Each error found in the source, as it would be reported.
+ */
+fn errors(source: &str) -> Vec<String> {
+    check(source)
+        .unwrap()
+        .iter()
+        .map(|error| error.to_string())
+        .collect()
+}
+
+#[test]
+fn test_well_typed_program() {
+    let source = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+let id = fn(x) { x };
+let greeting = id(\"hello\") + \" world\";
+let doubled = map([1, 2, 3], fn(x) { x * 2 });
+let words = join(map(doubled, fn(x) { format(\"{}\", x) }), \", \");
+let total = reduce(doubled, 0, fn(sum, x) { sum + x });
+let first = fn(items) { if (len(items) > 0) { return items[0]; } null };
+print(fib(id(10)) + total + len(greeting) + len(words));
+first([\"a\"]) + \"b\"";
+    assert_eq!(errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_operator_errors() {
+    let source = "\"a\" - 1;
+let x = [1] + [2];
+-\"a\";
+!1;
+1 < \"b\";
+if (1) { 2 }";
+    assert_eq!(
+        errors(source),
+        vec![
+            "1:5: Cannot apply - to string and int",
+            "2:13: Cannot apply + to [int] and [int]",
+            "3:1: Cannot apply - to string",
+            "4:1: Cannot apply ! to int",
+            "5:3: Cannot apply < to int and string",
+            "6:1: The condition must be a bool, not int",
+        ]
+    );
+}
+
+#[test]
+fn test_inferred_through_functions() {
+    let source = "let add = fn(a, b) { a + b };
+add(1, \"b\");
+let twice = fn(f, x) { f(f(x)) };
+twice(fn(n) { n * 2 }, \"a\");
+let name = fn() { \"monkey\" };
+name() - 1;
+upper(1)";
+    assert_eq!(
+        errors(source),
+        vec![
+            "2:4: Argument 2 to 'add' should be int but is string",
+            "4:6: Argument 2 to 'twice' should be int but is string",
+            "6:8: Cannot apply - to string and int",
+            "7:6: Argument 1 to 'upper' should be string but is int",
+        ]
+    );
+}
+
+#[test]
+fn test_let_polymorphism() {
+    let source = "let id = fn(x) { x };
+id(1) + 1;
+id(\"a\") + \"b\";
+let pair = fn(a, b) { [a, b] };
+pair(1, 2)[0] - 1;
+pair(\"a\", \"b\")[1] + \"c\"";
+    assert_eq!(errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_calls() {
+    let source = "let f = fn(a) { a };
+f(1, 2);
+1(2);
+let s = \"a\";
+s(1);
+format(\"{} {}\", 1, \"b\");
+format(1)";
+    assert_eq!(
+        errors(source),
+        vec![
+            "2:2: 'f' takes 1 argument but is given 2",
+            "3:2: Cannot call int",
+            "5:2: Cannot call string",
+            "7:7: Argument 1 to 'format' should be string but is int",
+        ]
+    );
+}
+
+#[test]
+fn test_index() {
+    let source = "let a = [1, 2];
+a[0] + 1;
+a[\"x\"];
+\"abc\"[0];
+a[0] + \"b\"";
+    assert_eq!(
+        errors(source),
+        vec![
+            "3:2: Cannot index with string",
+            "4:6: Cannot index string",
+            "5:6: Cannot apply + to int and string",
+        ]
+    );
+}
+
+#[test]
+fn test_annotations() {
+    let source = "let x: int = \"a\";
+let f = fn(a: string) -> int { len(a) };
+f(1);
+let g = fn(a) -> string { a * 2 };
+let h = fn(a: int) -> bool { if (a > 0) { return 1; } true };
+let ok: fn(int) -> int = fn(n) { n + 1 };
+let id: fn(A) -> A = fn(x) { x };
+id(1) + id(2);
+id(\"a\") + \"b\"";
+    assert_eq!(
+        errors(source),
+        vec![
+            "1:5: 'x' is declared as int but bound to string",
+            "3:2: Argument 1 to 'f' should be string but is int",
+            "4:29: The function should return string but returns int",
+            "5:9: The function should return bool but returns int",
+        ]
+    );
+}
+
+#[test]
+fn test_mixed_values_are_unchecked() {
+    let source = "let mixed = [1, \"a\"];
+mixed[0] - 1;
+mixed[0] + \"b\";
+let either = fn(a) { if (a) { 1 } else { \"b\" } };
+either(true) - 1;
+let maybe = fn(a) { if (a) { 1 } };
+maybe(false) + 1;
+let any: any = 1;
+any + \"a\";
+unbound - 1";
+    assert_eq!(errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_forward_references_are_unchecked() {
+    let source = "let f = fn() { g() - 1 };
+let g = fn() { \"a\" };
+f()";
+    assert_eq!(errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_recursion() {
+    let source = "let count = fn(n) { if (n > 0) { count(n - 1) } else { 0 } };
+count(\"a\");
+let loop = fn(f) { f(f) };
+loop(loop)";
+    assert_eq!(
+        errors(source),
+        vec!["2:6: Argument 1 to 'count' should be int but is string"]
+    );
+}
+
+#[test]
+fn test_builtin_signatures_parse() {
+    for (_, builtin) in BuiltinRegistry::with_defaults().iter() {
+        let signature = builtins::signature(&builtin.name).unwrap();
+        assert!(
+            signature.parse::<crate::ast::Type>().is_ok(),
+            "{}: {}",
+            builtin.name,
+            signature
+        );
+    }
+}
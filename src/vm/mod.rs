@@ -1,25 +1,57 @@
+use crate::ast::Position;
+use crate::eval::builtins::BuiltinRegistry;
+use crate::limits::{InterruptHandle, Interrupted, LimitExceeded, Limits, Meter};
 use crate::{code, compiler, logic, object};
-use object::Object;
+use core::cell::RefCell;
+use object::{heap, CallContext, CompiledFunction, Object};
+use std::fmt;
+use std::ops::Range;
 use std::rc::Rc;
+use value::{Singletons, Value};
+
+mod value;
 
 const STACK_SIZE: usize = 2048;
 
-struct Stack<'a> {
-    elements: Vec<Rc<Object<'a>>>,
+struct Stack {
+    elements: Vec<Value>,
 }
 
-impl<'a> Stack<'a> {
+impl Stack {
     fn new() -> Self {
         Stack {
             elements: Vec::with_capacity(STACK_SIZE),
         }
     }
-    fn push(&mut self, obj: Rc<Object<'a>>) {
-        self.elements.push(obj);
+    fn push(&mut self, value: Value) {
+        self.elements.push(value);
+    }
+    fn pop(&mut self) -> Option<Value> {
+        self.elements.pop()
+    }
+    fn len(&self) -> usize {
+        self.elements.len()
+    }
+    fn get(&self, index: usize) -> Value {
+        self.elements[index].clone()
+    }
+    fn set(&mut self, index: usize, value: Value) {
+        self.elements[index] = value;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.elements.truncate(len);
     }
-    fn pop(&mut self) -> Option<Rc<Object<'a>>> {
-        let el = self.elements.pop();
-        el
+    fn remove(&mut self, range: Range<usize>) {
+        self.elements.drain(range);
+    }
+    /**
+     * Removes and returns the top `count` elements, in stack order.
+     */
+    fn pop_many(&mut self, count: usize) -> Result<Vec<Value>, VmError> {
+        if count > self.elements.len() {
+            return Err(VmError::PopEmptyStack);
+        }
+        Ok(self.elements.split_off(self.elements.len() - count))
     }
 }
 
@@ -27,116 +59,917 @@ impl<'a> Stack<'a> {
 pub enum VmError {
     PopEmptyStack,
     Misc(String),
+    LimitExceeded(LimitExceeded),
+    /// The names of the functions that were running, innermost first.
+    Interrupted {
+        stack_trace: Vec<String>,
+    },
+    /// An error raised by the program, along with the functions that were
+    /// running and where they had got to, innermost first.
+    Located {
+        error: Box<VmError>,
+        stack_trace: Vec<StackFrame>,
+    },
+}
+
+impl VmError {
+    pub(crate) fn into_message(self) -> String {
+        match self {
+            VmError::PopEmptyStack => String::from("Cannot pop from an empty stack"),
+            VmError::Misc(message) => message,
+            VmError::LimitExceeded(limit) => limit.to_string(),
+            VmError::Interrupted { .. } => Interrupted.to_string(),
+            VmError::Located { error, .. } => error.into_message(),
+        }
+    }
 }
 
-pub struct Vm<'ast, 'bytecode>
-where
-    'ast: 'bytecode,
-{
-    bytecode: &'bytecode compiler::Bytecode<'ast>,
-    stack: Stack<'ast>,
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    /// The function's name, or `<main>` for the main program.
+    pub function: String,
+    /// Where the instruction being run came from, if the bytecode's source
+    /// map says.
+    pub position: Option<Position>,
 }
 
-impl<'ast, 'bytecode> Vm<'ast, 'bytecode> {
-    pub fn new(bytecode: &'bytecode compiler::Bytecode<'ast>) -> Vm<'ast, 'bytecode> {
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)?;
+        if let Some(position) = self.position {
+            write!(f, " at {}", position)?;
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    // Always an `Object::Closure`; kept whole for `CurrentClosure`.
+    closure: Rc<Object>,
+    function: Rc<CompiledFunction>,
+    ip: usize,
+    base_pointer: usize,
+}
+
+pub struct Vm<'bytecode> {
+    bytecode: &'bytecode compiler::Bytecode,
+    // The constant pool with its integers unboxed.
+    constants: Vec<Value>,
+    stack: Stack,
+    globals: Vec<Value>,
+    builtins: Rc<BuiltinRegistry>,
+    frames: Vec<Frame>,
+    last_popped: Option<Value>,
+    singletons: Singletons,
+    meter: Meter,
+}
+
+impl<'bytecode> Vm<'bytecode> {
+    pub fn new(bytecode: &'bytecode compiler::Bytecode) -> Vm<'bytecode> {
+        Vm::with_state(bytecode, vec![], Rc::new(BuiltinRegistry::with_defaults()))
+    }
+
+    /**
+     * Creates a VM that starts with the globals left behind by an earlier
+     * one, for running bytecode compiled by the same `Compiler`. `builtins`
+     * must be the registry the compiler was given.
+     */
+    pub fn with_state(
+        bytecode: &'bytecode compiler::Bytecode,
+        globals: Vec<Rc<Object>>,
+        builtins: Rc<BuiltinRegistry>,
+    ) -> Vm<'bytecode> {
         Vm {
             bytecode,
+            constants: bytecode.constants.iter().map(Value::from_rc).collect(),
             stack: Stack::new(),
+            globals: globals.iter().map(Value::from_rc).collect(),
+            builtins,
+            frames: vec![],
+            last_popped: None,
+            singletons: Singletons::new(),
+            meter: Meter::default(),
         }
     }
 
-    pub fn run(&mut self) -> Result<Option<Rc<Object<'ast>>>, VmError> {
-        let mut instructions_iter = self.bytecode.instructions.iter();
-        let mut should_continue = true;
-        let mut last_popped: Option<Rc<Object>> = None;
-        while should_continue {
-            let instruction = code::Instruction::from_bytes(&mut instructions_iter);
-            if let Some(instruction) = instruction {
-                match instruction {
-                    code::Instruction::Constant(constant_index) => {
-                        self.stack
-                            .push(Rc::clone(&self.bytecode.constants[constant_index as usize]));
-                    }
-                    code::Instruction::Add => {
-                        self.handle_infix(&logic::InfixOperator::Plus)?;
-                    }
-                    code::Instruction::Sub => {
-                        self.handle_infix(&logic::InfixOperator::Minus)?;
-                    }
-                    code::Instruction::Mul => {
-                        self.handle_infix(&logic::InfixOperator::Multiply)?;
-                    }
-                    code::Instruction::Div => {
-                        self.handle_infix(&logic::InfixOperator::Divide)?;
-                    }
-                    code::Instruction::Pop => {
-                        last_popped = self.stack.pop();
-                    }
-                    code::Instruction::True => {
-                        self.stack.push(Rc::new(Object::Boolean(true)));
-                    }
-                    code::Instruction::False => {
-                        self.stack.push(Rc::new(Object::Boolean(false)));
-                    }
-                    code::Instruction::Equal => {
-                        self.handle_infix(&logic::InfixOperator::Eq)?;
-                    }
-                    code::Instruction::NotEqual => {
-                        self.handle_infix(&logic::InfixOperator::NotEq)?;
-                    }
-                    code::Instruction::GreaterThan => {
-                        self.handle_infix(&logic::InfixOperator::Gt)?;
+    /**
+     * Limits what the bytecode can do, for running untrusted scripts.
+     */
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.meter = Meter::new(limits);
+    }
+
+    pub fn set_interrupt(&mut self, interrupt: InterruptHandle) {
+        self.meter.set_interrupt(interrupt);
+    }
+
+    pub fn into_globals(self) -> Vec<Rc<Object>> {
+        let singletons = self.singletons;
+        self.globals
+            .into_iter()
+            .map(|value| singletons.boxed(value))
+            .collect()
+    }
+
+    pub fn run(&mut self) -> Result<Option<Rc<Object>>, VmError> {
+        let function = Rc::new(CompiledFunction {
+            instructions: self.bytecode.instructions.clone(),
+            num_locals: 0,
+            num_parameters: 0,
+            name: None,
+        });
+        let closure = Rc::new(Object::Closure {
+            function: Rc::clone(&function),
+            free: vec![],
+        });
+        self.frames.push(Frame {
+            closure,
+            function,
+            ip: 0,
+            base_pointer: 0,
+        });
+        let result = self.execute(0).map_err(|error| self.locate(error))?;
+        Ok(result.map(|value| self.singletons.boxed(value)))
+    }
+
+    /**
+     * Runs instructions until the frame count drops back to `base_depth`:
+     * either the main program finishing (depth 0) or a function called by a
+     * builtin returning.
+     */
+    fn execute(&mut self, base_depth: usize) -> Result<Option<Value>, VmError> {
+        loop {
+            self.meter.step().map_err(VmError::LimitExceeded)?;
+            if self.meter.poll().is_err() {
+                return Err(self.interrupted());
+            }
+            let frame = self.frames.last_mut().unwrap();
+            let instruction = code::Instruction::read_at(&frame.function.instructions, frame.ip)
+                .map_err(|e| VmError::Misc(format!("Bad instruction at {}: {}", frame.ip, e)))?;
+            let instruction = if let Some((instruction, next_ip)) = instruction {
+                frame.ip = next_ip;
+                instruction
+            } else {
+                // Only the main program can run off the end of its
+                // instructions, as functions always end with a return.
+                self.frames.pop();
+                return Ok(self.last_popped.take());
+            };
+            match instruction {
+                code::Instruction::Constant(constant_index) => {
+                    self.stack
+                        .push(self.constants[constant_index as usize].clone());
+                }
+                code::Instruction::ConstantWide(constant_index) => {
+                    self.stack
+                        .push(self.constants[constant_index as usize].clone());
+                }
+                code::Instruction::Add => {
+                    self.handle_infix(&logic::InfixOperator::Plus)?;
+                }
+                code::Instruction::Sub => {
+                    self.handle_infix(&logic::InfixOperator::Minus)?;
+                }
+                code::Instruction::Mul => {
+                    self.handle_infix(&logic::InfixOperator::Multiply)?;
+                }
+                code::Instruction::Div => {
+                    self.handle_infix(&logic::InfixOperator::Divide)?;
+                }
+                code::Instruction::Pop => {
+                    self.last_popped = self.stack.pop();
+                }
+                code::Instruction::True => {
+                    self.push_new(Object::Boolean(true))?;
+                }
+                code::Instruction::False => {
+                    self.push_new(Object::Boolean(false))?;
+                }
+                code::Instruction::Equal => {
+                    self.handle_infix(&logic::InfixOperator::Eq)?;
+                }
+                code::Instruction::NotEqual => {
+                    self.handle_infix(&logic::InfixOperator::NotEq)?;
+                }
+                code::Instruction::GreaterThan => {
+                    self.handle_infix(&logic::InfixOperator::Gt)?;
+                }
+                code::Instruction::LessThan => {
+                    self.handle_infix(&logic::InfixOperator::Lt)?;
+                }
+                code::Instruction::Minus => {
+                    self.handle_prefix(&logic::PrefixOperator::Minus)?;
+                }
+                code::Instruction::Bang => {
+                    self.handle_prefix(&logic::PrefixOperator::Bang)?;
+                }
+                code::Instruction::JumpFalse(position) => {
+                    self.jump_if_false(position as usize)?;
+                }
+                code::Instruction::JumpFalseWide(position) => {
+                    self.jump_if_false(position as usize)?;
+                }
+                code::Instruction::Jump(position) => {
+                    self.current_frame().ip = position as usize;
+                }
+                code::Instruction::JumpWide(position) => {
+                    self.current_frame().ip = position as usize;
+                }
+                code::Instruction::Null => {
+                    self.push_new(Object::Null)?;
+                }
+                code::Instruction::GetGlobal(index) => {
+                    let global = self.globals.get(index as usize).ok_or_else(|| {
+                        VmError::Misc(format!("Global {} hasn't been set", index))
+                    })?;
+                    self.stack.push(global.clone());
+                }
+                code::Instruction::SetGlobal(index) => {
+                    // Only let statements in the main program set globals,
+                    // and like the interpreter, a program that ends with
+                    // one has no value.
+                    self.last_popped = None;
+                    let value = self.try_pop()?;
+                    let index = index as usize;
+                    if index >= self.globals.len() {
+                        self.globals.resize(index + 1, Value::Null);
                     }
-                    code::Instruction::Minus => {
-                        self.handle_prefix(&logic::PrefixOperator::Minus)?;
+                    self.globals[index] = value;
+                }
+                code::Instruction::Array(length) => {
+                    let elements = self.stack.pop_many(length as usize)?;
+                    let elements = self.boxed_all(elements);
+                    self.push_new(Object::Array(elements))?;
+                }
+                code::Instruction::Index => {
+                    let index = self.try_pop()?;
+                    let left = self.try_pop()?;
+                    let result = logic::eval_index(&left.as_object(), &index.as_object())
+                        .map_err(VmError::Misc)?;
+                    self.stack.push(Value::from_rc(&result));
+                }
+                code::Instruction::Call(num_args) => {
+                    self.call(num_args as usize)?;
+                }
+                code::Instruction::TailCall(num_args) => {
+                    self.tail_call(num_args as usize)?;
+                }
+                code::Instruction::ReturnValue => {
+                    let value = self.try_pop()?;
+                    let frame = self.frames.pop().unwrap();
+                    if frame.base_pointer == 0 {
+                        // A return statement in the main program ends it.
+                        return Ok(Some(value));
                     }
-                    code::Instruction::Bang => {
-                        self.handle_prefix(&logic::PrefixOperator::Bang)?;
+                    // Also removes the function being called.
+                    self.stack.truncate(frame.base_pointer - 1);
+                    if self.frames.len() == base_depth {
+                        return Ok(Some(value));
                     }
-                    code::Instruction::JumpFalse(_) => todo!(),
-                    code::Instruction::Jump(_) => todo!(),
+                    self.stack.push(value);
+                }
+                code::Instruction::GetLocal(index) => {
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.stack
+                        .push(self.stack.get(base_pointer + index as usize));
+                }
+                code::Instruction::SetLocal(index) => {
+                    let base_pointer = self.current_frame().base_pointer;
+                    let value = self.try_pop()?;
+                    self.stack.set(base_pointer + index as usize, value);
+                }
+                code::Instruction::GetBuiltin(index) => {
+                    let builtin = self.builtins.get_by_index(index as usize).ok_or_else(|| {
+                        VmError::Misc(format!("There is no builtin with index {}", index))
+                    })?;
+                    let function = Rc::clone(&builtin.function);
+                    self.push_new(Object::BuiltinFunction(function))?;
+                }
+                code::Instruction::Closure(constant_index, num_free) => {
+                    self.push_closure(constant_index as usize, num_free)?;
+                }
+                code::Instruction::ClosureWide(constant_index, num_free) => {
+                    self.push_closure(constant_index as usize, num_free)?;
+                }
+                code::Instruction::GetFree(index) => {
+                    let free_value = match &*self.current_frame().closure {
+                        Object::Closure { free, .. } => Value::from_rc(&free[index as usize]),
+                        _ => unreachable!(),
+                    };
+                    self.stack.push(free_value);
+                }
+                code::Instruction::CurrentClosure => {
+                    let closure = Rc::clone(&self.current_frame().closure);
+                    self.stack.push(Value::Object(closure));
+                }
+                code::Instruction::NewCell(index) => {
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.stack
+                        .set(base_pointer + index as usize, Value::Object(new_cell()));
+                }
+                code::Instruction::GetCell(index) => {
+                    let base_pointer = self.current_frame().base_pointer;
+                    let cell = self.stack.get(base_pointer + index as usize);
+                    let value = read_cell(&cell.as_object())?;
+                    self.stack.push(Value::from_rc(&value));
+                }
+                code::Instruction::SetCell(index) => {
+                    let base_pointer = self.current_frame().base_pointer;
+                    let value = self.try_pop()?;
+                    let value = self.singletons.boxed(value);
+                    let cell = self.stack.get(base_pointer + index as usize);
+                    write_cell(&cell.as_object(), value)?;
+                }
+                code::Instruction::GetFreeCell(index) => {
+                    let value = match &*self.current_frame().closure {
+                        Object::Closure { free, .. } => read_cell(&free[index as usize])?,
+                        _ => unreachable!(),
+                    };
+                    self.stack.push(Value::from_rc(&value));
                 }
-            } else {
-                should_continue = false;
             }
         }
-        Ok(last_popped)
+    }
+    /**
+     * Calls a function value, such as a closure read out of the globals,
+     * running it to completion.
+     */
+    pub fn call_value(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, VmError> {
+        let num_args = arguments.len();
+        let depth = self.frames.len();
+        self.stack.push(Value::from_rc(function));
+        for argument in &arguments {
+            self.stack.push(Value::from_rc(argument));
+        }
+        self.call(num_args)?;
+        let result = if self.frames.len() == depth {
+            // It was a builtin, so the result is already on the stack.
+            self.try_pop()?
+        } else {
+            self.execute(depth)
+                .map_err(|error| self.locate(error))?
+                .unwrap_or(Value::Null)
+        };
+        Ok(self.singletons.boxed(result))
+    }
+    fn current_frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+    /**
+     * Calls the function sitting below `num_args` arguments on the stack.
+     * Closures get a new frame which the dispatch loop then runs; builtins
+     * are run straight away and their result pushed.
+     */
+    fn call(&mut self, num_args: usize) -> Result<(), VmError> {
+        if num_args >= self.stack.len() {
+            return Err(VmError::PopEmptyStack);
+        }
+        let callee = match self.stack.get(self.stack.len() - 1 - num_args) {
+            Value::Object(callee) => callee,
+            value => return Err(VmError::Misc(format!("Cannot call {}", *value.as_object()))),
+        };
+        match &*callee {
+            Object::Closure { function, .. } => {
+                if num_args != function.num_parameters {
+                    return Err(VmError::Misc(format!(
+                        "Expected {} args, got {}",
+                        function.num_parameters, num_args
+                    )));
+                }
+                // The main program's frame isn't a call.
+                let depth = match self.frames.first() {
+                    Some(frame) if frame.base_pointer == 0 => self.frames.len(),
+                    _ => self.frames.len() + 1,
+                };
+                self.meter
+                    .check_depth(depth)
+                    .map_err(VmError::LimitExceeded)?;
+                let base_pointer = self.stack.len() - num_args;
+                for _ in function.num_parameters..function.num_locals {
+                    self.stack.push(Value::Null);
+                }
+                self.frames.push(Frame {
+                    closure: Rc::clone(&callee),
+                    function: Rc::clone(function),
+                    ip: 0,
+                    base_pointer,
+                });
+            }
+            Object::BuiltinFunction(builtin) => {
+                let arguments = self.stack.pop_many(num_args)?;
+                let arguments = self.boxed_all(arguments);
+                self.stack.pop();
+                let result = builtin.run(&arguments, self).map_err(|m| self.error(m))?;
+                if Rc::strong_count(&result) == 1 {
+                    // Made by the builtin rather than passed through it.
+                    self.meter
+                        .allocate(&result)
+                        .map_err(VmError::LimitExceeded)?;
+                }
+                self.stack.push(Value::from_rc(&result));
+            }
+            _ => return Err(VmError::Misc(format!("Cannot call {}", callee))),
+        }
+        Ok(())
+    }
+    /**
+     * Like `call`, but a closure replaces the current frame instead of
+     * getting a new one on top of it, so recursion in tail position runs in
+     * constant space.
+     */
+    fn tail_call(&mut self, num_args: usize) -> Result<(), VmError> {
+        if num_args >= self.stack.len() {
+            return Err(VmError::PopEmptyStack);
+        }
+        let callee_position = self.stack.len() - 1 - num_args;
+        let base_pointer = self.current_frame().base_pointer;
+        let callee = match self.stack.get(callee_position) {
+            // The main program's frame can't be replaced.
+            Value::Object(callee) if base_pointer != 0 => callee,
+            _ => return self.call(num_args),
+        };
+        let function = match &*callee {
+            Object::Closure { function, .. } => Rc::clone(function),
+            _ => return self.call(num_args),
+        };
+        if num_args != function.num_parameters {
+            return Err(VmError::Misc(format!(
+                "Expected {} args, got {}",
+                function.num_parameters, num_args
+            )));
+        }
+        // The caller and its locals are done with; the callee and its
+        // arguments take their place.
+        self.stack.remove(base_pointer - 1..callee_position);
+        for _ in function.num_parameters..function.num_locals {
+            self.stack.push(Value::Null);
+        }
+        *self.current_frame() = Frame {
+            closure: callee,
+            function,
+            ip: 0,
+            base_pointer,
+        };
+        Ok(())
+    }
+    fn jump_if_false(&mut self, position: usize) -> Result<(), VmError> {
+        let condition = self.try_pop()?;
+        match condition {
+            Value::Boolean(true) => {}
+            Value::Boolean(false) => self.current_frame().ip = position,
+            _ => {
+                return Err(VmError::Misc(format!(
+                    "The condition in an if statement must be a bool. Got {}",
+                    condition.as_object().type_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+    fn push_closure(&mut self, constant_index: usize, num_free: u8) -> Result<(), VmError> {
+        let constant = &self.bytecode.constants[constant_index];
+        let function = if let Object::CompiledFunction(function) = &**constant {
+            Rc::clone(function)
+        } else {
+            return Err(VmError::Misc(format!(
+                "Expected a compiled function, got {}",
+                constant.type_name()
+            )));
+        };
+        let free = self.stack.pop_many(num_free as usize)?;
+        let free = self.boxed_all(free);
+        self.push_new(Object::Closure { function, free })
     }
     fn handle_prefix(&mut self, operator: &logic::PrefixOperator) -> Result<(), VmError> {
         let operand = self.try_pop()?;
-        let result = logic::eval_prefix(operand, operator).map_err(VmError::Misc)?;
-        self.stack.push(Rc::new(result));
-        Ok(())
+        let result = logic::eval_prefix(&operand.as_object(), operator).map_err(VmError::Misc)?;
+        self.push_new(result)
     }
     fn handle_infix(&mut self, operator: &logic::InfixOperator) -> Result<(), VmError> {
         let right = self.try_pop()?;
         let left = self.try_pop()?;
-        let result = logic::eval_infix(left, operator, right).map_err(VmError::Misc)?;
-        self.stack.push(Rc::new(result));
+        let (left, right) = (left.as_object(), right.as_object());
+        self.meter
+            .check_infix(&left, operator, &right)
+            .map_err(VmError::LimitExceeded)?;
+        let result = logic::eval_infix(&left, operator, &right).map_err(VmError::Misc)?;
+        self.push_new(result)
+    }
+    /**
+     * Pushes an object the VM has just made, counting it against the limits.
+     */
+    fn push_new(&mut self, object: Object) -> Result<(), VmError> {
+        self.meter
+            .allocate(&object)
+            .map_err(VmError::LimitExceeded)?;
+        self.stack.push(Value::new(object));
         Ok(())
     }
-    fn try_pop(&mut self) -> Result<Rc<Object<'ast>>, VmError> {
+    /**
+     * Turns values into objects for storing outside the VM.
+     */
+    fn boxed_all(&self, values: Vec<Value>) -> Vec<Rc<Object>> {
+        values
+            .into_iter()
+            .map(|value| self.singletons.boxed(value))
+            .collect()
+    }
+    /**
+     * Turns an error message from a builtin back into a `VmError`, which
+     * is a `LimitExceeded` if a function it called ran out.
+     */
+    fn error(&self, message: String) -> VmError {
+        if self.meter.interrupted() {
+            return self.interrupted();
+        }
+        match self.meter.exceeded() {
+            Some(limit) => VmError::LimitExceeded(limit),
+            None => VmError::Misc(message),
+        }
+    }
+    fn interrupted(&self) -> VmError {
+        let stack_trace = self
+            .frames
+            .iter()
+            .rev()
+            // The main program's frame isn't a call.
+            .filter(|frame| frame.base_pointer != 0)
+            .map(|frame| String::from(frame.closure.function_name()))
+            .collect();
+        VmError::Interrupted { stack_trace }
+    }
+    /**
+     * Adds where each frame had got to to an error raised by the program.
+     * Frames are left in place when an error unwinds, so this still sees
+     * the ones that were running.
+     */
+    fn locate(&self, error: VmError) -> VmError {
+        if !matches!(error, VmError::Misc(_)) {
+            return error;
+        }
+        let stack_trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = if frame.base_pointer == 0 {
+                    String::from("<main>")
+                } else {
+                    String::from(frame.closure.function_name())
+                };
+                StackFrame {
+                    function,
+                    position: self.position(frame),
+                }
+            })
+            .collect();
+        VmError::Located {
+            error: Box::new(error),
+            stack_trace,
+        }
+    }
+    /**
+     * Where the instruction a frame last ran came from. That is the one
+     * ending at its `ip`, as the `ip` moves on before an instruction runs.
+     */
+    fn position(&self, frame: &Frame) -> Option<Position> {
+        let source_map = &self.bytecode.source_map;
+        let line_table = if frame.base_pointer == 0 {
+            &source_map.main
+        } else {
+            let index = self.bytecode.constants.iter().position(|constant| {
+                matches!(&**constant, Object::CompiledFunction(function)
+                    if Rc::ptr_eq(function, &frame.function))
+            })?;
+            source_map.functions.get(&index)?
+        };
+        let (offset, position) = line_table.before(frame.ip)?;
+        match code::Instruction::read_at(&frame.function.instructions, offset) {
+            Ok(Some((_, next))) if next == frame.ip => Some(position),
+            _ => None,
+        }
+    }
+    fn try_pop(&mut self) -> Result<Value, VmError> {
         self.stack.pop().ok_or(VmError::PopEmptyStack)
     }
 }
 
+/**
+ * Lets builtins call back into the VM: the function is pushed as if by a
+ * `Call` instruction and the dispatch loop runs until it returns.
+ */
+impl<'bytecode> CallContext for Vm<'bytecode> {
+    fn call(
+        &mut self,
+        function: &Rc<Object>,
+        arguments: Vec<Rc<Object>>,
+    ) -> Result<Rc<Object>, String> {
+        self.call_value(function, arguments)
+            .map_err(VmError::into_message)
+    }
+
+    fn check_string_length(&mut self, length: usize) -> Result<(), String> {
+        self.meter
+            .check_string_length(length)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/**
+ * Makes an empty cell for `NewCell`, in either VM.
+ */
+pub(crate) fn new_cell() -> Rc<Object> {
+    let cell = Rc::new(Object::Cell(RefCell::new(None)));
+    heap::track_cell(&cell);
+    cell
+}
+
+pub(crate) fn read_cell(cell: &Object) -> Result<Rc<Object>, VmError> {
+    match cell {
+        Object::Cell(value) => value
+            .borrow()
+            .clone()
+            .ok_or_else(|| VmError::Misc(String::from("A variable was used before it was bound"))),
+        _ => Err(VmError::Misc(String::from("Not a cell"))),
+    }
+}
+
+pub(crate) fn write_cell(cell: &Object, value: Rc<Object>) -> Result<(), VmError> {
+    match cell {
+        Object::Cell(cell) => {
+            cell.replace(Some(value));
+            Ok(())
+        }
+        _ => Err(VmError::Misc(String::from("Not a cell"))),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{compiler, lexer, object, parser, vm};
     use object::Object;
-    struct VmTestCase<'a> {
+    struct VmTestCase {
         input: &'static str,
-        expected: Object<'a>,
+        expected: Object,
     }
 
     fn run_vm_test(case: VmTestCase) {
         let mut lexer = lexer::new(case.input);
         let mut parser = parser::Parser::new(&mut lexer);
         let program = parser.parse_program().unwrap();
-        let bytecode = compiler::compile_program(&program);
+        let bytecode = compiler::compile_program(&program).unwrap();
         let mut vm = vm::Vm::new(&bytecode);
         let object = vm.run().unwrap();
-        assert_eq!(object.as_deref(), Some(&case.expected));
+        assert_eq!(object.as_deref(), Some(&case.expected), "{}", case.input);
+    }
+
+    fn run_vm_tests(tests: Vec<VmTestCase>) {
+        for test in tests {
+            run_vm_test(test);
+        }
+    }
+
+    fn int(input: &'static str, value: i64) -> VmTestCase {
+        VmTestCase {
+            input,
+            expected: Object::Integer(value),
+        }
+    }
+
+    fn string(input: &'static str, value: &str) -> VmTestCase {
+        VmTestCase {
+            input,
+            expected: Object::String(String::from(value)),
+        }
+    }
+
+    fn boolean(input: &'static str, value: bool) -> VmTestCase {
+        VmTestCase {
+            input,
+            expected: Object::Boolean(value),
+        }
+    }
+
+    fn run_vm_error_test(input: &'static str, message: &str) {
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        let bytecode = compiler::compile_program(&program).unwrap();
+        let mut vm = vm::Vm::new(&bytecode);
+        match vm.run() {
+            Err(vm::VmError::Located { error, .. }) => {
+                assert!(matches!(*error, vm::VmError::Misc(actual) if actual == message))
+            }
+            other => panic!("Expected an error for {}, got {:?}", input, other),
+        }
+    }
+
+    fn error_locations(input: &'static str) -> Vec<(String, Option<(usize, usize)>)> {
+        let mut lexer = lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer);
+        let program = parser.parse_program().unwrap();
+        let bytecode = compiler::compile_program(&program).unwrap();
+        match vm::Vm::new(&bytecode).run() {
+            Err(vm::VmError::Located { stack_trace, .. }) => stack_trace
+                .into_iter()
+                .map(|frame| {
+                    let position = frame.position.map(|p| (p.line, p.column));
+                    (frame.function, position)
+                })
+                .collect(),
+            other => panic!("Expected an error for {}, got {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn test_conditionals() {
+        run_vm_tests(vec![
+            int("if (true) { 10 }", 10),
+            int("if (true) { 10 } else { 20 }", 10),
+            int("if (false) { 10 } else { 20 }", 20),
+            int("if (1 < 2) { 10 } else { 20 }", 10),
+            VmTestCase {
+                input: "if (false) { 10 }",
+                expected: Object::Null,
+            },
+            VmTestCase {
+                input: "if (true) { let a = 1; }",
+                expected: Object::Null,
+            },
+            int("let a = 3; if (true) { let a = 5; } a", 3),
+            int("let a = 3; if (true) { let a = a + 2; a }", 5),
+            int("let a = { 2; 3; }; a", 3),
+        ]);
+    }
+
+    #[test]
+    fn test_globals_strings_and_arrays() {
+        run_vm_tests(vec![
+            int("let one = 1; one", 1),
+            int("let one = 1; let two = one + one; one + two", 3),
+            string(r#""mon" + "key""#, "monkey"),
+            int("[1, 2, 3][1]", 2),
+            int("let a = [1, 2 * 2]; a[0] + a[1]", 5),
+            VmTestCase {
+                input: "[1][5]",
+                expected: Object::Null,
+            },
+            boolean(r#""a" < "b""#, true),
+        ]);
+    }
+
+    #[test]
+    fn test_functions() {
+        run_vm_tests(vec![
+            int("let f = fn() { 5 + 10 }; f()", 15),
+            int("let f = fn() { return 1; 2 }; f()", 1),
+            int("let add = fn(a, b) { a + b }; add(1, add(2, 3))", 6),
+            int("let f = fn() { let a = 1; let b = 2; a + b }; f()", 3),
+            int(
+                "let g = 10; let f = fn(a) { let b = a; g + b }; f(1) + f(2)",
+                23,
+            ),
+            int("return 10; 9;", 10),
+            int("if (10 > 1) { if (10 > 1) { return 10; } return 1; }", 10),
+            VmTestCase {
+                input: "let f = fn() { }; f()",
+                expected: Object::Null,
+            },
+            int(
+                "let multiply = fn(x) { fn(y) { x * y }; }; multiply(3)(5);",
+                15,
+            ),
+            int(
+                "let a = fn(x) { fn(y) { fn(z) { x + y + z } } }; a(1)(2)(3)",
+                6,
+            ),
+            int(
+                "
+                let countdown = fn(x) { if (x == 0) { 0 } else { countdown(x - 1) } };
+                countdown(10);
+                ",
+                0,
+            ),
+            int(
+                "
+                let wrapper = fn() {
+                    let countdown = fn(x) { if (x == 0) { 0 } else { countdown(x - 1) } };
+                    countdown(10);
+                };
+                wrapper();
+                ",
+                0,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        run_vm_tests(vec![
+            int(
+                "
+                let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+                count(1000000, 0);
+                ",
+                1000000,
+            ),
+            int(
+                "
+                let count = fn(n) { let m = n - 1; if (n == 0) { return 0; } return count(m); };
+                count(1000000);
+                ",
+                0,
+            ),
+            // Called from a builtin, which has to get the result back.
+            int(
+                "
+                let count = fn(n) { if (n == 0) { 7 } else { count(n - 1) } };
+                map([10], count)[0];
+                ",
+                7,
+            ),
+            int("let f = fn(x) { len(x) }; f([1, 2]) + 1", 3),
+            int(
+                "let f = fn() { 1 }; let g = fn(a, b) { f() }; g(1, 2) + 1",
+                2,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_builtins() {
+        run_vm_tests(vec![
+            int(r#"len("four")"#, 4),
+            int("len([1, 2])", 2),
+            string(r#"upper("abc")"#, "ABC"),
+            int("let len = fn(x) { 1 }; len([1, 2])", 1),
+            int("map([1, 2, 3], fn(x) { x * 2 })[2]", 6),
+            int("len(filter([1, 2, 3, 4], fn(x) { x > 2 }))", 2),
+            int("reduce([1, 2, 3], 10, fn(acc, x) { acc + x })", 16),
+            int(
+                "let n = 5; reduce(map([1, 2], fn(x) { x + n }), 0, fn(a, b) { a + b })",
+                13,
+            ),
+            string(
+                r#"join(sort_by(["pear", "fig", "apple"], fn(x) { len(x) }), ",")"#,
+                "fig,pear,apple",
+            ),
+            boolean("any([1, 2], fn(x) { x == 2 })", true),
+            boolean("all([1, 2], fn(x) { x == 2 })", false),
+            int("zip([1, 2], [3, 4, 5])[1][1]", 4),
+            int("enumerate([7, 8])[1][0]", 1),
+            string(r#"map(["a"], upper)[0]"#, "A"),
+            VmTestCase {
+                input: "each([1], fn(x) { x })",
+                expected: Object::Null,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_errors() {
+        run_vm_error_test("let f = fn(a) { a }; f()", "Expected 1 args, got 0");
+        run_vm_error_test("1()", "Cannot call 1");
+        run_vm_error_test(
+            "if (1) { 2 }",
+            "The condition in an if statement must be a bool. Got Integer",
+        );
+        run_vm_error_test(
+            "map([1], fn(x) { x + true })",
+            "Cannot evaluate infix expression 1 + true",
+        );
+        run_vm_error_test(
+            "filter([1], fn(x) { x })",
+            "The function passed to filter must return a Boolean, got Integer",
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        assert_eq!(
+            error_locations("let a = 1;\nlet b = a +\n  true;"),
+            vec![(String::from("<main>"), Some((2, 11)))]
+        );
+        assert_eq!(
+            error_locations("let f = fn(x) {\n  -x\n};\nlet g = fn() { f(\"a\") + 1 };\ng();"),
+            vec![
+                (String::from("f"), Some((2, 3))),
+                (String::from("g"), Some((4, 17))),
+                (String::from("<main>"), Some((5, 2))),
+            ]
+        );
+        assert_eq!(
+            error_locations("if (1) { 2 }"),
+            vec![(String::from("<main>"), Some((1, 1)))]
+        );
+        assert_eq!(
+            error_locations("[1][0](2)"),
+            vec![(String::from("<main>"), Some((1, 7)))]
+        );
     }
 
     #[test]
@@ -0,0 +1,135 @@
+use crate::object::Object;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/**
+ * What the VM keeps on its stack and in its globals. Integers, booleans and
+ * null are held directly, so the arithmetic, comparisons and jumps that make
+ * up most of a program allocate nothing; everything else is a shared
+ * object. Values only become `Rc<Object>`s when they leave the VM: passed
+ * to a builtin, stored in an array or closure, or handed back to the host.
+ *
+ * It is `Clone` but not `Copy`, as the shared objects are held by `Rc`.
+ * A `Copy` value would need handles into a heap owned by the VM, with a
+ * collector of its own, and every handle would have to be turned back into
+ * an `Rc<Object>` for the builtins, the interpreter and the host, which all
+ * share objects that way. Cloning an immediate is as cheap as copying it,
+ * and cloning an object only bumps its count, so the hot loop allocates
+ * nothing either way.
+ */
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Boolean(bool),
+    Null,
+    Object(Rc<Object>),
+}
+
+impl Value {
+    /**
+     * Wraps an object that has just been made, keeping immediates unboxed.
+     */
+    pub fn new(object: Object) -> Self {
+        match object {
+            Object::Integer(value) => Value::Integer(value),
+            Object::Boolean(value) => Value::Boolean(value),
+            Object::Null => Value::Null,
+            object => Value::Object(Rc::new(object)),
+        }
+    }
+
+    pub fn from_rc(object: &Rc<Object>) -> Self {
+        match **object {
+            Object::Integer(value) => Value::Integer(value),
+            Object::Boolean(value) => Value::Boolean(value),
+            Object::Null => Value::Null,
+            _ => Value::Object(Rc::clone(object)),
+        }
+    }
+
+    /**
+     * Views the value as an object, for the operations in `logic`. An
+     * immediate is rebuilt on the Rust stack, which costs no allocation.
+     */
+    pub fn as_object(&self) -> ObjectRef<'_> {
+        match self {
+            Value::Integer(value) => ObjectRef::Immediate(Object::Integer(*value)),
+            Value::Boolean(value) => ObjectRef::Immediate(Object::Boolean(*value)),
+            Value::Null => ObjectRef::Immediate(Object::Null),
+            Value::Object(object) => ObjectRef::Heap(object),
+        }
+    }
+}
+
+pub enum ObjectRef<'a> {
+    Immediate(Object),
+    Heap(&'a Object),
+}
+
+impl Deref for ObjectRef<'_> {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        match self {
+            ObjectRef::Immediate(object) => object,
+            ObjectRef::Heap(object) => object,
+        }
+    }
+}
+
+/**
+ * The objects that `true`, `false` and `null` are boxed as, so that giving
+ * them to a builtin or putting them in an array doesn't allocate.
+ */
+pub struct Singletons {
+    true_object: Rc<Object>,
+    false_object: Rc<Object>,
+    null: Rc<Object>,
+}
+
+impl Singletons {
+    pub fn new() -> Self {
+        Singletons {
+            true_object: Rc::new(Object::Boolean(true)),
+            false_object: Rc::new(Object::Boolean(false)),
+            null: Rc::new(Object::Null),
+        }
+    }
+
+    pub fn boxed(&self, value: Value) -> Rc<Object> {
+        match value {
+            Value::Integer(value) => Rc::new(Object::Integer(value)),
+            Value::Boolean(true) => Rc::clone(&self.true_object),
+            Value::Boolean(false) => Rc::clone(&self.false_object),
+            Value::Null => Rc::clone(&self.null),
+            Value::Object(object) => object,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Singletons, Value};
+    use crate::object::Object;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_immediates_are_unboxed() {
+        assert!(matches!(Value::new(Object::Integer(5)), Value::Integer(5)));
+        assert!(matches!(
+            Value::from_rc(&Rc::new(Object::Boolean(true))),
+            Value::Boolean(true)
+        ));
+        assert!(matches!(
+            Value::new(Object::String(String::from("a"))),
+            Value::Object(_)
+        ));
+        assert_eq!(*Value::Null.as_object(), Object::Null);
+
+        let singletons = Singletons::new();
+        let a = singletons.boxed(Value::Boolean(true));
+        let b = singletons.boxed(Value::Boolean(true));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(*singletons.boxed(Value::Integer(3)), Object::Integer(3));
+    }
+}